@@ -0,0 +1,6 @@
+CREATE TABLE "users" (
+    "id" SERIAL PRIMARY KEY,
+    "username" TEXT NOT NULL,
+    "email" TEXT NOT NULL UNIQUE,
+    "hashed_password" TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE "usersettings" (
+    "id" SERIAL PRIMARY KEY,
+    "hue_index" INTEGER NOT NULL DEFAULT 0,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id")
+);
@@ -0,0 +1,7 @@
+CREATE TABLE "huebridges" (
+    "_id" SERIAL PRIMARY KEY,
+    "id" TEXT NOT NULL,
+    "ip" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "user_settings_id" INTEGER NOT NULL REFERENCES "usersettings"("id")
+);
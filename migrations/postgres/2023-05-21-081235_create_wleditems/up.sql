@@ -0,0 +1,6 @@
+CREATE TABLE "wleditems" (
+    "_id" SERIAL PRIMARY KEY,
+    "ip" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "user_settings_id" INTEGER NOT NULL REFERENCES "usersettings"("id")
+);
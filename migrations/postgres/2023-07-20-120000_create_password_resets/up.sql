@@ -0,0 +1,7 @@
+CREATE TABLE "password_resets" (
+    "id" SERIAL PRIMARY KEY,
+    "token_hash" TEXT NOT NULL UNIQUE,
+    "expires_at" BIGINT NOT NULL,
+    "used" BOOLEAN NOT NULL DEFAULT FALSE,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id")
+);
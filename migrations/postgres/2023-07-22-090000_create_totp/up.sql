@@ -0,0 +1,13 @@
+CREATE TABLE "totp_secrets" (
+    "id" SERIAL PRIMARY KEY,
+    "secret" TEXT NOT NULL,
+    "confirmed" BOOLEAN NOT NULL DEFAULT FALSE,
+    "user_id" INTEGER NOT NULL UNIQUE REFERENCES "users"("id")
+);
+
+CREATE TABLE "recovery_codes" (
+    "id" SERIAL PRIMARY KEY,
+    "code_hash" TEXT NOT NULL,
+    "used" BOOLEAN NOT NULL DEFAULT FALSE,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id")
+);
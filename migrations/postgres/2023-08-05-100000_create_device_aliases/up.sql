@@ -0,0 +1,9 @@
+ALTER TABLE "huebridges" ADD COLUMN "bridgeid" TEXT;
+
+CREATE TABLE "device_aliases" (
+    "id" SERIAL PRIMARY KEY,
+    "alias" TEXT NOT NULL,
+    "device_id" TEXT NOT NULL,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id"),
+    UNIQUE("user_id", "alias")
+);
@@ -0,0 +1,11 @@
+CREATE TABLE "device_metadata" (
+    "id" SERIAL PRIMARY KEY,
+    "device_id" TEXT NOT NULL,
+    "name" TEXT,
+    "icon" TEXT,
+    "room" TEXT,
+    "sort_order" INTEGER,
+    "favorite" BOOLEAN NOT NULL DEFAULT FALSE,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id"),
+    UNIQUE("user_id", "device_id")
+);
@@ -0,0 +1,9 @@
+CREATE TABLE "shelly_devices" (
+    "id" SERIAL PRIMARY KEY,
+    "device_id" TEXT NOT NULL,
+    "ip" TEXT NOT NULL,
+    "generation" INTEGER NOT NULL,
+    "model" TEXT NOT NULL,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id"),
+    UNIQUE("user_id", "device_id")
+);
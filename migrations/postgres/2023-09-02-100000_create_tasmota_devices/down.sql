@@ -0,0 +1 @@
+DROP TABLE "tasmota_devices";
@@ -0,0 +1,10 @@
+CREATE TABLE "tasmota_devices" (
+    "id" SERIAL PRIMARY KEY,
+    "device_id" TEXT NOT NULL,
+    "ip" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "username" TEXT,
+    "password" TEXT,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id"),
+    UNIQUE("user_id", "device_id")
+);
@@ -0,0 +1,6 @@
+CREATE TABLE "zigbee2mqtt_bridges" (
+    "id" SERIAL PRIMARY KEY,
+    "base_topic" TEXT NOT NULL,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id"),
+    UNIQUE("user_id", "base_topic")
+);
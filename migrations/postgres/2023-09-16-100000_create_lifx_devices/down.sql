@@ -0,0 +1 @@
+DROP TABLE "lifx_devices";
@@ -0,0 +1,9 @@
+CREATE TABLE "lifx_devices" (
+    "id" SERIAL PRIMARY KEY,
+    "device_id" TEXT NOT NULL,
+    "ip" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "product" INTEGER NOT NULL,
+    "user_id" INTEGER NOT NULL REFERENCES "users"("id"),
+    UNIQUE("user_id", "device_id")
+);
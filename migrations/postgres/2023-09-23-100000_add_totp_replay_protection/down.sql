@@ -0,0 +1,2 @@
+ALTER TABLE "totp_secrets" DROP COLUMN "mfa_nonce";
+ALTER TABLE "totp_secrets" DROP COLUMN "last_step";
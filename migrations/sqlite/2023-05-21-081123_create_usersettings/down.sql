@@ -0,0 +1 @@
+DROP TABLE "usersettings";
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE "huebridges";
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE "wleditems";
\ No newline at end of file
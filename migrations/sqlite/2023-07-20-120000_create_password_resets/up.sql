@@ -0,0 +1,9 @@
+CREATE TABLE "password_resets" (
+    "id" INTEGER NOT NULL,
+    "token_hash" TEXT NOT NULL UNIQUE,
+    "expires_at" BIGINT NOT NULL,
+    "used" BOOLEAN NOT NULL DEFAULT 0,
+    "user_id" INTEGER NOT NULL,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
@@ -0,0 +1,17 @@
+CREATE TABLE "totp_secrets" (
+    "id" INTEGER NOT NULL,
+    "secret" TEXT NOT NULL,
+    "confirmed" BOOLEAN NOT NULL DEFAULT 0,
+    "user_id" INTEGER NOT NULL UNIQUE,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE TABLE "recovery_codes" (
+    "id" INTEGER NOT NULL,
+    "code_hash" TEXT NOT NULL,
+    "used" BOOLEAN NOT NULL DEFAULT 0,
+    "user_id" INTEGER NOT NULL,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
@@ -0,0 +1,2 @@
+DROP TABLE "device_aliases";
+ALTER TABLE "huebridges" DROP COLUMN "bridgeid";
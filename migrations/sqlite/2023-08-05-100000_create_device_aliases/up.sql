@@ -0,0 +1,11 @@
+ALTER TABLE "huebridges" ADD COLUMN "bridgeid" TEXT;
+
+CREATE TABLE "device_aliases" (
+    "id" INTEGER NOT NULL,
+    "alias" TEXT NOT NULL,
+    "device_id" TEXT NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    UNIQUE("user_id", "alias"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE "device_metadata" (
+    "id" INTEGER NOT NULL,
+    "device_id" TEXT NOT NULL,
+    "name" TEXT,
+    "icon" TEXT,
+    "room" TEXT,
+    "sort_order" INTEGER,
+    "favorite" BOOLEAN NOT NULL DEFAULT 0,
+    "user_id" INTEGER NOT NULL,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    UNIQUE("user_id", "device_id"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE "huebridges" DROP COLUMN "allow_http";
+ALTER TABLE "huebridges" DROP COLUMN "certificate";
@@ -0,0 +1,2 @@
+ALTER TABLE "huebridges" ADD COLUMN "certificate" TEXT;
+ALTER TABLE "huebridges" ADD COLUMN "allow_http" BOOLEAN NOT NULL DEFAULT 0;
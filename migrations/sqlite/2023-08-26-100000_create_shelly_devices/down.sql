@@ -0,0 +1 @@
+DROP TABLE "shelly_devices";
@@ -0,0 +1,11 @@
+CREATE TABLE "shelly_devices" (
+    "id" INTEGER NOT NULL,
+    "device_id" TEXT NOT NULL,
+    "ip" TEXT NOT NULL,
+    "generation" INTEGER NOT NULL,
+    "model" TEXT NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    UNIQUE("user_id", "device_id"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE "tasmota_devices" (
+    "id" INTEGER NOT NULL,
+    "device_id" TEXT NOT NULL,
+    "ip" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "username" TEXT,
+    "password" TEXT,
+    "user_id" INTEGER NOT NULL,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    UNIQUE("user_id", "device_id"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
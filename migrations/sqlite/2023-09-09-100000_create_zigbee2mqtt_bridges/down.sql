@@ -0,0 +1 @@
+DROP TABLE "zigbee2mqtt_bridges";
@@ -0,0 +1,8 @@
+CREATE TABLE "zigbee2mqtt_bridges" (
+    "id" INTEGER NOT NULL,
+    "base_topic" TEXT NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    FOREIGN KEY("user_id") REFERENCES "users"("id"),
+    UNIQUE("user_id", "base_topic"),
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE "totp_secrets" ADD COLUMN "last_step" BIGINT;
+ALTER TABLE "totp_secrets" ADD COLUMN "mfa_nonce" TEXT;
@@ -11,7 +11,4 @@ rustup update
 chmod +x setup.sh
 ./setup.sh
 
-cp target/release/home_api_rust .
-
-cargo install diesel_cli --no-default-features --features "sqlite-bundled"
-cp $HOME/.cargo/bin/diesel .
\ No newline at end of file
+cp target/release/home_api_rust .
\ No newline at end of file
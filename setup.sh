@@ -90,17 +90,15 @@ cargo build --release
 
 printYellow "Generating api-key secret"
 
-SECRET=$(python3 -c "import os; import binascii; print(binascii.hexlify(os.urandom(32)))")
-ALGORITHM="HS256"
-substring=$(echo "$SECRET" | sed "s/'//g" | awk '{print $1}' | cut -c 2-)
+SECRET=$(python3 -c "import secrets; print(secrets.token_hex(32))")
 
 if [[ -f .env ]]; then
     printGreen ".env exists, skipping."
 else
     printYellow ".env does not exist, creating it."
-    echo "secret=$substring" > .env
-    echo "algorithm=$ALGORITHM" >> .env
-    echo "port=8000" >> .env
+    echo "HOME_API_JWT_SECRET=$SECRET" > .env
+    echo "HOME_API_DATABASE_URL=db.sqlite" >> .env
+    echo "HOME_API_PORT=8000" >> .env
 fi
 
 printGreen "api-key secret is generated."
\ No newline at end of file
@@ -1,142 +1,376 @@
-use std::{collections::BTreeMap, env};
-
-use hmac::{Hmac, Mac};
-use jwt::{AlgorithmType, Header, SignWithKey, Token, VerifyWithKey};
-use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
-use rocket::{
-    http::Status,
-    request::{FromRequest, Outcome, Request},
-};
-use rocket_okapi::{
-    gen::OpenApiGenerator,
-    request::{OpenApiFromRequest, RequestHeaderInput},
-};
-use schemars::_serde_json;
-use serde::{Deserialize, Serialize};
-use sha2::Sha256;
-
-use crate::db::models::User;
-
-static TOKEN_VERSION: &str = "1.0.0";
-
-static mut STATIC_SECRET_KEY: Option<String> = None;
-
-pub fn get_secret_key() -> String {
-    unsafe {
-        if STATIC_SECRET_KEY.is_none() {
-            STATIC_SECRET_KEY = Some(env::var("SECRET_KEY").unwrap_or("".to_string()));
-        }
-
-        STATIC_SECRET_KEY.clone().unwrap()
-    }
-}
-
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct JWTToken {
-    pub user_id: i32,
-    pub username: String,
-    pub email: String,
-    pub token_version: String,
-}
-
-pub fn hash_password(password: &str) -> String {
-    bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap()
-}
-
-pub fn verify_password(password: &str, hashed_password: &str) -> bool {
-    bcrypt::verify(password, hashed_password).unwrap()
-}
-
-pub fn read_token(token_str: &str) -> Result<JWTToken, String> {
-    let key: Hmac<Sha256> = Hmac::new_from_slice(get_secret_key().as_bytes()).unwrap();
-
-    let token: Result<Token<Header, BTreeMap<String, String>, _>, jwt::Error> =
-        VerifyWithKey::verify_with_key(token_str, &key);
-
-    if token.is_ok() {
-        let token = token.unwrap();
-        let header = token.header();
-        let claims = token.claims();
-
-        if header.algorithm != AlgorithmType::Hs256 {
-            return Err("Invalid algorithm".into());
-        }
-
-        let sub = claims["sub"].clone();
-        let token_data: JWTToken = _serde_json::from_str(&sub).unwrap();
-        Ok(token_data)
-    } else {
-        Err("Invalid token".into())
-    }
-}
-
-pub fn create_token(token_data: JWTToken) -> String {
-    let key: Hmac<Sha256> = Hmac::new_from_slice(get_secret_key().as_bytes()).unwrap();
-
-    let header = Header {
-        algorithm: AlgorithmType::Hs256,
-        ..Default::default()
-    };
-
-    let mut claims = BTreeMap::new();
-
-    claims.insert("sub", _serde_json::to_string(&token_data).unwrap());
-
-    let token = Token::new(header, claims);
-    token.sign_with_key(&key).unwrap().as_str().to_owned()
-}
-
-impl User {
-    pub fn generate_token(&self) -> String {
-        let token_data = JWTToken {
-            user_id: self.id,
-            username: self.username.clone(),
-            email: self.email.clone(),
-            token_version: TOKEN_VERSION.to_owned(),
-        };
-        create_token(token_data)
-    }
-}
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for JWTToken {
-    type Error = ();
-
-    async fn from_request(request: &'r Request<'_>) -> Outcome<JWTToken, Self::Error> {
-        let keys: Vec<_> = request.headers().get("Authorization").collect();
-        if keys.len() != 1 {
-            return Outcome::Failure((Status::Unauthorized, ()));
-        }
-        let key = keys[0];
-        let key = key.replace("Bearer ", "");
-        match read_token(&key) {
-            Ok(key) => Outcome::Success(key),
-            Err(_) => Outcome::Failure((Status::Unauthorized, ())),
-        }
-    }
-}
-
-impl<'r> OpenApiFromRequest<'r> for JWTToken {
-    fn from_request_input(
-        _: &mut OpenApiGenerator,
-        _: String,
-        _: bool,
-    ) -> rocket_okapi::Result<RequestHeaderInput> {
-        let mut requirements = SecurityRequirement::new();
-        requirements.insert("HttpAuth".to_owned(), Vec::new());
-
-        let input = RequestHeaderInput::Security(
-            "HttpAuth".to_owned(),
-            SecurityScheme {
-                description: Some("API Key".to_string()),
-                data: SecuritySchemeData::Http {
-                    scheme: "bearer".to_owned(),
-                    bearer_format: Some("bearer".to_owned()),
-                },
-                extensions: Object::default(),
-            },
-            requirements,
-        );
-        Ok(input)
-    }
-}
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use jwt::{AlgorithmType, Header, SignWithKey, Token, VerifyWithKey};
+use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+};
+use schemars::_serde_json;
+use serde::{Deserialize, Serialize};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::app_config,
+    db::{
+        connection::{self, DbConnection, DbPool},
+        models::User,
+    },
+    repsonses::ApiError,
+};
+
+static TOKEN_VERSION: &str = "1.0.0";
+
+pub fn get_secret_key() -> &'static str {
+    &app_config().jwt_secret
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct JWTToken {
+    pub user_id: i32,
+    pub username: String,
+    pub email: String,
+    pub token_version: String,
+    /// [`User::session_version`] when the token was issued. Tokens issued
+    /// before it was introduced carry none and count as `0`.
+    #[serde(default)]
+    pub session_version: i32,
+}
+
+pub fn hash_password(password: &str) -> String {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap()
+}
+
+pub fn verify_password(password: &str, hashed_password: &str) -> bool {
+    bcrypt::verify(password, hashed_password).unwrap()
+}
+
+pub fn generate_reset_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Checks signature, expiry and that the user still exists and has not
+/// changed the password since the token was issued.
+pub fn read_token(token_str: &str, connection: &mut DbConnection) -> Result<JWTToken, String> {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(get_secret_key().as_bytes()).unwrap();
+
+    let token: Token<Header, BTreeMap<String, String>, _> =
+        VerifyWithKey::verify_with_key(token_str, &key).map_err(|_| "Invalid token".to_string())?;
+    let header = token.header();
+    let claims = token.claims();
+
+    if header.algorithm != AlgorithmType::Hs256 {
+        return Err("Invalid algorithm".into());
+    }
+
+    if claims.contains_key("mfa") {
+        return Err("MFA pending".into());
+    }
+
+    let expires_at = claims
+        .get("exp")
+        .and_then(|exp| exp.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    if expires_at < unix_now() {
+        return Err("Token expired".into());
+    }
+
+    let token_data: JWTToken = claims
+        .get("sub")
+        .and_then(|sub| _serde_json::from_str(sub).ok())
+        .ok_or_else(|| "Invalid token".to_string())?;
+
+    let user =
+        User::get_user(connection, token_data.user_id).map_err(|_| "Invalid token".to_string())?;
+
+    if user.session_version != token_data.session_version {
+        return Err("Session revoked".into());
+    }
+
+    Ok(token_data)
+}
+
+pub fn create_token(token_data: JWTToken) -> String {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(get_secret_key().as_bytes()).unwrap();
+
+    let header = Header {
+        algorithm: AlgorithmType::Hs256,
+        ..Default::default()
+    };
+
+    let mut claims = BTreeMap::new();
+
+    claims.insert("sub", _serde_json::to_string(&token_data).unwrap());
+    claims.insert(
+        "exp",
+        (unix_now() + app_config().access_token_lifetime).to_string(),
+    );
+
+    let token = Token::new(header, claims);
+    token.sign_with_key(&key).unwrap().as_str().to_owned()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Short-lived token issued after the password step of a login when the user
+/// has TOTP enabled. It is rejected by [`read_token`] and can only be
+/// exchanged for an access token together with a valid code. `nonce` is
+/// stored with the user's TOTP secret and cleared on use, which makes the
+/// token single-use.
+pub fn create_mfa_token(user_id: i32, nonce: &str) -> String {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(get_secret_key().as_bytes()).unwrap();
+
+    let header = Header {
+        algorithm: AlgorithmType::Hs256,
+        ..Default::default()
+    };
+
+    let mut claims = BTreeMap::new();
+
+    claims.insert("sub", user_id.to_string());
+    claims.insert("mfa", "pending".to_string());
+    claims.insert("jti", nonce.to_string());
+    claims.insert("exp", (unix_now() + app_config().mfa_token_lifetime).to_string());
+
+    let token = Token::new(header, claims);
+    token.sign_with_key(&key).unwrap().as_str().to_owned()
+}
+
+/// Returns the user id and nonce of a pending MFA login.
+pub fn read_mfa_token(token_str: &str) -> Result<(i32, String), String> {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(get_secret_key().as_bytes()).unwrap();
+
+    let token: Token<Header, BTreeMap<String, String>, _> =
+        VerifyWithKey::verify_with_key(token_str, &key).map_err(|_| "Invalid token".to_string())?;
+    let claims = token.claims();
+
+    if token.header().algorithm != AlgorithmType::Hs256 {
+        return Err("Invalid algorithm".into());
+    }
+
+    if claims.get("mfa").map(|mfa| mfa.as_str()) != Some("pending") {
+        return Err("Invalid token".into());
+    }
+
+    let expires_at = claims
+        .get("exp")
+        .and_then(|exp| exp.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    if expires_at < unix_now() {
+        return Err("Token expired".into());
+    }
+
+    let user_id = claims.get("sub").and_then(|sub| sub.parse::<i32>().ok());
+
+    match (user_id, claims.get("jti")) {
+        (Some(user_id), Some(nonce)) => Ok((user_id, nonce.clone())),
+        _ => Err("Invalid token".into()),
+    }
+}
+
+impl User {
+    pub fn generate_token(&self) -> String {
+        create_token(self.token())
+    }
+
+    /// Claims for this user, also used to act on behalf of a user outside of
+    /// HTTP requests.
+    pub fn token(&self) -> JWTToken {
+        JWTToken {
+            user_id: self.id,
+            username: self.username.clone(),
+            email: self.email.clone(),
+            token_version: TOKEN_VERSION.to_owned(),
+            session_version: self.session_version,
+        }
+    }
+}
+
+impl JWTToken {
+    /// Loads the user the token was issued for. Fails with `401` when the
+    /// user no longer exists.
+    pub fn get_user(&self, connection: &mut DbConnection) -> Result<User, ApiError> {
+        User::get_user(connection, self.user_id).map_err(|e| match e {
+            diesel::result::Error::NotFound => ApiError::Unauthorized("Invalid token".to_string()),
+            e => ApiError::from(e),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for JWTToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<JWTToken, Self::Error> {
+        let keys: Vec<_> = request.headers().get("Authorization").collect();
+        if keys.len() != 1 {
+            return Outcome::Failure((Status::Unauthorized, ()));
+        }
+        let key = keys[0];
+        let key = key.replace("Bearer ", "");
+
+        let pool = match request.rocket().state::<DbPool>() {
+            Some(pool) => pool,
+            None => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        match connection::run(pool, move |connection| Ok(read_token(&key, connection))).await {
+            Ok(Ok(key)) => Outcome::Success(key),
+            Ok(Err(_)) => Outcome::Failure((Status::Unauthorized, ())),
+            Err(e) => Outcome::Failure((e.status(), ())),
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for JWTToken {
+    fn from_request_input(
+        _: &mut OpenApiGenerator,
+        _: String,
+        _: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        let mut requirements = SecurityRequirement::new();
+        requirements.insert("HttpAuth".to_owned(), Vec::new());
+
+        let input = RequestHeaderInput::Security(
+            "HttpAuth".to_owned(),
+            SecurityScheme {
+                description: Some("API Key".to_string()),
+                data: SecuritySchemeData::Http {
+                    scheme: "bearer".to_owned(),
+                    bearer_format: Some("bearer".to_owned()),
+                },
+                extensions: Object::default(),
+            },
+            requirements,
+        );
+        Ok(input)
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use crate::{
+        db::{
+            connection::{get_connection, run_migrations},
+            models::{NewUser, UpdateUser},
+        },
+        utils::testing::{init_config, memory_pool},
+    };
+
+    use super::*;
+
+    #[test]
+    fn rejects_tokens_issued_before_a_password_change() {
+        init_config();
+
+        let pool = memory_pool();
+        run_migrations(&pool).unwrap();
+
+        let connection = &mut get_connection(&pool).unwrap();
+
+        let user = User::create_user(
+            connection,
+            &NewUser {
+                username: "carol",
+                email: "carol@example.com",
+                hashed_password: "hash",
+            },
+        )
+        .unwrap();
+
+        let old_token = user.generate_token();
+        assert_eq!(read_token(&old_token, connection).unwrap().user_id, user.id);
+
+        let user = user
+            .update(
+                connection,
+                &UpdateUser {
+                    username: None,
+                    email: None,
+                    hashed_password: Some("new hash"),
+                },
+            )
+            .unwrap();
+
+        assert!(read_token(&old_token, connection).is_err());
+        assert!(read_token(&user.generate_token(), connection).is_ok());
+
+        // Other changes keep the sessions
+        let token = user.generate_token();
+        user.update(
+            connection,
+            &UpdateUser {
+                username: Some("caroline"),
+                email: None,
+                hashed_password: None,
+            },
+        )
+        .unwrap();
+        assert!(read_token(&token, connection).is_ok());
+
+        user.delete(connection).unwrap();
+        assert!(read_token(&token, connection).is_err());
+    }
+
+    fn sign(claims: &[(&str, &str)]) -> String {
+        let key: Hmac<Sha256> = Hmac::new_from_slice(get_secret_key().as_bytes()).unwrap();
+        let claims: BTreeMap<&str, &str> = claims.iter().copied().collect();
+
+        Token::new(Header::default(), claims)
+            .sign_with_key(&key)
+            .unwrap()
+            .as_str()
+            .to_owned()
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        init_config();
+
+        let pool = memory_pool();
+        run_migrations(&pool).unwrap();
+
+        let connection = &mut get_connection(&pool).unwrap();
+        let exp = (unix_now() + 60).to_string();
+
+        for token in [
+            "".to_string(),
+            "not.a.token".to_string(),
+            sign(&[("exp", &exp)]),
+            sign(&[("exp", &exp), ("sub", "1")]),
+            sign(&[("exp", &exp), ("sub", "{\"user_id\": \"1\"}")]),
+        ] {
+            assert_eq!(
+                read_token(&token, connection).err().unwrap(),
+                "Invalid token"
+            );
+        }
+
+        assert!(read_mfa_token("not.a.token").is_err());
+    }
+}
@@ -1,262 +1,791 @@
-use okapi::openapi3::OpenApi;
-use rocket::{get, http::Status, post, serde::json::Json, State};
-use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
-use schemars::JsonSchema;
-
-use crate::{
-    db::{
-        connection::{self, SqlitePool, SqlitePooledConnection},
-        models::{
-            HueBridge, NewHueBridge, NewUser, NewWledItem, UpdateUserSettings, User, WledItem,
-        },
-    },
-    repsonses::CustomResponse,
-};
-
-use super::auth::{hash_password, verify_password, JWTToken};
-
-#[derive(serde::Deserialize, JsonSchema)]
-struct HueBridgeRequest {
-    host: String,
-    user: String,
-}
-
-#[derive(serde::Deserialize, JsonSchema)]
-struct WledItemRequest {
-    name: String,
-    ip: String,
-}
-
-#[derive(serde::Deserialize, JsonSchema)]
-struct UserSettingsRequest {
-    hue_bridges: Vec<HueBridgeRequest>,
-    wled_ips: Vec<WledItemRequest>,
-}
-
-#[derive(serde::Deserialize, JsonSchema)]
-struct SignupRequest {
-    username: String,
-    email: String,
-    password: String,
-    settings: Option<UserSettingsRequest>,
-}
-
-#[derive(serde::Serialize, JsonSchema)]
-struct SignupResponse {
-    access_token: String,
-    token_type: String,
-}
-
-fn connection_from_pool(pool: &State<SqlitePool>) -> SqlitePooledConnection {
-    connection::get_connection(&pool).unwrap()
-}
-
-#[openapi(tag = "Auth")]
-#[post("/signup", format = "json", data = "<signup_request>")]
-fn signup(
-    signup_request: Json<SignupRequest>,
-    dbpool: &State<SqlitePool>,
-) -> Result<Json<SignupResponse>, CustomResponse> {
-    let connection = &mut connection_from_pool(dbpool);
-
-    let user_by_username = User::get_user_by_username(connection, &signup_request.username);
-
-    if user_by_username.is_ok() {
-        return Err(CustomResponse {
-            status: Status::Conflict,
-            message: "Username already exists".to_string(),
-        });
-    }
-
-    let user_by_email = User::get_user_by_mail(connection, &signup_request.email);
-
-    if user_by_email.is_ok() {
-        return Err(CustomResponse {
-            status: Status::Conflict,
-            message: "Email already exists".to_string(),
-        });
-    }
-
-    let user = User::create_user(
-        connection,
-        &NewUser {
-            username: &signup_request.username,
-            email: &signup_request.email,
-            hashed_password: &hash_password(&signup_request.password),
-        },
-    );
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Error creating user".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    if let Some(user_settings_obj) = &signup_request.settings {
-        let mut user_settings = user.get_usersettings(connection).unwrap();
-
-        if user_settings_obj.hue_bridges.len() > 0 {
-            let result = user_settings.update(
-                connection,
-                &UpdateUserSettings {
-                    hue_index: Some(&(user_settings.hue_index + 1)),
-                    user_id: None,
-                },
-            );
-
-            if result.is_err() {
-                println!("Error updating user settings: {:?}", result);
-                return Err(CustomResponse {
-                    status: Status::InternalServerError,
-                    message: "Error updating user settings".to_string(),
-                });
-            }
-
-            user_settings = result.unwrap();
-
-            for hue_bridge in user_settings_obj.hue_bridges.iter() {
-                let hue_bridge = HueBridge::create_huebridge(
-                    connection,
-                    &NewHueBridge {
-                        id: &user_settings.hue_index.to_string(),
-                        user: &hue_bridge.user,
-                        ip: &hue_bridge.host,
-                        user_settings_id: &user_settings.id,
-                    },
-                );
-
-                if hue_bridge.is_err() {
-                    println!("Error creating hue bridge: {:?}", hue_bridge);
-                    return Err(CustomResponse {
-                        status: Status::InternalServerError,
-                        message: "Error creating hue bridge".to_string(),
-                    });
-                }
-            }
-        }
-
-        if user_settings_obj.wled_ips.len() > 0 {
-            for wled_ip in user_settings_obj.wled_ips.iter() {
-                let wled_item = WledItem::create_wleditem(
-                    connection,
-                    &NewWledItem {
-                        name: &wled_ip.name,
-                        ip: &wled_ip.ip,
-                        user_settings_id: &user_settings.id,
-                    },
-                );
-
-                if wled_item.is_err() {
-                    println!("Error creating wled item: {:?}", wled_item);
-                    return Err(CustomResponse {
-                        status: Status::InternalServerError,
-                        message: "Error creating wled item".to_string(),
-                    });
-                }
-            }
-        }
-    }
-
-    Ok(Json(SignupResponse {
-        access_token: user.generate_token(),
-        token_type: "bearer".to_string(),
-    }))
-}
-
-#[derive(serde::Deserialize, JsonSchema)]
-struct LoginRequest {
-    email: String,
-    password: String,
-}
-
-#[openapi(tag = "Auth")]
-#[post("/login", format = "json", data = "<login_request>")]
-fn login(
-    login_request: Json<LoginRequest>,
-    dbpool: &State<SqlitePool>,
-) -> Result<Json<SignupResponse>, CustomResponse> {
-    let connection = &mut connection_from_pool(dbpool);
-
-    let user = User::get_user_by_mail(connection, &login_request.email);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Invalid email or password".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    if !verify_password(&login_request.password, &user.hashed_password) {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Invalid email or password".to_string(),
-        });
-    }
-
-    Ok(Json(SignupResponse {
-        access_token: user.generate_token(),
-        token_type: "bearer".to_string(),
-    }))
-}
-
-#[derive(serde::Serialize, JsonSchema)]
-struct MeResponse {
-    username: String,
-    email: String,
-}
-
-#[openapi(tag = "Auth")]
-#[get("/refresh")]
-fn refresh(
-    jwt: JWTToken,
-    db_pool: &State<SqlitePool>,
-) -> Result<Json<SignupResponse>, CustomResponse> {
-    let connection = &mut connection_from_pool(db_pool);
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Invalid token".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    Ok(Json(SignupResponse {
-        access_token: user.generate_token(),
-        token_type: "bearer".to_string(),
-    }))
-}
-
-#[openapi(tag = "Auth")]
-#[get("/me")]
-fn me(jwt: JWTToken, db_pool: &State<SqlitePool>) -> Result<Json<MeResponse>, CustomResponse> {
-    let connection = &mut connection_from_pool(db_pool);
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Invalid token".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    Ok(Json(MeResponse {
-        username: user.username.to_string(),
-        email: user.email.to_string(),
-    }))
-}
-
-pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
-    openapi_get_routes_spec![settings: signup, login, refresh, me]
-}
+use std::{
+    fs::remove_file,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use diesel::Connection;
+use okapi::openapi3::OpenApi;
+use rocket::{delete, get, patch, post, put, serde::json::Json, State};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    JsonSchema,
+    _serde_json::{json, Value},
+};
+
+use crate::{
+    config::app_config,
+    db::{
+        connection::{self, DbConnection, DbPool, DbPooledConnection},
+        models::{
+            HueBridge, NewHueBridge, NewPasswordReset, NewTotpSecret, NewUser, NewWledItem,
+            PasswordReset, RecoveryCode, TotpSecret, UpdateTotpSecret, UpdateUser,
+            UpdateUserSettings, User, WledItem,
+        },
+    },
+    mailer::{Mail, SharedMailer},
+    plugins::assets::{get_picture_path, PictureType},
+    ratelimit::RateLimit,
+    repsonses::ApiError,
+    validation::{
+        validate_email, validate_host, validate_password, validate_username, Validate, Validated,
+        ValidationErrors,
+    },
+};
+
+use super::{
+    auth::{
+        create_mfa_token, generate_reset_token, hash_password, hash_token, read_mfa_token,
+        verify_password, JWTToken,
+    },
+    totp::{
+        build_totp, generate_recovery_codes, generate_secret, hash_recovery_code,
+        normalize_recovery_code, verify_code, verify_recovery_code,
+    },
+};
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct HueBridgeRequest {
+    host: String,
+    user: String,
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct WledItemRequest {
+    name: String,
+    ip: String,
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct UserSettingsRequest {
+    hue_bridges: Vec<HueBridgeRequest>,
+    wled_ips: Vec<WledItemRequest>,
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct SignupRequest {
+    username: String,
+    email: String,
+    password: String,
+    settings: Option<UserSettingsRequest>,
+}
+
+impl Validate for SignupRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        errors.check("username", validate_username(&self.username));
+        errors.check("email", validate_email(&self.email));
+        errors.check("password", validate_password(&self.password));
+
+        if let Some(settings) = &self.settings {
+            for (index, hue_bridge) in settings.hue_bridges.iter().enumerate() {
+                errors.check(
+                    &format!("settings.hue_bridges[{}].host", index),
+                    validate_host(&hue_bridge.host),
+                );
+            }
+
+            for (index, wled_item) in settings.wled_ips.iter().enumerate() {
+                errors.check(
+                    &format!("settings.wled_ips[{}].ip", index),
+                    validate_host(&wled_item.ip),
+                );
+            }
+        }
+
+        errors.into_result()
+    }
+}
+
+#[derive(serde::Serialize, JsonSchema)]
+struct SignupResponse {
+    access_token: String,
+    token_type: String,
+}
+
+#[openapi(tag = "Auth")]
+#[post("/signup", format = "json", data = "<signup_request>")]
+fn signup(
+    _limit: RateLimit<'_>,
+    signup_request: Validated<SignupRequest>,
+    dbpool: &State<DbPool>,
+) -> Result<Json<SignupResponse>, ApiError> {
+    let connection = &mut connection::get_connection(dbpool)?;
+
+    let user_by_username = User::get_user_by_username(connection, &signup_request.username);
+
+    if user_by_username.is_ok() {
+        return Err(ApiError::Conflict("Username already exists".to_string()));
+    }
+
+    let user_by_email = User::get_user_by_mail(connection, &signup_request.email);
+
+    if user_by_email.is_ok() {
+        return Err(ApiError::Conflict("Email already exists".to_string()));
+    }
+
+    let user = User::create_user(
+        connection,
+        &NewUser {
+            username: &signup_request.username,
+            email: &signup_request.email,
+            hashed_password: &hash_password(&signup_request.password),
+        },
+    )?;
+
+    if let Some(user_settings_obj) = &signup_request.settings {
+        let mut user_settings = user.get_usersettings(connection)?;
+
+        if user_settings_obj.hue_bridges.len() > 0 {
+            user_settings = user_settings.update(
+                connection,
+                &UpdateUserSettings {
+                    hue_index: Some(&(user_settings.hue_index + 1)),
+                    user_id: None,
+                },
+            )?;
+
+            for hue_bridge in user_settings_obj.hue_bridges.iter() {
+                HueBridge::create_huebridge(
+                    connection,
+                    &NewHueBridge {
+                        id: &user_settings.hue_index.to_string(),
+                        user: &hue_bridge.user,
+                        ip: &hue_bridge.host,
+                        user_settings_id: &user_settings.id,
+                        bridgeid: None,
+                        certificate: None,
+                        allow_http: false,
+                    },
+                )?;
+            }
+        }
+
+        if user_settings_obj.wled_ips.len() > 0 {
+            for wled_ip in user_settings_obj.wled_ips.iter() {
+                WledItem::create_wleditem(
+                    connection,
+                    &NewWledItem {
+                        name: &wled_ip.name,
+                        ip: &wled_ip.ip,
+                        user_settings_id: &user_settings.id,
+                    },
+                )?;
+            }
+        }
+    }
+
+    Ok(Json(SignupResponse {
+        access_token: user.generate_token(),
+        token_type: "bearer".to_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct LoginRequest {
+    email: String,
+    password: String,
+}
+
+#[openapi(tag = "Auth")]
+#[post("/login", format = "json", data = "<login_request>")]
+fn login(
+    limit: RateLimit<'_>,
+    login_request: Json<LoginRequest>,
+    dbpool: &State<DbPool>,
+) -> Result<Json<SignupResponse>, ApiError> {
+    limit.check_account(&login_request.email)?;
+
+    let connection = &mut connection::get_connection(dbpool)?;
+
+    let user = User::get_user_by_mail(connection, &login_request.email);
+
+    if user.is_err() {
+        limit.login_failed(&login_request.email);
+        return Err(ApiError::Unauthorized("Invalid email or password".to_string()));
+    }
+
+    let user = user.unwrap();
+
+    if !verify_password(&login_request.password, &user.hashed_password) {
+        limit.login_failed(&login_request.email);
+        return Err(ApiError::Unauthorized("Invalid email or password".to_string()));
+    }
+
+    limit.login_succeeded(&login_request.email);
+
+    let totp_secret = TotpSecret::get_totpsecret_by_user_id(connection, user.id)
+        .ok()
+        .filter(|totp_secret| totp_secret.confirmed);
+
+    if let Some(totp_secret) = totp_secret {
+        let nonce = generate_reset_token();
+
+        totp_secret.start_login(connection, &nonce)?;
+
+        return Ok(Json(SignupResponse {
+            access_token: create_mfa_token(user.id, &nonce),
+            token_type: "mfa_pending".to_string(),
+        }));
+    }
+
+    Ok(Json(SignupResponse {
+        access_token: user.generate_token(),
+        token_type: "bearer".to_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct MfaLoginRequest {
+    mfa_token: String,
+    code: String,
+}
+
+/// Second login step for users with TOTP enabled. Accepts either a current
+/// TOTP code or an unused recovery code. The MFA token and TOTP codes can
+/// only be used once.
+#[openapi(tag = "Auth")]
+#[post("/login/mfa", format = "json", data = "<mfa_request>")]
+fn login_mfa(
+    limit: RateLimit<'_>,
+    mfa_request: Json<MfaLoginRequest>,
+    dbpool: &State<DbPool>,
+) -> Result<Json<SignupResponse>, ApiError> {
+    let connection = &mut connection::get_connection(dbpool)?;
+
+    let invalid_token = || ApiError::Unauthorized("Invalid or expired token".to_string());
+
+    let (user_id, nonce) = read_mfa_token(&mfa_request.mfa_token).map_err(|_| invalid_token())?;
+
+    let user = User::get_user(connection, user_id).map_err(|_| invalid_token())?;
+
+    limit.check_account(&user.email)?;
+
+    let totp_secret = TotpSecret::get_totpsecret_by_user_id(connection, user.id)
+        .map_err(|_| invalid_token())?;
+
+    let step = verify_code(
+        &totp_secret.secret,
+        &user.email,
+        &mfa_request.code,
+        totp_secret.last_step,
+    );
+
+    connection.transaction(|connection| {
+        if step.is_none() {
+            let code = normalize_recovery_code(&mfa_request.code);
+
+            let recovery_code = RecoveryCode::use_recoverycode(connection, user.id, |code_hash| {
+                verify_recovery_code(&code, code_hash)
+            });
+
+            if recovery_code.is_err() {
+                limit.login_failed(&user.email);
+                return Err(ApiError::Unauthorized("Invalid code".to_string()));
+            }
+        }
+
+        if !totp_secret.complete_login(connection, &nonce, step)? {
+            return Err(invalid_token());
+        }
+
+        Ok(())
+    })?;
+
+    limit.login_succeeded(&user.email);
+
+    Ok(Json(SignupResponse {
+        access_token: user.generate_token(),
+        token_type: "bearer".to_string(),
+    }))
+}
+
+#[derive(serde::Serialize, JsonSchema)]
+struct MeResponse {
+    username: String,
+    email: String,
+}
+
+#[openapi(tag = "Auth")]
+#[get("/refresh")]
+fn refresh(
+    jwt: JWTToken,
+    db_pool: &State<DbPool>,
+) -> Result<Json<SignupResponse>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = jwt.get_user(connection)?;
+
+    Ok(Json(SignupResponse {
+        access_token: user.generate_token(),
+        token_type: "bearer".to_string(),
+    }))
+}
+
+#[openapi(tag = "Auth")]
+#[get("/me")]
+fn me(jwt: JWTToken, db_pool: &State<DbPool>) -> Result<Json<MeResponse>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = jwt.get_user(connection)?;
+
+    Ok(Json(MeResponse {
+        username: user.username.to_string(),
+        email: user.email.to_string(),
+    }))
+}
+
+/// Loads the user and checks the password confirming a sensitive change.
+/// Wrong passwords count towards the same lockout as failed logins.
+fn authorized_user(
+    connection: &mut DbPooledConnection,
+    limit: &RateLimit<'_>,
+    jwt: &JWTToken,
+    password: &str,
+) -> Result<User, ApiError> {
+    let user = jwt.get_user(connection)?;
+
+    limit.check_account(&user.email)?;
+
+    if !verify_password(password, &user.hashed_password) {
+        limit.login_failed(&user.email);
+        return Err(ApiError::Unauthorized("Invalid password".to_string()));
+    }
+
+    limit.login_succeeded(&user.email);
+
+    Ok(user)
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+impl Validate for ChangePasswordRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        errors.check("new_password", validate_password(&self.new_password));
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "Auth")]
+#[put("/password", format = "json", data = "<password_request>")]
+fn change_password(
+    limit: RateLimit<'_>,
+    jwt: JWTToken,
+    password_request: Validated<ChangePasswordRequest>,
+    db_pool: &State<DbPool>,
+) -> Result<Json<SignupResponse>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = authorized_user(connection, &limit, &jwt, &password_request.current_password)?;
+
+    let user = user.update(
+        connection,
+        &UpdateUser {
+            username: None,
+            email: None,
+            hashed_password: Some(&hash_password(&password_request.new_password)),
+        },
+    )?;
+
+    Ok(Json(SignupResponse {
+        access_token: user.generate_token(),
+        token_type: "bearer".to_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct UpdateMeRequest {
+    current_password: String,
+    username: Option<String>,
+    email: Option<String>,
+}
+
+impl Validate for UpdateMeRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        if let Some(username) = &self.username {
+            errors.check("username", validate_username(username));
+        }
+
+        if let Some(email) = &self.email {
+            errors.check("email", validate_email(email));
+        }
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "Auth")]
+#[patch("/me", format = "json", data = "<update_request>")]
+fn update_me(
+    limit: RateLimit<'_>,
+    jwt: JWTToken,
+    update_request: Validated<UpdateMeRequest>,
+    db_pool: &State<DbPool>,
+) -> Result<Json<MeResponse>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = authorized_user(connection, &limit, &jwt, &update_request.current_password)?;
+
+    if let Some(username) = &update_request.username {
+        let user_by_username = User::get_user_by_username(connection, username);
+
+        if user_by_username.is_ok() && user_by_username.unwrap().id != user.id {
+            return Err(ApiError::Conflict("Username already exists".to_string()));
+        }
+    }
+
+    if let Some(email) = &update_request.email {
+        let user_by_email = User::get_user_by_mail(connection, email);
+
+        if user_by_email.is_ok() && user_by_email.unwrap().id != user.id {
+            return Err(ApiError::Conflict("Email already exists".to_string()));
+        }
+    }
+
+    let user = user.update(
+        connection,
+        &UpdateUser {
+            username: update_request.username.as_deref(),
+            email: update_request.email.as_deref(),
+            hashed_password: None,
+        },
+    )?;
+
+    Ok(Json(MeResponse {
+        username: user.username.to_string(),
+        email: user.email.to_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct DeleteMeRequest {
+    password: String,
+}
+
+#[openapi(tag = "Auth")]
+#[delete("/me", format = "json", data = "<delete_request>")]
+fn delete_me(
+    limit: RateLimit<'_>,
+    jwt: JWTToken,
+    delete_request: Json<DeleteMeRequest>,
+    db_pool: &State<DbPool>,
+) -> Result<Json<Value>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = authorized_user(connection, &limit, &jwt, &delete_request.password)?;
+
+    user.delete(connection)?;
+
+    let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
+
+    if Path::new(&picture).exists() {
+        let _ = remove_file(picture);
+    }
+
+    Ok(Json(json!({})))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct ForgotPasswordRequest {
+    email: String,
+}
+
+/// Creates a reset token for `email` and builds the mail carrying it, or
+/// returns `None` when the email is not registered.
+fn create_reset_mail(connection: &mut DbConnection, email: &str) -> Result<Option<Mail>, ApiError> {
+    let user = match User::get_user_by_mail(connection, email) {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    PasswordReset::invalidate_by_user_id(connection, user.id)?;
+
+    let token = generate_reset_token();
+
+    PasswordReset::create_passwordreset(
+        connection,
+        &NewPasswordReset {
+            token_hash: &hash_token(&token),
+            expires_at: &(unix_now() + app_config().reset_token_lifetime as i64),
+            user_id: &user.id,
+        },
+    )?;
+
+    let body = match &app_config().mail.password_reset_url {
+        Some(url) => format!(
+            "Use the following link to reset your password: {}{}",
+            url, token
+        ),
+        None => format!(
+            "Use the following token to reset your password: {}",
+            token
+        ),
+    };
+
+    Ok(Some(Mail {
+        to: user.email,
+        subject: "Password reset".to_string(),
+        body,
+    }))
+}
+
+/// Always answers with an empty object so the response does not reveal
+/// whether the email is registered. The lookup, token creation and mail are
+/// handled in the background so known and unknown emails take the same time.
+#[openapi(tag = "Auth")]
+#[post("/forgot", format = "json", data = "<forgot_request>")]
+fn forgot(
+    _limit: RateLimit<'_>,
+    forgot_request: Json<ForgotPasswordRequest>,
+    db_pool: &State<DbPool>,
+    mailer: &State<SharedMailer>,
+) -> Json<Value> {
+    let db_pool = db_pool.inner().clone();
+    let mailer = mailer.inner().clone();
+    let email = forgot_request.into_inner().email;
+
+    rocket::tokio::spawn(async move {
+        let mail = connection::run(&db_pool, move |connection| {
+            create_reset_mail(connection, &email)
+        })
+        .await;
+
+        let mail = match mail {
+            Ok(Some(mail)) => mail,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Error creating password reset: {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = mailer.send(mail).await {
+            eprintln!("Error sending password reset mail: {}", e);
+        }
+    });
+
+    Json(json!({}))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+impl Validate for ResetPasswordRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        errors.check("password", validate_password(&self.password));
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "Auth")]
+#[post("/reset", format = "json", data = "<reset_request>")]
+fn reset(
+    _limit: RateLimit<'_>,
+    reset_request: Validated<ResetPasswordRequest>,
+    db_pool: &State<DbPool>,
+) -> Result<Json<Value>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let invalid_token = || ApiError::BadRequest("Invalid or expired token".to_string());
+
+    let hashed_password = hash_password(&reset_request.password);
+
+    connection.transaction(|connection| {
+        let user_id =
+            PasswordReset::consume(connection, &hash_token(&reset_request.token), unix_now())?
+                .ok_or_else(invalid_token)?;
+
+        let user = User::get_user(connection, user_id).map_err(|_| invalid_token())?;
+
+        user.update(
+            connection,
+            &UpdateUser {
+                username: None,
+                email: None,
+                hashed_password: Some(&hashed_password),
+            },
+        )?;
+
+        Ok::<(), ApiError>(())
+    })?;
+
+    Ok(Json(json!({})))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct TotpEnrollRequest {
+    password: String,
+}
+
+#[derive(serde::Serialize, JsonSchema)]
+struct TotpEnrollResponse {
+    secret: String,
+    otpauth_uri: String,
+}
+
+/// Starts TOTP enrollment. The secret stays inactive until it is confirmed
+/// with a valid code via `/totp/confirm`.
+#[openapi(tag = "Auth")]
+#[post("/totp/enroll", format = "json", data = "<enroll_request>")]
+fn totp_enroll(
+    limit: RateLimit<'_>,
+    jwt: JWTToken,
+    enroll_request: Json<TotpEnrollRequest>,
+    db_pool: &State<DbPool>,
+) -> Result<Json<TotpEnrollResponse>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = authorized_user(connection, &limit, &jwt, &enroll_request.password)?;
+
+    let totp_secret = TotpSecret::get_totpsecret_by_user_id(connection, user.id);
+
+    if totp_secret.is_ok() && totp_secret.unwrap().confirmed {
+        return Err(ApiError::Conflict("TOTP already enabled".to_string()));
+    }
+
+    let secret = generate_secret();
+
+    let totp = build_totp(&secret, &user.email).map_err(ApiError::Internal)?;
+
+    TotpSecret::create_totpsecret(
+        connection,
+        &NewTotpSecret {
+            secret: &secret,
+            user_id: &user.id,
+        },
+    )?;
+
+    Ok(Json(TotpEnrollResponse {
+        secret,
+        otpauth_uri: totp.get_url(),
+    }))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct TotpConfirmRequest {
+    code: String,
+}
+
+#[derive(serde::Serialize, JsonSchema)]
+struct TotpConfirmResponse {
+    recovery_codes: Vec<String>,
+}
+
+/// Activates TOTP and returns the recovery codes. They are only stored
+/// hashed, so this is the only time they can be shown.
+#[openapi(tag = "Auth")]
+#[post("/totp/confirm", format = "json", data = "<confirm_request>")]
+fn totp_confirm(
+    jwt: JWTToken,
+    confirm_request: Json<TotpConfirmRequest>,
+    db_pool: &State<DbPool>,
+) -> Result<Json<TotpConfirmResponse>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = jwt.get_user(connection)?;
+
+    let totp_secret = TotpSecret::get_totpsecret_by_user_id(connection, user.id)
+        .map_err(|_| ApiError::NotFound("TOTP enrollment not started".to_string()))?;
+
+    if totp_secret.confirmed {
+        return Err(ApiError::Conflict("TOTP already enabled".to_string()));
+    }
+
+    let step = verify_code(
+        &totp_secret.secret,
+        &user.email,
+        &confirm_request.code,
+        totp_secret.last_step,
+    );
+
+    if !step.map_or(Ok(false), |step| totp_secret.accept_step(connection, step))? {
+        return Err(ApiError::Unauthorized("Invalid code".to_string()));
+    }
+
+    let recovery_codes = generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+
+    RecoveryCode::replace_recoverycodes(connection, user.id, &code_hashes)?;
+
+    totp_secret.update(
+        connection,
+        &UpdateTotpSecret {
+            confirmed: Some(true),
+        },
+    )?;
+
+    Ok(Json(TotpConfirmResponse { recovery_codes }))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct TotpDisableRequest {
+    password: String,
+}
+
+#[openapi(tag = "Auth")]
+#[delete("/totp", format = "json", data = "<disable_request>")]
+fn totp_disable(
+    limit: RateLimit<'_>,
+    jwt: JWTToken,
+    disable_request: Json<TotpDisableRequest>,
+    db_pool: &State<DbPool>,
+) -> Result<Json<Value>, ApiError> {
+    let connection = &mut connection::get_connection(db_pool)?;
+
+    let user = authorized_user(connection, &limit, &jwt, &disable_request.password)?;
+
+    let totp_secret = TotpSecret::get_totpsecret_by_user_id(connection, user.id)
+        .map_err(|_| ApiError::NotFound("TOTP not enabled".to_string()))?;
+
+    connection.transaction(|connection| {
+        totp_secret.delete(connection)?;
+        RecoveryCode::delete_by_user_id(connection, user.id)
+    })?;
+
+    Ok(Json(json!({})))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        settings: signup,
+        login,
+        refresh,
+        me,
+        change_password,
+        update_me,
+        delete_me,
+        forgot,
+        reset,
+        login_mfa,
+        totp_enroll,
+        totp_confirm,
+        totp_disable
+    ]
+}
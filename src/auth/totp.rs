@@ -0,0 +1,99 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+static TOTP_ISSUER: &str = "HomeApi";
+static RECOVERY_CODE_COUNT: usize = 10;
+
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+/// RFC 6238 defaults (SHA1, 6 digits, 30 second step) with one step of skew,
+/// which is what common authenticator apps expect.
+pub fn build_totp(secret: &str, account_name: &str) -> Result<TOTP, String> {
+    let secret = Secret::Encoded(secret.to_owned()).to_bytes();
+
+    if secret.is_err() {
+        return Err("Invalid TOTP secret".to_owned());
+    }
+
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret.unwrap(),
+        Some(TOTP_ISSUER.to_owned()),
+        account_name.replace(':', ""),
+    );
+
+    match totp {
+        Ok(totp) => Ok(totp),
+        Err(e) => Err(format!("Invalid TOTP configuration: {}", e)),
+    }
+}
+
+/// Returns the time step `code` is valid for. Steps up to and including
+/// `last_step` are rejected so a code can only be used once (RFC 6238 5.2).
+pub fn verify_code(
+    secret: &str,
+    account_name: &str,
+    code: &str,
+    last_step: Option<i64>,
+) -> Option<i64> {
+    let totp = build_totp(secret, account_name).ok()?;
+    let code = code.trim();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let current = now / totp.step;
+    let skew = totp.skew as u64;
+
+    (current.saturating_sub(skew)..=current + skew)
+        .filter(|step| last_step.is_none_or(|last_step| *step as i64 > last_step))
+        .find(|step| constant_time_eq(&totp.generate(step * totp.step), code))
+        .map(|step| step as i64)
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: u64 = rng.gen_range(0..10_000_000_000);
+            let code = format!("{:010}", code);
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are hashed with bcrypt like passwords.
+pub fn hash_recovery_code(code: &str) -> String {
+    bcrypt::hash(code, bcrypt::DEFAULT_COST).unwrap()
+}
+
+pub fn verify_recovery_code(code: &str, code_hash: &str) -> bool {
+    bcrypt::verify(code, code_hash).unwrap_or(false)
+}
+
+pub fn normalize_recovery_code(code: &str) -> String {
+    let code: String = code.chars().filter(|c| c.is_ascii_digit()).collect();
+
+    if code.len() != 10 {
+        return code;
+    }
+
+    format!("{}-{}", &code[..5], &code[5..])
+}
@@ -0,0 +1,365 @@
+use std::{collections::HashMap, fs::remove_file, io::stdin, path::Path};
+
+use schemars::_serde_json::{self, json};
+
+use crate::{
+    auth::auth::{generate_reset_token, hash_password},
+    config::{split_args, AppConfig},
+    db::{
+        connection::{self, DbPool, DbPooledConnection},
+        models::{HueBridge, NewUser, PasswordReset, UpdateUser, User, UserSettings},
+    },
+    plugins::{
+        assets::{get_picture_path, PictureType},
+        hue::remove_bridge,
+    },
+    validation::{validate_email, validate_password, validate_username},
+};
+
+pub static USAGE: &str = "Usage: home_api_rust [command] [--option value ...]
+
+Commands:
+  serve                                   Apply migrations and start the server (default)
+  migrate [--dry-run]                     Apply or list pending migrations
+  user list
+  user create <username> <email> [--password-stdin]
+  user delete <user>
+  user reset-password <user> [--password-stdin]
+  bridge list [--user <user>]
+  bridge remove <user> <bridge id> [--purge]
+  token issue <user>                      Print an access token for the user
+  config check                            Validate and print the configuration
+
+<user> is id:<user id>, an email or a username. --password-stdin reads the
+password from the first line of stdin, without it a random password is
+generated and printed. bridge remove keeps the aliases and metadata of the
+devices, --purge deletes them too. Any configuration key can be overridden
+with --<key> <value>, e.g. --database-url other.sqlite.";
+
+#[derive(Debug)]
+pub enum Command {
+    Serve,
+    Migrate { dry_run: bool },
+    UserList,
+    UserCreate {
+        username: String,
+        email: String,
+        password_stdin: bool,
+    },
+    UserDelete { user: String },
+    UserResetPassword {
+        user: String,
+        password_stdin: bool,
+    },
+    BridgeList { user: Option<String> },
+    BridgeRemove {
+        user: String,
+        bridge_id: String,
+        purge: bool,
+    },
+    TokenIssue { user: String },
+    ConfigCheck,
+    Help,
+}
+
+impl Command {
+    /// `--migrate-only`, `--migrate-dry-run` and the `MIGRATE` variable are the
+    /// old ways to run migrations and still map to `migrate`.
+    pub fn parse(args: &[String]) -> Result<Command, String> {
+        let (positional, options) = split_args(args);
+
+        let option = |name: &str| {
+            options
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        // Arguments are visible to other users of the machine
+        if option("password").is_some() {
+            return Err("--password is not supported, use --password-stdin".into());
+        }
+
+        let positional: Vec<&str> = positional.iter().map(|arg| arg.as_str()).collect();
+
+        let command = match positional.as_slice() {
+            [] | ["serve"] => {
+                if option("migrate-dry-run").is_some() {
+                    Command::Migrate { dry_run: true }
+                } else if option("migrate-only").is_some() || std::env::var("MIGRATE").is_ok() {
+                    Command::Migrate { dry_run: false }
+                } else {
+                    Command::Serve
+                }
+            }
+            ["migrate"] => Command::Migrate {
+                dry_run: option("dry-run").is_some(),
+            },
+            ["user", "list"] => Command::UserList,
+            ["user", "create", username, email] => Command::UserCreate {
+                username: username.to_string(),
+                email: email.to_string(),
+                password_stdin: option("password-stdin").is_some(),
+            },
+            ["user", "delete", user] => Command::UserDelete {
+                user: user.to_string(),
+            },
+            ["user", "reset-password", user] => Command::UserResetPassword {
+                user: user.to_string(),
+                password_stdin: option("password-stdin").is_some(),
+            },
+            ["bridge", "list"] => Command::BridgeList {
+                user: option("user"),
+            },
+            ["bridge", "remove", user, bridge_id] => Command::BridgeRemove {
+                user: user.to_string(),
+                bridge_id: bridge_id.to_string(),
+                purge: option("purge").is_some(),
+            },
+            ["token", "issue", user] => Command::TokenIssue {
+                user: user.to_string(),
+            },
+            ["config", "check"] => Command::ConfigCheck,
+            ["help"] => Command::Help,
+            _ => return Err(format!("Unknown command: {}", positional.join(" "))),
+        };
+
+        Ok(command)
+    }
+}
+
+/// Applies pending migrations, or only lists them for a dry run.
+pub fn migrate(pool: &DbPool, dry_run: bool) -> Result<(), String> {
+    if dry_run {
+        let pending = connection::pending_migrations(pool)?;
+
+        if pending.is_empty() {
+            println!("No pending migrations");
+        }
+
+        for name in pending {
+            println!("Pending migration {}", name);
+        }
+
+        return Ok(());
+    }
+
+    for version in connection::run_migrations(pool)? {
+        println!("Applied migration {}", version);
+    }
+
+    Ok(())
+}
+
+/// Prints the effective configuration with secrets masked.
+pub fn check_config(config: &AppConfig) -> Result<(), String> {
+    let mut value = _serde_json::to_value(config).map_err(|e| e.to_string())?;
+
+    value["jwt_secret"] = json!("********");
+
+    if config.mail.smtp_password.is_some() {
+        value["mail"]["smtp_password"] = json!("********");
+    }
+
+    println!(
+        "{}",
+        _serde_json::to_string_pretty(&value).map_err(|e| e.to_string())?
+    );
+    println!("Configuration is valid");
+
+    Ok(())
+}
+
+/// Accepts `id:<user id>`, an email or a username.
+fn find_user(connection: &mut DbPooledConnection, user: &str) -> Result<User, String> {
+    let result = if let Some(user_id) = user.strip_prefix("id:") {
+        let user_id = user_id
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid user id {}", user_id))?;
+
+        User::get_user(connection, user_id)
+    } else if user.contains('@') {
+        User::get_user_by_mail(connection, user)
+    } else {
+        User::get_user_by_username(connection, user)
+    };
+
+    result.map_err(|_| format!("User {} not found", user))
+}
+
+/// Reads the password from stdin or generates one, which is printed so it
+/// can be handed to the user.
+fn password_or_generate(password_stdin: bool) -> Result<String, String> {
+    if password_stdin {
+        let mut password = String::new();
+
+        stdin()
+            .read_line(&mut password)
+            .map_err(|e| format!("Could not read password: {}", e))?;
+
+        let password = password.trim_end_matches(['\r', '\n']).to_string();
+
+        validate_password(&password).map_err(|e| format!("Password: {}", e))?;
+        return Ok(password);
+    }
+
+    loop {
+        let password = generate_reset_token()[..20].to_string();
+
+        if validate_password(&password).is_ok() {
+            println!("Generated password: {}", password);
+            return Ok(password);
+        }
+    }
+}
+
+/// Runs the database commands. Refuses to work on a database with pending
+/// migrations, those are only applied by `serve` and `migrate`.
+pub async fn run(command: Command, pool: &DbPool) -> Result<(), String> {
+    if !connection::pending_migrations(pool)?.is_empty() {
+        return Err("Database has pending migrations, run `home_api_rust migrate` first".into());
+    }
+
+    let connection = &mut connection::get_connection(pool).map_err(|e| e.to_string())?;
+
+    match command {
+        Command::UserList => {
+            let users = User::get_users(connection).map_err(|e| e.to_string())?;
+
+            println!("id\tusername\temail");
+
+            for user in users {
+                println!("{}\t{}\t{}", user.id, user.username, user.email);
+            }
+        }
+        Command::UserCreate {
+            username,
+            email,
+            password_stdin,
+        } => {
+            validate_username(&username).map_err(|e| format!("Username: {}", e))?;
+            validate_email(&email).map_err(|e| format!("Email: {}", e))?;
+
+            if User::get_user_by_username(connection, &username).is_ok() {
+                return Err("Username already exists".into());
+            }
+
+            if User::get_user_by_mail(connection, &email).is_ok() {
+                return Err("Email already exists".into());
+            }
+
+            let password = password_or_generate(password_stdin)?;
+
+            let user = User::create_user(
+                connection,
+                &NewUser {
+                    username: &username,
+                    email: &email,
+                    hashed_password: &hash_password(&password),
+                },
+            )
+            .map_err(|e| e.to_string())?;
+
+            println!("Created user {} ({})", user.username, user.id);
+        }
+        Command::UserDelete { user } => {
+            let user = find_user(connection, &user)?;
+
+            user.delete(connection).map_err(|e| e.to_string())?;
+
+            let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
+
+            if Path::new(&picture).exists() {
+                let _ = remove_file(picture);
+            }
+
+            println!("Deleted user {} ({})", user.username, user.id);
+        }
+        Command::UserResetPassword {
+            user,
+            password_stdin,
+        } => {
+            let user = find_user(connection, &user)?;
+
+            let password = password_or_generate(password_stdin)?;
+
+            user.update(
+                connection,
+                &UpdateUser {
+                    username: None,
+                    email: None,
+                    hashed_password: Some(&hash_password(&password)),
+                },
+            )
+            .map_err(|e| e.to_string())?;
+
+            let _ = PasswordReset::invalidate_by_user_id(connection, user.id);
+
+            println!("Password of {} ({}) reset", user.username, user.id);
+        }
+        Command::BridgeList { user } => {
+            let hue_bridges = match user {
+                Some(user) => {
+                    let user = find_user(connection, &user)?;
+                    user.get_huebridges(connection)
+                }
+                None => HueBridge::get_huebridges(connection),
+            }
+            .map_err(|e| e.to_string())?;
+
+            let owners: HashMap<i32, i32> = UserSettings::get_usersettings(connection)
+                .map_err(|e| e.to_string())?
+                .iter()
+                .map(|usersettings| (usersettings.id, usersettings.user_id))
+                .collect();
+
+            println!("user\tid\tip\tbridgeid\tpaired");
+
+            for hue_bridge in hue_bridges {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    owners
+                        .get(&hue_bridge.user_settings_id)
+                        .map(|user_id| user_id.to_string())
+                        .unwrap_or("-".to_string()),
+                    hue_bridge.id,
+                    hue_bridge.ip,
+                    hue_bridge.bridgeid.as_deref().unwrap_or("-"),
+                    !hue_bridge.user.is_empty()
+                );
+            }
+        }
+        Command::BridgeRemove {
+            user,
+            bridge_id,
+            purge,
+        } => {
+            let user = find_user(connection, &user)?;
+
+            let hue_bridge = user
+                .get_huebridge(connection, &bridge_id)
+                .map_err(|_| format!("Bridge {} not found", bridge_id))?;
+
+            remove_bridge(connection, user.id, &hue_bridge, purge).map_err(|e| e.to_string())?;
+
+            #[cfg(feature = "mqtt")]
+            crate::plugins::mqtt::remove_bridge_configs(&crate::InternalMessage::bridge_deleted(
+                &hue_bridge,
+                user.id,
+            ))
+            .await?;
+
+            println!("Removed bridge {} of {}", hue_bridge.id, user.username);
+        }
+        Command::TokenIssue { user } => {
+            let user = find_user(connection, &user)?;
+
+            println!("{}", user.generate_token());
+        }
+        Command::Serve | Command::Migrate { .. } | Command::ConfigCheck | Command::Help => {
+            return Err("Not a database command".into());
+        }
+    }
+
+    Ok(())
+}
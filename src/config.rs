@@ -0,0 +1,394 @@
+use std::{env, net::IpAddr, path::PathBuf, sync::OnceLock};
+
+use rocket::figment::{
+    providers::{Env, Format, Serialized, Toml},
+    value::Value,
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+static DEFAULT_CONFIG_FILE: &str = "HomeApi.toml";
+#[cfg(feature = "sqlite")]
+static DEFAULT_DATABASE_URL: &str = "db.sqlite";
+#[cfg(feature = "postgres")]
+static DEFAULT_DATABASE_URL: &str = "postgres://localhost/home_api";
+static ENV_PREFIX: &str = "HOME_API_";
+static MIN_SECRET_LENGTH: usize = 32;
+
+// Secrets that shipped with the repository at some point and must never be
+// used in a deployment.
+static KNOWN_SECRETS: [&str; 4] = [
+    "secret",
+    "changeme",
+    "change-me-to-a-long-random-string",
+    "ENDM3ymkXOrZHhK1Z2q8bLOaxZr3LTGm540bd6oXGheaX8KmltC+cSwnJ0b9zK7PFiaXPp+zFBF+BPnZF/htXw==",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketConfig {
+    pub burst: f64,
+    pub per_minute: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub auth: BucketConfig,
+    pub account: BucketConfig,
+    pub devices: BucketConfig,
+    pub lockout_threshold: u32,
+    /// Seconds
+    pub lockout_base: u64,
+    /// Seconds
+    pub lockout_max: u64,
+    /// Reverse proxies whose `X-Real-IP` header is taken as the client
+    /// address. Any other peer is limited by its own address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// SMTP is used when set, otherwise mails go to `directory` or stdout.
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_tls: bool,
+    pub from: String,
+    pub directory: Option<PathBuf>,
+    /// Prefix the reset token is appended to, e.g. `https://home/reset?token=`.
+    pub password_reset_url: Option<String>,
+}
+
+/// Only used when built with the `mqtt` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttConfig {
+    /// The bridge is started when set.
+    pub host: Option<String>,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub client_id: String,
+    /// First topic level, `<prefix>/<user id>/<device id>/state`.
+    pub topic_prefix: String,
+    /// Home Assistant discovery configs are published below this prefix,
+    /// empty disables them.
+    pub discovery_prefix: String,
+    /// Seconds between publishing the state and discovery config of all
+    /// devices.
+    pub discovery_interval: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppConfig {
+    pub address: String,
+    pub port: u16,
+    pub database_url: String,
+    /// Maximum number of open database connections.
+    pub database_pool_size: u32,
+    /// Seconds a request waits for a free connection before failing with 503.
+    pub database_pool_timeout: u64,
+    pub jwt_secret: String,
+    /// Seconds
+    pub access_token_lifetime: u64,
+    /// Seconds
+    pub mfa_token_lifetime: u64,
+    /// Seconds
+    pub reset_token_lifetime: u64,
+    pub cors_origins: Vec<String>,
+    /// Seconds between background polls of bridges and devices.
+    pub poll_interval: u64,
+    /// Milliseconds bridge responses are reused. Concurrent identical requests
+    /// share one upstream call even with `0`.
+    pub bridge_cache_ttl: u64,
+    /// Milliseconds
+    pub bridge_connect_timeout: u64,
+    /// Milliseconds a whole bridge request may take.
+    pub bridge_timeout: u64,
+    /// Extra attempts for reads that failed to connect, timed out or got a
+    /// `5xx`.
+    pub bridge_retries: u32,
+    /// Consecutive failures after which a bridge is not contacted for
+    /// `bridge_circuit_open` seconds.
+    pub bridge_failure_threshold: u32,
+    /// Seconds
+    pub bridge_circuit_open: u64,
+    pub static_dir: PathBuf,
+    pub pictures_dir: PathBuf,
+    pub rate_limit: RateLimitConfig,
+    pub mail: MailConfig,
+    pub mqtt: MqttConfig,
+}
+
+impl Default for AppConfig {
+    fn default() -> AppConfig {
+        AppConfig {
+            address: "0.0.0.0".to_string(),
+            port: 8000,
+            database_url: DEFAULT_DATABASE_URL.to_string(),
+            database_pool_size: 10,
+            database_pool_timeout: 5,
+            jwt_secret: String::new(),
+            access_token_lifetime: 30 * 24 * 60 * 60,
+            mfa_token_lifetime: 5 * 60,
+            reset_token_lifetime: 60 * 60,
+            cors_origins: vec!["*".to_string()],
+            poll_interval: 30,
+            bridge_cache_ttl: 2000,
+            bridge_connect_timeout: 2000,
+            bridge_timeout: 5000,
+            bridge_retries: 2,
+            bridge_failure_threshold: 3,
+            bridge_circuit_open: 30,
+            static_dir: PathBuf::from("dist"),
+            pictures_dir: PathBuf::from("static/pictures"),
+            rate_limit: RateLimitConfig {
+                auth: BucketConfig {
+                    burst: 10.0,
+                    per_minute: 10.0,
+                },
+                account: BucketConfig {
+                    burst: 5.0,
+                    per_minute: 5.0,
+                },
+                devices: BucketConfig {
+                    burst: 30.0,
+                    per_minute: 120.0,
+                },
+                lockout_threshold: 5,
+                lockout_base: 30,
+                lockout_max: 60 * 60,
+                trusted_proxies: Vec::new(),
+            },
+            mail: MailConfig {
+                smtp_host: None,
+                smtp_port: 25,
+                smtp_username: None,
+                smtp_password: None,
+                smtp_tls: false,
+                from: "home-api@localhost".to_string(),
+                directory: None,
+                password_reset_url: None,
+            },
+            mqtt: MqttConfig {
+                host: None,
+                port: 1883,
+                username: None,
+                password: None,
+                client_id: "home-api".to_string(),
+                topic_prefix: "homeapi".to_string(),
+                discovery_prefix: "homeassistant".to_string(),
+                discovery_interval: 5 * 60,
+            },
+        }
+    }
+}
+
+/// Options that never take a value, so a following positional argument is not
+/// mistaken for their value.
+static FLAGS: [&str; 5] = [
+    "dry-run",
+    "migrate-only",
+    "migrate-dry-run",
+    "password-stdin",
+    "purge",
+];
+
+/// Splits arguments into positional arguments and `--key value`/`--key=value`
+/// options. A key without a value is a flag and set to `true`.
+pub fn split_args(args: &[String]) -> (Vec<String>, Vec<(String, String)>) {
+    let mut positional = Vec::new();
+    let mut options = Vec::new();
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let arg = match arg.strip_prefix("--") {
+            Some(arg) => arg,
+            None => {
+                positional.push(arg.to_string());
+                continue;
+            }
+        };
+
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key.to_string(), Some(value.to_string())),
+            None => (arg.to_string(), None),
+        };
+
+        let value = match value {
+            Some(value) => value,
+            None if FLAGS.contains(&key.as_str()) => "true".to_string(),
+            None => match args.next_if(|value| !value.starts_with("--")) {
+                Some(value) => value.to_string(),
+                None => "true".to_string(),
+            },
+        };
+
+        options.push((key, value));
+    }
+
+    (positional, options)
+}
+
+/// Turns options into figment key paths. Dashes become underscores, dots
+/// address nested sections (`--rate-limit.auth.burst 5`). `--config` selects
+/// the config file.
+fn parse_args(args: &[String]) -> (Option<String>, Vec<(String, String)>) {
+    let mut config_file = None;
+    let mut overrides = Vec::new();
+
+    for (key, value) in split_args(args).1 {
+        if key == "config" {
+            config_file = Some(value);
+        } else {
+            overrides.push((key.replace('-', "_"), value));
+        }
+    }
+
+    (config_file, overrides)
+}
+
+impl AppConfig {
+    /// Merges, from lowest to highest priority: defaults, the TOML file
+    /// (`HomeApi.toml` or `--config`/`HOME_API_CONFIG`), the legacy
+    /// `DATABASE_URL`/`SECRET_KEY` variables, `HOME_API_*` variables and
+    /// `--key value` arguments.
+    pub fn figment(args: &[String]) -> Figment {
+        let (config_file, overrides) = parse_args(args);
+
+        let config_file = config_file
+            .or(env::var(format!("{}CONFIG", ENV_PREFIX)).ok())
+            .unwrap_or(DEFAULT_CONFIG_FILE.to_string());
+
+        let mut figment = Figment::from(Serialized::defaults(AppConfig::default()))
+            .merge(Toml::file(config_file))
+            .merge(
+                Env::raw()
+                    .only(&["DATABASE_URL", "SECRET_KEY"])
+                    .map(|key| match key.as_str() {
+                        "SECRET_KEY" => "jwt_secret".into(),
+                        _ => key.as_str().to_lowercase().into(),
+                    }),
+            )
+            .merge(Env::prefixed(ENV_PREFIX).split("__"));
+
+        for (key, value) in overrides {
+            let value = value
+                .parse::<Value>()
+                .unwrap_or(Value::from(value.clone()));
+            figment = figment.merge(Serialized::default(&key, value));
+        }
+
+        figment
+    }
+
+    pub fn load(args: &[String]) -> Result<AppConfig, String> {
+        let config: AppConfig = match AppConfig::figment(args).extract() {
+            Ok(config) => config,
+            Err(e) => return Err(format!("Invalid configuration: {}", e)),
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if self.jwt_secret.is_empty() {
+            errors.push("jwt_secret must be set".to_string());
+        } else if KNOWN_SECRETS.contains(&self.jwt_secret.as_str()) {
+            errors.push("jwt_secret must not be a default value".to_string());
+        } else if self.jwt_secret.len() < MIN_SECRET_LENGTH {
+            errors.push(format!(
+                "jwt_secret must be at least {} characters long",
+                MIN_SECRET_LENGTH
+            ));
+        }
+
+        if self.database_url.is_empty() {
+            errors.push("database_url must be set".to_string());
+        }
+
+        if self.database_pool_size == 0 || self.database_pool_timeout == 0 {
+            errors.push(
+                "database_pool_size and database_pool_timeout must be greater than 0".to_string(),
+            );
+        }
+
+        if self.access_token_lifetime == 0
+            || self.mfa_token_lifetime == 0
+            || self.reset_token_lifetime == 0
+        {
+            errors.push("token lifetimes must be greater than 0".to_string());
+        }
+
+        if self.poll_interval == 0 {
+            errors.push("poll_interval must be greater than 0".to_string());
+        }
+
+        if self.bridge_connect_timeout == 0
+            || self.bridge_timeout == 0
+            || self.bridge_failure_threshold == 0
+            || self.bridge_circuit_open == 0
+        {
+            errors.push(
+                "bridge timeouts, bridge_failure_threshold and bridge_circuit_open must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        for (name, bucket) in [
+            ("auth", &self.rate_limit.auth),
+            ("account", &self.rate_limit.account),
+            ("devices", &self.rate_limit.devices),
+        ] {
+            if bucket.burst < 1.0 || bucket.per_minute <= 0.0 {
+                errors.push(format!(
+                    "rate_limit.{} needs a burst of at least 1 and a positive per_minute",
+                    name
+                ));
+            }
+        }
+
+        if self.mqtt.topic_prefix.is_empty() || self.mqtt.topic_prefix.contains(['+', '#']) {
+            errors.push("mqtt.topic_prefix must be set and must not contain wildcards".to_string());
+        }
+
+        if self.mqtt.discovery_prefix.contains(['+', '#']) || self.mqtt.discovery_interval == 0 {
+            errors.push(
+                "mqtt.discovery_prefix must not contain wildcards and mqtt.discovery_interval must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Rocket needs its own 256 bit key for private cookies; it is derived
+    /// from the JWT secret so there is only one secret to manage.
+    pub fn rocket_secret_key(&self) -> String {
+        Sha256::digest(format!("rocket:{}", self.jwt_secret).as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Stores the configuration for code paths that have no access to Rocket's
+/// managed state. Must be called once before the server starts.
+pub fn init(config: AppConfig) -> &'static AppConfig {
+    CONFIG.get_or_init(|| config)
+}
+
+pub fn app_config() -> &'static AppConfig {
+    CONFIG.get().expect("configuration not initialized")
+}
@@ -1,34 +1,68 @@
-use rocket::{
-    fairing::{Fairing, Info, Kind},
-    Request, Response, options,
-};
-
-pub struct CORS;
-
-#[options("/<_..>")]
-pub fn all_options() {}
-
-#[rocket::async_trait]
-impl Fairing for CORS {
-    fn info(&self) -> Info {
-        Info {
-            name: "CORS",
-            kind: Kind::Response,
-        }
-    }
-
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(rocket::http::Header::new(
-            "Access-Control-Allow-Origin",
-            "*",
-        ));
-        response.set_header(rocket::http::Header::new(
-            "Access-Control-Allow-Methods",
-            "POST, GET, PATCH, PUT, DELETE, OPTIONS",
-        ));
-        response.set_header(rocket::http::Header::new(
-            "Access-Control-Allow-Headers",
-            "Content-Type, Authorization",
-        ));
-    }
-}
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Request, Response, options,
+};
+
+pub struct CORS {
+    origins: Vec<String>,
+}
+
+impl CORS {
+    pub fn new(origins: Vec<String>) -> CORS {
+        CORS { origins }
+    }
+
+    fn allowed_origin(&self, request: &Request<'_>) -> Option<String> {
+        if self.origins.iter().any(|origin| origin == "*") {
+            return Some("*".to_string());
+        }
+
+        let origin = request.headers().get_one("Origin")?;
+
+        self.origins
+            .iter()
+            .find(|allowed| allowed.trim_end_matches('/') == origin)
+            .map(|allowed| allowed.trim_end_matches('/').to_string())
+    }
+}
+
+#[options("/<_..>")]
+pub fn all_options() {}
+
+#[rocket::async_trait]
+impl Fairing for CORS {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let origin = match self.allowed_origin(request) {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        if origin != "*" {
+            response.set_header(rocket::http::Header::new("Vary", "Origin"));
+        }
+
+        response.set_header(rocket::http::Header::new(
+            "Access-Control-Allow-Origin",
+            origin,
+        ));
+        response.set_header(rocket::http::Header::new(
+            "Access-Control-Allow-Methods",
+            "POST, GET, PATCH, PUT, DELETE, OPTIONS",
+        ));
+        response.set_header(rocket::http::Header::new(
+            "Access-Control-Allow-Headers",
+            "Content-Type, Authorization, If-None-Match",
+        ));
+        response.set_header(rocket::http::Header::new(
+            "Access-Control-Expose-Headers",
+            "ETag",
+        ));
+    }
+}
@@ -1,30 +1,227 @@
-use std::env;
-
-use diesel::r2d2::{ConnectionManager, Pool, PoolError};
-use diesel::sqlite::SqliteConnection;
-use r2d2::PooledConnection;
-
-pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
-pub type SqlitePooledConnection = PooledConnection<ConnectionManager<SqliteConnection>>;
-
-pub fn establish_connection() -> SqlitePool {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    init_pool(&database_url).expect("Failed to create pool.")
-}
-
-fn init_pool(database_url: &str) -> Result<SqlitePool, PoolError> {
-    let manager = ConnectionManager::<SqliteConnection>::new(database_url);
-    Pool::builder().build(manager)
-}
-
-pub fn get_connection(pool: &SqlitePool) -> Result<SqlitePooledConnection, PoolError> {
-    let _pool = pool.get().unwrap();
-    Ok(_pool)
-}
-
-pub fn run_migrations() {
-    let _ = std::process::Command::new("diesel")
-        .args(&["migration", "run"])
-        .stdout(std::process::Stdio::inherit())
-        .output();
-}
+use std::time::Duration;
+
+use diesel::r2d2::{ConnectionManager, Pool, PoolError};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use r2d2::PooledConnection;
+use rocket::tokio::task::spawn_blocking;
+
+use crate::{config::AppConfig, repsonses::ApiError};
+
+#[cfg(all(feature = "sqlite", feature = "postgres"))]
+compile_error!("features `sqlite` and `postgres` are mutually exclusive");
+
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("one of the features `sqlite` or `postgres` must be enabled");
+
+/// Connection type of the backend selected at compile time, all query
+/// functions in `db` take this.
+#[cfg(feature = "sqlite")]
+pub type DbConnection = diesel::sqlite::SqliteConnection;
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel::pg::PgConnection;
+
+pub type DbPool = Pool<ConnectionManager<DbConnection>>;
+pub type DbPooledConnection = PooledConnection<ConnectionManager<DbConnection>>;
+
+#[cfg(feature = "sqlite")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
+#[cfg(feature = "postgres")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
+
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite {
+    use diesel::connection::SimpleConnection;
+    use diesel::r2d2::CustomizeConnection;
+    use diesel::sqlite::SqliteConnection;
+
+    /// Milliseconds a connection waits for a lock held by another connection.
+    static BUSY_TIMEOUT: u32 = 5000;
+
+    /// Applied to every connection handed out by the pool. Foreign keys are off
+    /// by default in SQLite; WAL lets readers work while a write is in progress.
+    #[derive(Debug)]
+    pub struct ConnectionOptions;
+
+    impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+        fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+            conn.batch_execute(&format!(
+                "PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL; PRAGMA busy_timeout = {};",
+                BUSY_TIMEOUT
+            ))
+            .map_err(diesel::r2d2::Error::QueryError)
+        }
+    }
+}
+
+pub fn establish_connection(config: &AppConfig) -> DbPool {
+    init_pool(config).expect("Failed to create pool.")
+}
+
+fn init_pool(config: &AppConfig) -> Result<DbPool, PoolError> {
+    let manager = ConnectionManager::<DbConnection>::new(&config.database_url);
+
+    let builder = Pool::builder()
+        .max_size(config.database_pool_size)
+        .connection_timeout(Duration::from_secs(config.database_pool_timeout));
+
+    #[cfg(feature = "sqlite")]
+    let builder = builder.connection_customizer(Box::new(sqlite::ConnectionOptions));
+
+    builder.build(manager)
+}
+
+pub fn get_connection(pool: &DbPool) -> Result<DbPooledConnection, PoolError> {
+    pool.get()
+}
+
+/// Runs blocking Diesel work on Tokio's blocking thread pool so async handlers
+/// do not stall the runtime. Waiting for a free connection happens there as
+/// well and fails with `503` after `database_pool_timeout`.
+pub async fn run<T, F>(pool: &DbPool, work: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut DbConnection) -> Result<T, ApiError> + Send + 'static,
+{
+    let pool = pool.clone();
+
+    spawn_blocking(move || {
+        let connection = &mut get_connection(&pool)?;
+        work(connection)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(e.to_string()))?
+}
+
+/// Names of the embedded migrations that have not been applied yet.
+pub fn pending_migrations(pool: &DbPool) -> Result<Vec<String>, String> {
+    let connection = &mut get_connection(pool).map_err(|e| e.to_string())?;
+
+    let migrations = connection
+        .pending_migrations(MIGRATIONS)
+        .map_err(|e| format!("Could not read migrations: {}", e))?;
+
+    Ok(migrations
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect())
+}
+
+/// Applies all pending embedded migrations and returns their versions.
+pub fn run_migrations(pool: &DbPool) -> Result<Vec<String>, String> {
+    let connection = &mut get_connection(pool).map_err(|e| e.to_string())?;
+
+    let versions = connection
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|e| format!("Migration failed: {}", e))?;
+
+    Ok(versions.iter().map(|version| version.to_string()).collect())
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use crate::{
+        db::models::{DeviceAlias, NewDeviceAlias, NewUser, UpdateUser, User},
+        utils::testing::memory_pool,
+    };
+
+    use super::*;
+
+    #[test]
+    fn applies_and_reverts_embedded_migrations() {
+        let pool = memory_pool();
+
+        assert!(!pending_migrations(&pool).unwrap().is_empty());
+        assert!(!run_migrations(&pool).unwrap().is_empty());
+        assert!(pending_migrations(&pool).unwrap().is_empty());
+
+        let connection = &mut get_connection(&pool).unwrap();
+        connection.revert_all_migrations(MIGRATIONS).unwrap();
+        connection.run_pending_migrations(MIGRATIONS).unwrap();
+    }
+
+    #[test]
+    fn creates_reads_and_deletes_users() {
+        let pool = memory_pool();
+        run_migrations(&pool).unwrap();
+
+        let connection = &mut get_connection(&pool).unwrap();
+
+        let user = User::create_user(
+            connection,
+            &NewUser {
+                username: "alice",
+                email: "alice@example.com",
+                hashed_password: "hash",
+            },
+        )
+        .unwrap();
+
+        let found = User::get_user_by_mail(connection, "alice@example.com").unwrap();
+        assert_eq!(found.id, user.id);
+        assert_eq!(found.username, "alice");
+        assert_eq!(user.get_usersettings(connection).unwrap().hue_index, 0);
+
+        let updated = user
+            .update(
+                connection,
+                &UpdateUser {
+                    username: Some("alice2"),
+                    email: None,
+                    hashed_password: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(updated.username, "alice2");
+        assert_eq!(updated.email, "alice@example.com");
+
+        assert_eq!(user.delete(connection).unwrap(), 1);
+        assert!(matches!(
+            User::get_user(connection, user.id),
+            Err(diesel::result::Error::NotFound)
+        ));
+        assert!(User::get_users(connection).unwrap().is_empty());
+    }
+
+    #[test]
+    fn records_only_missing_device_aliases() {
+        let pool = memory_pool();
+        run_migrations(&pool).unwrap();
+
+        let connection = &mut get_connection(&pool).unwrap();
+
+        let user = User::create_user(
+            connection,
+            &NewUser {
+                username: "bob",
+                email: "bob@example.com",
+                hashed_password: "hash",
+            },
+        )
+        .unwrap();
+
+        let alias = |alias, device_id| NewDeviceAlias {
+            alias,
+            device_id,
+            user_id: &user.id,
+        };
+
+        let first = [alias("hue-1-1", "hue-abc-x")];
+        let second = [alias("hue-1-1", "hue-abc-y"), alias("hue-1-2", "hue-abc-z")];
+
+        assert_eq!(
+            DeviceAlias::create_device_aliases(connection, &first).unwrap(),
+            1
+        );
+        assert_eq!(
+            DeviceAlias::create_device_aliases(connection, &first).unwrap(),
+            0
+        );
+        assert_eq!(
+            DeviceAlias::create_device_aliases(connection, &second).unwrap(),
+            1
+        );
+
+        let stored = DeviceAlias::get_device_alias(connection, user.id, "hue-1-1").unwrap();
+        assert_eq!(stored.device_id, "hue-abc-x");
+    }
+}
@@ -0,0 +1,131 @@
+#![allow(dead_code)]
+
+use std::collections::HashSet;
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{DeviceAlias, NewDeviceAlias},
+    schema::device_aliases,
+};
+
+impl DeviceAlias {
+    /// Stores aliases that do not exist yet. An alias keeps pointing to the
+    /// device it was first recorded for. Known aliases are filtered out with a
+    /// read first, so only new ones cost a write transaction.
+    pub fn create_device_aliases<'a>(
+        conn: &mut DbConnection,
+        new_device_aliases: &[NewDeviceAlias<'a>],
+    ) -> Result<usize, diesel::result::Error> {
+        let known: HashSet<(i32, String)> = device_aliases::table
+            .filter(
+                device_aliases::alias.eq_any(
+                    new_device_aliases
+                        .iter()
+                        .map(|new_device_alias| new_device_alias.alias),
+                ),
+            )
+            .select((device_aliases::user_id, device_aliases::alias))
+            .load(conn)?
+            .into_iter()
+            .collect();
+
+        let missing: Vec<&NewDeviceAlias> = new_device_aliases
+            .iter()
+            .filter(|new_device_alias| {
+                !known.contains(&(*new_device_alias.user_id, new_device_alias.alias.to_owned()))
+            })
+            .collect();
+
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        conn.transaction(|conn| {
+            let mut inserted = 0;
+
+            for new_device_alias in missing {
+                inserted += diesel::insert_into(device_aliases::table)
+                    .values(new_device_alias)
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+
+            Ok(inserted)
+        })
+    }
+
+    pub fn get_device_alias(
+        conn: &mut DbConnection,
+        user_id: i32,
+        alias: &str,
+    ) -> Result<DeviceAlias, diesel::result::Error> {
+        conn.transaction(|conn| {
+            device_aliases::table
+                .filter(device_aliases::user_id.eq(user_id))
+                .filter(device_aliases::alias.eq(alias))
+                .first(conn)
+        })
+    }
+
+    pub fn get_device_aliases_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<Vec<DeviceAlias>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            device_aliases::table
+                .filter(device_aliases::user_id.eq(user_id))
+                .load::<DeviceAlias>(conn)
+        })
+    }
+
+    /// The aliases recorded for `device_id`.
+    pub fn get_device_aliases_by_device_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+        device_id: &str,
+    ) -> Result<Vec<DeviceAlias>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            device_aliases::table
+                .filter(device_aliases::user_id.eq(user_id))
+                .filter(device_aliases::device_id.eq(device_id))
+                .load::<DeviceAlias>(conn)
+        })
+    }
+
+    /// Deletes the aliases from or to ids starting with `prefix`.
+    pub fn delete_by_prefix(
+        conn: &mut DbConnection,
+        user_id: i32,
+        prefix: &str,
+    ) -> Result<usize, diesel::result::Error> {
+        let pattern = format!("{}%", prefix);
+
+        conn.transaction(|conn| {
+            diesel::delete(
+                device_aliases::table
+                    .filter(device_aliases::user_id.eq(user_id))
+                    .filter(
+                        device_aliases::alias
+                            .like(&pattern)
+                            .or(device_aliases::device_id.like(&pattern)),
+                    ),
+            )
+            .execute(conn)
+        })
+    }
+
+    pub fn delete_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(device_aliases::table.filter(device_aliases::user_id.eq(user_id)))
+                .execute(conn)
+        })
+    }
+}
@@ -0,0 +1,106 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{DeviceMetadata, NewDeviceMetadata, UpdateDeviceMetadata},
+    schema::device_metadata,
+};
+
+impl DeviceMetadata {
+    pub fn get_device_metadata(
+        conn: &mut DbConnection,
+        user_id: i32,
+        device_id: &str,
+    ) -> Result<DeviceMetadata, diesel::result::Error> {
+        conn.transaction(|conn| {
+            device_metadata::table
+                .filter(device_metadata::user_id.eq(user_id))
+                .filter(device_metadata::device_id.eq(device_id))
+                .first(conn)
+        })
+    }
+
+    pub fn get_device_metadata_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<Vec<DeviceMetadata>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            device_metadata::table
+                .filter(device_metadata::user_id.eq(user_id))
+                .load::<DeviceMetadata>(conn)
+        })
+    }
+
+    /// Creates the row on first use and applies `update` to it.
+    pub fn upsert<'a>(
+        conn: &mut DbConnection,
+        user_id: i32,
+        device_id: &str,
+        update: &UpdateDeviceMetadata<'a>,
+    ) -> Result<DeviceMetadata, diesel::result::Error> {
+        conn.transaction(|conn| {
+            let device_metadata = match DeviceMetadata::get_device_metadata(conn, user_id, device_id)
+            {
+                Ok(device_metadata) => device_metadata,
+                Err(diesel::result::Error::NotFound) => {
+                    diesel::insert_into(device_metadata::table)
+                        .values(&NewDeviceMetadata {
+                            device_id,
+                            user_id: &user_id,
+                        })
+                        .execute(conn)?;
+
+                    DeviceMetadata::get_device_metadata(conn, user_id, device_id)?
+                }
+                Err(e) => return Err(e),
+            };
+
+            if *update == UpdateDeviceMetadata::default() {
+                return Ok(device_metadata);
+            }
+
+            device_metadata.update(conn, update)
+        })
+    }
+
+    pub fn update<'a>(
+        &self,
+        conn: &mut DbConnection,
+        update: &UpdateDeviceMetadata<'a>,
+    ) -> Result<DeviceMetadata, diesel::result::Error> {
+        conn.transaction(|conn| diesel::update(self).set(update).get_result(conn))
+    }
+
+    /// Deletes the metadata of devices whose ids start with `prefix`.
+    pub fn delete_by_prefix(
+        conn: &mut DbConnection,
+        user_id: i32,
+        prefix: &str,
+    ) -> Result<usize, diesel::result::Error> {
+        let pattern = format!("{}%", prefix);
+
+        conn.transaction(|conn| {
+            diesel::delete(
+                device_metadata::table
+                    .filter(device_metadata::user_id.eq(user_id))
+                    .filter(device_metadata::device_id.like(&pattern)),
+            )
+            .execute(conn)
+        })
+    }
+
+    pub fn delete_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(device_metadata::table.filter(device_metadata::user_id.eq(user_id)))
+                .execute(conn)
+        })
+    }
+}
@@ -0,0 +1,78 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{LifxDevice, NewLifxDevice, UpdateLifxDevice},
+    schema::lifx_devices,
+};
+
+impl LifxDevice {
+    pub fn create_lifx_device<'a>(
+        conn: &mut DbConnection,
+        new_lifx_device: &NewLifxDevice<'a>,
+    ) -> Result<LifxDevice, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::insert_into(lifx_devices::table)
+                .values(new_lifx_device)
+                .get_result(conn)
+        })
+    }
+
+    pub fn get_lifx_device(
+        conn: &mut DbConnection,
+        user_id: i32,
+        device_id: &str,
+    ) -> Result<LifxDevice, diesel::result::Error> {
+        conn.transaction(|conn| {
+            lifx_devices::table
+                .filter(lifx_devices::user_id.eq(user_id))
+                .filter(lifx_devices::device_id.eq(device_id))
+                .first(conn)
+        })
+    }
+
+    pub fn get_lifx_devices(
+        conn: &mut DbConnection,
+    ) -> Result<Vec<LifxDevice>, diesel::result::Error> {
+        conn.transaction(|conn| lifx_devices::table.load::<LifxDevice>(conn))
+    }
+
+    pub fn get_lifx_devices_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<Vec<LifxDevice>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            lifx_devices::table
+                .filter(lifx_devices::user_id.eq(user_id))
+                .order(lifx_devices::id)
+                .load::<LifxDevice>(conn)
+        })
+    }
+
+    pub fn update<'a>(
+        &self,
+        conn: &mut DbConnection,
+        update: &UpdateLifxDevice<'a>,
+    ) -> Result<LifxDevice, diesel::result::Error> {
+        conn.transaction(|conn| diesel::update(self).set(update).get_result(conn))
+    }
+
+    pub fn delete(&self, conn: &mut DbConnection) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| diesel::delete(self).execute(conn))
+    }
+
+    pub fn delete_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(lifx_devices::table.filter(lifx_devices::user_id.eq(user_id)))
+                .execute(conn)
+        })
+    }
+}
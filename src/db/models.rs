@@ -1,122 +1,445 @@
-use diesel::prelude::*;
-use schemars::JsonSchema;
-use serde::Serialize;
-
-use super::schema::{huebridges, users, usersettings, wleditems};
-
-#[derive(Queryable, PartialEq, Identifiable, Selectable, Serialize, JsonSchema)]
-#[diesel(table_name = users)]
-pub struct User {
-    pub id: i32,
-    pub username: String,
-    pub email: String,
-    pub hashed_password: String,
-}
-
-#[derive(Insertable, PartialEq, Selectable)]
-#[diesel(table_name = users)]
-pub struct NewUser<'a> {
-    pub username: &'a str,
-    pub email: &'a str,
-    pub hashed_password: &'a str,
-}
-
-#[derive(AsChangeset, PartialEq, Selectable)]
-#[diesel(table_name = users)]
-pub struct UpdateUser<'a> {
-    pub username: Option<&'a str>,
-    pub email: Option<&'a str>,
-    pub hashed_password: Option<&'a str>,
-}
-
-#[derive(
-    Queryable, PartialEq, Identifiable, Selectable, Associations, Serialize, JsonSchema, Debug,
-)]
-#[diesel(table_name = usersettings)]
-#[diesel(belongs_to(User))]
-pub struct UserSettings {
-    pub id: i32,
-    pub hue_index: i32,
-    pub user_id: i32,
-}
-
-#[derive(Insertable, PartialEq, Associations)]
-#[diesel(table_name = usersettings)]
-#[diesel(belongs_to(User))]
-pub struct NewUserSettings<'a> {
-    pub hue_index: &'a i32,
-    pub user_id: &'a i32,
-}
-
-#[derive(AsChangeset, PartialEq, Associations)]
-#[diesel(table_name = usersettings)]
-#[diesel(belongs_to(User))]
-pub struct UpdateUserSettings<'a> {
-    pub hue_index: Option<&'a i32>,
-    pub user_id: Option<&'a i32>,
-}
-
-#[derive(
-    Queryable, PartialEq, Identifiable, Selectable, Associations, Serialize, JsonSchema, Debug,
-)]
-#[diesel(table_name = huebridges)]
-#[diesel(belongs_to(UserSettings))]
-#[diesel(primary_key(_id))]
-pub struct HueBridge {
-    pub _id: i32,
-    pub id: String,
-    pub ip: String,
-    pub user: String,
-    pub user_settings_id: i32,
-}
-
-#[derive(Insertable, PartialEq, Associations)]
-#[diesel(table_name = huebridges)]
-#[diesel(belongs_to(UserSettings))]
-pub struct NewHueBridge<'a> {
-    pub id: &'a String,
-    pub ip: &'a str,
-    pub user: &'a str,
-    pub user_settings_id: &'a i32,
-}
-
-#[derive(AsChangeset, PartialEq, Associations)]
-#[diesel(table_name = huebridges)]
-#[diesel(belongs_to(UserSettings))]
-pub struct UpdateHueBridge<'a> {
-    pub id: Option<&'a String>,
-    pub ip: Option<&'a str>,
-    pub user: Option<&'a str>,
-    pub user_settings_id: Option<&'a i32>,
-}
-
-#[derive(
-    Queryable, PartialEq, Identifiable, Selectable, Associations, Serialize, JsonSchema, Debug,
-)]
-#[diesel(table_name = wleditems)]
-#[diesel(belongs_to(UserSettings))]
-#[diesel(primary_key(_id))]
-pub struct WledItem {
-    pub _id: i32,
-    pub ip: String,
-    pub name: String,
-    pub user_settings_id: i32,
-}
-
-#[derive(Insertable, PartialEq, Associations)]
-#[diesel(table_name = wleditems)]
-#[diesel(belongs_to(UserSettings))]
-pub struct NewWledItem<'a> {
-    pub ip: &'a str,
-    pub name: &'a str,
-    pub user_settings_id: &'a i32,
-}
-
-#[derive(AsChangeset, PartialEq, Associations)]
-#[diesel(table_name = wleditems)]
-#[diesel(belongs_to(UserSettings))]
-pub struct UpdateWledItem<'a> {
-    pub ip: Option<&'a str>,
-    pub name: Option<&'a str>,
-    pub user_settings_id: Option<&'a i32>,
-}
+use diesel::prelude::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::schema::{
+    device_aliases, device_metadata, huebridges, lifx_devices, password_resets, recovery_codes,
+    shelly_devices, tasmota_devices, totp_secrets, users, usersettings, wleditems,
+    zigbee2mqtt_bridges,
+};
+
+#[derive(Queryable, PartialEq, Identifiable, Selectable, Serialize, JsonSchema)]
+#[diesel(table_name = users)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub hashed_password: String,
+    /// Raised on every password change, access tokens carrying an older
+    /// value are rejected.
+    pub session_version: i32,
+}
+
+#[derive(Insertable, PartialEq, Selectable)]
+#[diesel(table_name = users)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+    pub email: &'a str,
+    pub hashed_password: &'a str,
+}
+
+#[derive(AsChangeset, PartialEq, Selectable)]
+#[diesel(table_name = users)]
+pub struct UpdateUser<'a> {
+    pub username: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub hashed_password: Option<&'a str>,
+}
+
+#[derive(
+    Queryable, PartialEq, Identifiable, Selectable, Associations, Serialize, JsonSchema, Debug,
+)]
+#[diesel(table_name = usersettings)]
+#[diesel(belongs_to(User))]
+pub struct UserSettings {
+    pub id: i32,
+    pub hue_index: i32,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = usersettings)]
+#[diesel(belongs_to(User))]
+pub struct NewUserSettings<'a> {
+    pub hue_index: &'a i32,
+    pub user_id: &'a i32,
+}
+
+#[derive(AsChangeset, PartialEq, Associations)]
+#[diesel(table_name = usersettings)]
+#[diesel(belongs_to(User))]
+pub struct UpdateUserSettings<'a> {
+    pub hue_index: Option<&'a i32>,
+    pub user_id: Option<&'a i32>,
+}
+
+#[derive(
+    Queryable,
+    PartialEq,
+    Identifiable,
+    Selectable,
+    Associations,
+    Serialize,
+    JsonSchema,
+    Debug,
+    Clone,
+)]
+#[diesel(table_name = huebridges)]
+#[diesel(belongs_to(UserSettings))]
+#[diesel(primary_key(_id))]
+pub struct HueBridge {
+    pub _id: i32,
+    pub id: String,
+    pub ip: String,
+    pub user: String,
+    pub user_settings_id: i32,
+    /// The bridge's own id, unlike `id` it survives removing and re-adding
+    /// the bridge. Unknown until the bridge was reached once.
+    pub bridgeid: Option<String>,
+    /// Hex encoded SHA-256 fingerprint of the bridge's TLS certificate, pinned
+    /// on first contact.
+    pub certificate: Option<String>,
+    /// Falls back to plain HTTP when the bridge does not accept HTTPS.
+    pub allow_http: bool,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = huebridges)]
+#[diesel(belongs_to(UserSettings))]
+pub struct NewHueBridge<'a> {
+    pub id: &'a String,
+    pub ip: &'a str,
+    pub user: &'a str,
+    pub user_settings_id: &'a i32,
+    pub bridgeid: Option<&'a str>,
+    pub certificate: Option<&'a str>,
+    pub allow_http: bool,
+}
+
+#[derive(AsChangeset, PartialEq, Associations)]
+#[diesel(table_name = huebridges)]
+#[diesel(belongs_to(UserSettings))]
+pub struct UpdateHueBridge<'a> {
+    pub id: Option<&'a String>,
+    pub ip: Option<&'a str>,
+    pub user: Option<&'a str>,
+    pub user_settings_id: Option<&'a i32>,
+    pub bridgeid: Option<&'a str>,
+    /// `Some(None)` forgets the pinned certificate.
+    pub certificate: Option<Option<&'a str>>,
+    pub allow_http: Option<bool>,
+}
+
+#[derive(
+    Queryable, PartialEq, Identifiable, Selectable, Associations, Serialize, JsonSchema, Debug,
+)]
+#[diesel(table_name = wleditems)]
+#[diesel(belongs_to(UserSettings))]
+#[diesel(primary_key(_id))]
+pub struct WledItem {
+    pub _id: i32,
+    pub ip: String,
+    pub name: String,
+    pub user_settings_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = wleditems)]
+#[diesel(belongs_to(UserSettings))]
+pub struct NewWledItem<'a> {
+    pub ip: &'a str,
+    pub name: &'a str,
+    pub user_settings_id: &'a i32,
+}
+
+#[derive(AsChangeset, PartialEq, Associations)]
+#[diesel(table_name = wleditems)]
+#[diesel(belongs_to(UserSettings))]
+pub struct UpdateWledItem<'a> {
+    pub ip: Option<&'a str>,
+    pub name: Option<&'a str>,
+    pub user_settings_id: Option<&'a i32>,
+}
+
+#[derive(Queryable, PartialEq, Identifiable, Selectable, Associations, Debug)]
+#[diesel(table_name = password_resets)]
+#[diesel(belongs_to(User))]
+pub struct PasswordReset {
+    pub id: i32,
+    pub token_hash: String,
+    pub expires_at: i64,
+    pub used: bool,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = password_resets)]
+#[diesel(belongs_to(User))]
+pub struct NewPasswordReset<'a> {
+    pub token_hash: &'a str,
+    pub expires_at: &'a i64,
+    pub user_id: &'a i32,
+}
+
+#[derive(AsChangeset, PartialEq)]
+#[diesel(table_name = password_resets)]
+pub struct UpdatePasswordReset {
+    pub used: Option<bool>,
+}
+
+#[derive(Queryable, PartialEq, Identifiable, Selectable, Associations, Debug)]
+#[diesel(table_name = totp_secrets)]
+#[diesel(belongs_to(User))]
+pub struct TotpSecret {
+    pub id: i32,
+    pub secret: String,
+    pub confirmed: bool,
+    pub user_id: i32,
+    pub last_step: Option<i64>,
+    pub mfa_nonce: Option<String>,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = totp_secrets)]
+#[diesel(belongs_to(User))]
+pub struct NewTotpSecret<'a> {
+    pub secret: &'a str,
+    pub user_id: &'a i32,
+}
+
+#[derive(AsChangeset, PartialEq)]
+#[diesel(table_name = totp_secrets)]
+pub struct UpdateTotpSecret {
+    pub confirmed: Option<bool>,
+}
+
+#[derive(Queryable, PartialEq, Identifiable, Selectable, Associations, Debug)]
+#[diesel(table_name = recovery_codes)]
+#[diesel(belongs_to(User))]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub code_hash: String,
+    pub used: bool,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = recovery_codes)]
+#[diesel(belongs_to(User))]
+pub struct NewRecoveryCode<'a> {
+    pub code_hash: &'a str,
+    pub user_id: &'a i32,
+}
+
+/// Maps a device id that is no longer current, e.g. a `hue-<bridge index>-<light>`
+/// id, to the stable id of the same device.
+#[derive(Queryable, PartialEq, Identifiable, Selectable, Associations, Debug)]
+#[diesel(table_name = device_aliases)]
+#[diesel(belongs_to(User))]
+pub struct DeviceAlias {
+    pub id: i32,
+    pub alias: String,
+    pub device_id: String,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = device_aliases)]
+#[diesel(belongs_to(User))]
+pub struct NewDeviceAlias<'a> {
+    pub alias: &'a str,
+    pub device_id: &'a str,
+    pub user_id: &'a i32,
+}
+
+/// User-defined overlay for a device, keyed by its normalized id.
+#[derive(Queryable, PartialEq, Identifiable, Selectable, Associations, Debug, Clone)]
+#[diesel(table_name = device_metadata)]
+#[diesel(belongs_to(User))]
+pub struct DeviceMetadata {
+    pub id: i32,
+    pub device_id: String,
+    pub name: Option<String>,
+    pub icon: Option<String>,
+    pub room: Option<String>,
+    pub sort_order: Option<i32>,
+    pub favorite: bool,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = device_metadata)]
+#[diesel(belongs_to(User))]
+pub struct NewDeviceMetadata<'a> {
+    pub device_id: &'a str,
+    pub user_id: &'a i32,
+}
+
+/// `Some(None)` clears a field.
+#[derive(AsChangeset, PartialEq, Default)]
+#[diesel(table_name = device_metadata)]
+pub struct UpdateDeviceMetadata<'a> {
+    pub device_id: Option<&'a str>,
+    pub name: Option<Option<&'a str>>,
+    pub icon: Option<Option<&'a str>>,
+    pub room: Option<Option<&'a str>>,
+    pub sort_order: Option<Option<i32>>,
+    pub favorite: Option<bool>,
+}
+
+/// A Shelly relay, plug, dimmer or RGBW controller registered by a user.
+#[derive(
+    Queryable,
+    PartialEq,
+    Identifiable,
+    Selectable,
+    Associations,
+    Serialize,
+    JsonSchema,
+    Debug,
+    Clone,
+)]
+#[diesel(table_name = shelly_devices)]
+#[diesel(belongs_to(User))]
+pub struct ShellyDevice {
+    pub id: i32,
+    /// Lowercase MAC address without separators, part of the device ids.
+    pub device_id: String,
+    pub ip: String,
+    /// `1` for the HTTP API, `2` for RPC.
+    pub generation: i32,
+    pub model: String,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = shelly_devices)]
+#[diesel(belongs_to(User))]
+pub struct NewShellyDevice<'a> {
+    pub device_id: &'a str,
+    pub ip: &'a str,
+    pub generation: &'a i32,
+    pub model: &'a str,
+    pub user_id: &'a i32,
+}
+
+#[derive(AsChangeset, PartialEq, Default)]
+#[diesel(table_name = shelly_devices)]
+pub struct UpdateShellyDevice<'a> {
+    pub ip: Option<&'a str>,
+    pub generation: Option<&'a i32>,
+    pub model: Option<&'a str>,
+}
+
+/// A device running Tasmota, optionally protected by a web password.
+#[derive(
+    Queryable,
+    PartialEq,
+    Identifiable,
+    Selectable,
+    Associations,
+    Serialize,
+    JsonSchema,
+    Debug,
+    Clone,
+)]
+#[diesel(table_name = tasmota_devices)]
+#[diesel(belongs_to(User))]
+pub struct TasmotaDevice {
+    pub id: i32,
+    /// Lowercase MAC address without separators, part of the device ids.
+    pub device_id: String,
+    pub ip: String,
+    /// `DeviceName` reported by the device.
+    pub name: String,
+    pub username: Option<String>,
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = tasmota_devices)]
+#[diesel(belongs_to(User))]
+pub struct NewTasmotaDevice<'a> {
+    pub device_id: &'a str,
+    pub ip: &'a str,
+    pub name: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub user_id: &'a i32,
+}
+
+/// `Some(None)` clears the credentials.
+#[derive(AsChangeset, PartialEq, Default)]
+#[diesel(table_name = tasmota_devices)]
+pub struct UpdateTasmotaDevice<'a> {
+    pub ip: Option<&'a str>,
+    pub name: Option<&'a str>,
+    pub username: Option<Option<&'a str>>,
+    pub password: Option<Option<&'a str>>,
+}
+
+/// A Zigbee2MQTT instance reachable through the configured MQTT broker.
+#[derive(
+    Queryable,
+    PartialEq,
+    Identifiable,
+    Selectable,
+    Associations,
+    Serialize,
+    JsonSchema,
+    Debug,
+    Clone,
+)]
+#[diesel(table_name = zigbee2mqtt_bridges)]
+#[diesel(belongs_to(User))]
+pub struct Zigbee2MqttBridge {
+    pub id: i32,
+    /// `base_topic` from the Zigbee2MQTT configuration, usually `zigbee2mqtt`.
+    pub base_topic: String,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = zigbee2mqtt_bridges)]
+#[diesel(belongs_to(User))]
+pub struct NewZigbee2MqttBridge<'a> {
+    pub base_topic: &'a str,
+    pub user_id: &'a i32,
+}
+
+/// A LIFX bulb or strip, contacted via the LAN protocol.
+#[derive(
+    Queryable,
+    PartialEq,
+    Identifiable,
+    Selectable,
+    Associations,
+    Serialize,
+    JsonSchema,
+    Debug,
+    Clone,
+)]
+#[diesel(table_name = lifx_devices)]
+#[diesel(belongs_to(User))]
+pub struct LifxDevice {
+    pub id: i32,
+    /// Lowercase serial number (MAC address) without separators.
+    pub device_id: String,
+    pub ip: String,
+    /// Label set in the LIFX app.
+    pub name: String,
+    /// Product id from LIFX's product list, e.g. `32` for a LIFX Z.
+    pub product: i32,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, PartialEq, Associations)]
+#[diesel(table_name = lifx_devices)]
+#[diesel(belongs_to(User))]
+pub struct NewLifxDevice<'a> {
+    pub device_id: &'a str,
+    pub ip: &'a str,
+    pub name: &'a str,
+    pub product: &'a i32,
+    pub user_id: &'a i32,
+}
+
+#[derive(AsChangeset, PartialEq, Default)]
+#[diesel(table_name = lifx_devices)]
+pub struct UpdateLifxDevice<'a> {
+    pub ip: Option<&'a str>,
+    pub name: Option<&'a str>,
+    pub product: Option<&'a i32>,
+}
@@ -0,0 +1,80 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{NewPasswordReset, PasswordReset, UpdatePasswordReset},
+    schema::password_resets,
+};
+
+impl PasswordReset {
+    pub fn create_passwordreset<'a>(
+        conn: &mut DbConnection,
+        new_passwordreset: &NewPasswordReset<'a>,
+    ) -> Result<PasswordReset, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::insert_into(password_resets::table)
+                .values(new_passwordreset)
+                .get_result(conn)
+        })
+    }
+
+    /// Marks the unused, unexpired reset for `token_hash` as used and returns
+    /// its user id. The check and the update are a single statement, so a
+    /// token can only be consumed once.
+    pub fn consume(
+        conn: &mut DbConnection,
+        token_hash: &str,
+        now: i64,
+    ) -> Result<Option<i32>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            let user_ids: Vec<i32> = diesel::update(
+                password_resets::table
+                    .filter(password_resets::token_hash.eq(token_hash))
+                    .filter(password_resets::used.eq(false))
+                    .filter(password_resets::expires_at.gt(now)),
+            )
+            .set(password_resets::used.eq(true))
+            .returning(password_resets::user_id)
+            .get_results(conn)?;
+
+            match user_ids.as_slice() {
+                [user_id] => Ok(Some(*user_id)),
+                _ => Ok(None),
+            }
+        })
+    }
+
+    pub fn update(
+        &self,
+        conn: &mut DbConnection,
+        update_passwordreset: &UpdatePasswordReset,
+    ) -> Result<PasswordReset, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::update(self)
+                .set(update_passwordreset)
+                .execute(conn)?;
+
+            password_resets::table.find(self.id).first(conn)
+        })
+    }
+
+    pub fn invalidate_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::update(password_resets::table.filter(password_resets::user_id.eq(user_id)))
+                .set(password_resets::used.eq(true))
+                .execute(conn)
+        })
+    }
+
+    pub fn delete(&self, conn: &mut DbConnection) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| diesel::delete(self).execute(conn))
+    }
+}
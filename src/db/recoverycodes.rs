@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{NewRecoveryCode, RecoveryCode},
+    schema::recovery_codes,
+};
+
+impl RecoveryCode {
+    /// Replaces all recovery codes of the user with the given ones.
+    pub fn replace_recoverycodes(
+        conn: &mut DbConnection,
+        user_id: i32,
+        code_hashes: &[String],
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                .execute(conn)?;
+
+            let new_recoverycodes: Vec<NewRecoveryCode> = code_hashes
+                .iter()
+                .map(|code_hash| NewRecoveryCode {
+                    code_hash,
+                    user_id: &user_id,
+                })
+                .collect();
+
+            diesel::insert_into(recovery_codes::table)
+                .values(&new_recoverycodes)
+                .execute(conn)
+        })
+    }
+
+    /// Marks the first unused code whose hash satisfies `matches` as used.
+    /// Returns `NotFound` if there is none.
+    pub fn use_recoverycode<F>(
+        conn: &mut DbConnection,
+        user_id: i32,
+        matches: F,
+    ) -> Result<RecoveryCode, diesel::result::Error>
+    where
+        F: Fn(&str) -> bool,
+    {
+        conn.transaction(|conn| {
+            let recoverycodes: Vec<RecoveryCode> = recovery_codes::table
+                .filter(recovery_codes::user_id.eq(user_id))
+                .filter(recovery_codes::used.eq(false))
+                .load(conn)?;
+
+            let recoverycode = recoverycodes
+                .into_iter()
+                .find(|recoverycode| matches(&recoverycode.code_hash))
+                .ok_or(diesel::result::Error::NotFound)?;
+
+            let updated = diesel::update(
+                recovery_codes::table
+                    .filter(recovery_codes::id.eq(recoverycode.id))
+                    .filter(recovery_codes::used.eq(false)),
+            )
+            .set(recovery_codes::used.eq(true))
+            .execute(conn)?;
+
+            if updated != 1 {
+                return Err(diesel::result::Error::NotFound);
+            }
+
+            recovery_codes::table.find(recoverycode.id).first(conn)
+        })
+    }
+
+    pub fn delete_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                .execute(conn)
+        })
+    }
+}
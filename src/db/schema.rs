@@ -1,5 +1,27 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    device_aliases (id) {
+        id -> Integer,
+        alias -> Text,
+        device_id -> Text,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    device_metadata (id) {
+        id -> Integer,
+        device_id -> Text,
+        name -> Nullable<Text>,
+        icon -> Nullable<Text>,
+        room -> Nullable<Text>,
+        sort_order -> Nullable<Integer>,
+        favorite -> Bool,
+        user_id -> Integer,
+    }
+}
+
 diesel::table! {
     huebridges (_id) {
         _id -> Integer,
@@ -7,6 +29,73 @@ diesel::table! {
         ip -> Text,
         user -> Text,
         user_settings_id -> Integer,
+        bridgeid -> Nullable<Text>,
+        certificate -> Nullable<Text>,
+        allow_http -> Bool,
+    }
+}
+
+diesel::table! {
+    lifx_devices (id) {
+        id -> Integer,
+        device_id -> Text,
+        ip -> Text,
+        name -> Text,
+        product -> Integer,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    password_resets (id) {
+        id -> Integer,
+        token_hash -> Text,
+        expires_at -> BigInt,
+        used -> Bool,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Integer,
+        code_hash -> Text,
+        used -> Bool,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    shelly_devices (id) {
+        id -> Integer,
+        device_id -> Text,
+        ip -> Text,
+        generation -> Integer,
+        model -> Text,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    tasmota_devices (id) {
+        id -> Integer,
+        device_id -> Text,
+        ip -> Text,
+        name -> Text,
+        username -> Nullable<Text>,
+        password -> Nullable<Text>,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    totp_secrets (id) {
+        id -> Integer,
+        secret -> Text,
+        confirmed -> Bool,
+        user_id -> Integer,
+        last_step -> Nullable<BigInt>,
+        mfa_nonce -> Nullable<Text>,
     }
 }
 
@@ -16,6 +105,7 @@ diesel::table! {
         username -> Text,
         email -> Text,
         hashed_password -> Text,
+        session_version -> Integer,
     }
 }
 
@@ -36,13 +126,39 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    zigbee2mqtt_bridges (id) {
+        id -> Integer,
+        base_topic -> Text,
+        user_id -> Integer,
+    }
+}
+
+diesel::joinable!(device_aliases -> users (user_id));
+diesel::joinable!(device_metadata -> users (user_id));
 diesel::joinable!(huebridges -> usersettings (user_settings_id));
+diesel::joinable!(lifx_devices -> users (user_id));
+diesel::joinable!(password_resets -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(shelly_devices -> users (user_id));
+diesel::joinable!(tasmota_devices -> users (user_id));
+diesel::joinable!(totp_secrets -> users (user_id));
 diesel::joinable!(usersettings -> users (user_id));
 diesel::joinable!(wleditems -> usersettings (user_settings_id));
+diesel::joinable!(zigbee2mqtt_bridges -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    device_aliases,
+    device_metadata,
     huebridges,
+    lifx_devices,
+    password_resets,
+    recovery_codes,
+    shelly_devices,
+    tasmota_devices,
+    totp_secrets,
     users,
     usersettings,
     wleditems,
+    zigbee2mqtt_bridges,
 );
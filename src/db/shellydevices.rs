@@ -0,0 +1,78 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{NewShellyDevice, ShellyDevice, UpdateShellyDevice},
+    schema::shelly_devices,
+};
+
+impl ShellyDevice {
+    pub fn create_shelly_device<'a>(
+        conn: &mut DbConnection,
+        new_shelly_device: &NewShellyDevice<'a>,
+    ) -> Result<ShellyDevice, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::insert_into(shelly_devices::table)
+                .values(new_shelly_device)
+                .get_result(conn)
+        })
+    }
+
+    pub fn get_shelly_device(
+        conn: &mut DbConnection,
+        user_id: i32,
+        device_id: &str,
+    ) -> Result<ShellyDevice, diesel::result::Error> {
+        conn.transaction(|conn| {
+            shelly_devices::table
+                .filter(shelly_devices::user_id.eq(user_id))
+                .filter(shelly_devices::device_id.eq(device_id))
+                .first(conn)
+        })
+    }
+
+    pub fn get_shelly_devices(
+        conn: &mut DbConnection,
+    ) -> Result<Vec<ShellyDevice>, diesel::result::Error> {
+        conn.transaction(|conn| shelly_devices::table.load::<ShellyDevice>(conn))
+    }
+
+    pub fn get_shelly_devices_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<Vec<ShellyDevice>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            shelly_devices::table
+                .filter(shelly_devices::user_id.eq(user_id))
+                .order(shelly_devices::id)
+                .load::<ShellyDevice>(conn)
+        })
+    }
+
+    pub fn update<'a>(
+        &self,
+        conn: &mut DbConnection,
+        update: &UpdateShellyDevice<'a>,
+    ) -> Result<ShellyDevice, diesel::result::Error> {
+        conn.transaction(|conn| diesel::update(self).set(update).get_result(conn))
+    }
+
+    pub fn delete(&self, conn: &mut DbConnection) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| diesel::delete(self).execute(conn))
+    }
+
+    pub fn delete_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(shelly_devices::table.filter(shelly_devices::user_id.eq(user_id)))
+                .execute(conn)
+        })
+    }
+}
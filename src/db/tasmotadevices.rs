@@ -0,0 +1,78 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{NewTasmotaDevice, TasmotaDevice, UpdateTasmotaDevice},
+    schema::tasmota_devices,
+};
+
+impl TasmotaDevice {
+    pub fn create_tasmota_device<'a>(
+        conn: &mut DbConnection,
+        new_tasmota_device: &NewTasmotaDevice<'a>,
+    ) -> Result<TasmotaDevice, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::insert_into(tasmota_devices::table)
+                .values(new_tasmota_device)
+                .get_result(conn)
+        })
+    }
+
+    pub fn get_tasmota_device(
+        conn: &mut DbConnection,
+        user_id: i32,
+        device_id: &str,
+    ) -> Result<TasmotaDevice, diesel::result::Error> {
+        conn.transaction(|conn| {
+            tasmota_devices::table
+                .filter(tasmota_devices::user_id.eq(user_id))
+                .filter(tasmota_devices::device_id.eq(device_id))
+                .first(conn)
+        })
+    }
+
+    pub fn get_tasmota_devices(
+        conn: &mut DbConnection,
+    ) -> Result<Vec<TasmotaDevice>, diesel::result::Error> {
+        conn.transaction(|conn| tasmota_devices::table.load::<TasmotaDevice>(conn))
+    }
+
+    pub fn get_tasmota_devices_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<Vec<TasmotaDevice>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            tasmota_devices::table
+                .filter(tasmota_devices::user_id.eq(user_id))
+                .order(tasmota_devices::id)
+                .load::<TasmotaDevice>(conn)
+        })
+    }
+
+    pub fn update<'a>(
+        &self,
+        conn: &mut DbConnection,
+        update: &UpdateTasmotaDevice<'a>,
+    ) -> Result<TasmotaDevice, diesel::result::Error> {
+        conn.transaction(|conn| diesel::update(self).set(update).get_result(conn))
+    }
+
+    pub fn delete(&self, conn: &mut DbConnection) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| diesel::delete(self).execute(conn))
+    }
+
+    pub fn delete_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(tasmota_devices::table.filter(tasmota_devices::user_id.eq(user_id)))
+                .execute(conn)
+        })
+    }
+}
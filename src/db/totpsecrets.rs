@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{NewTotpSecret, TotpSecret, UpdateTotpSecret},
+    schema::totp_secrets,
+};
+
+impl TotpSecret {
+    /// Replaces any existing secret of the user.
+    pub fn create_totpsecret<'a>(
+        conn: &mut DbConnection,
+        new_totpsecret: &NewTotpSecret<'a>,
+    ) -> Result<TotpSecret, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(
+                totp_secrets::table.filter(totp_secrets::user_id.eq(new_totpsecret.user_id)),
+            )
+            .execute(conn)?;
+
+            diesel::insert_into(totp_secrets::table)
+                .values(new_totpsecret)
+                .get_result(conn)
+        })
+    }
+
+    pub fn get_totpsecret_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<TotpSecret, diesel::result::Error> {
+        conn.transaction(|conn| {
+            totp_secrets::table
+                .filter(totp_secrets::user_id.eq(user_id))
+                .first(conn)
+        })
+    }
+
+    pub fn update(
+        &self,
+        conn: &mut DbConnection,
+        update_totpsecret: &UpdateTotpSecret,
+    ) -> Result<TotpSecret, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::update(self).set(update_totpsecret).execute(conn)?;
+
+            totp_secrets::table.find(self.id).first(conn)
+        })
+    }
+
+    /// Stores the nonce of the MFA token handed out for a pending login. Only
+    /// the latest one can be exchanged, see [`TotpSecret::complete_login`].
+    pub fn start_login(
+        &self,
+        conn: &mut DbConnection,
+        mfa_nonce: &str,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::update(self)
+                .set(totp_secrets::mfa_nonce.eq(mfa_nonce))
+                .execute(conn)
+        })
+    }
+
+    /// Consumes the MFA nonce and, for TOTP codes, records the accepted time
+    /// step. Returns `false` if the nonce was already used or the step is not
+    /// newer than the last accepted one.
+    pub fn complete_login(
+        &self,
+        conn: &mut DbConnection,
+        mfa_nonce: &str,
+        step: Option<i64>,
+    ) -> Result<bool, diesel::result::Error> {
+        conn.transaction(|conn| {
+            let query = totp_secrets::table
+                .filter(totp_secrets::id.eq(self.id))
+                .filter(totp_secrets::mfa_nonce.eq(mfa_nonce));
+
+            let updated = match step {
+                Some(step) => diesel::update(
+                    query.filter(
+                        totp_secrets::last_step
+                            .is_null()
+                            .or(totp_secrets::last_step.lt(step)),
+                    ),
+                )
+                .set((
+                    totp_secrets::mfa_nonce.eq(None::<String>),
+                    totp_secrets::last_step.eq(step),
+                ))
+                .execute(conn)?,
+                None => diesel::update(query)
+                    .set(totp_secrets::mfa_nonce.eq(None::<String>))
+                    .execute(conn)?,
+            };
+
+            Ok(updated == 1)
+        })
+    }
+
+    /// Records an accepted time step so the same code cannot be used again.
+    /// Returns `false` if the step is not newer than the last accepted one.
+    pub fn accept_step(
+        &self,
+        conn: &mut DbConnection,
+        step: i64,
+    ) -> Result<bool, diesel::result::Error> {
+        conn.transaction(|conn| {
+            let updated = diesel::update(
+                totp_secrets::table
+                    .filter(totp_secrets::id.eq(self.id))
+                    .filter(
+                        totp_secrets::last_step
+                            .is_null()
+                            .or(totp_secrets::last_step.lt(step)),
+                    ),
+            )
+            .set(totp_secrets::last_step.eq(step))
+            .execute(conn)?;
+
+            Ok(updated == 1)
+        })
+    }
+
+    pub fn delete(&self, conn: &mut DbConnection) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| diesel::delete(self).execute(conn))
+    }
+}
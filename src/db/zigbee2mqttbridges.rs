@@ -0,0 +1,72 @@
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+
+use diesel::Connection;
+
+use super::connection::DbConnection;
+
+use super::{
+    models::{NewZigbee2MqttBridge, Zigbee2MqttBridge},
+    schema::zigbee2mqtt_bridges,
+};
+
+impl Zigbee2MqttBridge {
+    pub fn create_zigbee2mqtt_bridge<'a>(
+        conn: &mut DbConnection,
+        new_bridge: &NewZigbee2MqttBridge<'a>,
+    ) -> Result<Zigbee2MqttBridge, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::insert_into(zigbee2mqtt_bridges::table)
+                .values(new_bridge)
+                .get_result(conn)
+        })
+    }
+
+    pub fn get_zigbee2mqtt_bridge(
+        conn: &mut DbConnection,
+        user_id: i32,
+        id: i32,
+    ) -> Result<Zigbee2MqttBridge, diesel::result::Error> {
+        conn.transaction(|conn| {
+            zigbee2mqtt_bridges::table
+                .filter(zigbee2mqtt_bridges::user_id.eq(user_id))
+                .filter(zigbee2mqtt_bridges::id.eq(id))
+                .first(conn)
+        })
+    }
+
+    pub fn get_zigbee2mqtt_bridges(
+        conn: &mut DbConnection,
+    ) -> Result<Vec<Zigbee2MqttBridge>, diesel::result::Error> {
+        conn.transaction(|conn| zigbee2mqtt_bridges::table.load::<Zigbee2MqttBridge>(conn))
+    }
+
+    pub fn get_zigbee2mqtt_bridges_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<Vec<Zigbee2MqttBridge>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            zigbee2mqtt_bridges::table
+                .filter(zigbee2mqtt_bridges::user_id.eq(user_id))
+                .order(zigbee2mqtt_bridges::id)
+                .load::<Zigbee2MqttBridge>(conn)
+        })
+    }
+
+    pub fn delete(&self, conn: &mut DbConnection) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| diesel::delete(self).execute(conn))
+    }
+
+    pub fn delete_by_user_id(
+        conn: &mut DbConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(
+                zigbee2mqtt_bridges::table.filter(zigbee2mqtt_bridges::user_id.eq(user_id)),
+            )
+            .execute(conn)
+        })
+    }
+}
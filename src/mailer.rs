@@ -0,0 +1,170 @@
+use std::{
+    fs::{create_dir_all, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::config::MailConfig;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[rocket::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<(), String>;
+}
+
+pub type SharedMailer = Arc<dyn Mailer>;
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, String)>,
+        tls: bool,
+        from: &str,
+    ) -> Result<SmtpMailer, String> {
+        let builder = if tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|e| format!("Invalid SMTP relay: {}", e))?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+
+        let mut builder = builder.port(port);
+
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        let from = from
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid sender address: {}", e))?;
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<(), String> {
+        let to = mail
+            .to
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid recipient address: {}", e))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(mail.subject)
+            .body(mail.body)
+            .map_err(|e| format!("Could not build mail: {}", e))?;
+
+        match self.transport.send(message).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Could not send mail: {}", e)),
+        }
+    }
+}
+
+/// Writes mails to a directory (one file per recipient) or to stdout when no
+/// directory is set. Meant for development and tests.
+pub struct FileMailer {
+    directory: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn new(directory: Option<PathBuf>) -> FileMailer {
+        FileMailer { directory }
+    }
+}
+
+/// Keeps only `[A-Za-z0-9@._-]` of the recipient so the address cannot point
+/// outside the mail directory.
+fn file_name(to: &str) -> String {
+    let name: String = to
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '_' | '-'))
+        .collect();
+
+    format!("{}.eml", name.trim_start_matches('.'))
+}
+
+#[rocket::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), String> {
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            mail.to, mail.subject, mail.body
+        );
+
+        let directory = match &self.directory {
+            Some(directory) => directory,
+            None => {
+                println!("{}", content);
+                return Ok(());
+            }
+        };
+
+        if !directory.exists() {
+            if let Err(e) = create_dir_all(directory) {
+                return Err(format!("Could not create mail directory: {}", e));
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(directory.join(file_name(&mail.to)));
+
+        match file {
+            Ok(mut file) => match file.write_all(content.as_bytes()) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("Could not write mail: {}", e)),
+            },
+            Err(e) => Err(format!("Could not open mail file: {}", e)),
+        }
+    }
+}
+
+/// Uses SMTP when `smtp_host` is set, otherwise falls back to a
+/// [`FileMailer`] writing to `directory` (or stdout).
+pub fn create_mailer(config: &MailConfig) -> SharedMailer {
+    if let Some(host) = &config.smtp_host {
+        let credentials = match (&config.smtp_username, &config.smtp_password) {
+            (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+            _ => None,
+        };
+
+        match SmtpMailer::new(
+            host,
+            config.smtp_port,
+            credentials,
+            config.smtp_tls,
+            &config.from,
+        ) {
+            Ok(mailer) => return Arc::new(mailer),
+            Err(e) => eprintln!("Error: {}, falling back to file mailer", e),
+        }
+    }
+
+    Arc::new(FileMailer::new(config.directory.clone()))
+}
@@ -3,34 +3,61 @@
 mod repsonses;
 mod db {
     pub mod connection;
+    pub mod devicealiases;
+    pub mod devicemetadata;
     pub mod huebridges;
+    pub mod lifxdevices;
     pub mod models;
+    pub mod passwordresets;
+    pub mod recoverycodes;
     pub mod schema;
+    pub mod shellydevices;
+    pub mod tasmotadevices;
+    pub mod totpsecrets;
     pub mod users;
     pub mod usersettings;
     pub mod wleditems;
+    pub mod zigbee2mqttbridges;
 }
 
 mod auth {
     pub mod auth;
     pub mod routes;
+    pub mod totp;
 }
 
 mod plugins {
     pub mod assets;
+    pub mod backup;
+    pub mod discovery;
     pub mod hue;
+    pub mod lifx;
     pub mod main;
+    #[cfg(feature = "mqtt")]
+    pub mod homeassistant;
+    #[cfg(feature = "mqtt")]
+    pub mod mqtt;
+    pub mod shelly;
+    pub mod tasmota;
     pub mod user;
+    pub mod zigbee2mqtt;
 }
 
 mod utils {
     pub mod color;
     pub mod extensions;
+    pub mod tls;
+
+    #[cfg(test)]
+    pub mod testing;
 }
 
+mod cli;
+mod config;
 mod cors;
-
-use std::path::Path;
+mod mailer;
+mod ratelimit;
+mod validation;
 
 use auth::auth::JWTToken;
 
@@ -40,20 +67,26 @@ use rocket::tokio::select;
 use rocket::tokio::sync::broadcast::error::RecvError;
 use rocket::tokio::sync::broadcast::{channel, Sender};
 use rocket::{
-    catch, catchers, figment::Figment, fs::relative, fs::FileServer, get, response::Redirect,
-    routes, serde::json::Json, Build, Rocket,
+    catch, catchers, figment::Figment, fs::FileServer, get, response::Redirect,
+    routes, Build, Rocket,
 };
-use rocket::{FromForm, Shutdown, State};
+use rocket::{http::Status, FromForm, Shutdown, State};
 use rocket_okapi::swagger_ui::{make_swagger_ui, SwaggerUIConfig};
 use rocket_okapi::{
     mount_endpoints_and_merged_docs,
     settings::{OpenApiSettings, UrlObject},
 };
-use schemars::_serde_json;
+use schemars::_serde_json::{self, json};
 use schemars::gen::SchemaSettings;
 use serde::{Deserialize, Serialize};
 
-use crate::db::connection;
+use crate::cli::Command;
+use crate::db::connection::{self, DbPool};
+use crate::db::models::HueBridge;
+use crate::config::AppConfig;
+use crate::ratelimit::{RateLimitHeaders, RateLimiter};
+use crate::repsonses::ApiError;
+use crate::validation::ValidationResponse;
 
 #[get("/")]
 fn redirect() -> Redirect {
@@ -78,7 +111,7 @@ pub async fn events(
                 _ = &mut end => break,
             };
 
-            if msg.token.user_id != jwt.user_id {
+            if msg.user_id != jwt.user_id {
                 yield Event::json(&Message {
                     _type: msg._type,
                     data: "".to_owned(),
@@ -91,35 +124,47 @@ pub async fn events(
 }
 
 #[catch(400)]
-fn bad_request() -> Json<&'static str> {
-    Json("Bad Request")
+fn bad_request() -> ApiError {
+    ApiError::from_status(Status::BadRequest)
 }
 
 #[catch(401)]
-fn unauthorized() -> Json<&'static str> {
-    Json("Unauthorized")
+fn unauthorized() -> ApiError {
+    ApiError::from_status(Status::Unauthorized)
 }
 
 #[catch(404)]
-fn not_found() -> Json<&'static str> {
-    Json("Not Found")
+fn not_found() -> ApiError {
+    ApiError::from_status(Status::NotFound)
 }
 
 #[catch(409)]
-fn conflict() -> Json<&'static str> {
-    Json("Conflict")
+fn conflict() -> ApiError {
+    ApiError::from_status(Status::Conflict)
+}
+
+#[catch(422)]
+fn unprocessable_entity(request: &rocket::Request) -> ValidationResponse {
+    ValidationResponse::from_request(request)
+}
+
+#[catch(429)]
+fn too_many_requests() -> ApiError {
+    ApiError::RateLimited
 }
 
 #[catch(500)]
-fn internal_error() -> Json<&'static str> {
-    Json("Internal Server Error")
+fn internal_error() -> ApiError {
+    ApiError::from_status(Status::InternalServerError)
 }
 
-fn configure_rocket() -> Figment {
-    rocket::Config::figment().merge((
-        "secret_key",
-        "ENDM3ymkXOrZHhK1Z2q8bLOaxZr3LTGm540bd6oXGheaX8KmltC+cSwnJ0b9zK7PFiaXPp+zFBF+BPnZF/htXw==",
-    ))
+fn configure_rocket(config: &AppConfig) -> Figment {
+    rocket::Config::figment()
+        .merge(("secret_key", config.rocket_secret_key()))
+        .merge(("address", config.address.clone()))
+        .merge(("port", config.port))
+        // Imports carry the profile picture as base64.
+        .merge(("limits.json", "20 MiB"))
 }
 
 #[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
@@ -133,7 +178,8 @@ pub struct Message {
 pub struct InternalMessage {
     _type: String,
     data: String,
-    token: JWTToken,
+    /// Only this user receives the data, others just the type.
+    user_id: i32,
 }
 
 impl InternalMessage {
@@ -141,14 +187,43 @@ impl InternalMessage {
         InternalMessage {
             _type: "light_update".to_owned(),
             data: _serde_json::to_string(&light).unwrap(),
-            token,
+            user_id: token.user_id,
         }
     }
     pub fn plug_update(plug: NormalizedPlug, token: JWTToken) -> InternalMessage {
         InternalMessage {
             _type: "plug_update".to_owned(),
             data: _serde_json::to_string(&plug).unwrap(),
-            token,
+            user_id: token.user_id,
+        }
+    }
+    pub fn bridge_readdressed(
+        hue_bridge: &HueBridge,
+        previous_ip: &str,
+        user_id: i32,
+    ) -> InternalMessage {
+        InternalMessage {
+            _type: "bridge_readdressed".to_owned(),
+            data: _serde_json::to_string(&json!({
+                "id": hue_bridge.id,
+                "bridgeid": hue_bridge.bridgeid,
+                "ip": hue_bridge.ip,
+                "previous_ip": previous_ip,
+            }))
+            .unwrap(),
+            user_id,
+        }
+    }
+
+    pub fn bridge_deleted(hue_bridge: &HueBridge, user_id: i32) -> InternalMessage {
+        InternalMessage {
+            _type: "bridge_deleted".to_owned(),
+            data: _serde_json::to_string(&json!({
+                "id": hue_bridge.id,
+                "bridgeid": hue_bridge.bridgeid,
+            }))
+            .unwrap(),
+            user_id,
         }
     }
 
@@ -160,18 +235,22 @@ impl InternalMessage {
     }
 }
 
-fn create_server() -> Rocket<Build> {
-    let mut api = rocket::custom(configure_rocket());
+fn create_server(config: &AppConfig, pool: DbPool) -> Rocket<Build> {
+    let mut api = rocket::custom(configure_rocket(config));
 
-    let dist = Path::new(relative!("dist"));
+    let dist = config.static_dir.as_path();
     if dist.exists() {
         api = api.mount("/static", FileServer::from(dist));
     }
 
     api = api
-        .attach(cors::CORS)
-        .manage(connection::establish_connection())
+        .attach(cors::CORS::new(config.cors_origins.clone()))
+        .attach(RateLimitHeaders)
+        .manage(pool)
         .manage(channel::<InternalMessage>(1024).0)
+        .manage(mailer::create_mailer(&config.mail))
+        .manage(RateLimiter::new(config.rate_limit.clone()))
+        .attach(plugins::discovery::fairing())
         .mount("/", routes![redirect, events, cors::all_options])
         .mount(
             "/docs",
@@ -191,6 +270,8 @@ fn create_server() -> Rocket<Build> {
                 unauthorized,
                 not_found,
                 conflict,
+                unprocessable_entity,
+                too_many_requests,
                 internal_error
             ],
         );
@@ -200,13 +281,23 @@ fn create_server() -> Rocket<Build> {
         ..Default::default()
     };
 
+    #[cfg(feature = "mqtt")]
+    {
+        api = api.attach(plugins::mqtt::fairing());
+    }
+
     mount_endpoints_and_merged_docs! {
         api,
         "/".to_owned(),
         openapi_settings,
         "/api" => plugins::main::routes(&openapi_settings),
         "/api/user" => plugins::user::routes(&openapi_settings),
+        "/api/user" => plugins::backup::routes(&openapi_settings),
         "/api/hue" => plugins::hue::routes(&openapi_settings),
+        "/api/shelly" => plugins::shelly::routes(&openapi_settings),
+        "/api/tasmota" => plugins::tasmota::routes(&openapi_settings),
+        "/api/zigbee2mqtt" => plugins::zigbee2mqtt::routes(&openapi_settings),
+        "/api/lifx" => plugins::lifx::routes(&openapi_settings),
         "/api/auth" => auth::routes::routes(&openapi_settings),
     };
 
@@ -217,17 +308,58 @@ fn create_server() -> Rocket<Build> {
 async fn main() {
     dotenv::dotenv().ok();
 
-    if std::env::var("MIGRATE").is_ok() {
-        connection::run_migrations();
-        return;
-    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-    let launch_result = create_server().launch().await;
+    let command = match Command::parse(&args) {
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    match launch_result {
-        Ok(_) => {}
+    let config = match AppConfig::load(&args) {
+        Ok(config) => config::init(config),
         Err(e) => {
             eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Command::ConfigCheck = command {
+        if let Err(e) = cli::check_config(config) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let pool = connection::establish_connection(&config);
+    let serve = matches!(command, Command::Serve);
+
+    let result = match command {
+        Command::Serve => cli::migrate(&pool, false),
+        Command::Migrate { dry_run } => cli::migrate(&pool, dry_run),
+        command => cli::run(command, &pool).await,
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    if serve {
+        let launch_result = create_server(config, pool).launch().await;
+
+        match launch_result {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error: {}", e);
+            }
         }
     }
 }
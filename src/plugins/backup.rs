@@ -0,0 +1,651 @@
+use std::{
+    fs::{create_dir_all, read, write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use diesel::Connection;
+use okapi::openapi3::OpenApi;
+use rocket::{
+    form::{self, ValueField},
+    get, post,
+    serde::json::Json,
+    tokio::sync::broadcast::Sender,
+    State,
+};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    _serde_json::{self, Value},
+    schema_for, JsonSchema,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::auth::JWTToken,
+    db::{
+        connection::{self, DbConnection, DbPool},
+        models::{HueBridge, NewHueBridge, NewWledItem, UpdateUserSettings, User, WledItem},
+    },
+    repsonses::ApiError,
+    validation::{
+        validate_bridge_user, validate_fingerprint, validate_host, validate_label, SchemaValidated,
+        Validate, ValidationErrors,
+    },
+    InternalMessage,
+};
+
+use super::{
+    assets::{get_picture_path, PictureType},
+    hue::remove_bridge,
+};
+
+/// Bumped on incompatible changes to [`ExportDocument`]. Older versions stay
+/// importable.
+static EXPORT_VERSION: u32 = 1;
+static PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Everything needed to set up a user's devices on another server.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ExportDocument {
+    pub version: u32,
+    /// Unix timestamp
+    pub exported_at: i64,
+    pub hue_index: i32,
+    pub hue_bridges: Vec<ExportHueBridge>,
+    pub wled_items: Vec<ExportWledItem>,
+    /// Base64 encoded PNG
+    pub profile_picture: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ExportHueBridge {
+    pub id: String,
+    pub ip: String,
+    /// Username issued by the bridge, so it does not have to be paired again.
+    pub user: String,
+    /// Hardware id of the bridge, missing in older exports.
+    #[serde(default)]
+    pub bridgeid: Option<String>,
+    /// Pinned certificate fingerprint, missing in older exports.
+    #[serde(default)]
+    pub certificate: Option<String>,
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ExportWledItem {
+    pub name: String,
+    pub ip: String,
+}
+
+impl Validate for ExportDocument {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        if self.version == 0 || self.version > EXPORT_VERSION {
+            errors.check(
+                "version",
+                Err(format!(
+                    "Unsupported version, the newest is {}",
+                    EXPORT_VERSION
+                )),
+            );
+        }
+
+        for (index, hue_bridge) in self.hue_bridges.iter().enumerate() {
+            // Ids are handed out from `hue_index`
+            if !hue_bridge.id.parse::<i32>().is_ok_and(|id| id > 0) {
+                errors.check(
+                    &format!("hue_bridges[{}].id", index),
+                    Err("Must be a positive integer".to_owned()),
+                );
+            }
+
+            errors.check(
+                &format!("hue_bridges[{}].ip", index),
+                validate_host(&hue_bridge.ip),
+            );
+
+            errors.check(
+                &format!("hue_bridges[{}].user", index),
+                validate_bridge_user(&hue_bridge.user),
+            );
+
+            if let Some(certificate) = &hue_bridge.certificate {
+                errors.check(
+                    &format!("hue_bridges[{}].certificate", index),
+                    validate_fingerprint(certificate),
+                );
+            }
+        }
+
+        for (index, wled_item) in self.wled_items.iter().enumerate() {
+            errors.check(
+                &format!("wled_items[{}].name", index),
+                validate_label(&wled_item.name),
+            );
+
+            errors.check(
+                &format!("wled_items[{}].ip", index),
+                validate_host(&wled_item.ip),
+            );
+        }
+
+        if let Some(picture) = &self.profile_picture {
+            let result = match BASE64.decode(picture) {
+                Ok(bytes) if bytes.starts_with(PNG_SIGNATURE) => Ok(()),
+                Ok(_) => Err("Must be a PNG image".to_owned()),
+                Err(_) => Err("Must be valid base64".to_owned()),
+            };
+
+            errors.check("profile_picture", result);
+        }
+
+        errors.into_result()
+    }
+}
+
+/// How to handle imported bridges and WLED items that clash with existing ones
+/// by id, name or IP.
+#[derive(Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// Keep the existing entry and drop the imported one.
+    Skip,
+    /// Delete the existing entry and import the new one.
+    Replace,
+    /// Import under a new id or name. IP clashes are skipped, as they point
+    /// to the same device.
+    Rename,
+}
+
+// Implemented by hand so a missing `conflict` defaults to `skip` while an
+// unknown value is still rejected.
+#[rocket::async_trait]
+impl<'v> form::FromFormField<'v> for ConflictStrategy {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        match field.value {
+            "skip" => Ok(ConflictStrategy::Skip),
+            "replace" => Ok(ConflictStrategy::Replace),
+            "rename" => Ok(ConflictStrategy::Rename),
+            _ => Err(form::Error::validation("Must be skip, replace or rename").into()),
+        }
+    }
+
+    fn default() -> Option<Self> {
+        Some(ConflictStrategy::Skip)
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Created,
+    Skipped,
+    Replaced,
+    Renamed,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ImportResult {
+    /// Id of the bridge or name of the WLED item in the document.
+    pub source: String,
+    /// Id or name it was stored under, if imported.
+    pub target: Option<String>,
+    pub action: ImportAction,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ImportResponse {
+    pub hue_bridges: Vec<ImportResult>,
+    pub wled_items: Vec<ImportResult>,
+    pub profile_picture: bool,
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn export_user(
+    connection: &mut DbConnection,
+    user: &User,
+) -> Result<ExportDocument, ApiError> {
+    let usersettings = user.get_usersettings(connection)?;
+
+    let hue_bridges = usersettings
+        .get_huebridges(connection)?
+        .into_iter()
+        .map(|hue_bridge| ExportHueBridge {
+            id: hue_bridge.id,
+            ip: hue_bridge.ip,
+            user: hue_bridge.user,
+            bridgeid: hue_bridge.bridgeid,
+            certificate: hue_bridge.certificate,
+            allow_http: hue_bridge.allow_http,
+        })
+        .collect();
+
+    let wled_items = usersettings
+        .get_wleditems(connection)?
+        .into_iter()
+        .map(|wled_item| ExportWledItem {
+            name: wled_item.name,
+            ip: wled_item.ip,
+        })
+        .collect();
+
+    let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
+
+    let profile_picture = if Path::new(&picture).exists() {
+        let bytes = read(&picture).map_err(|e| ApiError::Internal(e.to_string()))?;
+        Some(BASE64.encode(bytes))
+    } else {
+        None
+    };
+
+    Ok(ExportDocument {
+        version: EXPORT_VERSION,
+        exported_at: unix_now(),
+        hue_index: usersettings.hue_index,
+        hue_bridges,
+        wled_items,
+        profile_picture,
+    })
+}
+
+/// Returns the results and the replaced bridges, whose Home Assistant configs
+/// are cleared once the import is committed.
+fn import_hue_bridges(
+    connection: &mut DbConnection,
+    user: &User,
+    hue_bridges: &[ExportHueBridge],
+    hue_index: i32,
+    conflict: ConflictStrategy,
+) -> Result<(Vec<ImportResult>, Vec<HueBridge>), ApiError> {
+    let usersettings = user.get_usersettings(connection)?;
+
+    // Bridge ids are handed out from `hue_index`, keep it ahead of every
+    // imported id so later bridges do not collide.
+    let max_id = hue_bridges
+        .iter()
+        .filter_map(|hue_bridge| hue_bridge.id.parse::<i32>().ok())
+        .max()
+        .unwrap_or(0);
+
+    let mut next_index = usersettings.hue_index.max(hue_index).max(max_id);
+    let mut results = Vec::new();
+    let mut removed: Vec<HueBridge> = Vec::new();
+
+    for hue_bridge in hue_bridges {
+        let existing = usersettings.get_huebridges(connection)?;
+
+        let same_ip = existing
+            .iter()
+            .find(|existing| existing.ip == hue_bridge.ip);
+        let same_id = existing
+            .iter()
+            .find(|existing| existing.id == hue_bridge.id);
+
+        let (id, action) = match (same_id, same_ip, conflict) {
+            (None, None, _) => (hue_bridge.id.clone(), ImportAction::Created),
+            (_, _, ConflictStrategy::Skip) | (_, Some(_), ConflictStrategy::Rename) => {
+                results.push(ImportResult {
+                    source: hue_bridge.id.clone(),
+                    target: None,
+                    action: ImportAction::Skipped,
+                });
+                continue;
+            }
+            (_, _, ConflictStrategy::Replace) => {
+                for clash in same_id.iter().chain(same_ip.iter()) {
+                    if !removed.iter().any(|removed| removed._id == clash._id) {
+                        remove_bridge(connection, user.id, clash, false)?;
+                        removed.push((*clash).clone());
+                    }
+                }
+                (hue_bridge.id.clone(), ImportAction::Replaced)
+            }
+            (Some(_), None, ConflictStrategy::Rename) => {
+                next_index += 1;
+                (next_index.to_string(), ImportAction::Renamed)
+            }
+        };
+
+        HueBridge::create_huebridge(
+            connection,
+            &NewHueBridge {
+                id: &id,
+                ip: &hue_bridge.ip,
+                user: &hue_bridge.user,
+                user_settings_id: &usersettings.id,
+                bridgeid: hue_bridge.bridgeid.as_deref(),
+                certificate: hue_bridge.certificate.as_deref(),
+                allow_http: hue_bridge.allow_http,
+            },
+        )?;
+
+        results.push(ImportResult {
+            source: hue_bridge.id.clone(),
+            target: Some(id),
+            action,
+        });
+    }
+
+    if next_index != usersettings.hue_index {
+        usersettings.update(
+            connection,
+            &UpdateUserSettings {
+                hue_index: Some(&next_index),
+                user_id: None,
+            },
+        )?;
+    }
+
+    Ok((results, removed))
+}
+
+fn import_wled_items(
+    connection: &mut DbConnection,
+    user: &User,
+    wled_items: &[ExportWledItem],
+    conflict: ConflictStrategy,
+) -> Result<Vec<ImportResult>, ApiError> {
+    let usersettings = user.get_usersettings(connection)?;
+    let mut results = Vec::new();
+
+    for wled_item in wled_items {
+        let existing = usersettings.get_wleditems(connection)?;
+
+        let same_ip = existing.iter().find(|existing| existing.ip == wled_item.ip);
+        let same_name = existing
+            .iter()
+            .find(|existing| existing.name == wled_item.name);
+
+        let (name, action) = match (same_name, same_ip, conflict) {
+            (None, None, _) => (wled_item.name.clone(), ImportAction::Created),
+            (_, _, ConflictStrategy::Skip) | (_, Some(_), ConflictStrategy::Rename) => {
+                results.push(ImportResult {
+                    source: wled_item.name.clone(),
+                    target: None,
+                    action: ImportAction::Skipped,
+                });
+                continue;
+            }
+            (_, _, ConflictStrategy::Replace) => {
+                for clash in same_name.iter().chain(same_ip.iter()) {
+                    clash.delete(connection)?;
+                }
+                (wled_item.name.clone(), ImportAction::Replaced)
+            }
+            (Some(_), None, ConflictStrategy::Rename) => {
+                let name = (2..)
+                    .map(|suffix| format!("{} ({})", wled_item.name, suffix))
+                    .find(|name| !existing.iter().any(|existing| &existing.name == name))
+                    .unwrap_or_default();
+                (name, ImportAction::Renamed)
+            }
+        };
+
+        WledItem::create_wleditem(
+            connection,
+            &NewWledItem {
+                name: &name,
+                ip: &wled_item.ip,
+                user_settings_id: &usersettings.id,
+            },
+        )?;
+
+        results.push(ImportResult {
+            source: wled_item.name.clone(),
+            target: Some(name),
+            action,
+        });
+    }
+
+    Ok(results)
+}
+
+/// Exports the user's bridges (including their usernames), WLED items and
+/// profile picture.
+#[openapi(tag = "User")]
+#[get("/export")]
+pub async fn export(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+) -> Result<Json<ExportDocument>, ApiError> {
+    let document = connection::run(pool, move |connection| {
+        let user = jwt.get_user(connection)?;
+
+        export_user(connection, &user)
+    })
+    .await?;
+
+    Ok(Json(document))
+}
+
+/// JSON schema of the document produced by `/export` and accepted by
+/// `/import`.
+#[openapi(tag = "User")]
+#[get("/export/schema")]
+pub fn export_schema() -> Result<Json<Value>, ApiError> {
+    _serde_json::to_value(schema_for!(ExportDocument))
+        .map(Json)
+        .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+/// Restores a document produced by `/export`, checked against the schema of
+/// `/export/schema`. Conflicting bridges and WLED items are skipped unless
+/// `conflict` says otherwise. Either everything is imported or nothing.
+#[openapi(tag = "User")]
+#[post("/import?<conflict>", format = "json", data = "<document>")]
+pub async fn import(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    conflict: ConflictStrategy,
+    document: SchemaValidated<ExportDocument>,
+    queue: &State<Sender<InternalMessage>>,
+) -> Result<Json<ImportResponse>, ApiError> {
+    let document = document.into_inner();
+    let user_id = jwt.user_id;
+
+    let (response, removed) = connection::run(pool, move |connection| {
+        let user = jwt.get_user(connection)?;
+
+        let ((hue_bridges, removed), wled_items) =
+            connection.transaction::<_, ApiError, _>(|connection| {
+                let hue_bridges = import_hue_bridges(
+                    connection,
+                    &user,
+                    &document.hue_bridges,
+                    document.hue_index,
+                    conflict,
+                )?;
+
+                let wled_items =
+                    import_wled_items(connection, &user, &document.wled_items, conflict)?;
+
+                Ok((hue_bridges, wled_items))
+            })?;
+
+        let profile_picture = import_profile_picture(&user, &document, conflict)?;
+
+        let response = ImportResponse {
+            hue_bridges,
+            wled_items,
+            profile_picture,
+        };
+
+        Ok((response, removed))
+    })
+    .await?;
+
+    for hue_bridge in &removed {
+        let _ = queue.send(InternalMessage::bridge_deleted(hue_bridge, user_id));
+    }
+
+    Ok(Json(response))
+}
+
+/// Writes the imported picture unless one exists and conflicts are skipped.
+fn import_profile_picture(
+    user: &User,
+    document: &ExportDocument,
+    conflict: ConflictStrategy,
+) -> Result<bool, ApiError> {
+    let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
+
+    match &document.profile_picture {
+        Some(encoded) if conflict != ConflictStrategy::Skip || !Path::new(&picture).exists() => {
+            let bytes = BASE64
+                .decode(encoded)
+                .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+            if let Some(parent) = Path::new(&picture).parent() {
+                create_dir_all(parent).map_err(|e| ApiError::Internal(e.to_string()))?;
+            }
+
+            write(&picture, bytes).map_err(|e| ApiError::Internal(e.to_string()))?;
+
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: export, export_schema, import]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(
+        hue_bridges: Vec<ExportHueBridge>,
+        wled_items: Vec<ExportWledItem>,
+    ) -> ExportDocument {
+        ExportDocument {
+            version: EXPORT_VERSION,
+            exported_at: 0,
+            hue_index: 1,
+            hue_bridges,
+            wled_items,
+            profile_picture: None,
+        }
+    }
+
+    fn hue_bridge(id: &str, ip: &str, user: &str) -> ExportHueBridge {
+        ExportHueBridge {
+            id: id.to_owned(),
+            ip: ip.to_owned(),
+            user: user.to_owned(),
+            bridgeid: None,
+            certificate: None,
+            allow_http: false,
+        }
+    }
+
+    #[test]
+    fn validates_documents() {
+        let valid = document(
+            vec![hue_bridge(
+                "1",
+                "192.168.1.10",
+                "1028d66426293e821ecfd9ef1a0731df",
+            )],
+            vec![ExportWledItem {
+                name: "Desk".to_owned(),
+                ip: "192.168.1.20".to_owned(),
+            }],
+        );
+        assert!(valid.validate().is_ok());
+
+        let invalid = document(
+            vec![
+                hue_bridge("0", "192.168.1.10", ""),
+                hue_bridge("abc", "192.168.1.11", ""),
+                hue_bridge("2", "192.168.1.12", "user/../config"),
+            ],
+            vec![ExportWledItem {
+                name: " ".to_owned(),
+                ip: "192.168.1.20".to_owned(),
+            }],
+        );
+
+        let fields: Vec<String> = invalid
+            .validate()
+            .unwrap_err()
+            .0
+            .into_iter()
+            .map(|error| error.field)
+            .collect();
+
+        assert_eq!(
+            fields,
+            [
+                "hue_bridges[0].id",
+                "hue_bridges[1].id",
+                "hue_bridges[2].user",
+                "wled_items[0].name"
+            ]
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn reports_replaced_bridges_once() {
+        use crate::{
+            db::{
+                connection::{get_connection, run_migrations},
+                models::NewUser,
+            },
+            utils::testing::memory_pool,
+        };
+
+        let pool = memory_pool();
+        run_migrations(&pool).unwrap();
+
+        let connection = &mut get_connection(&pool).unwrap();
+
+        let user = User::create_user(
+            connection,
+            &NewUser {
+                username: "erin",
+                email: "erin@example.com",
+                hashed_password: "hash",
+            },
+        )
+        .unwrap();
+
+        let imported = [
+            hue_bridge("1", "192.168.1.10", ""),
+            hue_bridge("2", "192.168.1.11", ""),
+        ];
+
+        import_hue_bridges(connection, &user, &imported, 2, ConflictStrategy::Skip).unwrap();
+
+        // Clashes with the first bridge by id and IP, the second by IP
+        let imported = [
+            hue_bridge("1", "192.168.1.10", ""),
+            hue_bridge("3", "192.168.1.11", ""),
+        ];
+
+        let (results, removed) =
+            import_hue_bridges(connection, &user, &imported, 3, ConflictStrategy::Replace).unwrap();
+
+        assert!(results
+            .iter()
+            .all(|result| matches!(result.action, ImportAction::Replaced)));
+
+        let removed: Vec<&str> = removed
+            .iter()
+            .map(|hue_bridge| hue_bridge.id.as_str())
+            .collect();
+        assert_eq!(removed, ["1", "2"]);
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use hyper::Method;
+use rocket::{
+    fairing::AdHoc,
+    tokio::{
+        self,
+        net::UdpSocket,
+        select,
+        sync::broadcast::Sender,
+        time::{sleep, timeout},
+    },
+    Shutdown,
+};
+use schemars::_serde_json::{self, Value};
+
+use crate::{
+    config::app_config,
+    db::{
+        connection::{self, DbPool},
+        models::{HueBridge, UpdateHueBridge, UserSettings},
+    },
+    repsonses::ApiError,
+    utils::tls::{self, Transport},
+    InternalMessage,
+};
+
+use super::hue;
+
+static SSDP_ADDRESS: &str = "239.255.255.250:1900";
+static SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+/// Per address while scanning the subnet.
+static SCAN_TIMEOUT: Duration = Duration::from_millis(1500);
+/// A bridge that could not be found is searched again after this long.
+static RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+static LAST_SEARCH: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+
+/// Starts the background task once the server is running. Every
+/// `poll_interval` seconds it looks for bridges that keep failing, searches
+/// the LAN for their bridgeid and stores the new address.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Bridge rediscovery", |rocket| {
+        Box::pin(async move {
+            let pool = rocket.state::<DbPool>().cloned();
+            let queue = rocket.state::<Sender<InternalMessage>>().cloned();
+
+            if let (Some(pool), Some(queue)) = (pool, queue) {
+                tokio::spawn(run(pool, queue, rocket.shutdown()));
+            }
+        })
+    })
+}
+
+async fn run(pool: DbPool, queue: Sender<InternalMessage>, mut shutdown: Shutdown) {
+    let interval = Duration::from_secs(app_config().poll_interval);
+
+    loop {
+        select! {
+            _ = sleep(interval) => {},
+            _ = &mut shutdown => break,
+        }
+
+        if let Err(e) = rediscover_bridges(&pool, &queue).await {
+            println!("Error rediscovering bridges: {}", e);
+        }
+    }
+}
+
+/// Rows of all users that point to the same failing bridge are updated
+/// together, each owner gets a `bridge_readdressed` event.
+async fn rediscover_bridges(
+    pool: &DbPool,
+    queue: &Sender<InternalMessage>,
+) -> Result<(), ApiError> {
+    let hue_bridges =
+        connection::run(pool, |connection| Ok(HueBridge::get_huebridges(connection)?)).await?;
+
+    let mut lost: HashMap<(String, String), Vec<HueBridge>> = HashMap::new();
+
+    for hue_bridge in hue_bridges {
+        if !hue::needs_rediscovery(&hue_bridge) {
+            continue;
+        }
+
+        if let Some(bridgeid) = hue_bridge.bridgeid.clone() {
+            lost.entry((bridgeid, hue_bridge.ip.clone()))
+                .or_default()
+                .push(hue_bridge);
+        }
+    }
+
+    for ((bridgeid, previous_ip), hue_bridges) in lost {
+        if !should_search(&bridgeid) {
+            continue;
+        }
+
+        let (address, hue_bridges) = match find_bridge(&bridgeid, &previous_ip, hue_bridges).await {
+            Some(found) => found,
+            None => {
+                println!("Bridge {} not found on the network", bridgeid);
+                continue;
+            }
+        };
+
+        println!("Bridge {} moved from {} to {}", bridgeid, previous_ip, address);
+
+        let updated = connection::run(pool, move |connection| {
+            let owners: HashMap<i32, i32> = UserSettings::get_usersettings(connection)?
+                .iter()
+                .map(|usersettings| (usersettings.id, usersettings.user_id))
+                .collect();
+
+            let mut updated = Vec::new();
+
+            for hue_bridge in hue_bridges {
+                let hue_bridge = hue_bridge.update(
+                    connection,
+                    &UpdateHueBridge {
+                        id: None,
+                        ip: Some(&address),
+                        user: None,
+                        user_settings_id: None,
+                        bridgeid: None,
+                        certificate: None,
+                        allow_http: None,
+                    },
+                )?;
+
+                if let Some(user_id) = owners.get(&hue_bridge.user_settings_id) {
+                    updated.push((hue_bridge, *user_id));
+                }
+            }
+
+            Ok(updated)
+        })
+        .await?;
+
+        for (hue_bridge, user_id) in updated {
+            let _ = queue.send(InternalMessage::bridge_readdressed(
+                &hue_bridge,
+                &previous_ip,
+                user_id,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn should_search(bridgeid: &str) -> bool {
+    let mut last_search = LAST_SEARCH
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+
+    match last_search.get(bridgeid) {
+        Some(searched) if searched.elapsed() < RETRY_INTERVAL => false,
+        _ => {
+            last_search.insert(bridgeid.to_owned(), Instant::now());
+            true
+        }
+    }
+}
+
+/// Asks via SSDP first and falls back to scanning the /24 of the previous
+/// address. Returns the first address that answers with the bridgeid and
+/// passes `verify_address` for any of the rows, together with those rows.
+async fn find_bridge(
+    bridgeid: &str,
+    previous_ip: &str,
+    hue_bridges: Vec<HueBridge>,
+) -> Option<(String, Vec<HueBridge>)> {
+    let mut candidates = Vec::new();
+
+    if let Some(address) = ssdp_search(bridgeid).await {
+        if address != previous_ip && read_bridgeid(&address).await.as_deref() == Some(bridgeid) {
+            candidates.push(address);
+        }
+    }
+
+    if candidates.is_empty() {
+        candidates = scan_subnet(bridgeid, previous_ip).await;
+    }
+
+    for address in candidates {
+        let verified = join_all(
+            hue_bridges
+                .iter()
+                .map(|hue_bridge| verify_address(hue_bridge, &address)),
+        )
+        .await;
+
+        let hue_bridges: Vec<HueBridge> = hue_bridges
+            .iter()
+            .zip(verified)
+            .filter(|(_, verified)| *verified)
+            .map(|(hue_bridge, _)| hue_bridge.clone())
+            .collect();
+
+        if !hue_bridges.is_empty() {
+            return Some((address, hue_bridges));
+        }
+    }
+
+    None
+}
+
+/// The bridge answers unauthenticated requests to `/api/config` with its
+/// bridgeid. Anyone can claim a bridgeid, so this only finds candidates.
+async fn read_bridgeid(address: &str) -> Option<String> {
+    let response = tls::connect(address, Transport::Http, SCAN_TIMEOUT)
+        .await
+        .ok()?
+        .send(Method::GET, "/api/config", None, SCAN_TIMEOUT)
+        .await
+        .ok()?;
+
+    let config: Value = _serde_json::from_str(&response.body).ok()?;
+
+    config
+        .get("bridgeid")
+        .and_then(|bridgeid| bridgeid.as_str())
+        .map(|bridgeid| bridgeid.to_lowercase())
+}
+
+/// Whether `address` is the bridge: it has to present the pinned certificate
+/// or, if none is pinned yet, one issued to the bridgeid and accept the
+/// bridge's username. The username is only ever sent via HTTPS.
+async fn verify_address(hue_bridge: &HueBridge, address: &str) -> bool {
+    let bridgeid = match &hue_bridge.bridgeid {
+        Some(bridgeid) => bridgeid,
+        None => return false,
+    };
+
+    let pin = hue_bridge.certificate.as_deref();
+
+    let mut connection = match tls::connect(address, Transport::Https { pin }, SCAN_TIMEOUT).await {
+        Ok(connection) => connection,
+        Err(_) => return false,
+    };
+
+    if pin.is_some() {
+        return true;
+    }
+
+    let issued_to_bridge = connection
+        .common_name
+        .as_deref()
+        .is_some_and(|common_name| common_name.eq_ignore_ascii_case(bridgeid));
+
+    if !issued_to_bridge || hue_bridge.user.is_empty() {
+        return false;
+    }
+
+    let path = format!("/api/{}/config", hue_bridge.user);
+
+    let response = match connection
+        .send(Method::GET, &path, None, SCAN_TIMEOUT)
+        .await
+    {
+        Ok(response) if response.status == 200 => response,
+        _ => return false,
+    };
+
+    // Without a valid username the bridge answers with the public subset of
+    // its config, which has no whitelist.
+    _serde_json::from_str::<Value>(&response.body).is_ok_and(|config| {
+        config.get("whitelist").is_some()
+            && config
+                .get("bridgeid")
+                .and_then(|found| found.as_str())
+                .is_some_and(|found| found.eq_ignore_ascii_case(bridgeid))
+    })
+}
+
+/// Hue bridges include a `hue-bridgeid` header in their SSDP answers.
+async fn ssdp_search(bridgeid: &str) -> Option<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: ssdp:all\r\n\r\n",
+        SSDP_ADDRESS
+    );
+    socket.send_to(request.as_bytes(), SSDP_ADDRESS).await.ok()?;
+
+    let deadline = Instant::now() + SSDP_TIMEOUT;
+    let mut buffer = [0u8; 2048];
+
+    loop {
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+
+        let (length, from) = match timeout(remaining, socket.recv_from(&mut buffer)).await {
+            Ok(Ok(received)) => received,
+            _ => return None,
+        };
+
+        let response = String::from_utf8_lossy(&buffer[..length]);
+
+        let matches = response.lines().any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.trim().eq_ignore_ascii_case("hue-bridgeid")
+                    && value.trim().eq_ignore_ascii_case(bridgeid)
+            })
+        });
+
+        if matches {
+            return Some(from.ip().to_string());
+        }
+    }
+}
+
+/// Only works for IPv4 addresses. A port of the previous address is kept.
+async fn scan_subnet(bridgeid: &str, previous_ip: &str) -> Vec<String> {
+    let (host, port) = match previous_ip.split_once(':') {
+        Some((host, port)) => (host, format!(":{}", port)),
+        None => (previous_ip, String::new()),
+    };
+
+    let [a, b, c, d] = match host.parse::<Ipv4Addr>() {
+        Ok(address) => address.octets(),
+        Err(_) => return Vec::new(),
+    };
+
+    let candidates = (1..=254)
+        .filter(|host| *host != d)
+        .map(|host| format!("{}.{}.{}.{}{}", a, b, c, host, port));
+
+    let found = join_all(candidates.map(|address| async move {
+        match read_bridgeid(&address).await {
+            Some(found) if found == bridgeid => Some(address),
+            _ => None,
+        }
+    }))
+    .await;
+
+    found.into_iter().flatten().collect()
+}
@@ -0,0 +1,169 @@
+use schemars::_serde_json::{json, Value};
+
+use crate::config::app_config;
+
+use super::main::{NormalizedLight, NormalizedPlug};
+
+static PAYLOAD_ON: &str = r#"{"on":true}"#;
+static PAYLOAD_OFF: &str = r#"{"on":false}"#;
+static STATE_TEMPLATE: &str = "{{ 'ON' if value_json.on else 'OFF' }}";
+
+/// Home Assistant only accepts `[a-zA-Z0-9_-]` in discovery topics.
+fn object_id(device_id: &str) -> String {
+    device_id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+pub fn state_topic(user_id: i32, device_id: &str) -> String {
+    format!("{}/{}/{}/state", app_config().mqtt.topic_prefix, user_id, device_id)
+}
+
+fn command_topic(user_id: i32, device_id: &str) -> String {
+    format!("{}/{}/{}/set", app_config().mqtt.topic_prefix, user_id, device_id)
+}
+
+fn config_topic(component: &str, device_id: &str) -> String {
+    format!(
+        "{}/{}/{}/config",
+        app_config().mqtt.discovery_prefix,
+        component,
+        object_id(device_id)
+    )
+}
+
+/// Shared by lights and switches. The state topic carries the same JSON as
+/// `/api/lights` and `/api/plugs`, commands take `LightState`/`PlugState`.
+fn base_config(
+    user_id: i32,
+    id: &str,
+    name: &str,
+    uniqueid: &str,
+    manufacturer: &str,
+    model: &str,
+    swversion: &str,
+) -> Value {
+    json!({
+        "name": name,
+        "unique_id": uniqueid,
+        "state_topic": state_topic(user_id, id),
+        "command_topic": command_topic(user_id, id),
+        "payload_on": PAYLOAD_ON,
+        "payload_off": PAYLOAD_OFF,
+        "device": {
+            "identifiers": [uniqueid],
+            "name": name,
+            "manufacturer": manufacturer,
+            "model": model,
+            "sw_version": swversion,
+        },
+        "origin": {
+            "name": "home-api",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+/// `homeassistant/light/<id>/config` for the default light schema. Lights
+/// that report a color get `rgb`, those with a color temperature
+/// `color_temp`, all others only `brightness`.
+pub fn light_config(user_id: i32, light: &NormalizedLight) -> (String, Value) {
+    let mut config = base_config(
+        user_id,
+        &light.id,
+        &light.name,
+        &light.uniqueid,
+        &light.manufacturer,
+        &light.model,
+        &light.swversion,
+    );
+
+    let state_topic = state_topic(user_id, &light.id);
+    let command_topic = command_topic(user_id, &light.id);
+
+    let mut color_modes = Vec::new();
+
+    config["state_value_template"] = json!(STATE_TEMPLATE);
+    config["brightness_state_topic"] = json!(state_topic);
+    config["brightness_command_topic"] = json!(command_topic);
+    config["brightness_scale"] = json!(255);
+    config["brightness_value_template"] =
+        json!("{{ (value_json.brightness * 255) | round(0) | int }}");
+    config["brightness_command_template"] = json!(r#"{"brigthness": {{ value }}}"#);
+
+    if light.color_temperature.is_some() {
+        color_modes.push("color_temp");
+
+        config["color_temp_state_topic"] = json!(state_topic);
+        config["color_temp_command_topic"] = json!(command_topic);
+        config["color_temp_value_template"] = json!("{{ value_json.color_temperature }}");
+        config["color_temp_command_template"] = json!(r#"{"color_temperature": {{ value }}}"#);
+    }
+
+    if !light.color.is_empty() {
+        color_modes.push("rgb");
+
+        config["rgb_state_topic"] = json!(state_topic);
+        config["rgb_command_topic"] = json!(command_topic);
+        config["rgb_value_template"] = json!("{{ value_json.color[0] | join(',') }}");
+        config["rgb_command_template"] =
+            json!(r#"{"color": [[{{ red }}, {{ green }}, {{ blue }}]]}"#);
+    }
+
+    if color_modes.is_empty() {
+        color_modes.push("brightness");
+    }
+
+    config["supported_color_modes"] = json!(color_modes);
+
+    (config_topic("light", &light.id), config)
+}
+
+/// `homeassistant/switch/<id>/config`.
+pub fn switch_config(user_id: i32, plug: &NormalizedPlug) -> (String, Value) {
+    let mut config = base_config(
+        user_id,
+        &plug.id,
+        &plug.name,
+        &plug.uniqueid,
+        &plug.manufacturer,
+        &plug.model,
+        &plug.swversion,
+    );
+
+    config["value_template"] = json!(STATE_TEMPLATE);
+    config["state_on"] = json!("ON");
+    config["state_off"] = json!("OFF");
+
+    (config_topic("switch", &plug.id), config)
+}
+
+/// Subscriptions that return the retained discovery configs, including
+/// those published before a restart.
+pub fn config_subscriptions() -> [String; 2] {
+    let prefix = &app_config().mqtt.discovery_prefix;
+
+    [
+        format!("{}/light/+/config", prefix),
+        format!("{}/switch/+/config", prefix),
+    ]
+}
+
+/// The user and device of a config published by this server, `None` for
+/// configs of other integrations.
+pub fn config_owner(config: &Value) -> Option<(i32, String)> {
+    let rest = config
+        .get("state_topic")?
+        .as_str()?
+        .strip_prefix(app_config().mqtt.topic_prefix.as_str())?
+        .strip_prefix('/')?
+        .strip_suffix("/state")?;
+
+    let (user_id, device_id) = rest.split_once('/')?;
+
+    Some((user_id.parse().ok()?, device_id.to_owned()))
+}
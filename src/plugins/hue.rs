@@ -1,936 +1,1545 @@
-use ::serde::{Deserialize, Serialize};
-use okapi::openapi3::OpenApi;
-use rocket::{
-    delete, get,
-    http::Status,
-    put,
-    serde::{self, json::Json},
-    State,
-};
-use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
-use schemars::{
-    JsonSchema,
-    _serde_json::{self, json, Value},
-};
-
-use crate::{
-    auth::auth::JWTToken,
-    db::{
-        connection::{self, SqlitePool, SqlitePooledConnection},
-        models::{HueBridge, NewHueBridge, UpdateHueBridge, UpdateUserSettings, User},
-    },
-    repsonses::CustomResponse,
-    utils::color::{hsb_to_hsv, hsv_to_hsb, hsv_to_rgb, rgb_to_hsv},
-};
-
-use super::main::{LightState, NormalizedColor, NormalizedLight, NormalizedPlug, PlugState};
-use crate::utils::extensions::ValueExt;
-
-static mut STATIC_CLIENT: Option<reqwest::Client> = None;
-
-fn client() -> reqwest::Client {
-    unsafe {
-        if STATIC_CLIENT.is_none() {
-            STATIC_CLIENT = Some(reqwest::Client::new());
-        }
-
-        STATIC_CLIENT.clone().unwrap()
-    }
-}
-
-fn connection_from_pool(pool: &State<SqlitePool>) -> SqlitePooledConnection {
-    connection::get_connection(&pool).unwrap()
-}
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct HueScene {
-    pub id: String,
-    pub name: String,
-    #[serde(rename = "type")]
-    pub type_: String,
-    pub group: String,
-    pub lights: Vec<String>,
-    pub owner: String,
-    pub recycle: bool,
-    pub locked: bool,
-    pub appdata: HueAppData,
-    pub picture: String,
-    pub lastupdated: String,
-    pub version: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct HueAppData {
-    pub version: i32,
-    pub data: Value,
-}
-
-async fn get_hue_json(hue_bridge: &HueBridge, mut path: String) -> Result<String, CustomResponse> {
-    if path.starts_with('/') {
-        path.remove(0);
-    }
-    let res = client()
-        .get(&format!(
-            "http://{}/api/{}/{}",
-            hue_bridge.ip, hue_bridge.user, path
-        ))
-        .send()
-        .await;
-
-    if res.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to connect to Hue Bridge".to_owned(),
-        });
-    }
-
-    let res = res.unwrap().text().await;
-
-    if res.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to connect to Hue Bridge".to_owned(),
-        });
-    }
-
-    Ok(res.unwrap())
-}
-
-async fn post_hue_json(
-    hue_bridge: &HueBridge,
-    mut path: String,
-    body: String,
-) -> Result<String, CustomResponse> {
-    if path.starts_with('/') {
-        path.remove(0);
-    }
-    let res = client()
-        .post(&format!(
-            "http://{}/api/{}/{}",
-            hue_bridge.ip, hue_bridge.user, path
-        ))
-        .body(body)
-        .send()
-        .await;
-
-    if res.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to connect to Hue Bridge".to_owned(),
-        });
-    }
-
-    let res = res.unwrap().text().await;
-
-    if res.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to connect to Hue Bridge".to_owned(),
-        });
-    }
-
-    Ok(res.unwrap())
-}
-
-async fn put_hue_json(
-    hue_bridge: &HueBridge,
-    mut path: String,
-    body: String,
-) -> Result<String, CustomResponse> {
-    if path.starts_with('/') {
-        path.remove(0);
-    }
-    let res = client()
-        .put(&format!(
-            "http://{}/api/{}/{}",
-            hue_bridge.ip, hue_bridge.user, path
-        ))
-        .body(body)
-        .send()
-        .await;
-
-    if res.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to connect to Hue Bridge".to_owned(),
-        });
-    }
-
-    let res = res.unwrap().text().await;
-
-    if res.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to connect to Hue Bridge".to_owned(),
-        });
-    }
-
-    Ok(res.unwrap())
-}
-
-#[derive(Serialize, Deserialize, JsonSchema)]
-struct HueLightState {
-    on: Option<bool>,
-    sat: Option<u8>,
-    bri: Option<u8>,
-    hue: Option<u16>,
-}
-
-pub async fn set_light(
-    hue_bridge: &HueBridge,
-    light_id: String,
-    light_state: LightState,
-) -> Result<Status, CustomResponse> {
-    if hue_bridge.user.is_empty() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Hue Bridge not configured".to_owned(),
-        });
-    }
-
-    let brigthness = light_state.brigthness;
-    let color = light_state.color;
-
-    if light_state.on.is_none() && brigthness.is_none() && color.is_none() {
-        return Ok(Status::Ok);
-    }
-
-    let mut hsb: Option<(u16, u8, u8)> = None;
-
-    if color.is_some() {
-        let color = color.unwrap();
-        if color.len() > 0 {
-            let hsv = rgb_to_hsv(
-                color.get(0).unwrap().0,
-                color.get(0).unwrap().1,
-                color.get(0).unwrap().2,
-            );
-
-            hsb = Some(hsv_to_hsb(hsv.0, hsv.1, hsv.2));
-        }
-    }
-
-    let mut body = HueLightState {
-        on: light_state.on,
-        hue: None,
-        sat: None,
-        bri: None,
-    };
-
-    if hsb.is_some() {
-        body.hue = Some(hsb.unwrap().0);
-        body.sat = Some(hsb.unwrap().1);
-        body.bri = Some(hsb.unwrap().2);
-    }
-
-    let response = put_hue_json(
-        hue_bridge,
-        format!("lights/{}/state", light_id),
-        _serde_json::ser::to_string(&body).unwrap(),
-    )
-    .await;
-
-    if response.is_err() {
-        return Err(response.unwrap_err());
-    }
-
-    Ok(Status::Ok)
-}
-
-pub async fn get_lights(hue_bridge: &HueBridge) -> Vec<NormalizedLight> {
-    if hue_bridge.user.is_empty() {
-        return Vec::new();
-    }
-    let response = get_hue_json(hue_bridge, "lights".to_owned()).await;
-
-    if response.is_err() {
-        return Vec::new();
-    }
-
-    let response = response.unwrap();
-
-    let json: Value = _serde_json::de::from_str(&response).unwrap();
-
-    let json = json.as_object().unwrap();
-
-    let mut lights = Vec::new();
-
-    for (id, light_json) in json.iter() {
-        let light = light_json.as_object().unwrap();
-        let state = light.get("state").unwrap().as_object().unwrap();
-
-        if state.contains_key("colormode") {
-            let hsv = hsb_to_hsv(
-                state.get("hue").to_f64() as f32,
-                state.get("sat").to_f64() as f32,
-                state.get("bri").to_f64() as f32,
-            );
-            let rgb = hsv_to_rgb(hsv.0, hsv.1, hsv.2);
-
-            lights.push(NormalizedLight {
-                id: format!("hue-{}-{}", hue_bridge.id, id),
-                name: light.get("name").to_string(),
-                on: state.get("on").to_bool(),
-                brightness: (state.get("bri").to_f64() / 255.0) as f32,
-                color: vec![NormalizedColor(rgb.0, rgb.1, rgb.2)],
-                reachable: state.get("reachable").to_bool(),
-                type_: light.get("type").to_string(),
-                model: light.get("modelid").to_string(),
-                manufacturer: light.get("manufacturername").to_string(),
-                uniqueid: light.get("uniqueid").to_string(),
-                swversion: light.get("swversion").to_string(),
-                productid: Some(light.get("productid").to_string()),
-            });
-        }
-    }
-
-    lights
-}
-
-pub async fn get_light(
-    hue_bridge: &HueBridge,
-    light_id: &String,
-) -> Result<NormalizedLight, CustomResponse> {
-    let light_id = light_id.to_owned();
-    if hue_bridge.user.is_empty() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Hue Bridge not configured".to_owned(),
-        });
-    }
-
-    let response = get_hue_json(hue_bridge, format!("lights/{}", light_id.to_owned())).await;
-
-    if response.is_err() {
-        return Err(response.unwrap_err());
-    }
-
-    let response = response.unwrap();
-
-    let json: Value = _serde_json::de::from_str(&response).unwrap();
-
-    let json = json.as_object();
-
-    if json.is_none() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to parse Hue Bridge response".to_owned(),
-        });
-    }
-
-    let json = json.unwrap();
-
-    let light = json.get("state").unwrap().as_object().unwrap();
-
-    if !light.contains_key("colormode") {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Light is not a color light".to_owned(),
-        });
-    }
-
-    if !light.contains_key("reachable") || !light.get("reachable").to_bool() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Light is not reachable".to_owned(),
-        });
-    }
-
-    let hsv = hsb_to_hsv(
-        light.get("hue").to_f64() as f32,
-        light.get("sat").to_f64() as f32,
-        light.get("bri").to_f64() as f32,
-    );
-
-    let rgb = hsv_to_rgb(hsv.0, hsv.1, hsv.2);
-
-    Ok(NormalizedLight {
-        id: format!("hue-{}-{}", hue_bridge.id, light_id),
-        name: json.get("name").to_string(),
-        on: light.get("on").to_bool(),
-        brightness: (light.get("bri").to_f64() / 255.0) as f32,
-        color: vec![NormalizedColor(rgb.0, rgb.1, rgb.2)],
-        reachable: light.get("reachable").to_bool(),
-        type_: json.get("type").to_string(),
-        model: json.get("modelid").to_string(),
-        manufacturer: json.get("manufacturername").to_string(),
-        uniqueid: json.get("uniqueid").to_string(),
-        swversion: json.get("swversion").to_string(),
-        productid: Some(json.get("productid").to_string()),
-    })
-}
-
-pub async fn set_plug(
-    hue_bridge: &HueBridge,
-    plug_id: String,
-    plug_state: PlugState,
-) -> Result<Status, CustomResponse> {
-    if plug_state.on.is_none() {
-        return Ok(Status::Ok);
-    }
-
-    if hue_bridge.user.is_empty() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Hue Bridge not configured".to_owned(),
-        });
-    }
-
-    let response = put_hue_json(
-        hue_bridge,
-        format!("lights/{}/state", plug_id),
-        _serde_json::ser::to_string(&plug_state).unwrap(),
-    )
-    .await;
-
-    if response.is_err() {
-        return Err(response.unwrap_err());
-    }
-
-    Ok(Status::Ok)
-}
-
-pub async fn get_plugs(hue_bridge: &HueBridge) -> Vec<NormalizedPlug> {
-    if hue_bridge.user.is_empty() {
-        return Vec::new();
-    }
-    let response = get_hue_json(hue_bridge, "lights".to_owned()).await;
-
-    if response.is_err() {
-        return Vec::new();
-    }
-
-    let response = response.unwrap();
-
-    let json: Value = _serde_json::de::from_str(&response).unwrap();
-
-    let json = json.as_object().unwrap();
-
-    let mut plugs = Vec::new();
-
-    for (id, light_json) in json.iter() {
-        let light = light_json.as_object().unwrap();
-        let state = light.get("state").unwrap().as_object().unwrap();
-
-        if light
-            .get("config")
-            .unwrap()
-            .as_object()
-            .unwrap()
-            .get("archetype")
-            .to_string()
-            .eq("plug")
-        {
-            plugs.push(NormalizedPlug {
-                id: format!("hue-{}-{}", hue_bridge.id, id),
-                name: light.get("name").to_string(),
-                on: state.get("on").to_bool(),
-                reachable: state.get("reachable").to_bool(),
-                type_: light.get("type").to_string(),
-                model: light.get("modelid").to_string(),
-                manufacturer: light.get("manufacturername").to_string(),
-                uniqueid: light.get("uniqueid").to_string(),
-                swversion: light.get("swversion").to_string(),
-                productid: Some(light.get("productid").to_string()),
-            });
-        }
-    }
-
-    plugs
-}
-
-pub async fn get_plug(
-    hue_bridge: &HueBridge,
-    plug_id: &String,
-) -> Result<NormalizedPlug, CustomResponse> {
-    let plug_id = plug_id.to_owned();
-    if hue_bridge.user.is_empty() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Hue Bridge not configured".to_owned(),
-        });
-    }
-
-    let response = get_hue_json(hue_bridge, format!("lights/{}", plug_id)).await;
-
-    if response.is_err() {
-        return Err(response.unwrap_err());
-    }
-
-    let response = response.unwrap();
-
-    let json: Value = _serde_json::de::from_str(&response).unwrap();
-
-    let json = json.as_object();
-
-    if json.is_none() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Failed to parse Hue Bridge response".to_owned(),
-        });
-    }
-
-    let json = json.unwrap();
-
-    let light = json.get("state").unwrap().as_object().unwrap();
-
-    if !json
-        .get("config")
-        .unwrap()
-        .as_object()
-        .unwrap()
-        .get("archetype")
-        .to_string()
-        .eq("plug")
-    {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Light is not a plug".to_owned(),
-        });
-    }
-
-    if !json.contains_key("reachable") || !json.get("reachable").to_bool() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Plug is not reachable".to_owned(),
-        });
-    }
-
-    Ok(NormalizedPlug {
-        id: format!("hue-{}-{}", hue_bridge.id, plug_id),
-        name: json.get("name").to_string(),
-        on: light.get("on").to_bool(),
-        reachable: light.get("reachable").to_bool(),
-        type_: json.get("type").to_string(),
-        model: json.get("modelid").to_string(),
-        manufacturer: json.get("manufacturername").to_string(),
-        uniqueid: json.get("uniqueid").to_string(),
-        swversion: json.get("swversion").to_string(),
-        productid: Some(json.get("productid").to_string()),
-    })
-}
-
-async fn __get_scenes__(hue_bridge: &HueBridge) -> Result<Vec<HueScene>, CustomResponse> {
-    if hue_bridge.user.is_empty() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Hue Bridge not configured".to_owned(),
-        });
-    }
-
-    let response = get_hue_json(hue_bridge, "scenes".to_owned()).await;
-
-    if response.is_err() {
-        return Err(response.unwrap_err());
-    }
-
-    let response = response.unwrap();
-
-    let json: Value = _serde_json::de::from_str(&response).unwrap();
-
-    let json = json.as_object().unwrap();
-
-    let mut scenes = Vec::new();
-
-    for (id, scene_json) in json.iter() {
-        let mut scene_json = scene_json.as_object().unwrap().to_owned();
-        scene_json.insert("id".to_owned(), id.to_owned().into());
-
-        let scene: HueScene = _serde_json::from_value(Value::Object(scene_json)).unwrap();
-        scenes.push(scene);
-    }
-
-    Ok(scenes)
-}
-
-async fn __set_scene__(
-    hue_bridge: &HueBridge,
-    scene_id: &String,
-    group_id: &String,
-) -> Result<String, CustomResponse> {
-    if hue_bridge.user.is_empty() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Hue Bridge not configured".to_owned(),
-        });
-    }
-
-    let response = put_hue_json(
-        hue_bridge,
-        format!("groups/{}/action", group_id),
-        format!("{{\"scene\": \"{}\"}}", scene_id),
-    )
-    .await;
-
-    if response.is_err() {
-        return Err(response.unwrap_err());
-    }
-
-    let response = response.unwrap();
-
-    let json = _serde_json::from_str::<Value>(&response).unwrap();
-
-    if json.is_array() {
-        let json = json.as_array().unwrap();
-
-        for json in json.iter() {
-            let json = json.as_object().unwrap();
-
-            if json.contains_key("error") {
-                let error: Option<&_serde_json::Map<String, Value>> =
-                    json.get("error").unwrap().as_object();
-
-                if error.is_none() {
-                    return Err(CustomResponse {
-                        status: Status::InternalServerError,
-                        message: json.get("error").unwrap().as_str().unwrap().to_owned(),
-                    });
-                }
-
-                let error = error.unwrap();
-
-                if error.contains_key("description") {
-                    return Err(CustomResponse {
-                        status: Status::InternalServerError,
-                        message: error
-                            .get("description")
-                            .unwrap()
-                            .as_str()
-                            .unwrap()
-                            .to_owned(),
-                    });
-                }
-            }
-
-            if json.contains_key("success") {
-                let json = json.get("success").unwrap().as_object().unwrap();
-
-                return Ok(json.values().next().unwrap().as_str().unwrap().to_owned());
-            }
-        }
-    }
-
-    Ok(response)
-}
-
-#[derive(serde::Serialize, JsonSchema)]
-struct ConfigResponse {
-    id: String,
-}
-
-#[derive(serde::Deserialize, JsonSchema)]
-struct ConfigRequest {
-    host: Option<String>,
-    user: Option<String>,
-}
-
-#[openapi(tag = "Hue")]
-#[put("/config/add", format = "json", data = "<config_json>")]
-async fn add_config(
-    jwt: JWTToken,
-    _dbpool: &State<SqlitePool>,
-    config_json: Json<ConfigRequest>,
-) -> Result<Json<ConfigResponse>, CustomResponse> {
-    let connection = &mut connection::get_connection(_dbpool).unwrap();
-
-    let config = config_json.into_inner();
-
-    let config_ip = if let Some(host) = &config.host {
-        host
-    } else {
-        ""
-    };
-
-    let config_user = if let Some(user) = &config.user {
-        user
-    } else {
-        ""
-    };
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let usersettings = user.get_usersettings(connection);
-
-    if usersettings.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not get usersettings".to_string(),
-        });
-    }
-
-    let usersettings = usersettings.unwrap();
-
-    let hue_bridges = usersettings.get_huebridges(connection);
-
-    if hue_bridges.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not get hue bridges".to_string(),
-        });
-    }
-
-    let hue_bridges = hue_bridges.unwrap();
-
-    for hue_bridge in hue_bridges {
-        if hue_bridge.ip == config_ip {
-            return Err(CustomResponse {
-                status: Status::Conflict,
-                message: "Bridge already exists".to_string(),
-            });
-        }
-    }
-
-    let usersettings = usersettings.update(
-        connection,
-        &UpdateUserSettings {
-            hue_index: Some(&(usersettings.hue_index + 1)),
-            user_id: None,
-        },
-    );
-
-    if usersettings.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not update usersettings".to_string(),
-        });
-    }
-
-    let usersettings = usersettings.unwrap();
-
-    let hue_bridge = HueBridge::create_huebridge(
-        connection,
-        &NewHueBridge {
-            id: &usersettings.hue_index.to_owned().to_string(),
-            ip: config_ip,
-            user: config_user,
-            user_settings_id: &usersettings.id,
-        },
-    );
-
-    if hue_bridge.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not create hue bridge".to_string(),
-        });
-    }
-
-    let hue_bridge = hue_bridge.unwrap();
-
-    Ok(Json(ConfigResponse {
-        id: hue_bridge.id.to_owned(),
-    }))
-}
-
-#[derive(serde::Serialize, JsonSchema)]
-struct InitResponse {
-    username: String,
-}
-
-#[openapi(tag = "Hue")]
-#[get("/bridges")]
-async fn get_bridges(
-    jwt: JWTToken,
-    _dbpool: &State<SqlitePool>,
-) -> Result<Json<Vec<HueBridge>>, CustomResponse> {
-    let connection = &mut connection_from_pool(_dbpool);
-
-    let hue_bridges = HueBridge::get_huebridges_by_user_id(connection, jwt.user_id);
-
-    if hue_bridges.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not get hue bridges".to_string(),
-        });
-    }
-
-    let hue_bridges = hue_bridges.unwrap();
-
-    Ok(Json(hue_bridges))
-}
-
-#[openapi(tag = "Hue")]
-#[get("/scenes/<bridge_id>")]
-async fn get_scenes(
-    jwt: JWTToken,
-    _dbpool: &State<SqlitePool>,
-    bridge_id: String,
-) -> Result<Json<Vec<HueScene>>, CustomResponse> {
-    let connection = &mut connection_from_pool(_dbpool);
-
-    let hue_bridge = HueBridge::get_huebridge_by_bridge_id(connection, jwt.user_id, &bridge_id);
-
-    println!("{:?}", hue_bridge);
-    print!("{}", jwt.user_id);
-    print!("{}", bridge_id);
-
-    if hue_bridge.is_err() {
-        return Err(CustomResponse {
-            status: Status::NotFound,
-            message: "Bridge not found".to_string(),
-        });
-    }
-
-    let hue_bridge = hue_bridge.unwrap();
-
-    let hue_scenes = __get_scenes__(&hue_bridge).await;
-
-    if hue_scenes.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not get hue scenes".to_string(),
-        });
-    }
-
-    let hue_scenes = hue_scenes.unwrap();
-
-    Ok(Json(hue_scenes))
-}
-
-#[openapi(tag = "Hue")]
-#[put("/scenes/<bridge_id>/<group_id>/<scene_id>")]
-async fn set_scene(
-    jwt: JWTToken,
-    _dbpool: &State<SqlitePool>,
-    bridge_id: String,
-    scene_id: String,
-    group_id: String,
-) -> Result<Json<Value>, CustomResponse> {
-    let connection = &mut connection_from_pool(_dbpool);
-
-    let hue_bridge = HueBridge::get_huebridge_by_bridge_id(connection, jwt.user_id, &bridge_id);
-
-    if hue_bridge.is_err() {
-        return Err(CustomResponse {
-            status: Status::NotFound,
-            message: "Bridge not found".to_string(),
-        });
-    }
-
-    let hue_bridge = hue_bridge.unwrap();
-
-    let response = __set_scene__(&hue_bridge, &scene_id, &group_id).await;
-
-    if response.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not set hue scene".to_string(),
-        });
-    }
-
-    Ok(Json(json!({"success": response.unwrap()})))
-}
-
-#[openapi(tag = "Hue")]
-#[delete("/config/<bridge_id>")]
-async fn delete_bridge(
-    jwt: JWTToken,
-    _dbpool: &State<SqlitePool>,
-    bridge_id: String,
-) -> Result<Json<Value>, CustomResponse> {
-    let connection = &mut connection_from_pool(_dbpool);
-
-    let hue_bridge = HueBridge::get_huebridge_by_bridge_id(connection, jwt.user_id, &bridge_id);
-
-    if hue_bridge.is_err() {
-        return Err(CustomResponse {
-            status: Status::NotFound,
-            message: "Bridge not found".to_string(),
-        });
-    }
-
-    let hue_bridge = hue_bridge.unwrap();
-
-    let hue_bridge = hue_bridge.delete(connection);
-
-    if hue_bridge.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not delete hue bridge".to_string(),
-        });
-    }
-
-    Ok(Json(json!({})))
-}
-
-#[openapi(tag = "Hue")]
-#[get("/init/<bridge_id>")]
-async fn init(
-    jwt: JWTToken,
-    _dbpool: &State<SqlitePool>,
-    bridge_id: String,
-) -> Result<Json<InitResponse>, CustomResponse> {
-    let connection = &mut connection_from_pool(_dbpool);
-
-    let hue_bridge = HueBridge::get_huebridge_by_bridge_id(connection, jwt.user_id, &bridge_id);
-
-    if hue_bridge.is_err() {
-        return Err(CustomResponse {
-            status: Status::NotFound,
-            message: "Bridge not found".to_string(),
-        });
-    }
-
-    let hue_bridge = &mut hue_bridge.unwrap();
-
-    let user_request = post_hue_json(
-        hue_bridge,
-        "".to_string(),
-        "{\"devicetype\":\"hue#home api rust\"}".to_string(),
-    )
-    .await;
-
-    if user_request.is_err() {
-        return Err(user_request.unwrap_err());
-    }
-
-    let user_request = user_request.unwrap();
-
-    let json_value: Value = _serde_json::de::from_str(&user_request).unwrap();
-
-    let json = json_value.clone()[0].clone();
-    let error = json["error"].clone();
-    if !error.is_null() {
-        if error["type"] == 101 {
-            return Err(CustomResponse {
-                status: Status::Unauthorized,
-                message: "Link button not pressed".to_string(),
-            });
-        }
-    }
-
-    let username = json["success"]["username"].as_str().unwrap();
-
-    let hue_bridge = hue_bridge.update(
-        connection,
-        &UpdateHueBridge {
-            id: None,
-            ip: None,
-            user: Some(username),
-            user_settings_id: None,
-        },
-    );
-
-    if hue_bridge.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Could not update hue bridge".to_string(),
-        });
-    }
-
-    Ok(Json(InitResponse {
-        username: username.to_owned(),
-    }))
-}
-
-pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
-    openapi_get_routes_spec![settings: init, add_config, get_bridges, delete_bridge, get_scenes, set_scene]
-}
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ::serde::{Deserialize, Serialize};
+use diesel::Connection;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use okapi::openapi3::OpenApi;
+use rand::Rng;
+use rocket::{
+    delete, get,
+    http::Status,
+    patch, put,
+    serde::{self, json::Json},
+    tokio::{sync::broadcast::Sender, time::sleep},
+    State,
+};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    JsonSchema,
+    _serde_json::{self, json, Value},
+};
+
+use crate::{
+    auth::auth::JWTToken,
+    config::app_config,
+    db::{
+        connection::{self, DbConnection, DbPool},
+        models::{
+            DeviceAlias, DeviceMetadata, HueBridge, NewHueBridge, UpdateHueBridge,
+            UpdateUserSettings,
+        },
+    },
+    ratelimit::RateLimit,
+    repsonses::ApiError,
+    validation::{validate_bridge_user, validate_host, Validate, Validated, ValidationErrors},
+    utils::{
+        color::{hsb_to_hsv, hsv_to_hsb, hsv_to_rgb, rgb_to_hsv},
+        tls::{self, Transport},
+    },
+    InternalMessage,
+};
+
+use super::main::{
+    DeviceOverlay, LightState, NormalizedColor, NormalizedLight, NormalizedPlug, PlugState,
+    ProviderHealth,
+};
+use crate::utils::extensions::ValueExt;
+
+/// Milliseconds before the first retry, doubled for every further attempt.
+static RETRY_BASE_DELAY: u64 = 100;
+
+static PROVIDER: &str = "hue";
+
+fn hue_error<E: Into<ApiError>>(error: E) -> ApiError {
+    error.into().with_provider(PROVIDER)
+}
+
+fn not_configured() -> ApiError {
+    ApiError::upstream(PROVIDER, "Hue Bridge not configured")
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct HueScene {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub group: String,
+    pub lights: Vec<String>,
+    pub owner: String,
+    pub recycle: bool,
+    pub locked: bool,
+    pub appdata: HueAppData,
+    pub picture: String,
+    pub lastupdated: String,
+    pub version: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct HueAppData {
+    pub version: i32,
+    pub data: Value,
+}
+
+/// Failures of a single bridge, keyed by its address.
+#[derive(Debug, Clone, Default)]
+struct BridgeHealth {
+    failures: u32,
+    open_until: Option<Instant>,
+    last_error: Option<String>,
+    /// Unix timestamp
+    last_error_at: Option<i64>,
+}
+
+impl BridgeHealth {
+    fn is_open(&self) -> bool {
+        self.open_until
+            .is_some_and(|open_until| open_until > Instant::now())
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+static HEALTH: OnceLock<Mutex<HashMap<String, BridgeHealth>>> = OnceLock::new();
+
+fn health() -> &'static Mutex<HashMap<String, BridgeHealth>> {
+    HEALTH.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn bridge_health(hue_bridge: &HueBridge) -> BridgeHealth {
+    health()
+        .lock()
+        .unwrap()
+        .get(&hue_bridge.ip)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Bridges that failed often enough to open their circuit may have a new
+/// address.
+pub fn needs_rediscovery(hue_bridge: &HueBridge) -> bool {
+    hue_bridge.bridgeid.is_some()
+        && bridge_health(hue_bridge).failures >= app_config().bridge_failure_threshold
+}
+
+/// Summary for `/api/health`, based on the outcome of earlier requests. No
+/// bridge is contacted.
+pub fn provider_health(hue_bridges: &[HueBridge]) -> ProviderHealth {
+    let health: Vec<BridgeHealth> = hue_bridges.iter().map(bridge_health).collect();
+
+    ProviderHealth {
+        provider: PROVIDER.to_owned(),
+        bridges: hue_bridges.len(),
+        failing: health.iter().filter(|health| health.failures > 0).count(),
+        circuit_open: health.iter().filter(|health| health.is_open()).count(),
+    }
+}
+
+/// Fails fast while the bridge's circuit is open. Once `bridge_circuit_open`
+/// passed requests go through again, the next failure opens it right away.
+fn check_circuit(hue_bridge: &HueBridge) -> Result<(), ApiError> {
+    let health = health().lock().unwrap();
+
+    match health
+        .get(&hue_bridge.ip)
+        .and_then(|health| health.open_until)
+    {
+        Some(open_until) if open_until > Instant::now() => Err(ApiError::upstream(
+            PROVIDER,
+            &format!(
+                "Bridge unavailable after repeated failures, retrying in {}s",
+                (open_until - Instant::now()).as_secs() + 1
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Only failures that point at an unreachable or broken bridge count, the
+/// bridge rejecting a request does not.
+fn record_result<T>(hue_bridge: &HueBridge, result: &Result<T, ApiError>) {
+    let mut health = health().lock().unwrap();
+    let health = health.entry(hue_bridge.ip.clone()).or_default();
+
+    match result {
+        Err(e) if is_transient(e) => {
+            let config = app_config();
+
+            health.failures += 1;
+            health.last_error = Some(e.message());
+            health.last_error_at = Some(unix_now());
+
+            if health.failures >= config.bridge_failure_threshold {
+                health.open_until =
+                    Some(Instant::now() + Duration::from_secs(config.bridge_circuit_open));
+            }
+        }
+        _ => {
+            health.failures = 0;
+            health.open_until = None;
+        }
+    }
+}
+
+fn is_transient(error: &ApiError) -> bool {
+    match error {
+        ApiError::Timeout { .. } => true,
+        ApiError::Upstream {
+            status: Some(status),
+            ..
+        } => *status >= 500,
+        ApiError::Upstream { status: None, .. } => true,
+        _ => false,
+    }
+}
+
+/// Exponential backoff with up to 100% jitter so retries of several clients
+/// do not hit the bridge at the same time.
+fn retry_delay(attempt: u32) -> Duration {
+    let delay = RETRY_BASE_DELAY << attempt.min(6);
+
+    Duration::from_millis(delay + rand::thread_rng().gen_range(0..=delay))
+}
+
+/// Reads are retried up to `bridge_retries` times, writes are sent once since
+/// they may have been applied before the connection failed.
+async fn send_hue_request(
+    hue_bridge: &HueBridge,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<String>,
+) -> Result<Value, ApiError> {
+    check_circuit(hue_bridge)?;
+
+    let retries = match method {
+        reqwest::Method::GET => app_config().bridge_retries,
+        _ => 0,
+    };
+
+    let mut attempt = 0;
+
+    let result = loop {
+        let result = send_hue_request_once(hue_bridge, method.clone(), path, body.clone()).await;
+
+        match result {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                sleep(retry_delay(attempt)).await;
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+
+    record_result(hue_bridge, &result);
+
+    _serde_json::from_str(&result?).map_err(hue_error)
+}
+
+/// Idle connections are closed after this long, before the bridge does.
+static IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Idle connections kept per bridge.
+static MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// A certificate seen on first contact.
+#[derive(Clone)]
+struct LearnedCertificate {
+    fingerprint: String,
+    common_name: Option<String>,
+}
+
+/// Certificates seen on first contact that are not stored in `huebridges`
+/// yet, keyed by the bridge's `_id`. `with_identity` stores them.
+static LEARNED_CERTIFICATES: OnceLock<Mutex<HashMap<i32, LearnedCertificate>>> = OnceLock::new();
+
+fn learned_certificates() -> &'static Mutex<HashMap<i32, LearnedCertificate>> {
+    LEARNED_CERTIFICATES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn learned_certificate(hue_bridge: &HueBridge) -> Option<LearnedCertificate> {
+    learned_certificates()
+        .lock()
+        .unwrap()
+        .get(&hue_bridge._id)
+        .cloned()
+}
+
+fn pinned_certificate(hue_bridge: &HueBridge) -> Option<String> {
+    hue_bridge
+        .certificate
+        .clone()
+        .or_else(|| learned_certificate(hue_bridge).map(|learned| learned.fingerprint))
+}
+
+/// Bridges present a self-signed certificate issued to their bridgeid.
+fn is_issued_to(common_name: Option<&str>, bridgeid: &str) -> bool {
+    common_name.is_some_and(|common_name| common_name.eq_ignore_ascii_case(bridgeid))
+}
+
+/// The first certificate wins if two connections race to pin one. Once the
+/// bridgeid is known only a certificate issued to it is pinned.
+fn learn_certificate(hue_bridge: &HueBridge, connection: &tls::Connection) -> Result<(), ApiError> {
+    let untrusted = || ApiError::Untrusted {
+        provider: PROVIDER.to_owned(),
+    };
+
+    let fingerprint = connection.fingerprint.clone().ok_or_else(untrusted)?;
+    let common_name = connection.common_name.clone();
+
+    if let Some(bridgeid) = &hue_bridge.bridgeid {
+        if !is_issued_to(common_name.as_deref(), bridgeid) {
+            return Err(untrusted());
+        }
+    }
+
+    let mut learned = learned_certificates().lock().unwrap();
+    let pinned = learned
+        .entry(hue_bridge._id)
+        .or_insert_with(|| LearnedCertificate {
+            fingerprint: fingerprint.clone(),
+            common_name,
+        });
+
+    match pinned.fingerprint == fingerprint {
+        true => Ok(()),
+        false => Err(untrusted()),
+    }
+}
+
+fn forget_certificate(hue_bridge: &HueBridge) {
+    learned_certificates()
+        .lock()
+        .unwrap()
+        .remove(&hue_bridge._id);
+
+    idle_connections().lock().unwrap().remove(&hue_bridge._id);
+}
+
+struct IdleConnection {
+    ip: String,
+    connection: tls::Connection,
+    since: Instant,
+}
+
+/// HTTPS connections kept alive between requests, keyed by the bridge's
+/// `_id`, so only the first request pays for the TLS handshake.
+static IDLE_CONNECTIONS: OnceLock<Mutex<HashMap<i32, Vec<IdleConnection>>>> = OnceLock::new();
+
+fn idle_connections() -> &'static Mutex<HashMap<i32, Vec<IdleConnection>>> {
+    IDLE_CONNECTIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// An idle connection to the bridge's current address that presented the
+/// pinned certificate.
+async fn take_idle_connection(hue_bridge: &HueBridge) -> Option<tls::Connection> {
+    let pin = pinned_certificate(hue_bridge)?;
+
+    loop {
+        let idle = {
+            let mut idle_connections = idle_connections().lock().unwrap();
+            let idle = idle_connections.get_mut(&hue_bridge._id)?;
+
+            idle.retain(|idle| {
+                idle.since.elapsed() < IDLE_TIMEOUT
+                    && idle.ip == hue_bridge.ip
+                    && idle.connection.fingerprint.as_deref() == Some(pin.as_str())
+            });
+
+            idle.pop()?
+        };
+
+        let mut connection = idle.connection;
+
+        if connection.is_reusable().await {
+            return Some(connection);
+        }
+    }
+}
+
+/// Keeps HTTPS connections for the next request, plain HTTP ones are closed
+/// so HTTPS is tried again.
+fn release_connection(hue_bridge: &HueBridge, connection: tls::Connection) {
+    if connection.fingerprint.is_none() {
+        return;
+    }
+
+    let mut idle_connections = idle_connections().lock().unwrap();
+    let idle = idle_connections.entry(hue_bridge._id).or_default();
+
+    if idle.len() < MAX_IDLE_CONNECTIONS {
+        idle.push(IdleConnection {
+            ip: hue_bridge.ip.clone(),
+            connection,
+            since: Instant::now(),
+        });
+    }
+}
+
+/// HTTPS with the pinned certificate, or pinning the presented one if there
+/// is none yet. Plain HTTP is only used for bridges with `allow_http` that
+/// cannot be reached via HTTPS, never after a certificate mismatch.
+async fn connect_bridge(hue_bridge: &HueBridge) -> Result<tls::Connection, ApiError> {
+    if let Some(connection) = take_idle_connection(hue_bridge).await {
+        return Ok(connection);
+    }
+
+    let connect_timeout = Duration::from_millis(app_config().bridge_connect_timeout);
+    let pin = pinned_certificate(hue_bridge);
+
+    let result = tls::connect(
+        &hue_bridge.ip,
+        Transport::Https { pin: pin.as_deref() },
+        connect_timeout,
+    )
+    .await;
+
+    match result {
+        Ok(connection) => {
+            if pin.is_none() {
+                learn_certificate(hue_bridge, &connection).map_err(hue_error)?;
+            }
+
+            Ok(connection)
+        }
+        Err(e @ ApiError::Untrusted { .. }) => Err(hue_error(e)),
+        Err(_) if hue_bridge.allow_http => {
+            tls::connect(&hue_bridge.ip, Transport::Http, connect_timeout)
+                .await
+                .map_err(hue_error)
+        }
+        Err(e) => Err(hue_error(e)),
+    }
+}
+
+/// `bridge_timeout` covers connecting as well as the request.
+async fn send_hue_request_once(
+    hue_bridge: &HueBridge,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<String>,
+) -> Result<String, ApiError> {
+    let started = Instant::now();
+    let mut connection = connect_bridge(hue_bridge).await?;
+
+    let response = connection
+        .send(
+            method,
+            &format!("/api/{}/{}", hue_bridge.user, path.trim_start_matches('/')),
+            body,
+            Duration::from_millis(app_config().bridge_timeout).saturating_sub(started.elapsed()),
+        )
+        .await
+        .map_err(hue_error)?;
+
+    release_connection(hue_bridge, connection);
+
+    if !(200..300).contains(&response.status) {
+        return Err(ApiError::Upstream {
+            provider: PROVIDER.to_owned(),
+            status: Some(response.status),
+            message: "Unexpected response status".to_owned(),
+        });
+    }
+
+    Ok(response.body)
+}
+
+type SharedResponse = Shared<BoxFuture<'static, Result<Value, ApiError>>>;
+
+struct CacheEntry {
+    created: Instant,
+    response: SharedResponse,
+}
+
+impl CacheEntry {
+    /// Requests in flight are always shared, finished ones only while they are
+    /// fresh and succeeded.
+    fn is_usable(&self, ttl: Duration) -> bool {
+        match self.response.peek() {
+            None => true,
+            Some(Ok(_)) => self.created.elapsed() < ttl,
+            Some(Err(_)) => false,
+        }
+    }
+}
+
+static CACHE: OnceLock<Mutex<HashMap<String, CacheEntry>>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<HashMap<String, CacheEntry>> {
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn cache_prefix(hue_bridge: &HueBridge) -> String {
+    format!("{}/{}/", hue_bridge.ip, hue_bridge.user)
+}
+
+/// Removes all cached responses of the bridge so the next read sees the
+/// result of a write.
+fn invalidate_cache(hue_bridge: &HueBridge) {
+    let prefix = cache_prefix(hue_bridge);
+
+    cache()
+        .lock()
+        .unwrap()
+        .retain(|key, _| !key.starts_with(&prefix));
+}
+
+/// Responses are cached per bridge and path for `bridge_cache_ttl`, and
+/// concurrent requests for the same path wait for one upstream call.
+async fn get_hue_json(hue_bridge: &HueBridge, path: &str) -> Result<Value, ApiError> {
+    let ttl = Duration::from_millis(app_config().bridge_cache_ttl);
+    let path = path.trim_start_matches('/').to_owned();
+    let key = format!("{}{}", cache_prefix(hue_bridge), path);
+
+    let response = {
+        let mut cache = cache().lock().unwrap();
+
+        cache.retain(|_, entry| entry.is_usable(ttl));
+
+        let entry = cache.entry(key).or_insert_with(|| {
+            let hue_bridge = hue_bridge.clone();
+
+            CacheEntry {
+                created: Instant::now(),
+                response: async move {
+                    send_hue_request(&hue_bridge, reqwest::Method::GET, &path, None).await
+                }
+                .boxed()
+                .shared(),
+            }
+        });
+
+        entry.response.clone()
+    };
+
+    response.await
+}
+
+async fn post_hue_json(
+    hue_bridge: &HueBridge,
+    path: &str,
+    body: String,
+) -> Result<Value, ApiError> {
+    send_hue_request(hue_bridge, reqwest::Method::POST, path, Some(body)).await
+}
+
+/// Invalidates the bridge's cached responses, also when the write failed
+/// halfway.
+async fn put_hue_json(
+    hue_bridge: &HueBridge,
+    path: &str,
+    body: String,
+) -> Result<Value, ApiError> {
+    let response = send_hue_request(hue_bridge, reqwest::Method::PUT, path, Some(body)).await;
+
+    invalidate_cache(hue_bridge);
+
+    response
+}
+
+/// The bridge answers writes with `200` and a list of `success`/`error`
+/// objects. Returns the first error description as an upstream error.
+fn check_hue_errors(json: &Value) -> Result<(), ApiError> {
+    let results = match json.as_array() {
+        Some(results) => results,
+        None => return Ok(()),
+    };
+
+    for result in results {
+        if let Some(error) = result.get("error") {
+            let message = error
+                .get("description")
+                .and_then(|description| description.as_str())
+                .or(error.as_str())
+                .unwrap_or("Unknown error");
+
+            return Err(ApiError::upstream(PROVIDER, message));
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct HueLightState {
+    on: Option<bool>,
+    sat: Option<u8>,
+    bri: Option<u8>,
+    hue: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ct: Option<u16>,
+}
+
+pub async fn set_light(
+    hue_bridge: &HueBridge,
+    light_id: String,
+    light_state: LightState,
+) -> Result<Status, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Err(not_configured());
+    }
+
+    let brigthness = light_state.brigthness;
+    let color = light_state.color;
+    let ct = light_state.color_temperature;
+
+    if light_state.on.is_none() && brigthness.is_none() && color.is_none() && ct.is_none() {
+        return Ok(Status::Ok);
+    }
+
+    let mut body = HueLightState {
+        on: light_state.on,
+        hue: None,
+        sat: None,
+        bri: None,
+        ct,
+    };
+
+    if let Some(color) = color.as_ref().and_then(|color| color.first()) {
+        let hsv = rgb_to_hsv(color.0, color.1, color.2);
+        let hsb = hsv_to_hsb(hsv.0, hsv.1, hsv.2);
+
+        body.hue = Some(hsb.0);
+        body.sat = Some(hsb.1);
+        body.bri = Some(hsb.2);
+    }
+
+    let response = put_hue_json(
+        hue_bridge,
+        &format!("lights/{}/state", light_id),
+        _serde_json::to_string(&body)?,
+    )
+    .await?;
+
+    check_hue_errors(&response)?;
+
+    Ok(Status::Ok)
+}
+
+fn legacy_device_id(hue_bridge: &HueBridge, id: &str) -> String {
+    format!("hue-{}-{}", hue_bridge.id, id)
+}
+
+/// `hue-<bridgeid>-<uniqueid>` once the bridgeid is known, the legacy
+/// `hue-<bridge index>-<light>` form until then.
+fn device_id(hue_bridge: &HueBridge, id: &str, light: &Value) -> String {
+    let uniqueid = light.get("uniqueid").and_then(|uniqueid| uniqueid.as_str());
+
+    match (&hue_bridge.bridgeid, uniqueid) {
+        (Some(bridgeid), Some(uniqueid)) => format!("hue-{}-{}", bridgeid, uniqueid),
+        _ => legacy_device_id(hue_bridge, id),
+    }
+}
+
+/// Stable device ids use the light's `uniqueid`, the bridge only knows its
+/// own numbering.
+pub fn is_legacy_device_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())
+}
+
+fn parse_light(
+    hue_bridge: &HueBridge,
+    id: &str,
+    light: &Value,
+) -> Result<Option<NormalizedLight>, ApiError> {
+    let state = light.get("state");
+
+    if state.is_none_or(|state| state.get("colormode").is_none()) {
+        return Ok(None);
+    }
+
+    let state = state.unwrap();
+
+    let hsv = hsb_to_hsv(
+        state.get("hue").to_f64()? as f32,
+        state.get("sat").to_f64()? as f32,
+        state.get("bri").to_f64()? as f32,
+    );
+    let rgb = hsv_to_rgb(hsv.0, hsv.1, hsv.2);
+
+    Ok(Some(NormalizedLight {
+        id: device_id(hue_bridge, id, light),
+        legacy_id: Some(legacy_device_id(hue_bridge, id)),
+        name: light.get("name").to_string()?,
+        on: state.get("on").to_bool()?,
+        brightness: (state.get("bri").to_f64()? / 255.0) as f32,
+        color: vec![NormalizedColor(rgb.0, rgb.1, rgb.2)],
+        color_temperature: state.get("ct").and_then(|ct| ct.as_u64()).map(|ct| ct as u16),
+        reachable: state.get("reachable").to_bool().unwrap_or(false),
+        type_: light.get("type").to_string().unwrap_or_default(),
+        model: light.get("modelid").to_string().unwrap_or_default(),
+        manufacturer: light.get("manufacturername").to_string().unwrap_or_default(),
+        uniqueid: light.get("uniqueid").to_string().unwrap_or_default(),
+        swversion: light.get("swversion").to_string().unwrap_or_default(),
+        productid: light.get("productid").to_string().ok(),
+        overlay: DeviceOverlay::default(),
+    }))
+}
+
+fn parse_plug(
+    hue_bridge: &HueBridge,
+    id: &str,
+    light: &Value,
+) -> Result<Option<NormalizedPlug>, ApiError> {
+    let archetype = light
+        .get("config")
+        .and_then(|config| config.get("archetype"))
+        .to_string()
+        .unwrap_or_default();
+
+    if archetype != "plug" {
+        return Ok(None);
+    }
+
+    let state = light.get("state");
+
+    Ok(Some(NormalizedPlug {
+        id: device_id(hue_bridge, id, light),
+        legacy_id: Some(legacy_device_id(hue_bridge, id)),
+        name: light.get("name").to_string()?,
+        on: state.and_then(|state| state.get("on")).to_bool()?,
+        reachable: state
+            .and_then(|state| state.get("reachable"))
+            .to_bool()
+            .unwrap_or(false),
+        type_: light.get("type").to_string().unwrap_or_default(),
+        model: light.get("modelid").to_string().unwrap_or_default(),
+        manufacturer: light.get("manufacturername").to_string().unwrap_or_default(),
+        uniqueid: light.get("uniqueid").to_string().unwrap_or_default(),
+        swversion: light.get("swversion").to_string().unwrap_or_default(),
+        productid: light.get("productid").to_string().ok(),
+        power: None,
+        energy: None,
+        overlay: DeviceOverlay::default(),
+    }))
+}
+
+async fn get_all_lights(hue_bridge: &HueBridge) -> Result<_serde_json::Map<String, Value>, ApiError> {
+    let response = get_hue_json(hue_bridge, "lights").await?;
+
+    match response {
+        Value::Object(lights) => Ok(lights),
+        _ => Err(ApiError::upstream(PROVIDER, "Unexpected response format")),
+    }
+}
+
+/// Finds the bridge's own number of the light with the given `uniqueid`.
+pub async fn find_light_id(hue_bridge: &HueBridge, uniqueid: &str) -> Result<String, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Err(not_configured());
+    }
+
+    let lights = get_all_lights(hue_bridge).await?;
+
+    lights
+        .iter()
+        .find(|(_, light)| light.get("uniqueid").and_then(|id| id.as_str()) == Some(uniqueid))
+        .map(|(id, _)| id.to_owned())
+        .ok_or(ApiError::NotFound("Unknown device".to_string()))
+}
+
+/// Bridges that are not paired yet have no lights. A light that cannot be
+/// parsed is logged and skipped.
+pub async fn get_lights(hue_bridge: &HueBridge) -> Result<Vec<NormalizedLight>, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let lights = get_all_lights(hue_bridge).await?;
+
+    Ok(lights
+        .iter()
+        .filter_map(|(id, light)| match parse_light(hue_bridge, id, light) {
+            Ok(light) => light,
+            Err(e) => {
+                println!("Error parsing light {}: {}", id, hue_error(e));
+                None
+            }
+        })
+        .collect())
+}
+
+pub async fn get_light(hue_bridge: &HueBridge, light_id: &String) -> Result<NormalizedLight, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Err(not_configured());
+    }
+
+    let response = get_hue_json(hue_bridge, &format!("lights/{}", light_id)).await?;
+
+    check_hue_errors(&response)?;
+
+    let light = parse_light(hue_bridge, light_id, &response).map_err(hue_error)?;
+
+    let light = match light {
+        Some(light) => light,
+        None => {
+            return Err(ApiError::BadRequest(
+                "Light is not a color light".to_owned(),
+            ))
+        }
+    };
+
+    if !light.reachable {
+        return Err(ApiError::upstream(PROVIDER, "Light is not reachable"));
+    }
+
+    Ok(light)
+}
+
+pub async fn set_plug(
+    hue_bridge: &HueBridge,
+    plug_id: String,
+    plug_state: PlugState,
+) -> Result<Status, ApiError> {
+    if plug_state.on.is_none() {
+        return Ok(Status::Ok);
+    }
+
+    if hue_bridge.user.is_empty() {
+        return Err(not_configured());
+    }
+
+    let response = put_hue_json(
+        hue_bridge,
+        &format!("lights/{}/state", plug_id),
+        _serde_json::to_string(&plug_state)?,
+    )
+    .await?;
+
+    check_hue_errors(&response)?;
+
+    Ok(Status::Ok)
+}
+
+/// See [`get_lights`] for how errors are handled.
+pub async fn get_plugs(hue_bridge: &HueBridge) -> Result<Vec<NormalizedPlug>, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let lights = get_all_lights(hue_bridge).await?;
+
+    Ok(lights
+        .iter()
+        .filter_map(|(id, light)| match parse_plug(hue_bridge, id, light) {
+            Ok(plug) => plug,
+            Err(e) => {
+                println!("Error parsing plug {}: {}", id, hue_error(e));
+                None
+            }
+        })
+        .collect())
+}
+
+pub async fn get_plug(hue_bridge: &HueBridge, plug_id: &String) -> Result<NormalizedPlug, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Err(not_configured());
+    }
+
+    let response = get_hue_json(hue_bridge, &format!("lights/{}", plug_id)).await?;
+
+    check_hue_errors(&response)?;
+
+    let plug = parse_plug(hue_bridge, plug_id, &response).map_err(hue_error)?;
+
+    let plug = match plug {
+        Some(plug) => plug,
+        None => return Err(ApiError::BadRequest("Light is not a plug".to_owned())),
+    };
+
+    if !plug.reachable {
+        return Err(ApiError::upstream(PROVIDER, "Plug is not reachable"));
+    }
+
+    Ok(plug)
+}
+
+async fn __get_scenes__(hue_bridge: &HueBridge) -> Result<Vec<HueScene>, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Err(not_configured());
+    }
+
+    let response = get_hue_json(hue_bridge, "scenes").await?;
+
+    check_hue_errors(&response)?;
+
+    let scenes = match response {
+        Value::Object(scenes) => scenes,
+        _ => return Err(ApiError::upstream(PROVIDER, "Unexpected response format")),
+    };
+
+    let mut result = Vec::new();
+
+    for (id, scene_json) in scenes {
+        let mut scene_json = match scene_json {
+            Value::Object(scene_json) => scene_json,
+            _ => continue,
+        };
+        scene_json.insert("id".to_owned(), id.into());
+
+        let scene: HueScene = _serde_json::from_value(Value::Object(scene_json)).map_err(hue_error)?;
+        result.push(scene);
+    }
+
+    Ok(result)
+}
+
+async fn __set_scene__(
+    hue_bridge: &HueBridge,
+    scene_id: &String,
+    group_id: &String,
+) -> Result<String, ApiError> {
+    if hue_bridge.user.is_empty() {
+        return Err(not_configured());
+    }
+
+    let response = put_hue_json(
+        hue_bridge,
+        &format!("groups/{}/action", group_id),
+        json!({ "scene": scene_id }).to_string(),
+    )
+    .await?;
+
+    check_hue_errors(&response)?;
+
+    let success = response
+        .as_array()
+        .and_then(|results| results.iter().find_map(|result| result.get("success")))
+        .and_then(|success| success.as_object())
+        .and_then(|success| success.values().next())
+        .and_then(|value| value.as_str());
+
+    match success {
+        Some(success) => Ok(success.to_owned()),
+        None => Ok(response.to_string()),
+    }
+}
+
+#[derive(serde::Serialize, JsonSchema)]
+struct ConfigResponse {
+    id: String,
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct ConfigRequest {
+    host: Option<String>,
+    user: Option<String>,
+}
+
+impl Validate for ConfigRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        match &self.host {
+            Some(host) => errors.check("host", validate_host(host)),
+            None => errors.check("host", Err("Must not be empty".to_owned())),
+        }
+
+        if let Some(user) = &self.user {
+            errors.check("user", validate_bridge_user(user));
+        }
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "Hue")]
+#[put("/config/add", format = "json", data = "<config_json>")]
+async fn add_config(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    config_json: Validated<ConfigRequest>,
+) -> Result<Json<ConfigResponse>, ApiError> {
+    let config = config_json.into_inner();
+
+    let hue_bridge = connection::run(_dbpool, move |connection| {
+        let config_ip = config.host.as_deref().unwrap_or("");
+        let config_user = config.user.as_deref().unwrap_or("");
+
+        let user = jwt.get_user(connection)?;
+
+        let usersettings = user.get_usersettings(connection)?;
+
+        let hue_bridges = usersettings.get_huebridges(connection)?;
+
+        if hue_bridges.iter().any(|hue_bridge| hue_bridge.ip == config_ip) {
+            return Err(ApiError::Conflict("Bridge already exists".to_string()));
+        }
+
+        let usersettings = usersettings.update(
+            connection,
+            &UpdateUserSettings {
+                hue_index: Some(&(usersettings.hue_index + 1)),
+                user_id: None,
+            },
+        )?;
+
+        Ok(HueBridge::create_huebridge(
+            connection,
+            &NewHueBridge {
+                id: &usersettings.hue_index.to_owned().to_string(),
+                ip: config_ip,
+                user: config_user,
+                user_settings_id: &usersettings.id,
+                bridgeid: None,
+                certificate: None,
+                allow_http: false,
+            },
+        )?)
+    })
+    .await?;
+
+    Ok(Json(ConfigResponse {
+        id: hue_bridge.id.to_owned(),
+    }))
+}
+
+#[derive(serde::Serialize, JsonSchema)]
+struct InitResponse {
+    username: String,
+}
+
+async fn get_bridgeid(hue_bridge: &HueBridge) -> Result<String, ApiError> {
+    let config = get_hue_json(hue_bridge, "config").await?;
+
+    config
+        .get("bridgeid")
+        .to_string()
+        .map(|bridgeid| bridgeid.to_lowercase())
+        .map_err(hue_error)
+}
+
+/// Fetches and stores the bridgeid and certificate of paired bridges that
+/// do not have them yet. Failures are logged and the bridge is returned
+/// unchanged.
+pub async fn with_identity(pool: &DbPool, hue_bridge: HueBridge) -> HueBridge {
+    if hue_bridge.user.is_empty() {
+        return hue_bridge;
+    }
+
+    let fetched = match hue_bridge.bridgeid.is_none() || pinned_certificate(&hue_bridge).is_none() {
+        true => match get_bridgeid(&hue_bridge).await {
+            Ok(bridgeid) => Some(bridgeid),
+            Err(e) => {
+                println!("Error identifying bridge {}: {}", hue_bridge.id, e);
+                None
+            }
+        },
+        false => None,
+    };
+
+    let bridgeid = match &hue_bridge.bridgeid {
+        Some(_) => None,
+        None => fetched,
+    };
+
+    let certificate = match &hue_bridge.certificate {
+        Some(_) => None,
+        None => learned_certificate(&hue_bridge),
+    };
+
+    // The bridgeid is only trusted if the certificate was issued to it.
+    let known_bridgeid = hue_bridge.bridgeid.as_ref().or(bridgeid.as_ref());
+
+    if let (Some(learned), Some(bridgeid)) = (&certificate, known_bridgeid) {
+        if !is_issued_to(learned.common_name.as_deref(), bridgeid) {
+            println!(
+                "Error identifying bridge {}: certificate is not issued to {}",
+                hue_bridge.id, bridgeid
+            );
+            forget_certificate(&hue_bridge);
+            return hue_bridge;
+        }
+    }
+
+    let certificate = certificate.map(|learned| learned.fingerprint);
+
+    if bridgeid.is_none() && certificate.is_none() {
+        return hue_bridge;
+    }
+
+    let update = hue_bridge.clone();
+
+    let result = connection::run(pool, move |connection| {
+        Ok(update.update(
+            connection,
+            &UpdateHueBridge {
+                id: None,
+                ip: None,
+                user: None,
+                user_settings_id: None,
+                bridgeid: bridgeid.as_deref(),
+                certificate: certificate.as_deref().map(Some),
+                allow_http: None,
+            },
+        )?)
+    })
+    .await;
+
+    match result {
+        Ok(hue_bridge) => {
+            forget_certificate(&hue_bridge);
+            hue_bridge
+        }
+        Err(e) => {
+            println!("Error storing identity of bridge {}: {}", hue_bridge.id, e);
+            hue_bridge
+        }
+    }
+}
+
+#[derive(serde::Serialize, JsonSchema)]
+struct BridgeStatus {
+    id: String,
+    reachable: bool,
+    /// Milliseconds `/config` took to answer.
+    latency: Option<u64>,
+    model: Option<String>,
+    api_version: Option<String>,
+    firmware_version: Option<String>,
+    /// Whether the bridge still accepts the stored username.
+    whitelisted: bool,
+    /// Fingerprint of the pinned certificate, unknown until the bridge was
+    /// reached via HTTPS.
+    certificate: Option<String>,
+    /// The bridge presented a different certificate than the pinned one.
+    untrusted: bool,
+    lights: Option<usize>,
+    sensors: Option<usize>,
+    consecutive_failures: u32,
+    circuit_open: bool,
+    last_error: Option<String>,
+    /// Unix timestamp
+    last_error_at: Option<i64>,
+}
+
+async fn count_resources(hue_bridge: &HueBridge, path: &str) -> Option<usize> {
+    match get_hue_json(hue_bridge, path).await {
+        Ok(Value::Object(resources)) => Some(resources.len()),
+        _ => None,
+    }
+}
+
+/// Always contacts the bridge, bypassing the cache and an open circuit.
+/// Bridges only include the `whitelist` in `/config` for known usernames.
+async fn probe_bridge(hue_bridge: &HueBridge) -> BridgeStatus {
+    let started = Instant::now();
+    let result = send_hue_request_once(hue_bridge, reqwest::Method::GET, "config", None).await;
+    let latency = started.elapsed().as_millis() as u64;
+
+    record_result(hue_bridge, &result);
+
+    let untrusted = matches!(result, Err(ApiError::Untrusted { .. }));
+
+    let config = result
+        .ok()
+        .and_then(|response| _serde_json::from_str::<Value>(&response).ok());
+
+    let whitelisted = !hue_bridge.user.is_empty()
+        && config
+            .as_ref()
+            .is_some_and(|config| config.get("whitelist").is_some());
+
+    let (lights, sensors) = if whitelisted {
+        (
+            count_resources(hue_bridge, "lights").await,
+            count_resources(hue_bridge, "sensors").await,
+        )
+    } else {
+        (None, None)
+    };
+
+    let field = |name: &str| {
+        config
+            .as_ref()
+            .and_then(|config| config.get(name))
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_owned())
+    };
+
+    let health = bridge_health(hue_bridge);
+
+    BridgeStatus {
+        id: hue_bridge.id.clone(),
+        reachable: config.is_some(),
+        latency: config.as_ref().map(|_| latency),
+        model: field("modelid"),
+        api_version: field("apiversion"),
+        firmware_version: field("swversion"),
+        whitelisted,
+        certificate: pinned_certificate(hue_bridge),
+        untrusted,
+        lights,
+        sensors,
+        consecutive_failures: health.failures,
+        circuit_open: health.is_open(),
+        last_error: health.last_error,
+        last_error_at: health.last_error_at,
+    }
+}
+
+fn bridge_not_found(_: diesel::result::Error) -> ApiError {
+    ApiError::NotFound("Bridge not found".to_string())
+}
+
+async fn find_bridge(
+    pool: &DbPool,
+    user_id: i32,
+    bridge_id: String,
+) -> Result<HueBridge, ApiError> {
+    connection::run(pool, move |connection| {
+        HueBridge::get_huebridge_by_bridge_id(connection, user_id, &bridge_id)
+            .map_err(bridge_not_found)
+    })
+    .await
+}
+
+#[openapi(tag = "Hue")]
+#[get("/bridges")]
+async fn get_bridges(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+) -> Result<Json<Vec<HueBridge>>, ApiError> {
+    let hue_bridges = connection::run(_dbpool, move |connection| {
+        Ok(HueBridge::get_huebridges_by_user_id(connection, jwt.user_id)?)
+    })
+    .await?;
+
+    Ok(Json(hue_bridges))
+}
+
+#[openapi(tag = "Hue")]
+#[get("/bridges/<bridge_id>/status")]
+async fn bridge_status(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    bridge_id: String,
+) -> Result<Json<BridgeStatus>, ApiError> {
+    let hue_bridge = find_bridge(_dbpool, jwt.user_id, bridge_id).await?;
+
+    Ok(Json(probe_bridge(&hue_bridge).await))
+}
+
+#[derive(serde::Deserialize, JsonSchema)]
+struct BridgeUpdateRequest {
+    /// Use plain HTTP when the bridge cannot be reached via HTTPS.
+    allow_http: Option<bool>,
+    /// Forgets the pinned certificate so the next connection pins the one the
+    /// bridge presents then, e.g. after the bridge was replaced.
+    #[serde(default)]
+    reset_certificate: bool,
+}
+
+#[openapi(tag = "Hue")]
+#[patch("/bridges/<bridge_id>", format = "json", data = "<update>")]
+async fn update_bridge(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    bridge_id: String,
+    update: Json<BridgeUpdateRequest>,
+) -> Result<Json<HueBridge>, ApiError> {
+    let update = update.into_inner();
+    let hue_bridge = find_bridge(_dbpool, jwt.user_id, bridge_id).await?;
+
+    if update.reset_certificate {
+        forget_certificate(&hue_bridge);
+    }
+
+    let hue_bridge = connection::run(_dbpool, move |connection| {
+        Ok(hue_bridge.update(
+            connection,
+            &UpdateHueBridge {
+                id: None,
+                ip: None,
+                user: None,
+                user_settings_id: None,
+                bridgeid: None,
+                certificate: match update.reset_certificate {
+                    true => Some(None),
+                    false => None,
+                },
+                allow_http: update.allow_http,
+            },
+        )?)
+    })
+    .await?;
+
+    Ok(Json(hue_bridge))
+}
+
+#[openapi(tag = "Hue")]
+#[get("/scenes/<bridge_id>")]
+async fn get_scenes(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    bridge_id: String,
+) -> Result<Json<Vec<HueScene>>, ApiError> {
+    let hue_bridge = find_bridge(_dbpool, jwt.user_id, bridge_id).await?;
+
+    let hue_scenes = __get_scenes__(&hue_bridge).await?;
+
+    Ok(Json(hue_scenes))
+}
+
+#[openapi(tag = "Hue")]
+#[put("/scenes/<bridge_id>/<group_id>/<scene_id>")]
+async fn set_scene(
+    jwt: JWTToken,
+    _limit: RateLimit<'_>,
+    _dbpool: &State<DbPool>,
+    bridge_id: String,
+    scene_id: String,
+    group_id: String,
+) -> Result<Json<Value>, ApiError> {
+    let hue_bridge = find_bridge(_dbpool, jwt.user_id, bridge_id).await?;
+
+    let response = __set_scene__(&hue_bridge, &scene_id, &group_id).await?;
+
+    Ok(Json(json!({ "success": response })))
+}
+
+/// Deletes the bridge and forgets its certificate. Used by
+/// `DELETE /config/<bridge_id>` and `bridge remove`, which then clear the Home
+/// Assistant configs of its devices. The aliases and metadata of its devices
+/// are kept so bookmarks and names survive adding it again, unless `purge` is
+/// set.
+pub fn remove_bridge(
+    connection: &mut DbConnection,
+    user_id: i32,
+    hue_bridge: &HueBridge,
+    purge: bool,
+) -> Result<(), ApiError> {
+    let mut prefixes = vec![format!("hue-{}-", hue_bridge.id)];
+    prefixes.extend(
+        hue_bridge
+            .bridgeid
+            .iter()
+            .map(|bridgeid| format!("hue-{}-", bridgeid)),
+    );
+
+    connection.transaction(|connection| {
+        if purge {
+            for prefix in &prefixes {
+                DeviceAlias::delete_by_prefix(connection, user_id, prefix)?;
+                DeviceMetadata::delete_by_prefix(connection, user_id, prefix)?;
+            }
+        }
+
+        hue_bridge.delete(connection)
+    })?;
+
+    forget_certificate(hue_bridge);
+
+    Ok(())
+}
+
+/// `purge=true` also deletes the aliases and metadata of the bridge's devices.
+#[openapi(tag = "Hue")]
+#[delete("/config/<bridge_id>?<purge>")]
+async fn delete_bridge(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    bridge_id: String,
+    purge: Option<bool>,
+    queue: &State<Sender<InternalMessage>>,
+) -> Result<Json<Value>, ApiError> {
+    let user_id = jwt.user_id;
+
+    let hue_bridge = connection::run(_dbpool, move |connection| {
+        let hue_bridge =
+            HueBridge::get_huebridge_by_bridge_id(connection, jwt.user_id, &bridge_id)
+                .map_err(bridge_not_found)?;
+
+        remove_bridge(connection, user_id, &hue_bridge, purge.unwrap_or(false))?;
+
+        Ok(hue_bridge)
+    })
+    .await?;
+
+    let _ = queue.send(InternalMessage::bridge_deleted(&hue_bridge, user_id));
+
+    Ok(Json(json!({})))
+}
+
+#[openapi(tag = "Hue")]
+#[get("/init/<bridge_id>")]
+async fn init(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    bridge_id: String,
+) -> Result<Json<InitResponse>, ApiError> {
+    let hue_bridge = find_bridge(_dbpool, jwt.user_id, bridge_id).await?;
+
+    let response = post_hue_json(
+        &hue_bridge,
+        "",
+        json!({ "devicetype": "hue#home api rust" }).to_string(),
+    )
+    .await?;
+
+    if response[0]["error"]["type"] == 101 {
+        return Err(ApiError::Unauthorized("Link button not pressed".to_string()));
+    }
+
+    check_hue_errors(&response)?;
+
+    let username = match response[0]["success"]["username"].as_str() {
+        Some(username) => username.to_owned(),
+        None => return Err(ApiError::upstream(PROVIDER, "Unexpected response format")),
+    };
+
+    let update_username = username.clone();
+
+    let hue_bridge = connection::run(_dbpool, move |connection| {
+        Ok(hue_bridge.update(
+            connection,
+            &UpdateHueBridge {
+                id: None,
+                ip: None,
+                user: Some(&update_username),
+                user_settings_id: None,
+                bridgeid: None,
+                certificate: None,
+                allow_http: None,
+            },
+        )?)
+    })
+    .await?;
+
+    with_identity(_dbpool, hue_bridge).await;
+
+    Ok(Json(InitResponse { username }))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        settings: init,
+        add_config,
+        get_bridges,
+        bridge_status,
+        update_bridge,
+        delete_bridge,
+        get_scenes,
+        set_scene
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "sqlite")]
+    use crate::{
+        db::{
+            connection::{get_connection, run_migrations},
+            models::{NewDeviceAlias, NewUser, UpdateDeviceMetadata, User, UserSettings},
+        },
+        utils::testing::memory_pool,
+    };
+
+    use super::*;
+
+    #[rocket::async_test]
+    async fn shares_requests_in_flight_even_without_a_ttl() {
+        let entry = |response: Result<Value, ApiError>| CacheEntry {
+            created: Instant::now(),
+            response: async move { response }.boxed().shared(),
+        };
+
+        let succeeded = entry(Ok(json!({})));
+        assert!(succeeded.is_usable(Duration::ZERO));
+
+        succeeded.response.clone().await.unwrap();
+        assert!(!succeeded.is_usable(Duration::ZERO));
+        assert!(succeeded.is_usable(Duration::from_secs(60)));
+
+        let failed = entry(Err(ApiError::Internal("Failed".to_string())));
+        assert!(failed.response.clone().await.is_err());
+        assert!(!failed.is_usable(Duration::from_secs(60)));
+    }
+
+    #[cfg(feature = "sqlite")]
+    static DEVICE_ID: &str = "hue-001788fffe4a5b6c-00:17:88:01:00:bd:c7:b9-0b";
+
+    #[cfg(feature = "sqlite")]
+    fn add_bridge(connection: &mut DbConnection, user_id: i32, id: &str) -> HueBridge {
+        let user_settings = UserSettings::get_usersettings_by_user_id(connection, user_id).unwrap();
+
+        let hue_bridge = HueBridge::create_huebridge(
+            connection,
+            &NewHueBridge {
+                id: &id.to_string(),
+                ip: "192.168.1.10",
+                user: "user",
+                user_settings_id: &user_settings.id,
+                bridgeid: Some("001788fffe4a5b6c"),
+                certificate: None,
+                allow_http: false,
+            },
+        )
+        .unwrap();
+
+        DeviceAlias::create_device_aliases(
+            connection,
+            &[NewDeviceAlias {
+                alias: &format!("hue-{}-1", id),
+                device_id: DEVICE_ID,
+                user_id: &user_id,
+            }],
+        )
+        .unwrap();
+
+        DeviceMetadata::upsert(
+            connection,
+            user_id,
+            DEVICE_ID,
+            &UpdateDeviceMetadata {
+                name: Some(Some("Desk")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        hue_bridge
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn purges_aliases_and_metadata_only_on_request() {
+        let pool = memory_pool();
+        run_migrations(&pool).unwrap();
+
+        let connection = &mut get_connection(&pool).unwrap();
+
+        let user = User::create_user(
+            connection,
+            &NewUser {
+                username: "dave",
+                email: "dave@example.com",
+                hashed_password: "hash",
+            },
+        )
+        .unwrap();
+
+        let hue_bridge = add_bridge(connection, user.id, "0");
+        remove_bridge(connection, user.id, &hue_bridge, false).unwrap();
+
+        assert!(HueBridge::get_huebridge_by_bridge_id(connection, user.id, "0").is_err());
+        assert!(DeviceAlias::get_device_alias(connection, user.id, "hue-0-1").is_ok());
+        assert!(DeviceMetadata::get_device_metadata(connection, user.id, DEVICE_ID).is_ok());
+
+        let hue_bridge = add_bridge(connection, user.id, "1");
+        remove_bridge(connection, user.id, &hue_bridge, true).unwrap();
+
+        let aliases = DeviceAlias::get_device_aliases_by_user_id(connection, user.id).unwrap();
+        assert!(aliases.is_empty());
+        assert!(DeviceMetadata::get_device_metadata(connection, user.id, DEVICE_ID).is_err());
+    }
+}
@@ -0,0 +1,1318 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use okapi::openapi3::OpenApi;
+use rocket::{
+    delete, get, put,
+    serde::json::Json,
+    tokio::{net::UdpSocket, time::timeout},
+    State,
+};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    JsonSchema,
+    _serde_json::{json, Value},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::auth::JWTToken,
+    config::app_config,
+    db::{
+        connection::{self, DbPool},
+        models::{LifxDevice, NewLifxDevice, UpdateLifxDevice},
+    },
+    repsonses::ApiError,
+    utils::color::{hsbk_to_rgb, rgb_to_hsbk},
+    validation::{validate_host, Validate, Validated, ValidationErrors},
+};
+
+use super::main::{
+    DeviceOverlay, FailureTracker, LightState, NormalizedColor, NormalizedLight, ProviderHealth,
+};
+
+static PROVIDER: &str = "lifx";
+
+static PORT: u16 = 56700;
+
+/// LIFX devices are single lights, the channel only completes the id.
+static CHANNEL: &str = "main";
+
+/// How long `/api/lifx/discover` waits for answers.
+static DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Strips and beams, from LIFX's product list.
+static MULTIZONE_PRODUCTS: [i32; 19] = [
+    31, 32, 38, 117, 118, 119, 120, 141, 142, 143, 144, 161, 162, 203, 204, 205, 206, 213, 214,
+];
+
+/// Kelvin range accepted by all LIFX products.
+static KELVIN_MIN: u16 = 1500;
+static KELVIN_MAX: u16 = 9000;
+
+/// Message types, see https://lan.developer.lifx.com/docs/packet-contents.
+mod message {
+    pub const GET_SERVICE: u16 = 2;
+    pub const STATE_SERVICE: u16 = 3;
+    pub const SET_POWER: u16 = 21;
+    pub const GET_VERSION: u16 = 32;
+    pub const STATE_VERSION: u16 = 33;
+    pub const ACKNOWLEDGEMENT: u16 = 45;
+    pub const LIGHT_GET: u16 = 101;
+    pub const SET_COLOR: u16 = 102;
+    pub const LIGHT_STATE: u16 = 107;
+    pub const SET_COLOR_ZONES: u16 = 501;
+    pub const GET_COLOR_ZONES: u16 = 502;
+    pub const STATE_ZONE: u16 = 503;
+    pub const STATE_MULTI_ZONE: u16 = 506;
+}
+
+static HEADER_SIZE: usize = 36;
+/// Protocol number 1024 with the `addressable` bit.
+static PROTOCOL: u16 = 1024 | 1 << 12;
+static TAGGED: u16 = 1 << 13;
+static RES_REQUIRED: u8 = 1;
+static ACK_REQUIRED: u8 = 1 << 1;
+/// Only UDP is defined.
+static SERVICE_UDP: u8 = 1;
+/// `SetColorZones` applies all buffered zones with the last message.
+static APPLY: u8 = 1;
+static NO_APPLY: u8 = 0;
+
+/// Socket errors, e.g. an unreachable network.
+fn io_error(error: std::io::Error) -> ApiError {
+    ApiError::Upstream {
+        provider: PROVIDER.to_owned(),
+        status: None,
+        message: error.to_string(),
+    }
+}
+
+static FAILURES: FailureTracker = FailureTracker::new(PROVIDER);
+
+pub fn provider_health(devices: &[LifxDevice]) -> ProviderHealth {
+    FAILURES.health(devices.iter().map(|device| device.id))
+}
+
+/// Identifies this server in every header, devices echo it in answers.
+fn source() -> u32 {
+    static SOURCE: OnceLock<u32> = OnceLock::new();
+
+    *SOURCE.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or_default();
+
+        // Zero asks devices to broadcast their answers
+        (nanos ^ std::process::id()).max(2)
+    })
+}
+
+fn next_sequence() -> u8 {
+    static SEQUENCE: AtomicU8 = AtomicU8::new(0);
+
+    SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy)]
+struct Header {
+    tagged: bool,
+    source: u32,
+    /// Serial number padded to eight bytes, all zero to address every device.
+    target: [u8; 8],
+    flags: u8,
+    sequence: u8,
+    message_type: u16,
+}
+
+fn encode(header: &Header, payload: &[u8]) -> Vec<u8> {
+    let size = HEADER_SIZE + payload.len();
+    let mut packet = Vec::with_capacity(size);
+
+    let protocol = if header.tagged {
+        PROTOCOL | TAGGED
+    } else {
+        PROTOCOL
+    };
+
+    packet.extend((size as u16).to_le_bytes());
+    packet.extend(protocol.to_le_bytes());
+    packet.extend(header.source.to_le_bytes());
+    packet.extend(header.target);
+    packet.extend([0; 6]);
+    packet.push(header.flags);
+    packet.push(header.sequence);
+    packet.extend([0; 8]);
+    packet.extend(header.message_type.to_le_bytes());
+    packet.extend([0; 2]);
+    packet.extend(payload);
+
+    packet
+}
+
+fn decode(packet: &[u8]) -> Option<(Header, &[u8])> {
+    let mut reader = Reader(packet);
+
+    let size = reader.u16()? as usize;
+    let protocol = reader.u16()?;
+
+    if size < HEADER_SIZE || size > packet.len() || protocol & 0xfff != 1024 {
+        return None;
+    }
+
+    let source = reader.u32()?;
+    let target = reader.bytes::<8>()?;
+    reader.bytes::<6>()?;
+    let flags = reader.u8()?;
+    let sequence = reader.u8()?;
+    reader.bytes::<8>()?;
+    let message_type = reader.u16()?;
+
+    Some((
+        Header {
+            tagged: protocol & TAGGED != 0,
+            source,
+            target,
+            flags,
+            sequence,
+            message_type,
+        },
+        &packet[HEADER_SIZE..size],
+    ))
+}
+
+/// Reads little-endian fields from the front of a payload.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.0.get(..N)?.try_into().ok()?;
+        self.0 = &self.0[N..];
+
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes()?))
+    }
+
+    /// Labels are NUL padded UTF-8.
+    fn label(&mut self) -> Option<String> {
+        let bytes = self.bytes::<32>()?;
+        let length = bytes.iter().position(|byte| *byte == 0).unwrap_or(32);
+
+        Some(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+}
+
+/// A color as LIFX sends it, every field but `kelvin` spans `0..=65535`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Hsbk {
+    hue: u16,
+    saturation: u16,
+    brightness: u16,
+    kelvin: u16,
+}
+
+impl Hsbk {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        Some(Hsbk {
+            hue: reader.u16()?,
+            saturation: reader.u16()?,
+            brightness: reader.u16()?,
+            kelvin: reader.u16()?,
+        })
+    }
+
+    fn write(&self, payload: &mut Vec<u8>) {
+        payload.extend(self.hue.to_le_bytes());
+        payload.extend(self.saturation.to_le_bytes());
+        payload.extend(self.brightness.to_le_bytes());
+        payload.extend(self.kelvin.to_le_bytes());
+    }
+
+    fn rgb(&self) -> NormalizedColor {
+        let (red, green, blue) = hsbk_to_rgb(self.hue, self.saturation);
+
+        NormalizedColor(red, green, blue)
+    }
+
+    /// Changes hue and saturation and keeps brightness and kelvin.
+    fn with_rgb(self, color: &NormalizedColor) -> Self {
+        let (hue, saturation) = rgb_to_hsbk(color.0, color.1, color.2);
+
+        Hsbk {
+            hue,
+            saturation,
+            ..self
+        }
+    }
+}
+
+/// Payload of `LightState`.
+struct LightStatus {
+    color: Hsbk,
+    power: u16,
+    label: String,
+}
+
+fn parse_light_state(payload: &[u8]) -> Option<LightStatus> {
+    let mut reader = Reader(payload);
+
+    let color = Hsbk::read(&mut reader)?;
+    reader.bytes::<2>()?;
+    let power = reader.u16()?;
+    let label = reader.label()?;
+
+    Some(LightStatus {
+        color,
+        power,
+        label,
+    })
+}
+
+/// Port of the UDP service from a `StateService`.
+fn parse_service(payload: &[u8]) -> Option<u16> {
+    let mut reader = Reader(payload);
+
+    let service = reader.u8()?;
+    let port = reader.u32()?;
+
+    if service != SERVICE_UDP {
+        return None;
+    }
+
+    u16::try_from(port).ok()
+}
+
+/// Product id from a `StateVersion`.
+fn parse_version(payload: &[u8]) -> Option<u32> {
+    let mut reader = Reader(payload);
+
+    reader.u32()?;
+    reader.u32()
+}
+
+/// Zone count, index of the first zone and the colors of a `StateZone` (one
+/// zone) or `StateMultiZone` (eight zones).
+fn parse_zones(message_type: u16, payload: &[u8]) -> Option<(u8, u8, Vec<Hsbk>)> {
+    let mut reader = Reader(payload);
+
+    let count = reader.u8()?;
+    let index = reader.u8()?;
+
+    let zones = match message_type {
+        message::STATE_ZONE => 1,
+        message::STATE_MULTI_ZONE => 8,
+        _ => return None,
+    };
+
+    let colors = (0..zones)
+        .map(|_| Hsbk::read(&mut reader))
+        .collect::<Option<Vec<Hsbk>>>()?;
+
+    Some((count, index, colors))
+}
+
+fn serial(target: &[u8; 8]) -> String {
+    target[..6]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn parse_serial(serial: &str) -> Option<[u8; 8]> {
+    let mut target = [0; 8];
+
+    if serial.len() != 12 || !serial.is_ascii() {
+        return None;
+    }
+
+    for (index, byte) in target.iter_mut().take(6).enumerate() {
+        *byte = u8::from_str_radix(&serial[index * 2..index * 2 + 2], 16).ok()?;
+    }
+
+    Some(target)
+}
+
+/// `host` or `host:port`, LIFX devices listen on 56700.
+fn address(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, PORT).to_string(),
+        Err(_) if host.contains(':') => host.to_owned(),
+        Err(_) => format!("{}:{}", host, PORT),
+    }
+}
+
+fn timeout_error() -> ApiError {
+    ApiError::Timeout {
+        provider: PROVIDER.to_owned(),
+    }
+}
+
+/// One request to a device.
+struct Request<'a> {
+    host: &'a str,
+    target: [u8; 8],
+    message_type: u16,
+    payload: Vec<u8>,
+    /// `Acknowledgement` for writes, the state message for reads.
+    response_type: u16,
+}
+
+/// Sends the request and passes every matching answer to `handle` until it
+/// returns `true`. UDP is unreliable, so the request is sent again after
+/// `bridge_connect_timeout` up to `bridge_retries` times.
+async fn exchange(
+    request: &Request<'_>,
+    mut handle: impl FnMut(&Header, &[u8]) -> bool,
+) -> Result<(), ApiError> {
+    let config = app_config();
+    let attempt_timeout = Duration::from_millis(config.bridge_connect_timeout);
+
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(io_error)?;
+    socket
+        .connect(address(request.host))
+        .await
+        .map_err(io_error)?;
+
+    let flags = match request.response_type {
+        message::ACKNOWLEDGEMENT => ACK_REQUIRED,
+        _ => RES_REQUIRED,
+    };
+
+    let header = Header {
+        tagged: false,
+        source: source(),
+        target: request.target,
+        flags,
+        sequence: next_sequence(),
+        message_type: request.message_type,
+    };
+    let packet = encode(&header, &request.payload);
+    let mut buffer = [0u8; 1024];
+
+    for _ in 0..=config.bridge_retries {
+        socket.send(&packet).await.map_err(io_error)?;
+
+        let deadline = Instant::now() + attempt_timeout;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let length = match timeout(remaining, socket.recv(&mut buffer)).await {
+                Ok(Ok(length)) => length,
+                Ok(Err(e)) => return Err(io_error(e)),
+                Err(_) => break,
+            };
+
+            let (answer, payload) = match decode(&buffer[..length]) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+
+            if answer.source == header.source
+                && answer.sequence == header.sequence
+                && answer.message_type == request.response_type
+                && handle(&answer, payload)
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(timeout_error())
+}
+
+/// The first answer.
+async fn request(request: &Request<'_>) -> Result<(Header, Vec<u8>), ApiError> {
+    let mut answer = None;
+
+    exchange(request, |header, payload| {
+        answer = Some((*header, payload.to_vec()));
+        true
+    })
+    .await?;
+
+    answer.ok_or_else(timeout_error)
+}
+
+fn invalid_answer() -> ApiError {
+    ApiError::Upstream {
+        provider: PROVIDER.to_owned(),
+        status: None,
+        message: "Invalid answer".to_owned(),
+    }
+}
+
+/// Serial number and state of the device at `host`. A zero `target` is
+/// answered by whichever device listens there.
+async fn get_state(host: &str, target: [u8; 8]) -> Result<(String, LightStatus), ApiError> {
+    let (header, payload) = request(&Request {
+        host,
+        target,
+        message_type: message::LIGHT_GET,
+        payload: Vec::new(),
+        response_type: message::LIGHT_STATE,
+    })
+    .await?;
+
+    let status = parse_light_state(&payload).ok_or_else(invalid_answer)?;
+
+    Ok((serial(&header.target), status))
+}
+
+async fn get_product(host: &str, target: [u8; 8]) -> Result<i32, ApiError> {
+    let (_, payload) = request(&Request {
+        host,
+        target,
+        message_type: message::GET_VERSION,
+        payload: Vec::new(),
+        response_type: message::STATE_VERSION,
+    })
+    .await?;
+
+    parse_version(&payload)
+        .and_then(|product| i32::try_from(product).ok())
+        .ok_or_else(invalid_answer)
+}
+
+/// Colors of all zones of a strip, which answers with one `StateMultiZone`
+/// per eight zones.
+async fn get_zones(host: &str, target: [u8; 8]) -> Result<Vec<Hsbk>, ApiError> {
+    let mut zones: HashMap<u8, Hsbk> = HashMap::new();
+    let mut total = None;
+
+    let mut payload = Vec::new();
+    payload.extend([0u8, 255]);
+
+    exchange(
+        &Request {
+            host,
+            target,
+            message_type: message::GET_COLOR_ZONES,
+            payload,
+            response_type: message::STATE_MULTI_ZONE,
+        },
+        |header, payload| {
+            if let Some((count, index, colors)) = parse_zones(header.message_type, payload) {
+                total = Some(count as usize);
+
+                for (offset, color) in colors.into_iter().enumerate() {
+                    if let Some(zone) = index.checked_add(offset as u8) {
+                        if (zone as usize) < count as usize {
+                            zones.insert(zone, color);
+                        }
+                    }
+                }
+            }
+
+            total.is_some_and(|total| zones.len() >= total)
+        },
+    )
+    .await?;
+
+    let mut zones: Vec<(u8, Hsbk)> = zones.into_iter().collect();
+    zones.sort_by_key(|(zone, _)| *zone);
+
+    Ok(zones.into_iter().map(|(_, color)| color).collect())
+}
+
+fn target(device: &LifxDevice) -> Result<[u8; 8], ApiError> {
+    parse_serial(&device.device_id).ok_or_else(|| ApiError::Internal("Invalid serial".to_string()))
+}
+
+fn is_multizone(device: &LifxDevice) -> bool {
+    MULTIZONE_PRODUCTS.contains(&device.product)
+}
+
+fn mireds(kelvin: u16) -> Option<u16> {
+    match kelvin {
+        0 => None,
+        kelvin => Some((1_000_000 / kelvin as u32) as u16),
+    }
+}
+
+fn kelvin(mireds: u16) -> u16 {
+    (1_000_000 / mireds.max(1) as u32).clamp(KELVIN_MIN as u32, KELVIN_MAX as u32) as u16
+}
+
+/// Strips report one color per zone.
+fn to_light(device: &LifxDevice, status: &LightStatus, zones: &[Hsbk]) -> NormalizedLight {
+    let color = if zones.is_empty() {
+        vec![status.color.rgb()]
+    } else {
+        zones.iter().map(Hsbk::rgb).collect()
+    };
+
+    NormalizedLight {
+        id: format!("{}-{}-{}", PROVIDER, device.device_id, CHANNEL),
+        legacy_id: None,
+        name: status.label.clone(),
+        on: status.power > 0,
+        brightness: status.color.brightness as f32 / u16::MAX as f32,
+        color,
+        color_temperature: mireds(status.color.kelvin),
+        reachable: true,
+        type_: "Extended color light".to_owned(),
+        model: device.product.to_string(),
+        manufacturer: "LIFX".to_owned(),
+        uniqueid: device.device_id.clone(),
+        swversion: String::new(),
+        productid: None,
+        overlay: DeviceOverlay::default(),
+    }
+}
+
+fn unknown_device() -> ApiError {
+    ApiError::NotFound("Unknown device".to_string())
+}
+
+pub async fn get_lights(device: &LifxDevice) -> Result<Vec<NormalizedLight>, ApiError> {
+    Ok(vec![get_light(device, CHANNEL).await?])
+}
+
+pub async fn get_light(device: &LifxDevice, channel: &str) -> Result<NormalizedLight, ApiError> {
+    if channel != CHANNEL {
+        return Err(unknown_device());
+    }
+
+    let result = async {
+        let target = target(device)?;
+        let (_, status) = get_state(&device.ip, target).await?;
+
+        let zones = if is_multizone(device) {
+            get_zones(&device.ip, target).await?
+        } else {
+            Vec::new()
+        };
+
+        Ok(to_light(device, &status, &zones))
+    }
+    .await;
+
+    FAILURES.record(device.id, &result);
+
+    result
+}
+
+async fn send(
+    device: &LifxDevice,
+    target: [u8; 8],
+    message_type: u16,
+    payload: Vec<u8>,
+) -> Result<(), ApiError> {
+    exchange(
+        &Request {
+            host: &device.ip,
+            target,
+            message_type,
+            payload,
+            response_type: message::ACKNOWLEDGEMENT,
+        },
+        |_, _| true,
+    )
+    .await
+}
+
+async fn set_color(device: &LifxDevice, target: [u8; 8], color: Hsbk) -> Result<(), ApiError> {
+    let mut payload = vec![0];
+    color.write(&mut payload);
+    payload.extend(0u32.to_le_bytes());
+
+    send(device, target, message::SET_COLOR, payload).await
+}
+
+/// Spreads `colors` evenly over the zones, neighbouring zones with the same
+/// color are set with one `SetColorZones`.
+async fn set_zones(
+    device: &LifxDevice,
+    target: [u8; 8],
+    zones: usize,
+    colors: &[Hsbk],
+) -> Result<(), ApiError> {
+    let color_of = |zone: usize| colors[zone * colors.len() / zones];
+
+    let mut start = 0;
+
+    while start < zones {
+        let color = color_of(start);
+        let end = (start..zones)
+            .take_while(|zone| color_of(*zone) == color)
+            .last()
+            .unwrap_or(start);
+
+        let mut payload = vec![start as u8, end as u8];
+        color.write(&mut payload);
+        payload.extend(0u32.to_le_bytes());
+        payload.push(if end + 1 == zones { APPLY } else { NO_APPLY });
+
+        send(device, target, message::SET_COLOR_ZONES, payload).await?;
+
+        start = end + 1;
+    }
+
+    Ok(())
+}
+
+/// Brightness, color and color temperature are combined into one HSBK
+/// color; a color temperature switches to white. Several colors are only
+/// applied to strips, any other light takes the first.
+pub async fn set_light(
+    device: &LifxDevice,
+    channel: &str,
+    state: LightState,
+) -> Result<(), ApiError> {
+    if channel != CHANNEL {
+        return Err(unknown_device());
+    }
+
+    let result = async {
+        let target = target(device)?;
+        let (_, status) = get_state(&device.ip, target).await?;
+
+        let mut color = status.color;
+
+        if let Some(brightness) = state.brigthness {
+            color.brightness = (brightness as u32 * u16::MAX as u32 / 255) as u16;
+        }
+
+        if let Some(mireds) = state.color_temperature {
+            color.kelvin = kelvin(mireds);
+            color.saturation = 0;
+        }
+
+        let colors = state.color.unwrap_or_default();
+
+        match colors.as_slice() {
+            [] if color == status.color => {}
+            [] => set_color(device, target, color).await?,
+            [first] => set_color(device, target, color.with_rgb(first)).await?,
+            [first, ..] if !is_multizone(device) => {
+                set_color(device, target, color.with_rgb(first)).await?
+            }
+            _ => {
+                let zones = get_zones(&device.ip, target).await?.len();
+
+                if zones > 0 {
+                    let colors: Vec<Hsbk> = colors.iter().map(|rgb| color.with_rgb(rgb)).collect();
+
+                    set_zones(device, target, zones, &colors).await?;
+                }
+            }
+        }
+
+        if let Some(on) = state.on {
+            let level: u16 = if on { u16::MAX } else { 0 };
+
+            send(
+                device,
+                target,
+                message::SET_POWER,
+                level.to_le_bytes().to_vec(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    FAILURES.record(device.id, &result);
+
+    result
+}
+
+/// A device that answered `GetService`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DiscoveredDevice {
+    /// Serial number, the `device_id` once added.
+    pub device_id: String,
+    /// Address to pass to `PUT /api/lifx/config/add`.
+    pub host: String,
+}
+
+/// Sends a tagged `GetService` to `broadcast` and collects the answers for
+/// `DISCOVERY_TIMEOUT`.
+async fn discover(broadcast: &str) -> Result<Vec<DiscoveredDevice>, ApiError> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(io_error)?;
+    socket.set_broadcast(true).map_err(io_error)?;
+
+    let header = Header {
+        tagged: true,
+        source: source(),
+        target: [0; 8],
+        flags: RES_REQUIRED,
+        sequence: next_sequence(),
+        message_type: message::GET_SERVICE,
+    };
+    socket
+        .send_to(&encode(&header, &[]), address(broadcast))
+        .await
+        .map_err(io_error)?;
+
+    let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+    let mut buffer = [0u8; 1024];
+    let mut found: Vec<DiscoveredDevice> = Vec::new();
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let (length, from) = match timeout(remaining, socket.recv_from(&mut buffer)).await {
+            Ok(Ok(received)) => received,
+            _ => break,
+        };
+
+        let (answer, payload) = match decode(&buffer[..length]) {
+            Some(decoded) => decoded,
+            None => continue,
+        };
+
+        if answer.source != header.source || answer.message_type != message::STATE_SERVICE {
+            continue;
+        }
+
+        let port = match parse_service(payload) {
+            Some(port) => port,
+            None => continue,
+        };
+
+        let device_id = serial(&answer.target);
+
+        if found.iter().any(|device| device.device_id == device_id) {
+            continue;
+        }
+
+        found.push(DiscoveredDevice {
+            device_id,
+            host: if port == PORT {
+                from.ip().to_string()
+            } else {
+                SocketAddr::new(from.ip(), port).to_string()
+            },
+        });
+    }
+
+    Ok(found)
+}
+
+fn device_not_found(_: diesel::result::Error) -> ApiError {
+    ApiError::NotFound("Device not found".to_string())
+}
+
+pub async fn find_device(
+    pool: &DbPool,
+    user_id: i32,
+    device_id: &str,
+) -> Result<LifxDevice, ApiError> {
+    let device_id = device_id.to_lowercase();
+
+    connection::run(pool, move |connection| {
+        LifxDevice::get_lifx_device(connection, user_id, &device_id).map_err(device_not_found)
+    })
+    .await
+}
+
+pub async fn get_devices(pool: &DbPool, user_id: i32) -> Result<Vec<LifxDevice>, ApiError> {
+    connection::run(pool, move |connection| {
+        Ok(LifxDevice::get_lifx_devices_by_user_id(
+            connection, user_id,
+        )?)
+    })
+    .await
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeviceRequest {
+    host: String,
+}
+
+impl Validate for DeviceRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        errors.check("host", validate_host(&self.host));
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "LIFX")]
+#[get("/devices")]
+async fn get_lifx_devices(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+) -> Result<Json<Vec<LifxDevice>>, ApiError> {
+    Ok(Json(get_devices(_dbpool, jwt.user_id).await?))
+}
+
+/// Searches the LAN for LIFX devices, by default via
+/// `255.255.255.255`. Found devices are not added.
+#[openapi(tag = "LIFX")]
+#[get("/discover?<broadcast>")]
+async fn discover_devices(
+    _jwt: JWTToken,
+    broadcast: Option<String>,
+) -> Result<Json<Vec<DiscoveredDevice>>, ApiError> {
+    let broadcast = broadcast.unwrap_or_else(|| "255.255.255.255".to_owned());
+
+    validate_host(&broadcast).map_err(ApiError::BadRequest)?;
+
+    Ok(Json(discover(&broadcast).await?))
+}
+
+/// Contacts the device to learn its serial number, label and product.
+/// Adding a device that is already registered updates its address.
+#[openapi(tag = "LIFX")]
+#[put("/config/add", format = "json", data = "<device_json>")]
+async fn add_config(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_json: Validated<DeviceRequest>,
+) -> Result<Json<LifxDevice>, ApiError> {
+    let request = device_json.into_inner();
+
+    let (device_id, status) = get_state(&request.host, [0; 8]).await?;
+    let product = get_product(&request.host, [0; 8]).await?;
+
+    let device = connection::run(
+        _dbpool,
+        move |connection| match LifxDevice::get_lifx_device(connection, jwt.user_id, &device_id) {
+            Ok(device) => Ok(device.update(
+                connection,
+                &UpdateLifxDevice {
+                    ip: Some(&request.host),
+                    name: Some(&status.label),
+                    product: Some(&product),
+                },
+            )?),
+            Err(diesel::result::Error::NotFound) => Ok(LifxDevice::create_lifx_device(
+                connection,
+                &NewLifxDevice {
+                    device_id: &device_id,
+                    ip: &request.host,
+                    name: &status.label,
+                    product: &product,
+                    user_id: &jwt.user_id,
+                },
+            )?),
+            Err(e) => Err(e.into()),
+        },
+    )
+    .await?;
+
+    Ok(Json(device))
+}
+
+#[openapi(tag = "LIFX")]
+#[delete("/config/<device_id>")]
+async fn delete_device(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_id: String,
+) -> Result<Json<Value>, ApiError> {
+    let device = find_device(_dbpool, jwt.user_id, &device_id).await?;
+
+    connection::run(_dbpool, move |connection| Ok(device.delete(connection)?)).await?;
+
+    Ok(Json(json!({})))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        settings: get_lifx_devices,
+        discover_devices,
+        add_config,
+        delete_device
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::UdpSocket,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use crate::utils::testing::init_config;
+
+    use super::*;
+
+    static TARGET: [u8; 8] = [0xd0, 0x73, 0xd5, 0x01, 0x02, 0x03, 0, 0];
+
+    fn hsbk(hue: u16, saturation: u16, brightness: u16, kelvin: u16) -> Hsbk {
+        Hsbk {
+            hue,
+            saturation,
+            brightness,
+            kelvin,
+        }
+    }
+
+    fn label(label: &str) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes[..label.len()].copy_from_slice(label.as_bytes());
+        bytes
+    }
+
+    fn light_state(color: Hsbk, power: u16, name: &str) -> Vec<u8> {
+        let mut payload = Vec::new();
+        color.write(&mut payload);
+        payload.extend([0; 2]);
+        payload.extend(power.to_le_bytes());
+        payload.extend(label(name));
+        payload.extend([0; 8]);
+        payload
+    }
+
+    fn multi_zone(count: u8, index: u8, colors: &[Hsbk]) -> Vec<u8> {
+        let mut payload = vec![count, index];
+
+        for color in colors {
+            color.write(&mut payload);
+        }
+
+        payload
+    }
+
+    #[test]
+    fn encodes_the_header_layout() {
+        let header = Header {
+            tagged: false,
+            source: 0x12345678,
+            target: TARGET,
+            flags: ACK_REQUIRED | RES_REQUIRED,
+            sequence: 7,
+            message_type: message::SET_POWER,
+        };
+        let packet = encode(&header, &[0xff, 0xff]);
+
+        assert_eq!(packet.len(), HEADER_SIZE + 2);
+        assert_eq!(packet[0..2], [38, 0]);
+        // Protocol 1024 and the addressable bit, not tagged
+        assert_eq!(packet[2..4], [0x00, 0x14]);
+        assert_eq!(packet[4..8], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(packet[8..16], TARGET);
+        assert_eq!(packet[16..22], [0; 6]);
+        assert_eq!(packet[22], 0b11);
+        assert_eq!(packet[23], 7);
+        assert_eq!(packet[24..32], [0; 8]);
+        assert_eq!(packet[32..34], [21, 0]);
+        assert_eq!(packet[34..36], [0; 2]);
+        assert_eq!(packet[36..], [0xff, 0xff]);
+
+        let tagged = encode(
+            &Header {
+                tagged: true,
+                ..header
+            },
+            &[],
+        );
+        assert_eq!(tagged[2..4], [0x00, 0x34]);
+    }
+
+    #[test]
+    fn decodes_encoded_packets() {
+        let header = Header {
+            tagged: true,
+            source: 42,
+            target: [0; 8],
+            flags: RES_REQUIRED,
+            sequence: 255,
+            message_type: message::GET_SERVICE,
+        };
+        let packet = encode(&header, &[1, 2, 3]);
+        let (decoded, payload) = decode(&packet).unwrap();
+
+        assert!(decoded.tagged);
+        assert_eq!(decoded.source, 42);
+        assert_eq!(decoded.target, [0; 8]);
+        assert_eq!(decoded.flags, RES_REQUIRED);
+        assert_eq!(decoded.sequence, 255);
+        assert_eq!(decoded.message_type, message::GET_SERVICE);
+        assert_eq!(payload, [1, 2, 3]);
+
+        // Trailing bytes beyond the size are not part of the payload
+        let mut padded = packet.clone();
+        padded.extend([0; 4]);
+        assert_eq!(decode(&padded).unwrap().1, [1, 2, 3]);
+
+        assert!(decode(&packet[..HEADER_SIZE]).is_none());
+        assert!(decode(&packet[..20]).is_none());
+
+        let mut other_protocol = packet.clone();
+        other_protocol[2] = 0x01;
+        assert!(decode(&other_protocol).is_none());
+    }
+
+    #[test]
+    fn parses_state_payloads() {
+        let status = parse_light_state(&light_state(
+            hsbk(21845, 65535, 32768, 3500),
+            65535,
+            "Kitchen",
+        ))
+        .unwrap();
+        assert_eq!(status.color, hsbk(21845, 65535, 32768, 3500));
+        assert_eq!(status.power, 65535);
+        assert_eq!(status.label, "Kitchen");
+        assert!(parse_light_state(&[0; 20]).is_none());
+
+        let mut service = vec![SERVICE_UDP];
+        service.extend(56700u32.to_le_bytes());
+        assert_eq!(parse_service(&service), Some(56700));
+        service[0] = 5;
+        assert_eq!(parse_service(&service), None);
+
+        let mut version = Vec::new();
+        version.extend(1u32.to_le_bytes());
+        version.extend(32u32.to_le_bytes());
+        version.extend(0u32.to_le_bytes());
+        assert_eq!(parse_version(&version), Some(32));
+        assert_eq!(parse_version(&version[..6]), None);
+
+        let colors: Vec<Hsbk> = (0..8).map(|zone| hsbk(zone * 1000, 0, 0, 3500)).collect();
+        let (count, index, zones) =
+            parse_zones(message::STATE_MULTI_ZONE, &multi_zone(16, 8, &colors)).unwrap();
+        assert_eq!((count, index), (16, 8));
+        assert_eq!(zones, colors);
+
+        let (_, _, zone) =
+            parse_zones(message::STATE_ZONE, &multi_zone(1, 0, &colors[..1])).unwrap();
+        assert_eq!(zone, colors[..1]);
+
+        assert!(parse_zones(message::STATE_MULTI_ZONE, &multi_zone(16, 0, &colors[..3])).is_none());
+        assert!(parse_zones(message::LIGHT_STATE, &multi_zone(16, 0, &colors)).is_none());
+    }
+
+    #[test]
+    fn converts_serials() {
+        assert_eq!(serial(&TARGET), "d073d5010203");
+        assert_eq!(parse_serial("d073d5010203"), Some(TARGET));
+        assert_eq!(parse_serial("d073d501020"), None);
+        assert_eq!(parse_serial("d073d501020g"), None);
+    }
+
+    /// What the mock bulb is set to, with every message it received.
+    struct Bulb {
+        color: Hsbk,
+        power: u16,
+        zones: Vec<Hsbk>,
+        received: Vec<(u16, Vec<u8>)>,
+    }
+
+    /// Answers like a bulb at 127.0.0.1; strips have a non-empty `zones`.
+    fn serve(product: i32, zones: usize) -> (LifxDevice, Arc<Mutex<Bulb>>) {
+        init_config();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        let bulb = Arc::new(Mutex::new(Bulb {
+            color: hsbk(0, 0, 65535, 2700),
+            power: 65535,
+            zones: vec![hsbk(0, 0, 65535, 2700); zones],
+            received: Vec::new(),
+        }));
+        let shared = bulb.clone();
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+
+            while let Ok((length, from)) = socket.recv_from(&mut buffer) {
+                let (header, payload) = match decode(&buffer[..length]) {
+                    Some(decoded) => decoded,
+                    None => continue,
+                };
+                let mut reader = Reader(payload);
+                let mut bulb = shared.lock().unwrap();
+                let mut answers = Vec::new();
+
+                bulb.received.push((header.message_type, payload.to_vec()));
+
+                match header.message_type {
+                    message::LIGHT_GET => answers.push((
+                        message::LIGHT_STATE,
+                        light_state(bulb.color, bulb.power, "Desk"),
+                    )),
+                    message::GET_VERSION => {
+                        let mut version = Vec::new();
+                        version.extend(1u32.to_le_bytes());
+                        version.extend((product as u32).to_le_bytes());
+                        version.extend(0u32.to_le_bytes());
+                        answers.push((message::STATE_VERSION, version));
+                    }
+                    message::SET_COLOR => {
+                        reader.u8();
+                        bulb.color = Hsbk::read(&mut reader).unwrap();
+                    }
+                    message::SET_POWER => bulb.power = reader.u16().unwrap(),
+                    message::GET_COLOR_ZONES => {
+                        for index in (0..bulb.zones.len()).step_by(8) {
+                            let mut colors = bulb.zones[index..].to_vec();
+                            colors.resize(8, hsbk(0, 0, 0, 0));
+                            answers.push((
+                                message::STATE_MULTI_ZONE,
+                                multi_zone(bulb.zones.len() as u8, index as u8, &colors[..8]),
+                            ));
+                        }
+                    }
+                    message::SET_COLOR_ZONES => {
+                        let start = reader.u8().unwrap() as usize;
+                        let end = reader.u8().unwrap() as usize;
+                        let color = Hsbk::read(&mut reader).unwrap();
+                        bulb.zones[start..=end].fill(color);
+                    }
+                    _ => {}
+                }
+
+                if header.flags & ACK_REQUIRED != 0 {
+                    answers.push((message::ACKNOWLEDGEMENT, Vec::new()));
+                }
+
+                for (message_type, payload) in answers {
+                    let answer = Header {
+                        tagged: false,
+                        target: TARGET,
+                        flags: 0,
+                        message_type,
+                        ..header
+                    };
+                    let _ = socket.send_to(&encode(&answer, &payload), from);
+                }
+            }
+        });
+
+        let device = LifxDevice {
+            id: 1,
+            device_id: serial(&TARGET),
+            ip: address,
+            name: "Desk".to_string(),
+            product,
+            user_id: 1,
+        };
+
+        (device, bulb)
+    }
+
+    fn received(bulb: &Mutex<Bulb>) -> Vec<u16> {
+        let bulb = bulb.lock().unwrap();
+        bulb.received
+            .iter()
+            .map(|(message_type, _)| *message_type)
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn gets_and_sets_a_bulb() {
+        let (device, bulb) = serve(27, 0);
+
+        let light = get_light(&device, CHANNEL).await.unwrap();
+        assert_eq!(light.id, "lifx-d073d5010203-main");
+        assert_eq!(light.name, "Desk");
+        assert!(light.on);
+        assert_eq!(light.brightness, 1.0);
+        assert_eq!(light.color.len(), 1);
+        assert_eq!(light.color_temperature, Some(370));
+        assert_eq!(get_product(&device.ip, TARGET).await.unwrap(), 27);
+
+        set_light(
+            &device,
+            CHANNEL,
+            LightState {
+                on: Some(false),
+                brigthness: Some(51),
+                color: None,
+                color_temperature: Some(250),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            received(&bulb),
+            [
+                message::LIGHT_GET,
+                message::GET_VERSION,
+                message::LIGHT_GET,
+                message::SET_COLOR,
+                message::SET_POWER
+            ]
+        );
+        {
+            let bulb = bulb.lock().unwrap();
+            assert_eq!(bulb.color, hsbk(0, 0, 13107, 4000));
+            assert_eq!(bulb.power, 0);
+        }
+
+        // Nothing changes, so only the power is sent
+        set_light(
+            &device,
+            CHANNEL,
+            LightState {
+                on: Some(true),
+                brigthness: None,
+                color: None,
+                color_temperature: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            received(&bulb)[5..],
+            [message::LIGHT_GET, message::SET_POWER]
+        );
+        assert!(get_light(&device, CHANNEL).await.unwrap().on);
+    }
+
+    #[rocket::async_test]
+    async fn sets_the_zones_of_a_strip() {
+        let (device, bulb) = serve(32, 16);
+
+        // Answered with two `StateMultiZone`
+        let light = get_light(&device, CHANNEL).await.unwrap();
+        assert_eq!(light.color.len(), 16);
+
+        set_light(
+            &device,
+            CHANNEL,
+            LightState {
+                on: None,
+                brigthness: None,
+                color: Some(vec![NormalizedColor(255, 0, 0), NormalizedColor(0, 0, 255)]),
+                color_temperature: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let bulb = bulb.lock().unwrap();
+        let zones: Vec<_> = bulb
+            .received
+            .iter()
+            .filter(|(message_type, _)| *message_type == message::SET_COLOR_ZONES)
+            .map(|(_, payload)| (payload[0], payload[1], payload[payload.len() - 1]))
+            .collect();
+
+        // Half of the zones each, applied with the last message
+        assert_eq!(zones, [(0, 7, NO_APPLY), (8, 15, APPLY)]);
+        assert!(bulb.zones[..8].iter().all(|zone| *zone == bulb.zones[0]));
+        assert!(bulb.zones[8..].iter().all(|zone| *zone == bulb.zones[8]));
+        assert_ne!(bulb.zones[0], bulb.zones[8]);
+        assert_eq!(bulb.zones[0].hue, 0);
+        assert_eq!(bulb.zones[0].saturation, u16::MAX);
+    }
+}
@@ -1,490 +1,1163 @@
-use okapi::openapi3::OpenApi;
-use rocket::{get, http::Status, put, serde::json::Json, tokio::sync::broadcast::Sender, State};
-use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
-use schemars::{
-    JsonSchema,
-    _serde_json::{json, Value},
-};
-use serde::{Deserialize, Serialize};
-
-use crate::{
-    auth::auth::JWTToken,
-    db::{
-        connection::{self, SqlitePool, SqlitePooledConnection},
-        models::User,
-    },
-    repsonses::CustomResponse,
-    InternalMessage,
-};
-
-use super::hue;
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-struct StatusResponse {
-    status: String,
-    version: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct LightState {
-    pub on: Option<bool>,
-    pub brigthness: Option<u8>,
-    pub color: Option<Vec<NormalizedColor>>,
-}
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct PlugState {
-    pub on: Option<bool>,
-}
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct NormalizedColor(pub u8, pub u8, pub u8);
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct NormalizedLight {
-    pub id: String,
-    pub name: String,
-    pub on: bool,
-    pub brightness: f32,
-    pub color: Vec<NormalizedColor>,
-    pub reachable: bool,
-    #[serde(rename = "type")]
-    pub type_: String,
-    pub model: String,
-    pub manufacturer: String,
-    pub uniqueid: String,
-    pub swversion: String,
-    pub productid: Option<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct NormalizedPlug {
-    pub id: String,
-    pub name: String,
-    pub on: bool,
-    pub reachable: bool,
-    #[serde(rename = "type")]
-    pub type_: String,
-    pub model: String,
-    pub manufacturer: String,
-    pub uniqueid: String,
-    pub swversion: String,
-    pub productid: Option<String>,
-}
-
-async fn get_lights(
-    connection: &mut SqlitePooledConnection,
-    user: User,
-) -> Result<Vec<NormalizedLight>, CustomResponse> {
-    let bridges = user.get_huebridges(connection);
-
-    if bridges.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Bridges not found".to_string(),
-        });
-    }
-
-    let bridges = bridges.unwrap();
-
-    let mut lights = Vec::new();
-
-    for bridge in bridges {
-        let bridge_lights = hue::get_lights(&bridge).await;
-        lights.extend(bridge_lights);
-    }
-
-    Ok(lights)
-}
-
-async fn get_light(
-    connection: &mut SqlitePooledConnection,
-    user: &User,
-    light_id: &String,
-) -> Result<NormalizedLight, CustomResponse> {
-    let light_id = light_id.to_owned();
-    let provider = light_id.split('-').nth(0).unwrap();
-
-    if provider == "hue" {
-        let bridge_id = light_id.split('-').nth(1).unwrap();
-        let light_id = light_id.split('-').nth(2).unwrap();
-
-        let bridge = user.get_huebridge(connection, bridge_id);
-
-        if bridge.is_err() {
-            return Err(CustomResponse {
-                status: Status::NotFound,
-                message: "Bridge not found".to_string(),
-            });
-        }
-
-        let bridge = bridge.unwrap();
-
-        let light = hue::get_light(&bridge, &light_id.to_owned()).await;
-
-        if light.is_err() {
-            return Err(CustomResponse {
-                status: Status::NotFound,
-                message: "Light not found".to_string(),
-            });
-        }
-
-        let light = light.unwrap();
-
-        return Ok(light);
-    }
-
-    Err(CustomResponse {
-        status: Status::NotFound,
-        message: "Unknown provider".to_string(),
-    })
-}
-
-async fn set_light_state(
-    connection: &mut SqlitePooledConnection,
-    user: &User,
-    light_id: &String,
-    state: LightState,
-) -> Result<(), CustomResponse> {
-    let light_id = light_id.to_owned();
-    let provider = light_id.split('-').nth(0).unwrap();
-
-    if provider == "hue" {
-        let bridge_id = light_id.split('-').nth(1).unwrap();
-        let light_id = light_id.split('-').nth(2).unwrap();
-
-        let bridge = user.get_huebridge(connection, bridge_id);
-
-        if bridge.is_err() {
-            return Err(CustomResponse {
-                status: Status::NotFound,
-                message: "Bridge not found".to_string(),
-            });
-        }
-
-        let bridge = bridge.unwrap();
-
-        let light = hue::set_light(&bridge, light_id.to_owned(), state).await;
-
-        if light.is_err() {
-            return Err(light.unwrap_err());
-        }
-
-        return Ok(());
-    }
-
-    Err(CustomResponse {
-        status: Status::NotFound,
-        message: "Unknown provider".to_string(),
-    })
-}
-
-async fn get_plugs(
-    connection: &mut SqlitePooledConnection,
-    user: User,
-) -> Result<Vec<NormalizedPlug>, CustomResponse> {
-    let bridges = user.get_huebridges(connection);
-
-    if bridges.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Bridges not found".to_string(),
-        });
-    }
-
-    let bridges = bridges.unwrap();
-
-    let mut plugs = Vec::new();
-
-    for bridge in bridges {
-        let bridge_plugs = hue::get_plugs(&bridge).await;
-        plugs.extend(bridge_plugs);
-    }
-
-    Ok(plugs)
-}
-
-async fn get_plug(
-    connection: &mut SqlitePooledConnection,
-    user: &User,
-    plug_id: &String,
-) -> Result<NormalizedPlug, CustomResponse> {
-    let plug_id = plug_id.to_owned();
-    let provider = plug_id.split('-').nth(0).unwrap();
-
-    if provider == "hue" {
-        let bridge_id = plug_id.split('-').nth(1).unwrap();
-        let plug_id = plug_id.split('-').nth(2).unwrap();
-
-        let bridge = user.get_huebridge(connection, bridge_id);
-
-        if bridge.is_err() {
-            return Err(CustomResponse {
-                status: Status::NotFound,
-                message: "Bridge not found".to_string(),
-            });
-        }
-
-        let bridge = bridge.unwrap();
-
-        let plug = hue::get_plug(&bridge, &plug_id.to_owned()).await;
-
-        if plug.is_err() {
-            return Err(CustomResponse {
-                status: Status::NotFound,
-                message: "Plug not found".to_string(),
-            });
-        }
-
-        let plug = plug.unwrap();
-
-        return Ok(plug);
-    }
-
-    Err(CustomResponse {
-        status: Status::NotFound,
-        message: "Unknown provider".to_string(),
-    })
-}
-
-async fn set_plug_state(
-    connection: &mut SqlitePooledConnection,
-    user: &User,
-    plug_id: &String,
-    state: PlugState,
-) -> Result<(), CustomResponse> {
-    let plug_id = plug_id.to_owned();
-    let provider = plug_id.split('-').nth(0).unwrap();
-
-    if provider == "hue" {
-        let bridge_id = plug_id.split('-').nth(1).unwrap();
-        let plug_id = plug_id.split('-').nth(2).unwrap();
-
-        let bridge = user.get_huebridge(connection, bridge_id);
-
-        if bridge.is_err() {
-            return Err(CustomResponse {
-                status: Status::NotFound,
-                message: "Bridge not found".to_string(),
-            });
-        }
-
-        let bridge = bridge.unwrap();
-
-        let plug = hue::set_plug(&bridge, plug_id.to_owned(), state).await;
-
-        if plug.is_err() {
-            return Err(plug.unwrap_err());
-        }
-
-        return Ok(());
-    }
-
-    Err(CustomResponse {
-        status: Status::NotFound,
-        message: "Unknown provider".to_string(),
-    })
-}
-
-#[openapi]
-#[get("/status")]
-fn status() -> Json<StatusResponse> {
-    let status = StatusResponse {
-        status: "OK".to_string(),
-        version: env!("CARGO_PKG_VERSION").to_string(),
-    };
-
-    Json(status)
-}
-
-#[openapi(tag = "Main")]
-#[get("/lights")]
-pub async fn lights(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-) -> Result<Json<Vec<NormalizedLight>>, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let response = get_lights(connection, user).await;
-
-    if response.is_err() {
-        return Err(response.err().unwrap());
-    }
-
-    Ok(Json(response.unwrap()))
-}
-
-#[openapi(tag = "Main")]
-#[get("/lights/<light_id>")]
-pub async fn light(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-    light_id: String,
-) -> Result<Json<NormalizedLight>, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let response = get_light(connection, &user, &light_id).await;
-
-    if response.is_err() {
-        return Err(response.err().unwrap());
-    }
-
-    Ok(Json(response.unwrap()))
-}
-
-#[openapi(tag = "Main")]
-#[put("/lights/<light_id>/state", format = "json", data = "<state>")]
-pub async fn set_light(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-    light_id: String,
-    state: Json<LightState>,
-    queue: &State<Sender<InternalMessage>>,
-) -> Result<Json<Value>, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let response = set_light_state(connection, &user, &light_id, state.into_inner()).await;
-
-    if response.is_err() {
-        return Err(response.err().unwrap());
-    }
-
-    let response = get_light(connection, &user, &light_id).await;
-
-    if response.is_ok() {
-        let _ = queue.send(InternalMessage::light_update(response.unwrap(), jwt));
-    }
-
-    Ok(Json(json!({})))
-}
-
-#[openapi(tag = "Main")]
-#[get("/plugs")]
-pub async fn plugs(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-) -> Result<Json<Vec<NormalizedPlug>>, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let response = get_plugs(connection, user).await;
-
-    if response.is_err() {
-        return Err(response.err().unwrap());
-    }
-
-    Ok(Json(response.unwrap()))
-}
-
-#[openapi(tag = "Main")]
-#[get("/plugs/<plug_id>")]
-pub async fn plug(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-    plug_id: String,
-) -> Result<Json<NormalizedPlug>, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let response = get_plug(connection, &user, &plug_id).await;
-
-    if response.is_err() {
-        return Err(response.err().unwrap());
-    }
-
-    Ok(Json(response.unwrap()))
-}
-
-#[openapi(tag = "Main")]
-#[put("/plugs/<plug_id>/state", format = "json", data = "<state>")]
-pub async fn set_plug(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-    plug_id: String,
-    state: Json<PlugState>,
-    queue: &State<Sender<InternalMessage>>,
-) -> Result<Json<Value>, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let response = set_plug_state(connection, &user, &plug_id, state.into_inner()).await;
-
-    if response.is_err() {
-        return Err(response.err().unwrap());
-    }
-
-    let response = get_plug(connection, &user, &plug_id).await;
-
-    if response.is_ok() {
-        let _ = queue.send(InternalMessage::plug_update(response.unwrap(), jwt));
-    }
-
-    Ok(Json(json!({})))
-}
-
-pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
-    openapi_get_routes_spec![settings: status, lights, light, set_light, plugs, plug, set_plug]
-}
+use std::{
+    collections::BTreeSet,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use futures::future::{join3, join4, join_all};
+use okapi::openapi3::OpenApi;
+use rocket::{get, patch, put, serde::json::Json, tokio::sync::broadcast::Sender, State};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    JsonSchema,
+    _serde_json::{json, Value},
+};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    auth::auth::JWTToken,
+    config::app_config,
+    db::{
+        connection::{self, DbConnection, DbPool},
+        models::{
+            DeviceAlias, DeviceMetadata, HueBridge, LifxDevice, NewDeviceAlias, ShellyDevice,
+            TasmotaDevice, UpdateDeviceMetadata, Zigbee2MqttBridge,
+        },
+    },
+    ratelimit::RateLimit,
+    repsonses::{ApiError, ETagJson},
+    validation::{validate_label, Validate, Validated, ValidationErrors},
+    InternalMessage,
+};
+
+use super::{hue, lifx, shelly, tasmota, zigbee2mqtt};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct StatusResponse {
+    status: String,
+    version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ProviderHealth {
+    pub provider: String,
+    /// Bridges configured by all users.
+    pub bridges: usize,
+    /// Bridges whose last request failed.
+    pub failing: usize,
+    /// Bridges currently skipped after repeated failures.
+    pub circuit_open: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct HealthResponse {
+    /// `ok`, or `degraded` while any bridge is failing.
+    status: String,
+    version: String,
+    providers: Vec<ProviderHealth>,
+}
+
+/// Ids of a provider's devices whose last request failed, for `/api/health`.
+/// Used by providers that talk to every device directly.
+pub struct FailureTracker {
+    provider: &'static str,
+    failing: Mutex<BTreeSet<i32>>,
+}
+
+impl FailureTracker {
+    pub const fn new(provider: &'static str) -> FailureTracker {
+        FailureTracker {
+            provider,
+            failing: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn record<T>(&self, device_id: i32, result: &Result<T, ApiError>) {
+        let mut failing = self.failing.lock().unwrap();
+
+        match result {
+            Ok(_) => failing.remove(&device_id),
+            Err(_) => failing.insert(device_id),
+        };
+    }
+
+    pub fn health(&self, device_ids: impl Iterator<Item = i32>) -> ProviderHealth {
+        let failing = self.failing.lock().unwrap();
+        let mut health = ProviderHealth {
+            provider: self.provider.to_owned(),
+            bridges: 0,
+            failing: 0,
+            circuit_open: 0,
+        };
+
+        for device_id in device_ids {
+            health.bridges += 1;
+
+            if failing.contains(&device_id) {
+                health.failing += 1;
+            }
+        }
+
+        health
+    }
+}
+
+/// HTTP client for devices on the local network, with the timeouts of Hue
+/// bridges.
+pub fn device_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+    CLIENT.get_or_init(|| {
+        let config = app_config();
+
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(config.bridge_connect_timeout))
+            .timeout(Duration::from_millis(config.bridge_timeout))
+            .build()
+            .expect("Failed to create HTTP client")
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct LightState {
+    pub on: Option<bool>,
+    pub brigthness: Option<u8>,
+    pub color: Option<Vec<NormalizedColor>>,
+    /// Mireds, ignored by lights without white spectrum.
+    pub color_temperature: Option<u16>,
+}
+
+impl LightState {
+    /// `brigthness` scaled from `0..=255` to percent, never rounded down to
+    /// zero since most devices treat that as off.
+    pub fn brightness_percent(&self) -> Option<u8> {
+        self.brigthness
+            .map(|brightness| ((brightness as f64 / 255.0 * 100.0).round() as u8).max(1))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PlugState {
+    pub on: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NormalizedColor(pub u8, pub u8, pub u8);
+
+/// User-defined fields from `device_metadata`, set via `PATCH /api/lights/<id>`
+/// and `PATCH /api/plugs/<id>`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DeviceOverlay {
+    /// Name reported by the device, only set when `name` was overridden.
+    pub original_name: Option<String>,
+    pub icon: Option<String>,
+    pub room: Option<String>,
+    pub sort_order: Option<i32>,
+    pub favorite: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NormalizedLight {
+    /// `hue-<bridgeid>-<uniqueid>`, stays the same when the bridge is removed
+    /// and added again.
+    pub id: String,
+    /// Old `hue-<bridge index>-<light>` id, still accepted by all routes.
+    pub legacy_id: Option<String>,
+    pub name: String,
+    pub on: bool,
+    pub brightness: f32,
+    pub color: Vec<NormalizedColor>,
+    /// Mireds, for lights with white spectrum.
+    pub color_temperature: Option<u16>,
+    pub reachable: bool,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub model: String,
+    pub manufacturer: String,
+    pub uniqueid: String,
+    pub swversion: String,
+    pub productid: Option<String>,
+    #[serde(flatten)]
+    pub overlay: DeviceOverlay,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NormalizedPlug {
+    /// See [`NormalizedLight::id`].
+    pub id: String,
+    pub legacy_id: Option<String>,
+    pub name: String,
+    pub on: bool,
+    pub reachable: bool,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub model: String,
+    pub manufacturer: String,
+    pub uniqueid: String,
+    pub swversion: String,
+    pub productid: Option<String>,
+    /// Current consumption in watts, for plugs that measure it.
+    pub power: Option<f32>,
+    /// Energy counted by the plug in watt-hours.
+    pub energy: Option<f32>,
+    #[serde(flatten)]
+    pub overlay: DeviceOverlay,
+}
+
+/// A bridge whose devices could not be listed.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BridgeFailure {
+    pub provider: String,
+    pub bridge_id: String,
+    /// Same codes as error responses, e.g. `upstream_timeout`.
+    pub code: String,
+    pub message: String,
+}
+
+/// Devices of all bridges that answered. Failing bridges are reported in
+/// `errors` instead of failing the whole request.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DeviceList<T> {
+    pub items: Vec<T>,
+    pub errors: Vec<BridgeFailure>,
+}
+
+/// Appends the devices of every bridge in `results`, keyed by bridge id, to
+/// `list` and reports the bridges that failed.
+fn collect_results<T>(
+    list: &mut DeviceList<T>,
+    provider: &str,
+    results: Vec<(String, Result<Vec<T>, ApiError>)>,
+) {
+    for (bridge_id, result) in results {
+        match result {
+            Ok(bridge_items) => list.items.extend(bridge_items),
+            Err(e) => {
+                println!("Error listing devices of {} bridge {}: {}", provider, bridge_id, e);
+
+                list.errors.push(BridgeFailure {
+                    provider: provider.to_owned(),
+                    bridge_id,
+                    code: e.code().to_owned(),
+                    message: e.message(),
+                });
+            }
+        }
+    }
+}
+
+/// Fields shared by lights and plugs that the metadata overlay works on.
+trait Device {
+    fn ids(&self) -> (&str, Option<&str>);
+    fn overlay(&self) -> &DeviceOverlay;
+    fn overlay_mut(&mut self) -> (&mut String, &mut DeviceOverlay);
+}
+
+impl Device for NormalizedLight {
+    fn ids(&self) -> (&str, Option<&str>) {
+        (&self.id, self.legacy_id.as_deref())
+    }
+
+    fn overlay(&self) -> &DeviceOverlay {
+        &self.overlay
+    }
+
+    fn overlay_mut(&mut self) -> (&mut String, &mut DeviceOverlay) {
+        (&mut self.name, &mut self.overlay)
+    }
+}
+
+impl Device for NormalizedPlug {
+    fn ids(&self) -> (&str, Option<&str>) {
+        (&self.id, self.legacy_id.as_deref())
+    }
+
+    fn overlay(&self) -> &DeviceOverlay {
+        &self.overlay
+    }
+
+    fn overlay_mut(&mut self) -> (&mut String, &mut DeviceOverlay) {
+        (&mut self.name, &mut self.overlay)
+    }
+}
+
+/// Distinguishes a missing field (`None`) from an explicit `null`
+/// (`Some(None)`).
+pub fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+/// Missing fields are left unchanged, `null` clears them.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeviceMetadataRequest {
+    /// Display name, `null` restores the name reported by the device.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub icon: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub room: Option<Option<String>>,
+    /// Lists are ordered by this, devices without one come last.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub sort_order: Option<Option<i32>>,
+    pub favorite: Option<bool>,
+}
+
+/// The stored metadata, `id` is the device id it is stored under.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DeviceMetadataResponse {
+    pub id: String,
+    pub name: Option<String>,
+    pub icon: Option<String>,
+    pub room: Option<String>,
+    pub sort_order: Option<i32>,
+    pub favorite: bool,
+}
+
+impl From<DeviceMetadata> for DeviceMetadataResponse {
+    fn from(metadata: DeviceMetadata) -> Self {
+        DeviceMetadataResponse {
+            id: metadata.device_id,
+            name: metadata.name,
+            icon: metadata.icon,
+            room: metadata.room,
+            sort_order: metadata.sort_order,
+            favorite: metadata.favorite,
+        }
+    }
+}
+
+impl Validate for DeviceMetadataRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        for (field, value) in [("name", &self.name), ("icon", &self.icon), ("room", &self.room)] {
+            if let Some(Some(value)) = value {
+                errors.check(field, validate_label(value));
+            }
+        }
+
+        errors.into_result()
+    }
+}
+
+/// Overlays the metadata stored for the device's id, or for its legacy id if
+/// it was set before the stable id was known.
+fn apply_metadata<T: Device>(device: &mut T, metadata: &[DeviceMetadata]) {
+    let (id, legacy_id) = device.ids();
+
+    let metadata = metadata
+        .iter()
+        .find(|metadata| metadata.device_id == id)
+        .or_else(|| {
+            metadata
+                .iter()
+                .find(|metadata| Some(metadata.device_id.as_str()) == legacy_id)
+        });
+
+    let metadata = match metadata {
+        Some(metadata) => metadata,
+        None => return,
+    };
+
+    let (name, overlay) = device.overlay_mut();
+
+    if let Some(custom_name) = &metadata.name {
+        overlay.original_name = Some(std::mem::replace(name, custom_name.clone()));
+    }
+
+    overlay.icon = metadata.icon.clone();
+    overlay.room = metadata.room.clone();
+    overlay.sort_order = metadata.sort_order;
+    overlay.favorite = metadata.favorite;
+}
+
+/// Applies the user's metadata, keeps devices matching `room` (ignoring case)
+/// and `favorite` and orders them by `sort_order`. The sort is stable, so
+/// devices without one keep the order the bridges returned.
+fn with_metadata<T: Device>(
+    mut devices: Vec<T>,
+    metadata: &[DeviceMetadata],
+    room: Option<&str>,
+    favorite: Option<bool>,
+) -> Vec<T> {
+    for device in devices.iter_mut() {
+        apply_metadata(device, metadata);
+    }
+
+    devices.retain(|device| {
+        let overlay = device.overlay();
+
+        let room_matches = match room {
+            Some(room) => overlay
+                .room
+                .as_deref()
+                .is_some_and(|device_room| device_room.eq_ignore_ascii_case(room)),
+            None => true,
+        };
+
+        let favorite_matches = match favorite {
+            Some(favorite) => overlay.favorite == favorite,
+            None => true,
+        };
+
+        room_matches && favorite_matches
+    });
+
+    devices.sort_by_key(|device| device.overlay().sort_order.unwrap_or(i32::MAX));
+
+    devices
+}
+
+async fn get_metadata(pool: &DbPool, jwt: &JWTToken) -> Result<Vec<DeviceMetadata>, ApiError> {
+    let user_id = jwt.user_id;
+
+    connection::run(pool, move |connection| {
+        Ok(DeviceMetadata::get_device_metadata_by_user_id(
+            connection, user_id,
+        )?)
+    })
+    .await
+}
+
+/// Stores the changes under the device's current id, resolved through the
+/// recorded aliases. Metadata still stored under a legacy id is moved over
+/// first. Only the database is consulted, so devices that are offline can be
+/// edited too.
+async fn set_metadata(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    device_id: &str,
+    request: DeviceMetadataRequest,
+) -> Result<DeviceMetadata, ApiError> {
+    let user_id = jwt.user_id;
+    let device_id = device_id.to_owned();
+
+    connection::run(pool, move |connection| {
+        check_device_owner(connection, user_id, &device_id)?;
+
+        let id = match DeviceAlias::get_device_alias(connection, user_id, &device_id) {
+            Ok(alias) => alias.device_id,
+            Err(diesel::result::Error::NotFound) => device_id,
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Err(diesel::result::Error::NotFound) =
+            DeviceMetadata::get_device_metadata(connection, user_id, &id)
+        {
+            let legacy = DeviceAlias::get_device_aliases_by_device_id(connection, user_id, &id)?
+                .into_iter()
+                .find_map(|alias| {
+                    DeviceMetadata::get_device_metadata(connection, user_id, &alias.alias).ok()
+                });
+
+            if let Some(legacy) = legacy {
+                legacy.update(
+                    connection,
+                    &UpdateDeviceMetadata {
+                        device_id: Some(&id),
+                        ..Default::default()
+                    },
+                )?;
+            }
+        }
+
+        Ok(DeviceMetadata::upsert(
+            connection,
+            user_id,
+            &id,
+            &UpdateDeviceMetadata {
+                device_id: None,
+                name: request.name.as_ref().map(|name| name.as_deref()),
+                icon: request.icon.as_ref().map(|icon| icon.as_deref()),
+                room: request.room.as_ref().map(|room| room.as_deref()),
+                sort_order: request.sort_order,
+                favorite: request.favorite,
+            },
+        )?)
+    })
+    .await
+}
+
+/// Checks that the bridge or device named in `device_id` belongs to the user.
+fn check_device_owner(
+    connection: &mut DbConnection,
+    user_id: i32,
+    device_id: &str,
+) -> Result<(), ApiError> {
+    let (provider, owner, _) = split_device_id(device_id)?;
+    let owner = owner.to_lowercase();
+    let not_found = |_| ApiError::NotFound("Device not found".to_string());
+
+    match provider {
+        "hue" => {
+            let found = HueBridge::get_huebridges_by_user_id(connection, user_id)?
+                .iter()
+                .any(|bridge| bridge.id == owner || bridge.bridgeid.as_deref() == Some(&owner));
+
+            if !found {
+                return Err(ApiError::NotFound("Bridge not found".to_string()));
+            }
+        }
+        "shelly" => {
+            ShellyDevice::get_shelly_device(connection, user_id, &owner).map_err(not_found)?;
+        }
+        "tasmota" => {
+            TasmotaDevice::get_tasmota_device(connection, user_id, &owner).map_err(not_found)?;
+        }
+        "lifx" => {
+            LifxDevice::get_lifx_device(connection, user_id, &owner).map_err(not_found)?;
+        }
+        "zigbee2mqtt" => {
+            let bridges =
+                Zigbee2MqttBridge::get_zigbee2mqtt_bridges_by_user_id(connection, user_id)?;
+
+            if !zigbee2mqtt::has_device(&bridges, &owner) {
+                return Err(ApiError::NotFound("Device not found".to_string()));
+            }
+        }
+        _ => return Err(unknown_provider()),
+    }
+
+    Ok(())
+}
+
+/// Splits device ids of the form `<provider>-<bridge id>-<device id>`.
+fn split_device_id(device_id: &str) -> Result<(&str, &str, &str), ApiError> {
+    let mut parts = device_id.splitn(3, '-');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(provider), Some(bridge_id), Some(device_id)) => Ok((provider, bridge_id, device_id)),
+        _ => Err(ApiError::NotFound("Unknown device".to_string())),
+    }
+}
+
+fn unknown_provider() -> ApiError {
+    ApiError::NotFound("Unknown provider".to_string())
+}
+
+async fn get_bridges(pool: &DbPool, jwt: &JWTToken) -> Result<Vec<HueBridge>, ApiError> {
+    let jwt = jwt.clone();
+
+    connection::run(pool, move |connection| {
+        let user = jwt.get_user(connection)?;
+
+        Ok(user.get_huebridges(connection)?)
+    })
+    .await
+}
+
+/// Resolves a hue device id to its bridge and the bridge's own number of the
+/// device. Accepts stable ids, legacy ids and legacy ids recorded as aliases
+/// before the bridge was removed and added again.
+async fn resolve_hue_device(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    device_id: &str,
+) -> Result<(HueBridge, String), ApiError> {
+    let jwt = jwt.clone();
+    let device_id = device_id.to_owned();
+
+    let (bridge, device_id) = connection::run(pool, move |connection| {
+        let user = jwt.get_user(connection)?;
+
+        let device_id = match DeviceAlias::get_device_alias(connection, user.id, &device_id) {
+            Ok(alias) => alias.device_id,
+            Err(_) => device_id,
+        };
+
+        let (_, bridge_id, id) = split_device_id(&device_id)?;
+
+        let bridge = if hue::is_legacy_device_id(id) {
+            user.get_huebridge(connection, bridge_id).ok()
+        } else {
+            user.get_huebridges(connection)?
+                .into_iter()
+                .find(|bridge| bridge.bridgeid.as_deref() == Some(&bridge_id.to_lowercase()))
+        };
+
+        match bridge {
+            Some(bridge) => Ok((bridge, id.to_owned())),
+            None => Err(ApiError::NotFound("Bridge not found".to_string())),
+        }
+    })
+    .await?;
+
+    if hue::is_legacy_device_id(&device_id) {
+        return Ok((bridge, device_id));
+    }
+
+    let light_id = hue::find_light_id(&bridge, &device_id).await?;
+
+    Ok((bridge, light_id))
+}
+
+/// Remembers `(legacy id, stable id)` pairs so legacy ids keep working once
+/// the bridge index changes. Failures only cost the alias and are logged.
+async fn record_aliases(pool: &DbPool, jwt: &JWTToken, aliases: Vec<(String, String)>) {
+    if aliases.is_empty() {
+        return;
+    }
+
+    let user_id = jwt.user_id;
+
+    let result = connection::run(pool, move |connection| {
+        let new_device_aliases: Vec<NewDeviceAlias> = aliases
+            .iter()
+            .map(|(alias, device_id)| NewDeviceAlias {
+                alias,
+                device_id,
+                user_id: &user_id,
+            })
+            .collect();
+
+        Ok(DeviceAlias::create_device_aliases(
+            connection,
+            &new_device_aliases,
+        )?)
+    })
+    .await;
+
+    if let Err(e) = result {
+        println!("Error recording device aliases: {}", e);
+    }
+}
+
+fn new_aliases(ids: impl Iterator<Item = (Option<String>, String)>) -> Vec<(String, String)> {
+    ids.filter_map(|(legacy_id, id)| match legacy_id {
+        Some(legacy_id) if legacy_id != id => Some((legacy_id, id)),
+        _ => None,
+    })
+    .collect()
+}
+
+pub async fn get_lights(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    room: Option<&str>,
+    favorite: Option<bool>,
+) -> Result<DeviceList<NormalizedLight>, ApiError> {
+    let bridges = get_bridges(pool, jwt).await?;
+    let shelly_devices = shelly::get_devices(pool, jwt.user_id).await?;
+    let tasmota_devices = tasmota::get_devices(pool, jwt.user_id).await?;
+    let zigbee2mqtt_bridges = zigbee2mqtt::get_bridges(pool, jwt.user_id).await?;
+    let lifx_devices = lifx::get_devices(pool, jwt.user_id).await?;
+
+    let (hue_results, shelly_results, tasmota_results, lifx_results) = join4(
+        join_all(bridges.into_iter().map(|bridge| async move {
+            let bridge = hue::with_identity(pool, bridge).await;
+            let result = hue::get_lights(&bridge).await;
+            (bridge.id, result)
+        })),
+        join_all(shelly_devices.into_iter().map(|device| async move {
+            let result = shelly::get_lights(&device).await;
+            (device.device_id, result)
+        })),
+        join_all(tasmota_devices.into_iter().map(|device| async move {
+            let result = tasmota::get_lights(&device).await;
+            (device.device_id, result)
+        })),
+        join_all(lifx_devices.into_iter().map(|device| async move {
+            let result = lifx::get_lights(&device).await;
+            (device.device_id, result)
+        })),
+    )
+    .await;
+
+    let mut lights = DeviceList {
+        items: Vec::new(),
+        errors: Vec::new(),
+    };
+    collect_results(&mut lights, "hue", hue_results);
+    collect_results(&mut lights, "shelly", shelly_results);
+    collect_results(&mut lights, "tasmota", tasmota_results);
+    collect_results(
+        &mut lights,
+        "zigbee2mqtt",
+        zigbee2mqtt_bridges
+            .iter()
+            .map(|bridge| (bridge.base_topic.clone(), zigbee2mqtt::get_lights(bridge)))
+            .collect(),
+    );
+    collect_results(&mut lights, "lifx", lifx_results);
+
+    let aliases = new_aliases(
+        lights
+            .items
+            .iter()
+            .map(|light| (light.legacy_id.clone(), light.id.clone())),
+    );
+    record_aliases(pool, jwt, aliases).await;
+
+    let metadata = get_metadata(pool, jwt).await?;
+
+    Ok(DeviceList {
+        items: with_metadata(lights.items, &metadata, room, favorite),
+        errors: lights.errors,
+    })
+}
+
+async fn get_light(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    light_id: &str,
+) -> Result<NormalizedLight, ApiError> {
+    let mut light = get_device_light(pool, jwt, light_id).await?;
+
+    apply_metadata(&mut light, &get_metadata(pool, jwt).await?);
+
+    Ok(light)
+}
+
+/// The light as reported by its bridge, without metadata.
+pub async fn get_device_light(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    light_id: &str,
+) -> Result<NormalizedLight, ApiError> {
+    let (provider, device_id, channel) = split_device_id(light_id)?;
+
+    match provider {
+        "hue" => {
+            let (bridge, light_id) = resolve_hue_device(pool, jwt, light_id).await?;
+
+            hue::get_light(&bridge, &light_id).await
+        }
+        "shelly" => {
+            let device = shelly::find_device(pool, jwt.user_id, device_id).await?;
+
+            shelly::get_light(&device, channel).await
+        }
+        "tasmota" => {
+            let device = tasmota::find_device(pool, jwt.user_id, device_id).await?;
+
+            tasmota::get_light(&device, channel).await
+        }
+        "zigbee2mqtt" => {
+            let bridges = zigbee2mqtt::get_bridges(pool, jwt.user_id).await?;
+
+            zigbee2mqtt::get_light(&bridges, device_id, channel)
+        }
+        "lifx" => {
+            let device = lifx::find_device(pool, jwt.user_id, device_id).await?;
+
+            lifx::get_light(&device, channel).await
+        }
+        _ => Err(unknown_provider()),
+    }
+}
+
+async fn set_light_state(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    light_id: &str,
+    state: LightState,
+) -> Result<(), ApiError> {
+    let (provider, device_id, channel) = split_device_id(light_id)?;
+
+    match provider {
+        "hue" => {
+            let (bridge, light_id) = resolve_hue_device(pool, jwt, light_id).await?;
+
+            hue::set_light(&bridge, light_id, state).await?;
+
+            Ok(())
+        }
+        "shelly" => {
+            let device = shelly::find_device(pool, jwt.user_id, device_id).await?;
+
+            shelly::set_light(&device, channel, state).await
+        }
+        "tasmota" => {
+            let device = tasmota::find_device(pool, jwt.user_id, device_id).await?;
+
+            tasmota::set_light(&device, channel, state).await
+        }
+        "zigbee2mqtt" => {
+            let bridges = zigbee2mqtt::get_bridges(pool, jwt.user_id).await?;
+
+            zigbee2mqtt::set_light(&bridges, device_id, channel, state).await
+        }
+        "lifx" => {
+            let device = lifx::find_device(pool, jwt.user_id, device_id).await?;
+
+            lifx::set_light(&device, channel, state).await
+        }
+        _ => Err(unknown_provider()),
+    }
+}
+
+pub async fn get_plugs(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    room: Option<&str>,
+    favorite: Option<bool>,
+) -> Result<DeviceList<NormalizedPlug>, ApiError> {
+    let bridges = get_bridges(pool, jwt).await?;
+    let shelly_devices = shelly::get_devices(pool, jwt.user_id).await?;
+    let tasmota_devices = tasmota::get_devices(pool, jwt.user_id).await?;
+    let zigbee2mqtt_bridges = zigbee2mqtt::get_bridges(pool, jwt.user_id).await?;
+
+    let (hue_results, shelly_results, tasmota_results) = join3(
+        join_all(bridges.into_iter().map(|bridge| async move {
+            let bridge = hue::with_identity(pool, bridge).await;
+            let result = hue::get_plugs(&bridge).await;
+            (bridge.id, result)
+        })),
+        join_all(shelly_devices.into_iter().map(|device| async move {
+            let result = shelly::get_plugs(&device).await;
+            (device.device_id, result)
+        })),
+        join_all(tasmota_devices.into_iter().map(|device| async move {
+            let result = tasmota::get_plugs(&device).await;
+            (device.device_id, result)
+        })),
+    )
+    .await;
+
+    let mut plugs = DeviceList {
+        items: Vec::new(),
+        errors: Vec::new(),
+    };
+    collect_results(&mut plugs, "hue", hue_results);
+    collect_results(&mut plugs, "shelly", shelly_results);
+    collect_results(&mut plugs, "tasmota", tasmota_results);
+    collect_results(
+        &mut plugs,
+        "zigbee2mqtt",
+        zigbee2mqtt_bridges
+            .iter()
+            .map(|bridge| (bridge.base_topic.clone(), zigbee2mqtt::get_plugs(bridge)))
+            .collect(),
+    );
+
+    let aliases = new_aliases(
+        plugs
+            .items
+            .iter()
+            .map(|plug| (plug.legacy_id.clone(), plug.id.clone())),
+    );
+    record_aliases(pool, jwt, aliases).await;
+
+    let metadata = get_metadata(pool, jwt).await?;
+
+    Ok(DeviceList {
+        items: with_metadata(plugs.items, &metadata, room, favorite),
+        errors: plugs.errors,
+    })
+}
+
+async fn get_plug(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    plug_id: &str,
+) -> Result<NormalizedPlug, ApiError> {
+    let mut plug = get_device_plug(pool, jwt, plug_id).await?;
+
+    apply_metadata(&mut plug, &get_metadata(pool, jwt).await?);
+
+    Ok(plug)
+}
+
+/// The plug as reported by its bridge, without metadata.
+async fn get_device_plug(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    plug_id: &str,
+) -> Result<NormalizedPlug, ApiError> {
+    let (provider, device_id, channel) = split_device_id(plug_id)?;
+
+    match provider {
+        "hue" => {
+            let (bridge, plug_id) = resolve_hue_device(pool, jwt, plug_id).await?;
+
+            hue::get_plug(&bridge, &plug_id).await
+        }
+        "shelly" => {
+            let device = shelly::find_device(pool, jwt.user_id, device_id).await?;
+
+            shelly::get_plug(&device, channel).await
+        }
+        "tasmota" => {
+            let device = tasmota::find_device(pool, jwt.user_id, device_id).await?;
+
+            tasmota::get_plug(&device, channel).await
+        }
+        "zigbee2mqtt" => {
+            let bridges = zigbee2mqtt::get_bridges(pool, jwt.user_id).await?;
+
+            zigbee2mqtt::get_plug(&bridges, device_id, channel)
+        }
+        _ => Err(unknown_provider()),
+    }
+}
+
+async fn set_plug_state(
+    pool: &DbPool,
+    jwt: &JWTToken,
+    plug_id: &str,
+    state: PlugState,
+) -> Result<(), ApiError> {
+    let (provider, device_id, channel) = split_device_id(plug_id)?;
+
+    match provider {
+        "hue" => {
+            let (bridge, plug_id) = resolve_hue_device(pool, jwt, plug_id).await?;
+
+            hue::set_plug(&bridge, plug_id, state).await?;
+
+            Ok(())
+        }
+        "shelly" => {
+            let device = shelly::find_device(pool, jwt.user_id, device_id).await?;
+
+            shelly::set_plug(&device, channel, state).await
+        }
+        "tasmota" => {
+            let device = tasmota::find_device(pool, jwt.user_id, device_id).await?;
+
+            tasmota::set_plug(&device, channel, state).await
+        }
+        "zigbee2mqtt" => {
+            let bridges = zigbee2mqtt::get_bridges(pool, jwt.user_id).await?;
+
+            zigbee2mqtt::set_plug(&bridges, device_id, channel, state).await
+        }
+        _ => Err(unknown_provider()),
+    }
+}
+
+/// Sets the state and announces the new one to subscribers, shared by the
+/// REST routes and the MQTT bridge.
+pub async fn apply_light_state(
+    pool: &DbPool,
+    queue: &Sender<InternalMessage>,
+    jwt: JWTToken,
+    light_id: &str,
+    state: LightState,
+) -> Result<(), ApiError> {
+    set_light_state(pool, &jwt, light_id, state).await?;
+
+    if let Ok(light) = get_light(pool, &jwt, light_id).await {
+        let _ = queue.send(InternalMessage::light_update(light, jwt));
+    }
+
+    Ok(())
+}
+
+/// See [`apply_light_state`].
+pub async fn apply_plug_state(
+    pool: &DbPool,
+    queue: &Sender<InternalMessage>,
+    jwt: JWTToken,
+    plug_id: &str,
+    state: PlugState,
+) -> Result<(), ApiError> {
+    set_plug_state(pool, &jwt, plug_id, state).await?;
+
+    if let Ok(plug) = get_plug(pool, &jwt, plug_id).await {
+        let _ = queue.send(InternalMessage::plug_update(plug, jwt));
+    }
+
+    Ok(())
+}
+
+#[openapi]
+#[get("/status")]
+fn status() -> Json<StatusResponse> {
+    let status = StatusResponse {
+        status: "OK".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+
+    Json(status)
+}
+
+/// For monitoring, only reports counts. Fails with `503` when the database is
+/// unavailable.
+#[openapi]
+#[get("/health")]
+async fn health(pool: &State<DbPool>) -> Result<Json<HealthResponse>, ApiError> {
+    let (hue_bridges, shelly_devices, tasmota_devices, zigbee2mqtt_bridges, lifx_devices) =
+        connection::run(pool, |connection| {
+            Ok((
+                HueBridge::get_huebridges(connection)?,
+                ShellyDevice::get_shelly_devices(connection)?,
+                TasmotaDevice::get_tasmota_devices(connection)?,
+                Zigbee2MqttBridge::get_zigbee2mqtt_bridges(connection)?,
+                LifxDevice::get_lifx_devices(connection)?,
+            ))
+        })
+        .await?;
+
+    let providers = vec![
+        hue::provider_health(&hue_bridges),
+        shelly::provider_health(&shelly_devices),
+        tasmota::provider_health(&tasmota_devices),
+        zigbee2mqtt::provider_health(&zigbee2mqtt_bridges),
+        lifx::provider_health(&lifx_devices),
+    ];
+
+    let status = if providers.iter().all(|provider| provider.failing == 0) {
+        "ok"
+    } else {
+        "degraded"
+    };
+
+    Ok(Json(HealthResponse {
+        status: status.to_owned(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        providers,
+    }))
+}
+
+/// `room` is compared ignoring case.
+#[openapi(tag = "Main")]
+#[get("/lights?<room>&<favorite>")]
+pub async fn lights(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    room: Option<String>,
+    favorite: Option<bool>,
+) -> Result<ETagJson<DeviceList<NormalizedLight>>, ApiError> {
+    let response = get_lights(pool, &jwt, room.as_deref(), favorite).await?;
+
+    Ok(ETagJson(response))
+}
+
+#[openapi(tag = "Main")]
+#[get("/lights/<light_id>")]
+pub async fn light(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    light_id: String,
+) -> Result<Json<NormalizedLight>, ApiError> {
+    let response = get_light(pool, &jwt, &light_id).await?;
+
+    Ok(Json(response))
+}
+
+#[openapi(tag = "Main")]
+#[patch("/lights/<light_id>", format = "json", data = "<metadata>")]
+pub async fn update_light(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    light_id: String,
+    metadata: Validated<DeviceMetadataRequest>,
+    queue: &State<Sender<InternalMessage>>,
+) -> Result<Json<DeviceMetadataResponse>, ApiError> {
+    let metadata = set_metadata(pool, &jwt, &light_id, metadata.into_inner()).await?;
+
+    let pool = pool.inner().clone();
+    let queue = queue.inner().clone();
+    let stored = metadata.clone();
+
+    rocket::tokio::spawn(async move {
+        if let Ok(mut light) = get_device_light(&pool, &jwt, &light_id).await {
+            apply_metadata(&mut light, &[stored]);
+            let _ = queue.send(InternalMessage::light_update(light, jwt));
+        }
+    });
+
+    Ok(Json(metadata.into()))
+}
+
+#[openapi(tag = "Main")]
+#[put("/lights/<light_id>/state", format = "json", data = "<state>")]
+pub async fn set_light(
+    jwt: JWTToken,
+    _limit: RateLimit<'_>,
+    pool: &State<DbPool>,
+    light_id: String,
+    state: Json<LightState>,
+    queue: &State<Sender<InternalMessage>>,
+) -> Result<Json<Value>, ApiError> {
+    apply_light_state(pool, queue, jwt, &light_id, state.into_inner()).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// `room` is compared ignoring case.
+#[openapi(tag = "Main")]
+#[get("/plugs?<room>&<favorite>")]
+pub async fn plugs(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    room: Option<String>,
+    favorite: Option<bool>,
+) -> Result<ETagJson<DeviceList<NormalizedPlug>>, ApiError> {
+    let response = get_plugs(pool, &jwt, room.as_deref(), favorite).await?;
+
+    Ok(ETagJson(response))
+}
+
+#[openapi(tag = "Main")]
+#[get("/plugs/<plug_id>")]
+pub async fn plug(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    plug_id: String,
+) -> Result<Json<NormalizedPlug>, ApiError> {
+    let response = get_plug(pool, &jwt, &plug_id).await?;
+
+    Ok(Json(response))
+}
+
+#[openapi(tag = "Main")]
+#[patch("/plugs/<plug_id>", format = "json", data = "<metadata>")]
+pub async fn update_plug(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    plug_id: String,
+    metadata: Validated<DeviceMetadataRequest>,
+    queue: &State<Sender<InternalMessage>>,
+) -> Result<Json<DeviceMetadataResponse>, ApiError> {
+    let metadata = set_metadata(pool, &jwt, &plug_id, metadata.into_inner()).await?;
+
+    let pool = pool.inner().clone();
+    let queue = queue.inner().clone();
+    let stored = metadata.clone();
+
+    rocket::tokio::spawn(async move {
+        if let Ok(mut plug) = get_device_plug(&pool, &jwt, &plug_id).await {
+            apply_metadata(&mut plug, &[stored]);
+            let _ = queue.send(InternalMessage::plug_update(plug, jwt));
+        }
+    });
+
+    Ok(Json(metadata.into()))
+}
+
+#[openapi(tag = "Main")]
+#[put("/plugs/<plug_id>/state", format = "json", data = "<state>")]
+pub async fn set_plug(
+    jwt: JWTToken,
+    _limit: RateLimit<'_>,
+    pool: &State<DbPool>,
+    plug_id: String,
+    state: Json<PlugState>,
+    queue: &State<Sender<InternalMessage>>,
+) -> Result<Json<Value>, ApiError> {
+    apply_plug_state(pool, queue, jwt, &plug_id, state.into_inner()).await?;
+
+    Ok(Json(json!({})))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        settings: status,
+        health,
+        lights,
+        light,
+        update_light,
+        set_light,
+        plugs,
+        plug,
+        update_plug,
+        set_plug
+    ]
+}
@@ -0,0 +1,679 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use rocket::{
+    fairing::AdHoc,
+    tokio::{
+        self, select,
+        sync::broadcast::{error::RecvError, Sender},
+        time::{sleep, timeout},
+    },
+    Shutdown,
+};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS};
+use schemars::_serde_json::{self, Value};
+
+use crate::{
+    config::app_config,
+    db::{
+        connection::{self, DbPool},
+        models::{User, Zigbee2MqttBridge},
+    },
+    repsonses::ApiError,
+    InternalMessage,
+};
+
+use super::{
+    homeassistant,
+    main::{
+        apply_light_state, apply_plug_state, get_device_light, get_lights, get_plugs,
+        LightState, NormalizedLight, NormalizedPlug, PlugState,
+    },
+    zigbee2mqtt,
+};
+
+static KEEP_ALIVE: Duration = Duration::from_secs(30);
+static RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Requests queued for the event loop before publishing waits.
+static CAPACITY: usize = 64;
+/// Time to collect the retained discovery configs when running without the
+/// server, see [`remove_bridge_configs`].
+static RETAINED_WAIT: Duration = Duration::from_secs(2);
+
+/// Set once connected, used by providers that talk MQTT themselves.
+static CLIENT: OnceLock<AsyncClient> = OnceLock::new();
+
+/// A Home Assistant discovery config retained on the broker.
+struct Discovered {
+    payload: String,
+    user_id: i32,
+    device_id: String,
+}
+
+/// Discovery configs by topic, also filled from the retained configs of
+/// earlier runs so they can be removed after a restart.
+static DISCOVERED: OnceLock<Mutex<HashMap<String, Discovered>>> = OnceLock::new();
+
+fn discovered() -> &'static Mutex<HashMap<String, Discovered>> {
+    DISCOVERED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn discovery_enabled() -> bool {
+    !app_config().mqtt.discovery_prefix.is_empty()
+}
+
+/// Connects to `mqtt.host` once the server is running. Every
+/// `InternalMessage` is published retained to
+/// `<prefix>/<user id>/<device id>/state` and JSON states sent to
+/// `<prefix>/<user id>/<device id>/set` are applied like `PUT .../state`.
+/// Lights and plugs are announced to Home Assistant via MQTT discovery.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("MQTT bridge", |rocket| {
+        Box::pin(async move {
+            let config = &app_config().mqtt;
+
+            let host = match &config.host {
+                Some(host) => host.clone(),
+                None => return,
+            };
+
+            let pool = rocket.state::<DbPool>().cloned();
+            let queue = rocket.state::<Sender<InternalMessage>>().cloned();
+
+            if let (Some(pool), Some(queue)) = (pool, queue) {
+                let mut options = MqttOptions::new(&config.client_id, host, config.port);
+                options.set_keep_alive(KEEP_ALIVE);
+
+                if let Some(username) = &config.username {
+                    options.set_credentials(username, config.password.clone().unwrap_or_default());
+                }
+
+                let (client, eventloop) = AsyncClient::new(options, CAPACITY);
+
+                start(client, eventloop, pool, queue, rocket.shutdown());
+            }
+        })
+    })
+}
+
+/// Spawns the tasks of the bridge on the current runtime.
+pub(crate) fn start(
+    client: AsyncClient,
+    eventloop: EventLoop,
+    pool: DbPool,
+    queue: Sender<InternalMessage>,
+    shutdown: Shutdown,
+) {
+    let _ = CLIENT.set(client.clone());
+
+    tokio::spawn(publish_updates(
+        client.clone(),
+        queue.clone(),
+        shutdown.clone(),
+    ));
+    tokio::spawn(announce_devices(
+        client.clone(),
+        pool.clone(),
+        shutdown.clone(),
+    ));
+    tokio::spawn(run(client, eventloop, pool, queue, shutdown));
+}
+
+/// Device updates carry the device, bridge events the bridge as `id`.
+fn device_id(message: &InternalMessage) -> Option<String> {
+    let data: Value = _serde_json::from_str(&message.data).ok()?;
+
+    match data.get("id")? {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+async fn publish(client: &AsyncClient, topic: String, payload: String) {
+    if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, true, payload).await {
+        println!("Error publishing {} over MQTT: {}", topic, e);
+    }
+}
+
+/// Publishes a message that is not retained, e.g. a command to another
+/// client.
+pub async fn send(topic: String, payload: String) -> Result<(), ApiError> {
+    let client = CLIENT
+        .get()
+        .ok_or_else(|| ApiError::BadRequest("MQTT is not configured".to_string()))?;
+
+    client
+        .publish(&topic, QoS::AtLeastOnce, false, payload)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Error publishing {}: {}", topic, e)))
+}
+
+/// Subscribes right away if connected, otherwise on connecting.
+pub async fn subscribe(topic: String) {
+    if let Some(client) = CLIENT.get() {
+        if let Err(e) = client.subscribe(&topic, QoS::AtLeastOnce).await {
+            println!("Error subscribing to {}: {}", topic, e);
+        }
+    }
+}
+
+/// Subscribes to the base topics of all Zigbee2MQTT bridges.
+async fn subscribe_zigbee2mqtt(client: AsyncClient, pool: DbPool) {
+    let bridges = connection::run(&pool, |connection| {
+        Ok(Zigbee2MqttBridge::get_zigbee2mqtt_bridges(connection)?)
+    })
+    .await;
+
+    for bridge in bridges.unwrap_or_default() {
+        let topic = zigbee2mqtt::watch(&bridge.base_topic);
+
+        if let Err(e) = client.subscribe(&topic, QoS::AtLeastOnce).await {
+            println!("Error subscribing to {}: {}", topic, e);
+        }
+    }
+}
+
+/// Publishes the config unless the broker already retains the same one.
+async fn announce(
+    client: &AsyncClient,
+    user_id: i32,
+    device_id: &str,
+    discovery: (String, Value),
+) {
+    if !discovery_enabled() {
+        return;
+    }
+
+    let (topic, config) = discovery;
+    let payload = config.to_string();
+
+    {
+        let mut discovered = discovered().lock().unwrap();
+
+        if discovered
+            .get(&topic)
+            .is_some_and(|discovered| discovered.payload == payload)
+        {
+            return;
+        }
+
+        discovered.insert(
+            topic.clone(),
+            Discovered {
+                payload: payload.clone(),
+                user_id,
+                device_id: device_id.to_owned(),
+            },
+        );
+    }
+
+    publish(client, topic, payload).await;
+}
+
+async fn announce_light(client: &AsyncClient, user_id: i32, light: &NormalizedLight) {
+    announce(client, user_id, &light.id, homeassistant::light_config(user_id, light)).await;
+}
+
+async fn announce_plug(client: &AsyncClient, user_id: i32, plug: &NormalizedPlug) {
+    announce(client, user_id, &plug.id, homeassistant::switch_config(user_id, plug)).await;
+}
+
+/// Clears the retained configs and states of all devices of a deleted
+/// bridge, whose ids start with its bridgeid or, for bridges that never
+/// reported one, its id.
+async fn remove_bridge(client: &AsyncClient, message: &InternalMessage) {
+    let data: Value = match _serde_json::from_str(&message.data) {
+        Ok(data) => data,
+        Err(_) => return,
+    };
+
+    let prefixes: Vec<String> = ["id", "bridgeid"]
+        .iter()
+        .filter_map(|key| data.get(*key).and_then(|id| id.as_str()))
+        .map(|id| format!("hue-{}-", id))
+        .collect();
+
+    let removed: Vec<(String, String)> = {
+        let mut discovered = discovered().lock().unwrap();
+
+        let topics: Vec<String> = discovered
+            .iter()
+            .filter(|(_, discovered)| {
+                discovered.user_id == message.user_id
+                    && prefixes
+                        .iter()
+                        .any(|prefix| discovered.device_id.starts_with(prefix.as_str()))
+            })
+            .map(|(topic, _)| topic.clone())
+            .collect();
+
+        topics
+            .into_iter()
+            .filter_map(|topic| {
+                discovered
+                    .remove(&topic)
+                    .map(|discovered| (topic, discovered.device_id))
+            })
+            .collect()
+    };
+
+    for (topic, device_id) in removed {
+        publish(client, topic, String::new()).await;
+        publish(
+            client,
+            homeassistant::state_topic(message.user_id, &device_id),
+            String::new(),
+        )
+        .await;
+    }
+}
+
+/// Clears the Home Assistant configs of a bridge removed while the server is
+/// not running, as done for `bridge_deleted` messages. The retained configs
+/// are collected for `RETAINED_WAIT` first.
+pub async fn remove_bridge_configs(message: &InternalMessage) -> Result<(), String> {
+    let config = &app_config().mqtt;
+
+    let host = match &config.host {
+        Some(host) if discovery_enabled() => host.clone(),
+        _ => return Ok(()),
+    };
+
+    let mut options = MqttOptions::new(format!("{}-cli", config.client_id), host, config.port);
+    options.set_keep_alive(KEEP_ALIVE);
+
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(options, CAPACITY);
+
+    for topic in homeassistant::config_subscriptions() {
+        client
+            .subscribe(&topic, QoS::AtLeastOnce)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let collect = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::Publish(publish))) => track_config(&publish),
+                Ok(_) => {}
+                Err(e) => return format!("MQTT connection error: {}", e),
+            }
+        }
+    };
+
+    if let Ok(e) = timeout(RETAINED_WAIT, collect).await {
+        return Err(e);
+    }
+
+    let driver = tokio::spawn(async move { while eventloop.poll().await.is_ok() {} });
+
+    remove_bridge(&client, message).await;
+
+    let _ = client.disconnect().await;
+    let _ = timeout(RETAINED_WAIT, driver).await;
+
+    Ok(())
+}
+
+async fn publish_updates(
+    client: AsyncClient,
+    queue: Sender<InternalMessage>,
+    mut shutdown: Shutdown,
+) {
+    let mut rx = queue.subscribe();
+
+    loop {
+        let message = select! {
+            message = rx.recv() => match message {
+                Ok(message) => message,
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(_)) => continue,
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let device_id = match device_id(&message) {
+            Some(device_id) => device_id,
+            None => continue,
+        };
+
+        let topic = homeassistant::state_topic(message.user_id, &device_id);
+
+        match message._type.as_str() {
+            "light_update" => {
+                if let Ok(light) = _serde_json::from_str(&message.data) {
+                    announce_light(&client, message.user_id, &light).await;
+                }
+            }
+            "plug_update" => {
+                if let Ok(plug) = _serde_json::from_str(&message.data) {
+                    announce_plug(&client, message.user_id, &plug).await;
+                }
+            }
+            "bridge_deleted" => {
+                remove_bridge(&client, &message).await;
+                // Drops the retained `bridge_readdressed` state as well
+                publish(&client, topic, String::new()).await;
+                continue;
+            }
+            _ => {}
+        }
+
+        publish(&client, topic, message.data).await;
+    }
+}
+
+/// Publishes the state and discovery config of every device of every user
+/// every `discovery_interval` seconds, devices are otherwise only published
+/// when they change.
+async fn announce_devices(client: AsyncClient, pool: DbPool, mut shutdown: Shutdown) {
+    let interval = Duration::from_secs(app_config().mqtt.discovery_interval);
+
+    loop {
+        let users = connection::run(&pool, |connection| Ok(User::get_users(connection)?)).await;
+
+        for user in users.unwrap_or_default() {
+            let jwt = user.token();
+
+            if let Ok(lights) = get_lights(&pool, &jwt, None, None).await {
+                for light in lights.items {
+                    announce_light(&client, user.id, &light).await;
+
+                    if let Ok(state) = _serde_json::to_string(&light) {
+                        publish(&client, homeassistant::state_topic(user.id, &light.id), state)
+                            .await;
+                    }
+                }
+            }
+
+            if let Ok(plugs) = get_plugs(&pool, &jwt, None, None).await {
+                for plug in plugs.items {
+                    announce_plug(&client, user.id, &plug).await;
+
+                    if let Ok(state) = _serde_json::to_string(&plug) {
+                        publish(&client, homeassistant::state_topic(user.id, &plug.id), state)
+                            .await;
+                    }
+                }
+            }
+        }
+
+        select! {
+            _ = sleep(interval) => {},
+            _ = &mut shutdown => break,
+        }
+    }
+}
+
+/// Keeps `DISCOVERED` in line with the retained configs on the broker.
+fn track_config(publish: &Publish) {
+    let mut discovered = discovered().lock().unwrap();
+
+    if publish.payload.is_empty() {
+        discovered.remove(&publish.topic);
+        return;
+    }
+
+    let config: Value = match _serde_json::from_slice(&publish.payload) {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+
+    if let Some((user_id, device_id)) = homeassistant::config_owner(&config) {
+        discovered.insert(
+            publish.topic.clone(),
+            Discovered {
+                payload: config.to_string(),
+                user_id,
+                device_id,
+            },
+        );
+    }
+}
+
+/// Drives the connection; `poll` reconnects by itself after an error.
+async fn run(
+    client: AsyncClient,
+    mut eventloop: EventLoop,
+    pool: DbPool,
+    queue: Sender<InternalMessage>,
+    mut shutdown: Shutdown,
+) {
+    let topic = format!("{}/+/+/set", app_config().mqtt.topic_prefix);
+
+    loop {
+        let event = select! {
+            event = eventloop.poll() => event,
+            _ = &mut shutdown => break,
+        };
+
+        match event {
+            // Clean sessions lose their subscriptions on every reconnect
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                let mut topics = vec![topic.clone()];
+
+                if discovery_enabled() {
+                    topics.extend(homeassistant::config_subscriptions());
+                }
+
+                for topic in topics {
+                    if let Err(e) = client.try_subscribe(&topic, QoS::AtLeastOnce) {
+                        println!("Error subscribing to {}: {}", topic, e);
+                    }
+                }
+
+                tokio::spawn(subscribe_zigbee2mqtt(client.clone(), pool.clone()));
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                if parse_topic(&publish.topic).is_some() {
+                    tokio::spawn(handle_command(pool.clone(), queue.clone(), publish));
+                } else if !zigbee2mqtt::handle_message(&publish.topic, &publish.payload) {
+                    track_config(&publish);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("MQTT connection error: {}", e);
+                sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+
+    let _ = client.try_disconnect();
+}
+
+/// Splits `<prefix>/<user id>/<device id>/set`.
+fn parse_topic(topic: &str) -> Option<(i32, String)> {
+    let rest = topic
+        .strip_prefix(app_config().mqtt.topic_prefix.as_str())?
+        .strip_prefix('/')?
+        .strip_suffix("/set")?;
+
+    let (user_id, device_id) = rest.split_once('/')?;
+
+    if device_id.is_empty() || device_id.contains('/') {
+        return None;
+    }
+
+    Some((user_id.parse().ok()?, device_id.to_owned()))
+}
+
+async fn handle_command(pool: DbPool, queue: Sender<InternalMessage>, publish: Publish) {
+    if let Err(e) = apply_command(&pool, &queue, &publish).await {
+        println!("Error applying MQTT command on {}: {}", publish.topic, e);
+    }
+}
+
+/// Lights get the whole payload as `LightState`, any other device only `on`
+/// as `PlugState`.
+async fn apply_command(
+    pool: &DbPool,
+    queue: &Sender<InternalMessage>,
+    publish: &Publish,
+) -> Result<(), ApiError> {
+    let (user_id, device_id) = parse_topic(&publish.topic)
+        .ok_or_else(|| ApiError::BadRequest("Invalid topic".to_string()))?;
+
+    let state: LightState = _serde_json::from_slice(&publish.payload)
+        .map_err(|_| ApiError::BadRequest("Invalid payload".to_string()))?;
+
+    let jwt = connection::run(pool, move |connection| {
+        Ok(User::get_user(connection, user_id)?.token())
+    })
+    .await?;
+
+    // Hue answers `400` for a plug, other providers `404`
+    match get_device_light(pool, &jwt, &device_id).await {
+        Ok(_) => apply_light_state(pool, queue, jwt, &device_id, state).await,
+        Err(ApiError::NotFound(_) | ApiError::BadRequest(_)) => {
+            apply_plug_state(pool, queue, jwt, &device_id, PlugState { on: state.on }).await
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schemars::_serde_json::json;
+
+    use crate::utils::testing::init_config;
+
+    use super::*;
+
+    #[test]
+    fn parses_command_topics() {
+        init_config();
+
+        assert_eq!(
+            parse_topic("homeapi/1/zigbee2mqtt-0x00158d0001a2b3c4-main/set"),
+            Some((1, "zigbee2mqtt-0x00158d0001a2b3c4-main".to_string()))
+        );
+        assert_eq!(
+            parse_topic("homeapi/12/hue-1-2/set"),
+            Some((12, "hue-1-2".to_string()))
+        );
+
+        assert_eq!(parse_topic("other/1/hue-1-2/set"), None);
+        assert_eq!(parse_topic("homeapiextra/1/hue-1-2/set"), None);
+        assert_eq!(parse_topic("homeapi/1/hue-1-2/state"), None);
+        assert_eq!(parse_topic("homeapi/one/hue-1-2/set"), None);
+        assert_eq!(parse_topic("homeapi/1//set"), None);
+        assert_eq!(parse_topic("homeapi/1/set"), None);
+        assert_eq!(parse_topic("homeapi/1/hue/1/set"), None);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[rocket::async_test]
+    async fn applies_commands_and_publishes_retained_states() {
+        use crate::{
+            db::models::{NewUser, NewZigbee2MqttBridge},
+            utils::testing::{
+                eventually, mqtt_server, next_publish, publisher, subscriber, wait_for_subscription,
+            },
+        };
+
+        let server = mqtt_server();
+        let base_topic = "mqtt-test";
+
+        let (user, bridge) = connection::run(&server.pool, move |connection| {
+            let user = User::create_user(
+                connection,
+                &NewUser {
+                    username: "mqtt",
+                    email: "mqtt@example.com",
+                    hashed_password: "",
+                },
+            )?;
+            let bridge = Zigbee2MqttBridge::create_zigbee2mqtt_bridge(
+                connection,
+                &NewZigbee2MqttBridge {
+                    base_topic,
+                    user_id: &user.id,
+                },
+            )?;
+
+            Ok((user, bridge))
+        })
+        .await
+        .unwrap();
+
+        subscribe(zigbee2mqtt::watch(base_topic)).await;
+        wait_for_subscription("mqtt-test/#").await;
+        wait_for_subscription("homeapi/+/+/set").await;
+
+        let devices = json!([{
+            "ieee_address": "0x00158d0001a2b3c4",
+            "friendly_name": "Lamp",
+            "type": "Router",
+            "definition": {
+                "model": "LED1545G12",
+                "vendor": "IKEA",
+                "exposes": [{
+                    "type": "light",
+                    "features": [
+                        {"type": "binary", "name": "state", "property": "state",
+                         "value_on": "ON", "value_off": "OFF"},
+                        {"type": "numeric", "name": "brightness", "property": "brightness",
+                         "value_max": 254}
+                    ]
+                }]
+            }
+        }]);
+
+        let client = publisher().await;
+        for (topic, payload) in [
+            ("mqtt-test/bridge/state", json!({"state": "online"})),
+            ("mqtt-test/bridge/devices", devices),
+            ("mqtt-test/Lamp", json!({"state": "OFF", "brightness": 127})),
+        ] {
+            client
+                .publish(topic, QoS::AtLeastOnce, true, payload.to_string())
+                .await
+                .unwrap();
+        }
+
+        let bridges = vec![bridge];
+        let light =
+            eventually(|| zigbee2mqtt::get_light(&bridges, "0x00158d0001a2b3c4", "main").ok())
+                .await;
+        assert!(!light.on);
+
+        let state_topic = homeassistant::state_topic(user.id, &light.id);
+        let (_commands, mut commands) = subscriber(&["mqtt-test/Lamp/set"]).await;
+        let (_states, mut states) = subscriber(&[state_topic.as_str()]).await;
+
+        client
+            .publish(
+                format!("homeapi/{}/{}/set", user.id, light.id),
+                QoS::AtLeastOnce,
+                false,
+                json!({"on": true}).to_string(),
+            )
+            .await
+            .unwrap();
+
+        let command = next_publish(&mut commands, "mqtt-test/Lamp/set").await;
+        let command: Value = _serde_json::from_slice(&command.payload).unwrap();
+        assert_eq!(command, json!({"state": "ON"}));
+
+        let state = next_publish(&mut states, &state_topic).await;
+        let state: Value = _serde_json::from_slice(&state.payload).unwrap();
+        assert_eq!(state["id"], json!(light.id));
+        assert_eq!(state["on"], json!(true));
+
+        // Home Assistant subscribing later still gets the last state
+        let (_late, mut late) = subscriber(&[state_topic.as_str()]).await;
+        let retained = next_publish(&mut late, &state_topic).await;
+        let retained_state: Value = _serde_json::from_slice(&retained.payload).unwrap();
+        assert!(retained.retain);
+        assert_eq!(retained_state["on"], json!(true));
+    }
+}
@@ -0,0 +1,804 @@
+use okapi::openapi3::OpenApi;
+use rocket::{delete, get, put, serde::json::Json, State};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    JsonSchema,
+    _serde_json::{self, json, Value},
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::auth::JWTToken,
+    db::{
+        connection::{self, DbPool},
+        models::{NewShellyDevice, ShellyDevice, UpdateShellyDevice},
+    },
+    repsonses::ApiError,
+    utils::extensions::ValueExt,
+    validation::{validate_host, Validate, Validated, ValidationErrors},
+};
+
+use super::main::{
+    device_client, DeviceOverlay, FailureTracker, LightState, NormalizedColor, NormalizedLight,
+    NormalizedPlug, PlugState, ProviderHealth,
+};
+
+static PROVIDER: &str = "shelly";
+
+fn shelly_error<E: Into<ApiError>>(error: E) -> ApiError {
+    error.into().with_provider(PROVIDER)
+}
+
+static FAILURES: FailureTracker = FailureTracker::new(PROVIDER);
+
+pub fn provider_health(devices: &[ShellyDevice]) -> ProviderHealth {
+    FAILURES.health(devices.iter().map(|device| device.id))
+}
+
+async fn get_json(ip: &str, path: &str) -> Result<Value, ApiError> {
+    let response = device_client()
+        .get(format!("http://{}/{}", ip, path))
+        .send()
+        .await
+        .map_err(shelly_error)?;
+
+    if !response.status().is_success() {
+        return Err(ApiError::Upstream {
+            provider: PROVIDER.to_owned(),
+            status: Some(response.status().as_u16()),
+            message: "Unexpected response status".to_owned(),
+        });
+    }
+
+    let body = response.text().await.map_err(shelly_error)?;
+
+    _serde_json::from_str(&body).map_err(shelly_error)
+}
+
+struct DeviceInfo {
+    device_id: String,
+    generation: i32,
+    model: String,
+}
+
+/// Both generations answer `/shelly` without authentication, only Gen2 and
+/// newer include `gen`.
+async fn identify(ip: &str) -> Result<DeviceInfo, ApiError> {
+    let info = get_json(ip, "shelly").await?;
+
+    let generation = info.get("gen").and_then(|gen| gen.as_i64()).unwrap_or(1) as i32;
+
+    let model = match generation {
+        1 => info.get("type"),
+        _ => info.get("model"),
+    };
+
+    Ok(DeviceInfo {
+        device_id: info
+            .get("mac")
+            .to_string()
+            .map_err(shelly_error)?
+            .to_lowercase()
+            .replace(':', ""),
+        generation,
+        model: model.to_string().unwrap_or_default(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChannelKind {
+    Relay,
+    Dimmer,
+    Color,
+}
+
+/// One output of a device, as reported by either generation.
+#[derive(Debug)]
+struct Channel {
+    index: u32,
+    kind: ChannelKind,
+    name: Option<String>,
+    on: bool,
+    /// Percent
+    brightness: Option<f64>,
+    rgb: Option<(u8, u8, u8)>,
+    /// Watts
+    power: Option<f64>,
+    /// Watt-hours
+    energy: Option<f64>,
+}
+
+struct Snapshot {
+    channels: Vec<Channel>,
+    swversion: String,
+}
+
+fn name_of(config: Option<&Value>) -> Option<String> {
+    config
+        .and_then(|config| config.get("name"))
+        .and_then(|name| name.as_str())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+}
+
+fn color_of(
+    red: Option<&Value>,
+    green: Option<&Value>,
+    blue: Option<&Value>,
+) -> Option<(u8, u8, u8)> {
+    Some((
+        red?.as_u64()? as u8,
+        green?.as_u64()? as u8,
+        blue?.as_u64()? as u8,
+    ))
+}
+
+/// `/status` lists `relays` and `lights` with `meters` in the same order,
+/// `/settings` holds the names. Energy is counted in watt-minutes.
+fn parse_gen1(settings: &Value, status: &Value) -> Snapshot {
+    let mut channels = Vec::new();
+    let meters = status.get("meters").and_then(|meters| meters.as_array());
+    let meter = |index: usize, field: &str| {
+        meters
+            .and_then(|meters| meters.get(index))
+            .and_then(|meter| meter.get(field))
+            .and_then(|value| value.as_f64())
+    };
+
+    let relays = status.get("relays").and_then(|relays| relays.as_array());
+
+    for (index, relay) in relays.into_iter().flatten().enumerate() {
+        channels.push(Channel {
+            index: index as u32,
+            kind: ChannelKind::Relay,
+            name: name_of(settings.get("relays").and_then(|relays| relays.get(index))),
+            on: relay.get("ison").and_then(|on| on.as_bool()).unwrap_or(false),
+            brightness: None,
+            rgb: None,
+            power: meter(index, "power"),
+            energy: meter(index, "total").map(|total| total / 60.0),
+        });
+    }
+
+    let color_mode = settings.get("mode").and_then(|mode| mode.as_str()) == Some("color");
+    let lights = status.get("lights").and_then(|lights| lights.as_array());
+
+    for (index, light) in lights.into_iter().flatten().enumerate() {
+        let rgb = color_of(light.get("red"), light.get("green"), light.get("blue"))
+            .filter(|_| color_mode);
+
+        channels.push(Channel {
+            index: index as u32,
+            kind: match rgb {
+                Some(_) => ChannelKind::Color,
+                None => ChannelKind::Dimmer,
+            },
+            name: name_of(settings.get("lights").and_then(|lights| lights.get(index))),
+            on: light.get("ison").and_then(|on| on.as_bool()).unwrap_or(false),
+            brightness: light
+                .get("gain")
+                .filter(|_| rgb.is_some())
+                .or(light.get("brightness"))
+                .and_then(|brightness| brightness.as_f64()),
+            rgb,
+            power: meter(index, "power"),
+            energy: meter(index, "total").map(|total| total / 60.0),
+        });
+    }
+
+    Snapshot {
+        channels,
+        swversion: settings.get("fw").to_string().unwrap_or_default(),
+    }
+}
+
+/// Components are keyed `<type>:<index>` in both `GetStatus` and `GetConfig`.
+fn parse_gen2(config: &Value, status: &Value) -> Snapshot {
+    let mut channels = Vec::new();
+
+    for (key, component) in status.as_object().into_iter().flatten() {
+        let (kind, index) = match key.split_once(':') {
+            Some((kind, index)) => (kind, index),
+            None => continue,
+        };
+
+        let kind = match kind {
+            "switch" => ChannelKind::Relay,
+            "light" => ChannelKind::Dimmer,
+            "rgb" | "rgbw" => ChannelKind::Color,
+            _ => continue,
+        };
+
+        let index = match index.parse() {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+
+        let rgb = component.get("rgb").and_then(|rgb| rgb.as_array());
+
+        channels.push(Channel {
+            index,
+            kind,
+            name: name_of(config.get(key)),
+            on: component
+                .get("output")
+                .and_then(|on| on.as_bool())
+                .unwrap_or(false),
+            brightness: component
+                .get("brightness")
+                .and_then(|brightness| brightness.as_f64()),
+            rgb: rgb.and_then(|rgb| color_of(rgb.first(), rgb.get(1), rgb.get(2))),
+            power: component.get("apower").and_then(|power| power.as_f64()),
+            energy: component
+                .get("aenergy")
+                .and_then(|energy| energy.get("total"))
+                .and_then(|total| total.as_f64()),
+        });
+    }
+
+    channels.sort_by_key(|channel| (channel.kind as u8, channel.index));
+
+    Snapshot {
+        channels,
+        swversion: config
+            .get("sys")
+            .and_then(|sys| sys.get("device"))
+            .and_then(|device| device.get("fw_id"))
+            .to_string()
+            .unwrap_or_default(),
+    }
+}
+
+async fn get_snapshot(device: &ShellyDevice) -> Result<Snapshot, ApiError> {
+    let result = async {
+        match device.generation {
+            1 => {
+                let settings = get_json(&device.ip, "settings").await?;
+                let status = get_json(&device.ip, "status").await?;
+
+                Ok(parse_gen1(&settings, &status))
+            }
+            _ => {
+                let config = get_json(&device.ip, "rpc/Shelly.GetConfig").await?;
+                let status = get_json(&device.ip, "rpc/Shelly.GetStatus").await?;
+
+                Ok(parse_gen2(&config, &status))
+            }
+        }
+    }
+    .await;
+
+    FAILURES.record(device.id, &result);
+
+    result
+}
+
+fn device_name(device: &ShellyDevice, channel: &Channel) -> String {
+    match &channel.name {
+        Some(name) => name.clone(),
+        None => format!("{} {}", device.model, channel.index),
+    }
+}
+
+fn channel_id(device: &ShellyDevice, channel: &Channel) -> String {
+    format!("shelly-{}-{}", device.device_id, channel.index)
+}
+
+fn to_plug(device: &ShellyDevice, snapshot: &Snapshot, channel: &Channel) -> NormalizedPlug {
+    NormalizedPlug {
+        id: channel_id(device, channel),
+        legacy_id: None,
+        name: device_name(device, channel),
+        on: channel.on,
+        reachable: true,
+        type_: "Relay".to_owned(),
+        model: device.model.clone(),
+        manufacturer: "Shelly".to_owned(),
+        uniqueid: format!("{}-{}", device.device_id, channel.index),
+        swversion: snapshot.swversion.clone(),
+        productid: None,
+        power: channel.power.map(|power| power as f32),
+        energy: channel.energy.map(|energy| energy as f32),
+        overlay: DeviceOverlay::default(),
+    }
+}
+
+fn to_light(device: &ShellyDevice, snapshot: &Snapshot, channel: &Channel) -> NormalizedLight {
+    NormalizedLight {
+        id: channel_id(device, channel),
+        legacy_id: None,
+        name: device_name(device, channel),
+        on: channel.on,
+        brightness: (channel.brightness.unwrap_or(0.0) / 100.0) as f32,
+        color: channel
+            .rgb
+            .map(|(red, green, blue)| NormalizedColor(red, green, blue))
+            .into_iter()
+            .collect(),
+        color_temperature: None,
+        reachable: true,
+        type_: match channel.kind {
+            ChannelKind::Color => "Color light",
+            _ => "Dimmable light",
+        }
+        .to_owned(),
+        model: device.model.clone(),
+        manufacturer: "Shelly".to_owned(),
+        uniqueid: format!("{}-{}", device.device_id, channel.index),
+        swversion: snapshot.swversion.clone(),
+        productid: None,
+        overlay: DeviceOverlay::default(),
+    }
+}
+
+fn find_channel(snapshot: &Snapshot, channel: &str, relay: bool) -> Result<usize, ApiError> {
+    snapshot
+        .channels
+        .iter()
+        .position(|candidate| {
+            (candidate.kind == ChannelKind::Relay) == relay
+                && channel.parse() == Ok(candidate.index)
+        })
+        .ok_or(ApiError::NotFound("Unknown device".to_string()))
+}
+
+pub async fn get_plugs(device: &ShellyDevice) -> Result<Vec<NormalizedPlug>, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+
+    Ok(snapshot
+        .channels
+        .iter()
+        .filter(|channel| channel.kind == ChannelKind::Relay)
+        .map(|channel| to_plug(device, &snapshot, channel))
+        .collect())
+}
+
+pub async fn get_plug(device: &ShellyDevice, channel: &str) -> Result<NormalizedPlug, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+    let index = find_channel(&snapshot, channel, true)?;
+
+    Ok(to_plug(device, &snapshot, &snapshot.channels[index]))
+}
+
+pub async fn get_lights(device: &ShellyDevice) -> Result<Vec<NormalizedLight>, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+
+    Ok(snapshot
+        .channels
+        .iter()
+        .filter(|channel| channel.kind != ChannelKind::Relay)
+        .map(|channel| to_light(device, &snapshot, channel))
+        .collect())
+}
+
+pub async fn get_light(device: &ShellyDevice, channel: &str) -> Result<NormalizedLight, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+    let index = find_channel(&snapshot, channel, false)?;
+
+    Ok(to_light(device, &snapshot, &snapshot.channels[index]))
+}
+
+/// Gen1 answers with the new state, Gen2 with the previous one; only the
+/// status matters here.
+async fn send_command(
+    device: &ShellyDevice,
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<(), ApiError> {
+    let response = device_client()
+        .get(format!("http://{}/{}", device.ip, path))
+        .query(query)
+        .send()
+        .await
+        .map_err(shelly_error);
+
+    let response = match response {
+        Ok(response) if !response.status().is_success() => Err(ApiError::Upstream {
+            provider: PROVIDER.to_owned(),
+            status: Some(response.status().as_u16()),
+            message: "Unexpected response status".to_owned(),
+        }),
+        result => result.map(|_| ()),
+    };
+
+    FAILURES.record(device.id, &response);
+
+    response
+}
+
+pub async fn set_plug(
+    device: &ShellyDevice,
+    channel: &str,
+    state: PlugState,
+) -> Result<(), ApiError> {
+    let on = match state.on {
+        Some(on) => on,
+        None => return Ok(()),
+    };
+
+    let snapshot = get_snapshot(device).await?;
+    let index = snapshot.channels[find_channel(&snapshot, channel, true)?].index;
+
+    match device.generation {
+        1 => {
+            let turn = if on { "on" } else { "off" };
+
+            send_command(
+                device,
+                &format!("relay/{}", index),
+                &[("turn", turn.to_owned())],
+            )
+            .await
+        }
+        _ => {
+            send_command(
+                device,
+                "rpc/Switch.Set",
+                &[("id", index.to_string()), ("on", on.to_string())],
+            )
+            .await
+        }
+    }
+}
+
+/// Colors are only applied to RGBW channels, color temperatures are ignored.
+pub async fn set_light(
+    device: &ShellyDevice,
+    channel: &str,
+    state: LightState,
+) -> Result<(), ApiError> {
+    let snapshot = get_snapshot(device).await?;
+    let channel = &snapshot.channels[find_channel(&snapshot, channel, false)?];
+
+    let brightness = state.brightness_percent();
+    let color = state
+        .color
+        .as_ref()
+        .and_then(|color| color.first())
+        .filter(|_| channel.kind == ChannelKind::Color);
+
+    let mut query = Vec::new();
+
+    let path = match device.generation {
+        1 => {
+            if let Some(on) = state.on {
+                query.push(("turn", if on { "on" } else { "off" }.to_owned()));
+            }
+
+            if let Some(color) = color {
+                query.push(("red", color.0.to_string()));
+                query.push(("green", color.1.to_string()));
+                query.push(("blue", color.2.to_string()));
+            }
+
+            match channel.kind {
+                ChannelKind::Color => {
+                    if let Some(brightness) = brightness {
+                        query.push(("gain", brightness.to_string()));
+                    }
+
+                    format!("color/{}", channel.index)
+                }
+                _ => {
+                    if let Some(brightness) = brightness {
+                        query.push(("brightness", brightness.to_string()));
+                    }
+
+                    format!("light/{}", channel.index)
+                }
+            }
+        }
+        _ => {
+            query.push(("id", channel.index.to_string()));
+
+            if let Some(on) = state.on {
+                query.push(("on", on.to_string()));
+            }
+
+            if let Some(brightness) = brightness {
+                query.push(("brightness", brightness.to_string()));
+            }
+
+            if let Some(color) = color {
+                query.push(("rgb", format!("[{},{},{}]", color.0, color.1, color.2)));
+            }
+
+            match channel.kind {
+                ChannelKind::Color => "rpc/RGB.Set".to_owned(),
+                _ => "rpc/Light.Set".to_owned(),
+            }
+        }
+    };
+
+    if query.iter().all(|(name, _)| *name == "id") {
+        return Ok(());
+    }
+
+    send_command(device, &path, &query).await
+}
+
+fn device_not_found(_: diesel::result::Error) -> ApiError {
+    ApiError::NotFound("Device not found".to_string())
+}
+
+pub async fn find_device(
+    pool: &DbPool,
+    user_id: i32,
+    device_id: &str,
+) -> Result<ShellyDevice, ApiError> {
+    let device_id = device_id.to_lowercase();
+
+    connection::run(pool, move |connection| {
+        ShellyDevice::get_shelly_device(connection, user_id, &device_id).map_err(device_not_found)
+    })
+    .await
+}
+
+pub async fn get_devices(pool: &DbPool, user_id: i32) -> Result<Vec<ShellyDevice>, ApiError> {
+    connection::run(pool, move |connection| {
+        Ok(ShellyDevice::get_shelly_devices_by_user_id(
+            connection, user_id,
+        )?)
+    })
+    .await
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeviceRequest {
+    host: String,
+}
+
+impl Validate for DeviceRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        errors.check("host", validate_host(&self.host));
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "Shelly")]
+#[get("/devices")]
+async fn get_shelly_devices(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+) -> Result<Json<Vec<ShellyDevice>>, ApiError> {
+    Ok(Json(get_devices(_dbpool, jwt.user_id).await?))
+}
+
+/// Contacts the device to learn its MAC address and generation. Adding a
+/// device that is already registered updates its address.
+#[openapi(tag = "Shelly")]
+#[put("/config/add", format = "json", data = "<device_json>")]
+async fn add_config(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_json: Validated<DeviceRequest>,
+) -> Result<Json<ShellyDevice>, ApiError> {
+    let host = device_json.into_inner().host;
+    let info = identify(&host).await?;
+
+    let device = connection::run(_dbpool, move |connection| {
+        match ShellyDevice::get_shelly_device(connection, jwt.user_id, &info.device_id) {
+            Ok(device) => Ok(device.update(
+                connection,
+                &UpdateShellyDevice {
+                    ip: Some(&host),
+                    generation: Some(&info.generation),
+                    model: Some(&info.model),
+                },
+            )?),
+            Err(diesel::result::Error::NotFound) => Ok(ShellyDevice::create_shelly_device(
+                connection,
+                &NewShellyDevice {
+                    device_id: &info.device_id,
+                    ip: &host,
+                    generation: &info.generation,
+                    model: &info.model,
+                    user_id: &jwt.user_id,
+                },
+            )?),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await?;
+
+    Ok(Json(device))
+}
+
+#[openapi(tag = "Shelly")]
+#[delete("/config/<device_id>")]
+async fn delete_device(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_id: String,
+) -> Result<Json<Value>, ApiError> {
+    let device = find_device(_dbpool, jwt.user_id, &device_id).await?;
+
+    connection::run(_dbpool, move |connection| Ok(device.delete(connection)?)).await?;
+
+    Ok(Json(json!({})))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: get_shelly_devices, add_config, delete_device]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use schemars::_serde_json::{json, Value};
+
+    use crate::utils::testing::init_config;
+
+    use super::*;
+
+    type Requests = Arc<Mutex<Vec<String>>>;
+
+    /// Answers every request with the JSON `respond` returns for its path and
+    /// query, and records them.
+    fn serve<F>(respond: F) -> (String, Requests)
+    where
+        F: Fn(&str) -> Value + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let requests = Requests::default();
+        let recorded = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(length) => request.extend_from_slice(&buffer[..length]),
+                    }
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split(' ').nth(1).unwrap_or_default().to_owned();
+                let body = respond(&path).to_string();
+                recorded.lock().unwrap().push(path);
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        (address, requests)
+    }
+
+    fn device(ip: String, generation: i32) -> ShellyDevice {
+        init_config();
+
+        ShellyDevice {
+            id: generation,
+            device_id: "c8c9a3001122".to_owned(),
+            ip,
+            generation,
+            model: "SHPLG-S".to_owned(),
+            user_id: 1,
+        }
+    }
+
+    #[rocket::async_test]
+    async fn switches_gen1_relays() {
+        let on = Arc::new(Mutex::new(false));
+        let state = on.clone();
+
+        let (ip, requests) = serve(move |path| match path {
+            "/settings" => json!({"fw": "v1.14.0", "relays": [{"name": "Kettle"}]}),
+            "/status" => json!({
+                "relays": [{"ison": *state.lock().unwrap()}],
+                "meters": [{"power": 3.5, "total": 600}]
+            }),
+            "/relay/0?turn=on" => {
+                *state.lock().unwrap() = true;
+                json!({"ison": true})
+            }
+            _ => json!({}),
+        });
+        let device = device(ip, 1);
+
+        let plugs = get_plugs(&device).await.unwrap();
+        assert_eq!(plugs.len(), 1);
+        assert_eq!(plugs[0].id, "shelly-c8c9a3001122-0");
+        assert_eq!(plugs[0].name, "Kettle");
+        assert_eq!(plugs[0].swversion, "v1.14.0");
+        assert!(!plugs[0].on);
+        assert_eq!(plugs[0].power, Some(3.5));
+        // Gen1 meters count watt-minutes.
+        assert_eq!(plugs[0].energy, Some(10.0));
+
+        set_plug(&device, "0", PlugState { on: Some(true) })
+            .await
+            .unwrap();
+        assert!(*on.lock().unwrap());
+        assert!(get_plug(&device, "0").await.unwrap().on);
+
+        assert!(requests
+            .lock()
+            .unwrap()
+            .contains(&"/relay/0?turn=on".to_owned()));
+    }
+
+    #[rocket::async_test]
+    async fn switches_gen2_switches() {
+        let on = Arc::new(Mutex::new(false));
+        let state = on.clone();
+
+        let (ip, requests) = serve(move |path| {
+            let switch = json!({
+                "id": 0,
+                "output": *state.lock().unwrap(),
+                "apower": 12.5,
+                "aenergy": {"total": 345.6}
+            });
+
+            match path {
+                "/rpc/Shelly.GetConfig" => json!({
+                    "switch:0": {"name": "Heater"},
+                    "sys": {"device": {"fw_id": "20230912-082000/1.0.3"}}
+                }),
+                "/rpc/Shelly.GetStatus" => json!({"switch:0": switch, "sys": {}}),
+                "/rpc/Switch.Set?id=0&on=true" => {
+                    let was_on = std::mem::replace(&mut *state.lock().unwrap(), true);
+                    json!({"was_on": was_on})
+                }
+                _ => json!({}),
+            }
+        });
+        let device = device(ip, 2);
+
+        let plug = get_plug(&device, "0").await.unwrap();
+        assert_eq!(plug.name, "Heater");
+        assert_eq!(plug.swversion, "20230912-082000/1.0.3");
+        assert!(!plug.on);
+        assert_eq!(plug.power, Some(12.5));
+        assert_eq!(plug.energy, Some(345.6));
+
+        set_plug(&device, "0", PlugState { on: Some(true) })
+            .await
+            .unwrap();
+        assert!(*on.lock().unwrap());
+        assert!(get_plug(&device, "0").await.unwrap().on);
+
+        assert!(requests
+            .lock()
+            .unwrap()
+            .contains(&"/rpc/Switch.Set?id=0&on=true".to_owned()));
+    }
+
+    #[test]
+    fn parses_gen1_power_of_each_meter() {
+        let snapshot = parse_gen1(
+            &json!({"relays": [{"name": null}, {"name": "Lamp"}]}),
+            &json!({
+                "relays": [{"ison": true}, {"ison": false}],
+                "meters": [{"power": 0.0, "total": 0}, {"power": 41.2, "total": 120}]
+            }),
+        );
+
+        assert_eq!(snapshot.channels.len(), 2);
+        assert_eq!(snapshot.channels[0].power, Some(0.0));
+        assert_eq!(snapshot.channels[1].name.as_deref(), Some("Lamp"));
+        assert_eq!(snapshot.channels[1].power, Some(41.2));
+        assert_eq!(snapshot.channels[1].energy, Some(2.0));
+    }
+}
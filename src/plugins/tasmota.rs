@@ -0,0 +1,694 @@
+use okapi::openapi3::OpenApi;
+use rocket::{delete, get, patch, put, serde::json::Json, State};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    JsonSchema,
+    _serde_json::{self, json, Value},
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::auth::JWTToken,
+    db::{
+        connection::{self, DbPool},
+        models::{NewTasmotaDevice, TasmotaDevice, UpdateTasmotaDevice},
+    },
+    repsonses::ApiError,
+    utils::extensions::ValueExt,
+    validation::{validate_host, validate_label, Validate, Validated, ValidationErrors},
+};
+
+use super::main::{
+    deserialize_some, device_client, DeviceOverlay, FailureTracker, LightState, NormalizedColor,
+    NormalizedLight, NormalizedPlug, PlugState, ProviderHealth,
+};
+
+static PROVIDER: &str = "tasmota";
+
+/// Tasmota's web interface has a single, fixed user.
+static DEFAULT_USERNAME: &str = "admin";
+
+fn tasmota_error<E: Into<ApiError>>(error: E) -> ApiError {
+    error.into().with_provider(PROVIDER)
+}
+
+static FAILURES: FailureTracker = FailureTracker::new(PROVIDER);
+
+pub fn provider_health(devices: &[TasmotaDevice]) -> ProviderHealth {
+    FAILURES.health(devices.iter().map(|device| device.id))
+}
+
+struct Credentials<'a> {
+    username: Option<&'a str>,
+    password: Option<&'a str>,
+}
+
+impl<'a> From<&'a TasmotaDevice> for Credentials<'a> {
+    fn from(device: &'a TasmotaDevice) -> Self {
+        Credentials {
+            username: device.username.as_deref(),
+            password: device.password.as_deref(),
+        }
+    }
+}
+
+/// Runs a console command through `/cm`. The password is sent both as basic
+/// auth and as `user`/`password` parameters since older firmware only checks
+/// the latter.
+async fn command(
+    ip: &str,
+    credentials: &Credentials<'_>,
+    command: &str,
+) -> Result<Value, ApiError> {
+    let mut request = device_client().get(format!("http://{}/cm", ip));
+
+    if let Some(password) = credentials.password {
+        let username = credentials.username.unwrap_or(DEFAULT_USERNAME);
+
+        request = request
+            .basic_auth(username, Some(password))
+            .query(&[("user", username), ("password", password)]);
+    }
+
+    let response = request
+        .query(&[("cmnd", command)])
+        .send()
+        .await
+        .map_err(tasmota_error)?;
+
+    if !response.status().is_success() {
+        return Err(ApiError::Upstream {
+            provider: PROVIDER.to_owned(),
+            status: Some(response.status().as_u16()),
+            message: "Unexpected response status".to_owned(),
+        });
+    }
+
+    let body = response.text().await.map_err(tasmota_error)?;
+    let body: Value = _serde_json::from_str(&body).map_err(tasmota_error)?;
+
+    // Wrong or missing credentials still answer with `200`
+    if body.get("WARNING").is_some() {
+        return Err(ApiError::Upstream {
+            provider: PROVIDER.to_owned(),
+            status: Some(401),
+            message: "Authentication failed".to_owned(),
+        });
+    }
+
+    Ok(body)
+}
+
+/// One `POWER<n>` output. `n` starts at 1, a device with a single relay
+/// reports plain `POWER`.
+#[derive(Debug)]
+struct Channel {
+    index: u32,
+    name: Option<String>,
+    on: bool,
+    /// Watts
+    power: Option<f64>,
+    /// Watt-hours
+    energy: Option<f64>,
+}
+
+#[derive(Debug)]
+struct Light {
+    /// Percent
+    dimmer: f64,
+    rgb: Option<(u8, u8, u8)>,
+    /// Mireds
+    ct: Option<u16>,
+}
+
+struct Snapshot {
+    device_id: String,
+    name: String,
+    channels: Vec<Channel>,
+    /// Controlled by the last channel, as Tasmota assigns the light the
+    /// highest power index after all relays.
+    light: Option<Light>,
+    swversion: String,
+}
+
+/// `Color` is hex (`FF8000`, with extra white channels on RGBW bulbs) or,
+/// with `SetOption17`, comma separated. CT and single channel lights report
+/// fewer than three values.
+fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let values: Vec<u8> = if color.contains(',') {
+        color
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect::<Option<_>>()?
+    } else {
+        (0..color.len() / 2)
+            .map(|index| u8::from_str_radix(color.get(index * 2..index * 2 + 2)?, 16).ok())
+            .collect::<Option<_>>()?
+    };
+
+    match values[..] {
+        [red, green, blue, ..] => Some((red, green, blue)),
+        _ => None,
+    }
+}
+
+/// `ENERGY` holds single values on one-channel meters and arrays on devices
+/// that meter every relay.
+fn energy_value(energy: Option<&Value>, field: &str, index: usize, single: bool) -> Option<f64> {
+    match energy?.get(field)? {
+        Value::Array(values) => values.get(index)?.as_f64(),
+        value if single => value.as_f64(),
+        _ => None,
+    }
+}
+
+/// Parses the answer to `Status 0`.
+fn parse_status(status: &Value) -> Result<Snapshot, ApiError> {
+    let device_id = status
+        .get("StatusNET")
+        .and_then(|net| net.get("Mac"))
+        .to_string()
+        .map_err(tasmota_error)?
+        .to_lowercase()
+        .replace(':', "");
+
+    let info = status.get("Status");
+    let friendly_names = info
+        .and_then(|info| info.get("FriendlyName"))
+        .and_then(|names| names.as_array());
+
+    let sts = status.get("StatusSTS");
+    let energy = status.get("StatusSNS").and_then(|sns| sns.get("ENERGY"));
+
+    let mut channels = Vec::new();
+
+    for (key, value) in sts.and_then(|sts| sts.as_object()).into_iter().flatten() {
+        let index = match key.strip_prefix("POWER") {
+            Some("") => 1,
+            Some(index) => match index.parse() {
+                Ok(index) if index > 0 => index,
+                _ => continue,
+            },
+            None => continue,
+        };
+
+        channels.push(Channel {
+            index,
+            name: friendly_names
+                .and_then(|names| names.get(index as usize - 1))
+                .and_then(|name| name.as_str())
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_owned()),
+            on: value.as_str() == Some("ON"),
+            power: None,
+            energy: None,
+        });
+    }
+
+    channels.sort_by_key(|channel| channel.index);
+
+    let single = channels.len() == 1;
+
+    for (position, channel) in channels.iter_mut().enumerate() {
+        channel.power = energy_value(energy, "Power", position, single);
+        // Tasmota counts kilowatt-hours
+        channel.energy =
+            energy_value(energy, "Total", position, single).map(|total| total * 1000.0);
+    }
+
+    let light = sts
+        .and_then(|sts| sts.get("Dimmer"))
+        .and_then(|dimmer| dimmer.as_f64())
+        .filter(|_| !channels.is_empty())
+        .map(|dimmer| Light {
+            dimmer,
+            rgb: sts
+                .and_then(|sts| sts.get("Color"))
+                .and_then(|color| color.as_str())
+                .and_then(parse_color),
+            ct: sts
+                .and_then(|sts| sts.get("CT"))
+                .and_then(|ct| ct.as_u64())
+                .map(|ct| ct as u16),
+        });
+
+    Ok(Snapshot {
+        device_id,
+        name: info
+            .and_then(|info| info.get("DeviceName"))
+            .to_string()
+            .unwrap_or_default(),
+        channels,
+        light,
+        swversion: status
+            .get("StatusFWR")
+            .and_then(|fwr| fwr.get("Version"))
+            .to_string()
+            .unwrap_or_default(),
+    })
+}
+
+async fn get_snapshot(device: &TasmotaDevice) -> Result<Snapshot, ApiError> {
+    let result = async {
+        let status = command(&device.ip, &device.into(), "Status 0").await?;
+
+        parse_status(&status)
+    }
+    .await;
+
+    FAILURES.record(device.id, &result);
+
+    result
+}
+
+impl Snapshot {
+    fn light_channel(&self) -> Option<&Channel> {
+        self.light.as_ref().and(self.channels.last())
+    }
+
+    fn relays(&self) -> impl Iterator<Item = &Channel> {
+        let relays = match self.light {
+            Some(_) => self.channels.len() - 1,
+            None => self.channels.len(),
+        };
+
+        self.channels.iter().take(relays)
+    }
+}
+
+fn device_name(device: &TasmotaDevice, channel: &Channel) -> String {
+    match &channel.name {
+        Some(name) => name.clone(),
+        None => format!("{} {}", device.name, channel.index),
+    }
+}
+
+fn channel_id(device: &TasmotaDevice, channel: &Channel) -> String {
+    format!("tasmota-{}-{}", device.device_id, channel.index)
+}
+
+fn to_plug(device: &TasmotaDevice, snapshot: &Snapshot, channel: &Channel) -> NormalizedPlug {
+    NormalizedPlug {
+        id: channel_id(device, channel),
+        legacy_id: None,
+        name: device_name(device, channel),
+        on: channel.on,
+        reachable: true,
+        type_: "Relay".to_owned(),
+        model: snapshot.name.clone(),
+        manufacturer: "Tasmota".to_owned(),
+        uniqueid: format!("{}-{}", device.device_id, channel.index),
+        swversion: snapshot.swversion.clone(),
+        productid: None,
+        power: channel.power.map(|power| power as f32),
+        energy: channel.energy.map(|energy| energy as f32),
+        overlay: DeviceOverlay::default(),
+    }
+}
+
+fn to_light(
+    device: &TasmotaDevice,
+    snapshot: &Snapshot,
+    channel: &Channel,
+    light: &Light,
+) -> NormalizedLight {
+    NormalizedLight {
+        id: channel_id(device, channel),
+        legacy_id: None,
+        name: device_name(device, channel),
+        on: channel.on,
+        brightness: (light.dimmer / 100.0) as f32,
+        color: light
+            .rgb
+            .map(|(red, green, blue)| NormalizedColor(red, green, blue))
+            .into_iter()
+            .collect(),
+        color_temperature: light.ct,
+        reachable: true,
+        type_: match (light.rgb, light.ct) {
+            (Some(_), Some(_)) => "Extended color light",
+            (Some(_), None) => "Color light",
+            (None, Some(_)) => "Color temperature light",
+            (None, None) => "Dimmable light",
+        }
+        .to_owned(),
+        model: snapshot.name.clone(),
+        manufacturer: "Tasmota".to_owned(),
+        uniqueid: format!("{}-{}", device.device_id, channel.index),
+        swversion: snapshot.swversion.clone(),
+        productid: None,
+        overlay: DeviceOverlay::default(),
+    }
+}
+
+fn unknown_device() -> ApiError {
+    ApiError::NotFound("Unknown device".to_string())
+}
+
+fn find_relay<'a>(snapshot: &'a Snapshot, channel: &str) -> Result<&'a Channel, ApiError> {
+    snapshot
+        .relays()
+        .find(|candidate| channel.parse() == Ok(candidate.index))
+        .ok_or_else(unknown_device)
+}
+
+fn find_light<'a>(
+    snapshot: &'a Snapshot,
+    channel: &str,
+) -> Result<(&'a Channel, &'a Light), ApiError> {
+    match (snapshot.light_channel(), &snapshot.light) {
+        (Some(candidate), Some(light)) if channel.parse() == Ok(candidate.index) => {
+            Ok((candidate, light))
+        }
+        _ => Err(unknown_device()),
+    }
+}
+
+pub async fn get_plugs(device: &TasmotaDevice) -> Result<Vec<NormalizedPlug>, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+
+    Ok(snapshot
+        .relays()
+        .map(|channel| to_plug(device, &snapshot, channel))
+        .collect())
+}
+
+pub async fn get_plug(device: &TasmotaDevice, channel: &str) -> Result<NormalizedPlug, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+
+    Ok(to_plug(device, &snapshot, find_relay(&snapshot, channel)?))
+}
+
+pub async fn get_lights(device: &TasmotaDevice) -> Result<Vec<NormalizedLight>, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+
+    Ok(snapshot
+        .light_channel()
+        .zip(snapshot.light.as_ref())
+        .map(|(channel, light)| to_light(device, &snapshot, channel, light))
+        .into_iter()
+        .collect())
+}
+
+pub async fn get_light(
+    device: &TasmotaDevice,
+    channel: &str,
+) -> Result<NormalizedLight, ApiError> {
+    let snapshot = get_snapshot(device).await?;
+    let (channel, light) = find_light(&snapshot, channel)?;
+
+    Ok(to_light(device, &snapshot, channel, light))
+}
+
+/// Several commands are combined with `Backlog` so they are applied together.
+async fn send_commands(device: &TasmotaDevice, commands: Vec<String>) -> Result<(), ApiError> {
+    let command_line = match commands.len() {
+        0 => return Ok(()),
+        1 => commands[0].clone(),
+        _ => format!("Backlog {}", commands.join("; ")),
+    };
+
+    let result = command(&device.ip, &device.into(), &command_line)
+        .await
+        .map(|_| ());
+
+    FAILURES.record(device.id, &result);
+
+    result
+}
+
+fn power_command(index: u32, on: bool) -> String {
+    format!("Power{} {}", index, if on { "On" } else { "Off" })
+}
+
+pub async fn set_plug(
+    device: &TasmotaDevice,
+    channel: &str,
+    state: PlugState,
+) -> Result<(), ApiError> {
+    let on = match state.on {
+        Some(on) => on,
+        None => return Ok(()),
+    };
+
+    let snapshot = get_snapshot(device).await?;
+    let index = find_relay(&snapshot, channel)?.index;
+
+    send_commands(device, vec![power_command(index, on)]).await
+}
+
+/// Colors are only applied to RGB lights and color temperatures only to
+/// lights that report `CT`.
+pub async fn set_light(
+    device: &TasmotaDevice,
+    channel: &str,
+    state: LightState,
+) -> Result<(), ApiError> {
+    let snapshot = get_snapshot(device).await?;
+    let (channel, light) = find_light(&snapshot, channel)?;
+
+    let mut commands = Vec::new();
+
+    if let Some(brightness) = state.brightness_percent() {
+        commands.push(format!("Dimmer {}", brightness));
+    }
+
+    if let Some(color) = state.color.as_ref().and_then(|color| color.first()) {
+        if light.rgb.is_some() {
+            commands.push(format!("Color {},{},{}", color.0, color.1, color.2));
+        }
+    }
+
+    if let Some(ct) = state.color_temperature {
+        if light.ct.is_some() {
+            commands.push(format!("CT {}", ct));
+        }
+    }
+
+    // Dimmer and Color turn the light on, so switching off has to come last
+    if let Some(on) = state.on {
+        commands.push(power_command(channel.index, on));
+    }
+
+    send_commands(device, commands).await
+}
+
+fn device_not_found(_: diesel::result::Error) -> ApiError {
+    ApiError::NotFound("Device not found".to_string())
+}
+
+pub async fn find_device(
+    pool: &DbPool,
+    user_id: i32,
+    device_id: &str,
+) -> Result<TasmotaDevice, ApiError> {
+    let device_id = device_id.to_lowercase();
+
+    connection::run(pool, move |connection| {
+        TasmotaDevice::get_tasmota_device(connection, user_id, &device_id)
+            .map_err(device_not_found)
+    })
+    .await
+}
+
+pub async fn get_devices(pool: &DbPool, user_id: i32) -> Result<Vec<TasmotaDevice>, ApiError> {
+    connection::run(pool, move |connection| {
+        Ok(TasmotaDevice::get_tasmota_devices_by_user_id(
+            connection, user_id,
+        )?)
+    })
+    .await
+}
+
+fn validate_credential(value: &str) -> Result<(), String> {
+    // Tasmota limits the web password to 32 characters
+    if value.is_empty() || value.len() > 32 {
+        return Err("Must be between 1 and 32 characters".to_owned());
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeviceRequest {
+    host: String,
+    /// Defaults to `admin`, the only user Tasmota knows.
+    username: Option<String>,
+    /// `WebPassword` of the device, if one is set.
+    password: Option<String>,
+}
+
+impl Validate for DeviceRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        errors.check("host", validate_host(&self.host));
+
+        if let Some(username) = &self.username {
+            errors.check("username", validate_credential(username));
+        }
+
+        if let Some(password) = &self.password {
+            errors.check("password", validate_credential(password));
+        }
+
+        errors.into_result()
+    }
+}
+
+/// Missing fields are left unchanged, `null` clears the credentials.
+#[derive(Deserialize, JsonSchema)]
+struct DeviceUpdateRequest {
+    host: Option<String>,
+    name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    username: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    password: Option<Option<String>>,
+}
+
+impl Validate for DeviceUpdateRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        if let Some(host) = &self.host {
+            errors.check("host", validate_host(host));
+        }
+
+        if let Some(name) = &self.name {
+            errors.check("name", validate_label(name));
+        }
+
+        if let Some(Some(username)) = &self.username {
+            errors.check("username", validate_credential(username));
+        }
+
+        if let Some(Some(password)) = &self.password {
+            errors.check("password", validate_credential(password));
+        }
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "Tasmota")]
+#[get("/devices")]
+async fn get_tasmota_devices(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+) -> Result<Json<Vec<TasmotaDevice>>, ApiError> {
+    Ok(Json(get_devices(_dbpool, jwt.user_id).await?))
+}
+
+#[openapi(tag = "Tasmota")]
+#[get("/devices/<device_id>")]
+async fn get_tasmota_device(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_id: String,
+) -> Result<Json<TasmotaDevice>, ApiError> {
+    Ok(Json(find_device(_dbpool, jwt.user_id, &device_id).await?))
+}
+
+/// Contacts the device with the given credentials to learn its MAC address
+/// and name. Adding a device that is already registered updates its address
+/// and credentials.
+#[openapi(tag = "Tasmota")]
+#[put("/config/add", format = "json", data = "<device_json>")]
+async fn add_config(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_json: Validated<DeviceRequest>,
+) -> Result<Json<TasmotaDevice>, ApiError> {
+    let request = device_json.into_inner();
+    let credentials = Credentials {
+        username: request.username.as_deref(),
+        password: request.password.as_deref(),
+    };
+    let snapshot = parse_status(&command(&request.host, &credentials, "Status 0").await?)?;
+
+    let device = connection::run(_dbpool, move |connection| {
+        match TasmotaDevice::get_tasmota_device(connection, jwt.user_id, &snapshot.device_id) {
+            Ok(device) => Ok(device.update(
+                connection,
+                &UpdateTasmotaDevice {
+                    ip: Some(&request.host),
+                    name: Some(&snapshot.name),
+                    username: Some(request.username.as_deref()),
+                    password: Some(request.password.as_deref()),
+                },
+            )?),
+            Err(diesel::result::Error::NotFound) => Ok(TasmotaDevice::create_tasmota_device(
+                connection,
+                &NewTasmotaDevice {
+                    device_id: &snapshot.device_id,
+                    ip: &request.host,
+                    name: &snapshot.name,
+                    username: request.username.as_deref(),
+                    password: request.password.as_deref(),
+                    user_id: &jwt.user_id,
+                },
+            )?),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await?;
+
+    Ok(Json(device))
+}
+
+/// Changes are stored without contacting the device.
+#[openapi(tag = "Tasmota")]
+#[patch("/devices/<device_id>", format = "json", data = "<update>")]
+async fn update_device(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_id: String,
+    update: Validated<DeviceUpdateRequest>,
+) -> Result<Json<TasmotaDevice>, ApiError> {
+    let device = find_device(_dbpool, jwt.user_id, &device_id).await?;
+    let update = update.into_inner();
+
+    let device = connection::run(_dbpool, move |connection| {
+        let changes = UpdateTasmotaDevice {
+            ip: update.host.as_deref(),
+            name: update.name.as_deref(),
+            username: update.username.as_ref().map(|username| username.as_deref()),
+            password: update.password.as_ref().map(|password| password.as_deref()),
+        };
+
+        // Diesel refuses to run an update without any changes
+        if changes == UpdateTasmotaDevice::default() {
+            return Ok(device);
+        }
+
+        Ok(device.update(connection, &changes)?)
+    })
+    .await?;
+
+    Ok(Json(device))
+}
+
+#[openapi(tag = "Tasmota")]
+#[delete("/config/<device_id>")]
+async fn delete_device(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    device_id: String,
+) -> Result<Json<Value>, ApiError> {
+    let device = find_device(_dbpool, jwt.user_id, &device_id).await?;
+
+    connection::run(_dbpool, move |connection| Ok(device.delete(connection)?)).await?;
+
+    Ok(Json(json!({})))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        settings: get_tasmota_devices,
+        get_tasmota_device,
+        add_config,
+        update_device,
+        delete_device
+    ]
+}
@@ -1,106 +1,67 @@
-use std::{fs::create_dir_all, path::Path};
-
-use okapi::openapi3::OpenApi;
-use rocket::{data::ByteUnit, fs::NamedFile, get, http::Status, put, serde::json::Json, State};
-use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
-use schemars::_serde_json::{json, Value};
-
-use crate::{
-    auth::auth::JWTToken,
-    db::{
-        connection::{self, SqlitePool},
-        models::User,
-    },
-    repsonses::CustomResponse,
-};
-
-use super::assets::{get_picture_path, PictureType};
-
-#[openapi(tag = "User")]
-#[get("/profile_pic")]
-pub async fn get_profile_pic(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-) -> Result<NamedFile, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
-
-    if !Path::new(&picture).exists() {
-        return Err(CustomResponse {
-            status: Status::NotFound,
-            message: "Not Found".to_string(),
-        });
-    }
-
-    match NamedFile::open(picture).await.ok() {
-        Some(file) => Ok(file),
-        None => Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Internal Server Error".to_string(),
-        }),
-    }
-}
-
-#[openapi(tag = "User")]
-#[put("/profile_pic", data = "<data>")]
-pub async fn put_profile_pic(
-    jwt: JWTToken,
-    pool: &State<SqlitePool>,
-    data: rocket::Data<'_>,
-) -> Result<Json<Value>, CustomResponse> {
-    let connection = &mut connection::get_connection(pool).unwrap();
-
-    let user = User::get_user(connection, jwt.user_id);
-
-    if user.is_err() {
-        return Err(CustomResponse {
-            status: Status::Unauthorized,
-            message: "Unauthorized".to_string(),
-        });
-    }
-
-    let user = user.unwrap();
-
-    let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
-
-    if !Path::new(&picture).parent().unwrap().exists() {
-        create_dir_all(Path::new(&picture).parent().unwrap()).unwrap();
-    }
-
-    let result = data
-        .open(ByteUnit::Megabyte(15))
-        .into_file(Path::new(&picture))
-        .await;
-
-    if result.is_err() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Internal Server Error".to_string(),
-        });
-    }
-
-    if !result.unwrap().is_complete() {
-        return Err(CustomResponse {
-            status: Status::InternalServerError,
-            message: "Error Writing File".to_string(),
-        });
-    }
-
-    Ok(Json(json!({})))
-}
-
-pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
-    openapi_get_routes_spec![settings: get_profile_pic, put_profile_pic]
-}
+use std::{fs::create_dir_all, path::Path};
+
+use okapi::openapi3::OpenApi;
+use rocket::{data::ByteUnit, fs::NamedFile, get, put, serde::json::Json, State};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::_serde_json::{json, Value};
+
+use crate::{
+    auth::auth::JWTToken,
+    db::connection::{self, DbPool},
+    repsonses::ApiError,
+};
+
+use super::assets::{get_picture_path, PictureType};
+
+#[openapi(tag = "User")]
+#[get("/profile_pic")]
+pub async fn get_profile_pic(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+) -> Result<NamedFile, ApiError> {
+    let user = connection::run(pool, move |connection| jwt.get_user(connection)).await?;
+
+    let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
+
+    if !Path::new(&picture).exists() {
+        return Err(ApiError::NotFound("Not Found".to_string()));
+    }
+
+    NamedFile::open(picture)
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+#[openapi(tag = "User")]
+#[put("/profile_pic", data = "<data>")]
+pub async fn put_profile_pic(
+    jwt: JWTToken,
+    pool: &State<DbPool>,
+    data: rocket::Data<'_>,
+) -> Result<Json<Value>, ApiError> {
+    let user = connection::run(pool, move |connection| jwt.get_user(connection)).await?;
+
+    let picture = get_picture_path(PictureType::ProfilePic { user_id: user.id });
+
+    if let Some(parent) = Path::new(&picture).parent() {
+        if !parent.exists() {
+            create_dir_all(parent).map_err(|e| ApiError::Internal(e.to_string()))?;
+        }
+    }
+
+    let result = data
+        .open(ByteUnit::Megabyte(15))
+        .into_file(Path::new(&picture))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+
+    if !result.is_complete() {
+        return Err(ApiError::BadRequest("File too large".to_string()));
+    }
+
+    Ok(Json(json!({})))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: get_profile_pic, put_profile_pic]
+}
@@ -0,0 +1,1095 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use okapi::openapi3::OpenApi;
+use rocket::{delete, get, put, serde::json::Json, State};
+use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings};
+use schemars::{
+    JsonSchema,
+    _serde_json::{self, json, Map, Value},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::auth::JWTToken,
+    config::app_config,
+    db::{
+        connection::{self, DbPool},
+        models::{NewZigbee2MqttBridge, Zigbee2MqttBridge},
+    },
+    repsonses::ApiError,
+    utils::color::{hsv_to_rgb, xy_to_rgb},
+    validation::{Validate, Validated, ValidationErrors},
+};
+
+use super::main::{
+    DeviceOverlay, LightState, NormalizedColor, NormalizedLight, NormalizedPlug, PlugState,
+    ProviderHealth,
+};
+
+static PROVIDER: &str = "zigbee2mqtt";
+
+/// Channel of exposes without an `endpoint`.
+static MAIN_ENDPOINT: &str = "main";
+
+/// Brightness range Zigbee2MQTT uses when an expose does not state one.
+static DEFAULT_BRIGHTNESS_MAX: f64 = 254.0;
+
+/// One entry of `<base_topic>/bridge/devices`, see
+/// https://www.zigbee2mqtt.io/guide/usage/exposes.html.
+#[derive(Debug, Clone, Deserialize)]
+struct Expose {
+    #[serde(rename = "type")]
+    type_: String,
+    name: Option<String>,
+    property: Option<String>,
+    endpoint: Option<String>,
+    unit: Option<String>,
+    value_on: Option<Value>,
+    value_off: Option<Value>,
+    value_max: Option<f64>,
+    #[serde(default)]
+    features: Vec<Expose>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Definition {
+    model: String,
+    vendor: String,
+    #[serde(default)]
+    exposes: Vec<Expose>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Device {
+    ieee_address: String,
+    friendly_name: String,
+    #[serde(rename = "type")]
+    type_: String,
+    definition: Option<Definition>,
+    software_build_id: Option<String>,
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// What a Zigbee2MQTT instance published last. Device states are keyed by
+/// friendly name like their topics.
+#[derive(Default)]
+struct Network {
+    /// `None` until `bridge/state` was received.
+    online: Option<bool>,
+    /// `None` until `bridge/devices` was received.
+    devices: Option<Vec<Device>>,
+    states: HashMap<String, Map<String, Value>>,
+    availability: HashMap<String, bool>,
+}
+
+/// Networks by base topic, shared by all users that added the same one.
+static NETWORKS: OnceLock<Mutex<HashMap<String, Network>>> = OnceLock::new();
+
+fn networks() -> &'static Mutex<HashMap<String, Network>> {
+    NETWORKS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Starts collecting the messages below `base_topic`. Returns the topic
+/// filter to subscribe to.
+#[cfg(feature = "mqtt")]
+pub fn watch(base_topic: &str) -> String {
+    networks()
+        .lock()
+        .unwrap()
+        .entry(base_topic.to_owned())
+        .or_default();
+
+    format!("{}/#", base_topic)
+}
+
+/// Both the current `{"state":"online"}` and the legacy plain payload.
+#[cfg(feature = "mqtt")]
+fn parse_online(payload: &[u8]) -> bool {
+    let state = match _serde_json::from_slice::<Value>(payload) {
+        Ok(Value::Object(object)) => object
+            .get("state")
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+        _ => std::str::from_utf8(payload).ok().map(str::to_owned),
+    };
+
+    state.as_deref() == Some("online")
+}
+
+/// Stores a message received on a watched base topic. Returns `false` for
+/// topics of other clients.
+#[cfg(feature = "mqtt")]
+pub fn handle_message(topic: &str, payload: &[u8]) -> bool {
+    let mut networks = networks().lock().unwrap();
+
+    let (network, rest) = match networks.iter_mut().find_map(|(base_topic, network)| {
+        let rest = topic.strip_prefix(base_topic.as_str())?.strip_prefix('/')?;
+        Some((network, rest))
+    }) {
+        Some(found) => found,
+        None => return false,
+    };
+
+    match rest {
+        "bridge/devices" => match _serde_json::from_slice(payload) {
+            Ok(devices) => network.devices = Some(devices),
+            Err(e) => println!("Error parsing {}: {}", topic, e),
+        },
+        "bridge/state" => network.online = Some(parse_online(payload)),
+        _ if rest.starts_with("bridge/") => {}
+        _ => {
+            if let Some(name) = rest.strip_suffix("/availability") {
+                network
+                    .availability
+                    .insert(name.to_owned(), parse_online(payload));
+            } else if !rest.ends_with("/set") && !rest.ends_with("/get") {
+                // Removed devices are cleared with an empty payload
+                match _serde_json::from_slice(payload) {
+                    Ok(Value::Object(state)) => {
+                        network.states.insert(rest.to_owned(), state);
+                    }
+                    _ => {
+                        network.states.remove(rest);
+                    }
+                }
+            }
+        }
+    }
+
+    true
+}
+
+/// A light or switch expose, one per endpoint of a device.
+struct Channel {
+    endpoint: String,
+    light: bool,
+    state: Option<Expose>,
+    brightness: Option<Expose>,
+    color_temp: Option<Expose>,
+    color: Option<Expose>,
+}
+
+fn channels(device: &Device) -> Vec<Channel> {
+    let exposes = match &device.definition {
+        Some(definition) => &definition.exposes,
+        None => return Vec::new(),
+    };
+
+    exposes
+        .iter()
+        .filter(|expose| expose.type_ == "light" || expose.type_ == "switch")
+        .map(|expose| {
+            let feature = |names: &[&str]| {
+                expose
+                    .features
+                    .iter()
+                    .find(|feature| names.contains(&feature.name.as_deref().unwrap_or_default()))
+                    .cloned()
+            };
+
+            Channel {
+                endpoint: expose
+                    .endpoint
+                    .clone()
+                    .unwrap_or_else(|| MAIN_ENDPOINT.to_owned()),
+                light: expose.type_ == "light",
+                state: feature(&["state"]),
+                brightness: feature(&["brightness"]),
+                color_temp: feature(&["color_temp"]),
+                color: feature(&["color_xy", "color_hs"]),
+            }
+        })
+        .collect()
+}
+
+/// Top-level exposes like `temperature`, `occupancy` or `power`.
+fn readings(device: &Device) -> impl Iterator<Item = &Expose> {
+    device
+        .definition
+        .iter()
+        .flat_map(|definition| definition.exposes.iter())
+        .filter(|expose| ["numeric", "binary", "enum"].contains(&expose.type_.as_str()))
+        .filter(|expose| expose.property.is_some())
+}
+
+fn property<'a>(state: &'a Map<String, Value>, expose: &Option<Expose>) -> Option<&'a Value> {
+    state.get(expose.as_ref()?.property.as_ref()?)
+}
+
+fn parse_color(color: &Value) -> Option<(u8, u8, u8)> {
+    match (color.get("x"), color.get("y")) {
+        (Some(x), Some(y)) => Some(xy_to_rgb(x.as_f64()? as f32, y.as_f64()? as f32, 100.0)),
+        _ => Some(hsv_to_rgb(
+            color.get("hue")?.as_f64()? as f32,
+            color.get("saturation")?.as_f64()? as f32,
+            100.0,
+        )),
+    }
+}
+
+/// A device together with what its network reported about it.
+struct Snapshot<'a> {
+    device: &'a Device,
+    state: Option<&'a Map<String, Value>>,
+    reachable: bool,
+}
+
+impl<'a> Snapshot<'a> {
+    fn new(network: &'a Network, device: &'a Device) -> Self {
+        let available = network
+            .availability
+            .get(&device.friendly_name)
+            .copied()
+            .unwrap_or(true);
+
+        Snapshot {
+            device,
+            state: network.states.get(&device.friendly_name),
+            reachable: network.online != Some(false) && available,
+        }
+    }
+
+    fn value(&self, expose: &Option<Expose>) -> Option<&'a Value> {
+        property(self.state?, expose)
+    }
+
+    fn is_on(&self, channel: &Channel) -> bool {
+        let value_on = channel
+            .state
+            .as_ref()
+            .and_then(|state| state.value_on.clone())
+            .unwrap_or_else(|| json!("ON"));
+
+        self.value(&channel.state) == Some(&value_on)
+    }
+
+    fn reading(&self, name: &str) -> Option<f64> {
+        readings(self.device)
+            .find(|expose| expose.name.as_deref() == Some(name))
+            .and_then(|expose| self.state?.get(expose.property.as_ref()?))
+            .and_then(Value::as_f64)
+    }
+}
+
+fn channel_id(device: &Device, channel: &Channel) -> String {
+    format!("{}-{}-{}", PROVIDER, device.ieee_address, channel.endpoint)
+}
+
+fn channel_name(device: &Device, channel: &Channel, channels: usize) -> String {
+    match channels {
+        1 => device.friendly_name.clone(),
+        _ => format!("{} {}", device.friendly_name, channel.endpoint),
+    }
+}
+
+fn definition_field(device: &Device, field: fn(&Definition) -> &String) -> String {
+    device
+        .definition
+        .as_ref()
+        .map(|definition| field(definition).clone())
+        .unwrap_or_default()
+}
+
+fn to_light(snapshot: &Snapshot, channel: &Channel, channels: usize) -> NormalizedLight {
+    let device = snapshot.device;
+
+    let brightness_max = channel
+        .brightness
+        .as_ref()
+        .and_then(|brightness| brightness.value_max)
+        .unwrap_or(DEFAULT_BRIGHTNESS_MAX);
+
+    // Lights without brightness are either fully on or off
+    let brightness = match (&channel.brightness, snapshot.value(&channel.brightness)) {
+        (Some(_), Some(brightness)) => brightness.as_f64().unwrap_or_default() / brightness_max,
+        (Some(_), None) => 0.0,
+        (None, _) => 1.0,
+    };
+
+    let color = snapshot
+        .value(&channel.color)
+        .and_then(parse_color)
+        .map(|(red, green, blue)| NormalizedColor(red, green, blue));
+
+    NormalizedLight {
+        id: channel_id(device, channel),
+        legacy_id: None,
+        name: channel_name(device, channel, channels),
+        on: snapshot.is_on(channel),
+        brightness: brightness.clamp(0.0, 1.0) as f32,
+        color: color.into_iter().collect(),
+        color_temperature: snapshot
+            .value(&channel.color_temp)
+            .and_then(Value::as_u64)
+            .map(|ct| ct as u16),
+        reachable: snapshot.reachable,
+        type_: match (channel.color.is_some(), channel.color_temp.is_some()) {
+            (true, true) => "Extended color light",
+            (true, false) => "Color light",
+            (false, true) => "Color temperature light",
+            (false, false) => "Dimmable light",
+        }
+        .to_owned(),
+        model: definition_field(device, |definition| &definition.model),
+        manufacturer: definition_field(device, |definition| &definition.vendor),
+        uniqueid: format!("{}-{}", device.ieee_address, channel.endpoint),
+        swversion: device.software_build_id.clone().unwrap_or_default(),
+        productid: None,
+        overlay: DeviceOverlay::default(),
+    }
+}
+
+/// Power and energy are only reported per device, so they are only set on
+/// switches without an endpoint.
+fn to_plug(snapshot: &Snapshot, channel: &Channel, channels: usize) -> NormalizedPlug {
+    let device = snapshot.device;
+    let metered = channel.endpoint == MAIN_ENDPOINT;
+
+    NormalizedPlug {
+        id: channel_id(device, channel),
+        legacy_id: None,
+        name: channel_name(device, channel, channels),
+        on: snapshot.is_on(channel),
+        reachable: snapshot.reachable,
+        type_: "Relay".to_owned(),
+        model: definition_field(device, |definition| &definition.model),
+        manufacturer: definition_field(device, |definition| &definition.vendor),
+        uniqueid: format!("{}-{}", device.ieee_address, channel.endpoint),
+        swversion: device.software_build_id.clone().unwrap_or_default(),
+        productid: None,
+        power: snapshot
+            .reading("power")
+            .filter(|_| metered)
+            .map(|power| power as f32),
+        // Zigbee2MQTT reports kWh
+        energy: snapshot
+            .reading("energy")
+            .filter(|_| metered)
+            .map(|energy| (energy * 1000.0) as f32),
+        overlay: DeviceOverlay::default(),
+    }
+}
+
+/// A device without lights or switches, e.g. a motion or climate sensor.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Zigbee2MqttSensor {
+    /// `zigbee2mqtt-<ieee address>`.
+    pub id: String,
+    pub name: String,
+    pub reachable: bool,
+    pub model: String,
+    pub manufacturer: String,
+    pub readings: Vec<SensorReading>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SensorReading {
+    /// Property in the device state, e.g. `temperature`.
+    pub property: String,
+    /// `null` until the device reported it.
+    pub value: Value,
+    pub unit: Option<String>,
+}
+
+fn to_sensor(snapshot: &Snapshot) -> Zigbee2MqttSensor {
+    let device = snapshot.device;
+
+    Zigbee2MqttSensor {
+        id: format!("{}-{}", PROVIDER, device.ieee_address),
+        name: device.friendly_name.clone(),
+        reachable: snapshot.reachable,
+        model: definition_field(device, |definition| &definition.model),
+        manufacturer: definition_field(device, |definition| &definition.vendor),
+        readings: readings(device)
+            .map(|expose| {
+                let property = expose.property.clone().unwrap_or_default();
+
+                SensorReading {
+                    value: snapshot
+                        .state
+                        .and_then(|state| state.get(&property))
+                        .cloned()
+                        .unwrap_or(Value::Null),
+                    property,
+                    unit: expose.unit.clone(),
+                }
+            })
+            .collect(),
+    }
+}
+
+fn bridge_offline(bridge: &Zigbee2MqttBridge) -> ApiError {
+    ApiError::Upstream {
+        provider: PROVIDER.to_owned(),
+        status: None,
+        message: format!("No device list received on {}/bridge", bridge.base_topic),
+    }
+}
+
+/// Runs `f` on every paired device of the bridge's network. Fails while the
+/// device list was not received or Zigbee2MQTT reported itself offline.
+fn with_devices<T>(
+    bridge: &Zigbee2MqttBridge,
+    f: impl Fn(&Snapshot) -> Vec<T>,
+) -> Result<Vec<T>, ApiError> {
+    let networks = networks().lock().unwrap();
+
+    let network = networks
+        .get(&bridge.base_topic)
+        .filter(|network| network.online != Some(false))
+        .ok_or_else(|| bridge_offline(bridge))?;
+
+    let devices = network
+        .devices
+        .as_ref()
+        .ok_or_else(|| bridge_offline(bridge))?;
+
+    Ok(devices
+        .iter()
+        .filter(|device| device.type_ != "Coordinator" && !device.disabled)
+        .flat_map(|device| f(&Snapshot::new(network, device)))
+        .collect())
+}
+
+pub fn get_lights(bridge: &Zigbee2MqttBridge) -> Result<Vec<NormalizedLight>, ApiError> {
+    with_devices(bridge, |snapshot| {
+        let channels = channels(snapshot.device);
+
+        channels
+            .iter()
+            .filter(|channel| channel.light)
+            .map(|channel| to_light(snapshot, channel, channels.len()))
+            .collect()
+    })
+}
+
+pub fn get_plugs(bridge: &Zigbee2MqttBridge) -> Result<Vec<NormalizedPlug>, ApiError> {
+    with_devices(bridge, |snapshot| {
+        let channels = channels(snapshot.device);
+
+        channels
+            .iter()
+            .filter(|channel| !channel.light)
+            .map(|channel| to_plug(snapshot, channel, channels.len()))
+            .collect()
+    })
+}
+
+fn get_sensors(bridge: &Zigbee2MqttBridge) -> Result<Vec<Zigbee2MqttSensor>, ApiError> {
+    with_devices(bridge, |snapshot| {
+        if channels(snapshot.device).is_empty() && readings(snapshot.device).next().is_some() {
+            vec![to_sensor(snapshot)]
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+fn unknown_device() -> ApiError {
+    ApiError::NotFound("Unknown device".to_string())
+}
+
+/// Whether any of the user's networks has a device with the IEEE address.
+pub fn has_device(bridges: &[Zigbee2MqttBridge], ieee_address: &str) -> bool {
+    let networks = networks().lock().unwrap();
+
+    bridges
+        .iter()
+        .filter_map(|bridge| networks.get(&bridge.base_topic))
+        .flat_map(|network| network.devices.iter().flatten())
+        .any(|device| device.ieee_address.eq_ignore_ascii_case(ieee_address))
+}
+
+/// Looks up a device of any of the user's networks by IEEE address and runs
+/// `f` on the channel with the given endpoint.
+fn with_channel<T>(
+    bridges: &[Zigbee2MqttBridge],
+    ieee_address: &str,
+    endpoint: &str,
+    light: bool,
+    f: impl Fn(&str, &Snapshot, &Channel, usize) -> T,
+) -> Result<T, ApiError> {
+    let networks = networks().lock().unwrap();
+
+    for bridge in bridges {
+        let network = match networks.get(&bridge.base_topic) {
+            Some(network) => network,
+            None => continue,
+        };
+
+        let device = network.devices.iter().flatten().find(|device| {
+            device.ieee_address.eq_ignore_ascii_case(ieee_address) && !device.disabled
+        });
+
+        if let Some(device) = device {
+            let channels = channels(device);
+
+            return channels
+                .iter()
+                .find(|channel| channel.endpoint == endpoint && channel.light == light)
+                .map(|channel| {
+                    f(
+                        &bridge.base_topic,
+                        &Snapshot::new(network, device),
+                        channel,
+                        channels.len(),
+                    )
+                })
+                .ok_or_else(unknown_device);
+        }
+    }
+
+    Err(unknown_device())
+}
+
+pub fn get_light(
+    bridges: &[Zigbee2MqttBridge],
+    ieee_address: &str,
+    endpoint: &str,
+) -> Result<NormalizedLight, ApiError> {
+    with_channel(
+        bridges,
+        ieee_address,
+        endpoint,
+        true,
+        |_, snapshot, channel, channels| to_light(snapshot, channel, channels),
+    )
+}
+
+pub fn get_plug(
+    bridges: &[Zigbee2MqttBridge],
+    ieee_address: &str,
+    endpoint: &str,
+) -> Result<NormalizedPlug, ApiError> {
+    with_channel(
+        bridges,
+        ieee_address,
+        endpoint,
+        false,
+        |_, snapshot, channel, channels| to_plug(snapshot, channel, channels),
+    )
+}
+
+fn set_property(command: &mut Map<String, Value>, expose: &Option<Expose>, value: Value) {
+    if let Some(property) = expose.as_ref().and_then(|expose| expose.property.clone()) {
+        command.insert(property, value);
+    }
+}
+
+fn power_value(channel: &Channel, on: bool) -> Value {
+    let state = channel.state.as_ref();
+
+    match on {
+        true => state.and_then(|state| state.value_on.clone()),
+        false => state.and_then(|state| state.value_off.clone()),
+    }
+    .unwrap_or_else(|| json!(if on { "ON" } else { "OFF" }))
+}
+
+#[cfg(feature = "mqtt")]
+async fn send(topic: String, payload: String) -> Result<(), ApiError> {
+    super::mqtt::send(topic, payload).await
+}
+
+#[cfg(not(feature = "mqtt"))]
+async fn send(_topic: String, _payload: String) -> Result<(), ApiError> {
+    Err(mqtt_unavailable())
+}
+
+fn mqtt_unavailable() -> ApiError {
+    ApiError::BadRequest("MQTT is not configured".to_string())
+}
+
+/// Publishes to `<base_topic>/<friendly_name>/set`. The state is stored
+/// right away so that the update sent afterwards already contains it;
+/// Zigbee2MQTT publishes the confirmed state shortly after.
+async fn send_command(
+    bridges: &[Zigbee2MqttBridge],
+    ieee_address: &str,
+    endpoint: &str,
+    light: bool,
+    build: impl Fn(&Channel) -> Map<String, Value>,
+) -> Result<(), ApiError> {
+    let (base_topic, friendly_name, command) = with_channel(
+        bridges,
+        ieee_address,
+        endpoint,
+        light,
+        |base_topic, snapshot, channel, _| {
+            (
+                base_topic.to_owned(),
+                snapshot.device.friendly_name.clone(),
+                build(channel),
+            )
+        },
+    )?;
+
+    if command.is_empty() {
+        return Ok(());
+    }
+
+    send(
+        format!("{}/{}/set", base_topic, friendly_name),
+        Value::Object(command.clone()).to_string(),
+    )
+    .await?;
+
+    let mut networks = networks().lock().unwrap();
+
+    if let Some(network) = networks.get_mut(&base_topic) {
+        let state = network.states.entry(friendly_name).or_default();
+
+        // Colors are reported as xy or hs, never as the rgb sent here
+        for (property, value) in command
+            .into_iter()
+            .filter(|(property, _)| property != "color")
+        {
+            state.insert(property, value);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn set_plug(
+    bridges: &[Zigbee2MqttBridge],
+    ieee_address: &str,
+    endpoint: &str,
+    state: PlugState,
+) -> Result<(), ApiError> {
+    send_command(bridges, ieee_address, endpoint, false, |channel| {
+        let mut command = Map::new();
+
+        if let Some(on) = state.on {
+            set_property(&mut command, &channel.state, power_value(channel, on));
+        }
+
+        command
+    })
+    .await
+}
+
+/// Colors and color temperatures are only sent to lights that expose them.
+pub async fn set_light(
+    bridges: &[Zigbee2MqttBridge],
+    ieee_address: &str,
+    endpoint: &str,
+    state: LightState,
+) -> Result<(), ApiError> {
+    send_command(bridges, ieee_address, endpoint, true, |channel| {
+        let mut command = Map::new();
+
+        if let (Some(brightness), Some(expose)) = (state.brigthness, &channel.brightness) {
+            let max = expose.value_max.unwrap_or(DEFAULT_BRIGHTNESS_MAX);
+            let value = ((brightness as f64 / 255.0 * max).round() as u64).max(1);
+
+            set_property(&mut command, &channel.brightness, json!(value));
+        }
+
+        if let Some(color) = state.color.as_ref().and_then(|color| color.first()) {
+            set_property(
+                &mut command,
+                &channel.color,
+                json!({"r": color.0, "g": color.1, "b": color.2}),
+            );
+        }
+
+        if let Some(ct) = state.color_temperature {
+            set_property(&mut command, &channel.color_temp, json!(ct));
+        }
+
+        if let Some(on) = state.on {
+            set_property(&mut command, &channel.state, power_value(channel, on));
+        }
+
+        command
+    })
+    .await
+}
+
+/// Networks that reported themselves offline count as failing.
+pub fn provider_health(bridges: &[Zigbee2MqttBridge]) -> ProviderHealth {
+    let networks = networks().lock().unwrap();
+
+    ProviderHealth {
+        provider: PROVIDER.to_owned(),
+        bridges: bridges.len(),
+        failing: bridges
+            .iter()
+            .filter(|bridge| {
+                networks
+                    .get(&bridge.base_topic)
+                    .is_some_and(|network| network.online == Some(false))
+            })
+            .count(),
+        circuit_open: 0,
+    }
+}
+
+fn bridge_not_found(_: diesel::result::Error) -> ApiError {
+    ApiError::NotFound("Bridge not found".to_string())
+}
+
+pub async fn get_bridges(pool: &DbPool, user_id: i32) -> Result<Vec<Zigbee2MqttBridge>, ApiError> {
+    connection::run(pool, move |connection| {
+        Ok(Zigbee2MqttBridge::get_zigbee2mqtt_bridges_by_user_id(
+            connection, user_id,
+        )?)
+    })
+    .await
+}
+
+fn validate_base_topic(base_topic: &str) -> Result<(), String> {
+    if base_topic.is_empty() || base_topic.len() > 255 {
+        return Err("Must be between 1 and 255 characters".to_owned());
+    }
+
+    if base_topic.contains(['+', '#']) {
+        return Err("Must not contain wildcards".to_owned());
+    }
+
+    if base_topic.starts_with('/') || base_topic.ends_with('/') {
+        return Err("Must not start or end with /".to_owned());
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct BridgeRequest {
+    /// `base_topic` from the Zigbee2MQTT configuration, usually
+    /// `zigbee2mqtt`.
+    base_topic: String,
+}
+
+impl Validate for BridgeRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        errors.check("base_topic", validate_base_topic(&self.base_topic));
+
+        errors.into_result()
+    }
+}
+
+#[openapi(tag = "Zigbee2MQTT")]
+#[get("/bridges")]
+async fn get_zigbee2mqtt_bridges(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+) -> Result<Json<Vec<Zigbee2MqttBridge>>, ApiError> {
+    Ok(Json(get_bridges(_dbpool, jwt.user_id).await?))
+}
+
+/// Devices become available once Zigbee2MQTT's retained device list was
+/// received from the broker. Adding a base topic twice returns the existing
+/// bridge.
+#[openapi(tag = "Zigbee2MQTT")]
+#[put("/config/add", format = "json", data = "<bridge_json>")]
+async fn add_config(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    bridge_json: Validated<BridgeRequest>,
+) -> Result<Json<Zigbee2MqttBridge>, ApiError> {
+    if !cfg!(feature = "mqtt") || app_config().mqtt.host.is_none() {
+        return Err(mqtt_unavailable());
+    }
+
+    let request = bridge_json.into_inner();
+
+    let bridge = connection::run(_dbpool, move |connection| {
+        let existing =
+            Zigbee2MqttBridge::get_zigbee2mqtt_bridges_by_user_id(connection, jwt.user_id)?
+                .into_iter()
+                .find(|bridge| bridge.base_topic == request.base_topic);
+
+        match existing {
+            Some(bridge) => Ok(bridge),
+            None => Ok(Zigbee2MqttBridge::create_zigbee2mqtt_bridge(
+                connection,
+                &NewZigbee2MqttBridge {
+                    base_topic: &request.base_topic,
+                    user_id: &jwt.user_id,
+                },
+            )?),
+        }
+    })
+    .await?;
+
+    #[cfg(feature = "mqtt")]
+    super::mqtt::subscribe(watch(&bridge.base_topic)).await;
+
+    Ok(Json(bridge))
+}
+
+/// The subscription is kept until the next restart.
+#[openapi(tag = "Zigbee2MQTT")]
+#[delete("/config/<id>")]
+async fn delete_bridge(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+    id: i32,
+) -> Result<Json<Value>, ApiError> {
+    connection::run(_dbpool, move |connection| {
+        let bridge = Zigbee2MqttBridge::get_zigbee2mqtt_bridge(connection, jwt.user_id, id)
+            .map_err(bridge_not_found)?;
+
+        Ok(bridge.delete(connection)?)
+    })
+    .await?;
+
+    Ok(Json(json!({})))
+}
+
+/// Devices without lights or switches with their latest readings.
+#[openapi(tag = "Zigbee2MQTT")]
+#[get("/sensors")]
+async fn sensors(
+    jwt: JWTToken,
+    _dbpool: &State<DbPool>,
+) -> Result<Json<Vec<Zigbee2MqttSensor>>, ApiError> {
+    let mut sensors = Vec::new();
+
+    for bridge in get_bridges(_dbpool, jwt.user_id).await? {
+        sensors.extend(get_sensors(&bridge)?);
+    }
+
+    Ok(Json(sensors))
+}
+
+pub fn routes(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        settings: get_zigbee2mqtt_bridges,
+        add_config,
+        delete_bridge,
+        sensors
+    ]
+}
+
+#[cfg(all(test, feature = "mqtt"))]
+mod tests {
+    use super::*;
+
+    /// A network as published by Zigbee2MQTT 1.x: coordinator, color bulb,
+    /// metered plug, 2 gang switch, climate sensor and a disabled device.
+    static DEVICES: &str = include_str!("../../tests/fixtures/zigbee2mqtt/bridge_devices.json");
+
+    /// The last `<base_topic>/<friendly_name>` message of each device.
+    static STATES: &str = include_str!("../../tests/fixtures/zigbee2mqtt/device_states.json");
+
+    /// Feeds the fixtures to a network of its own, the networks are shared
+    /// by all tests.
+    fn load_network(base_topic: &str) -> Zigbee2MqttBridge {
+        watch(base_topic);
+
+        let topic = |rest: &str| format!("{}/{}", base_topic, rest);
+
+        assert!(handle_message(
+            &topic("bridge/state"),
+            br#"{"state":"online"}"#
+        ));
+        assert!(handle_message(&topic("bridge/devices"), DEVICES.as_bytes()));
+
+        let states: Map<String, Value> = _serde_json::from_str(STATES).unwrap();
+
+        for (name, state) in states {
+            assert!(handle_message(&topic(&name), state.to_string().as_bytes()));
+        }
+
+        Zigbee2MqttBridge {
+            id: 1,
+            base_topic: base_topic.to_owned(),
+            user_id: 1,
+        }
+    }
+
+    #[test]
+    fn ignores_commands_and_other_topics() {
+        let bridge = load_network("z2m-ignore");
+
+        assert!(!handle_message("z2m-ignored/bridge/state", b"offline"));
+        assert!(!handle_message("homeapi/1/hue-1-2/set", b"{}"));
+
+        // Commands of other clients are not states
+        assert!(handle_message(
+            "z2m-ignore/Coffee machine/set",
+            br#"{"state":"OFF"}"#
+        ));
+
+        let plug = get_plug(&[bridge], "0x04cf8cdf3c7b9e01", MAIN_ENDPOINT).unwrap();
+        assert!(plug.on);
+    }
+
+    #[test]
+    fn finds_devices_of_the_users_networks() {
+        let bridge = load_network("z2m-owner");
+        let other = Zigbee2MqttBridge {
+            id: 2,
+            base_topic: "z2m-owner-other".to_owned(),
+            user_id: 2,
+        };
+
+        let bridges = [bridge];
+
+        assert!(has_device(&bridges, "0x680AE2FFFE4F1C2D"));
+        assert!(!has_device(&bridges, "0x0000000000000000"));
+        assert!(!has_device(&[other], "0x680ae2fffe4f1c2d"));
+    }
+
+    #[test]
+    fn parses_lights() {
+        let bridge = load_network("z2m-lights");
+        let lights = get_lights(&bridge).unwrap();
+
+        assert_eq!(lights.len(), 1);
+
+        let light = &lights[0];
+        assert_eq!(light.id, "zigbee2mqtt-0x680ae2fffe4f1c2d-main");
+        assert_eq!(light.name, "Living room");
+        assert!(light.on);
+        assert!((light.brightness - 0.5).abs() < 0.01);
+        assert_eq!(light.color.len(), 1);
+        assert_eq!(light.color_temperature, Some(370));
+        assert!(light.reachable);
+        assert_eq!(light.type_, "Extended color light");
+        assert_eq!(light.model, "LED1924G9");
+        assert_eq!(light.manufacturer, "IKEA");
+        assert_eq!(light.swversion, "2.3.093");
+    }
+
+    #[test]
+    fn parses_plugs_per_endpoint() {
+        let bridge = load_network("z2m-plugs");
+        let plugs = get_plugs(&bridge).unwrap();
+
+        let names: Vec<_> = plugs.iter().map(|plug| plug.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Coffee machine",
+                "Hallway switch left",
+                "Hallway switch right"
+            ]
+        );
+
+        let coffee = &plugs[0];
+        assert_eq!(coffee.id, "zigbee2mqtt-0x04cf8cdf3c7b9e01-main");
+        assert!(coffee.on);
+        assert_eq!(coffee.power, Some(1180.5));
+        assert_eq!(coffee.energy, Some(12340.0));
+        assert_eq!(coffee.model, "ZNCZ04LM");
+
+        let (left, right) = (&plugs[1], &plugs[2]);
+        assert_eq!(left.id, "zigbee2mqtt-0xa4c138d2e1f0b567-left");
+        assert!(!left.on);
+        assert!(right.on);
+        assert_eq!(right.power, None);
+    }
+
+    #[test]
+    fn parses_sensors_and_availability() {
+        let bridge = load_network("z2m-sensors");
+
+        assert!(handle_message(
+            "z2m-sensors/Bedroom climate/availability",
+            br#"{"state":"offline"}"#
+        ));
+
+        // The disabled motion sensor is left out
+        let sensors = get_sensors(&bridge).unwrap();
+        assert_eq!(sensors.len(), 1);
+
+        let sensor = &sensors[0];
+        assert_eq!(sensor.id, "zigbee2mqtt-0x00158d0004a6f3e2");
+        assert_eq!(sensor.name, "Bedroom climate");
+        assert!(!sensor.reachable);
+        assert_eq!(sensor.model, "WSDCGQ11LM");
+
+        let reading = |property: &str| {
+            sensor
+                .readings
+                .iter()
+                .find(|reading| reading.property == property)
+                .map(|reading| (reading.value.clone(), reading.unit.clone()))
+        };
+
+        assert_eq!(
+            reading("temperature"),
+            Some((json!(21.35), Some("°C".to_string())))
+        );
+        assert_eq!(
+            reading("humidity"),
+            Some((json!(48.72), Some("%".to_string())))
+        );
+        assert_eq!(reading("battery"), Some((json!(91), Some("%".to_string()))));
+    }
+
+    #[test]
+    fn fails_while_the_bridge_is_offline() {
+        let bridge = load_network("z2m-offline");
+
+        assert!(handle_message("z2m-offline/bridge/state", b"offline"));
+
+        assert!(get_lights(&bridge).is_err());
+        assert!(
+            !get_light(&[bridge], "0x680ae2fffe4f1c2d", MAIN_ENDPOINT)
+                .unwrap()
+                .reachable
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[rocket::async_test]
+    async fn publishes_commands_on_the_set_topic() {
+        use crate::utils::testing::{mqtt_server, next_publish, subscriber};
+
+        mqtt_server();
+
+        let bridges = [load_network("z2m-commands")];
+        let (_client, mut commands) = subscriber(&["z2m-commands/+/set"]).await;
+
+        set_light(
+            &bridges,
+            "0x680ae2fffe4f1c2d",
+            MAIN_ENDPOINT,
+            LightState {
+                on: Some(false),
+                brigthness: Some(255),
+                color: None,
+                color_temperature: Some(300),
+            },
+        )
+        .await
+        .unwrap();
+
+        let command = next_publish(&mut commands, "z2m-commands/Living room/set").await;
+        assert_eq!(
+            _serde_json::from_slice::<Value>(&command.payload).unwrap(),
+            json!({"state": "OFF", "brightness": 254, "color_temp": 300})
+        );
+
+        set_plug(
+            &bridges,
+            "0xa4c138d2e1f0b567",
+            "left",
+            PlugState { on: Some(true) },
+        )
+        .await
+        .unwrap();
+
+        let command = next_publish(&mut commands, "z2m-commands/Hallway switch/set").await;
+        assert_eq!(
+            _serde_json::from_slice::<Value>(&command.payload).unwrap(),
+            json!({"state_left": "ON"})
+        );
+
+        // The sent state is applied before Zigbee2MQTT confirms it
+        let light = get_light(&bridges, "0x680ae2fffe4f1c2d", MAIN_ENDPOINT).unwrap();
+        assert!(!light.on);
+        assert_eq!(light.color_temperature, Some(300));
+    }
+}
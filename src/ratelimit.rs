@@ -0,0 +1,427 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Status},
+    request::{FromRequest, Outcome},
+    Request, Response, State,
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+};
+
+use crate::{
+    config::{BucketConfig, RateLimitConfig},
+    repsonses::ApiError,
+};
+
+static MAX_TRACKED_KEYS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Auth,
+    Account,
+    Devices,
+}
+
+impl RateLimitConfig {
+    fn bucket(&self, scope: Scope) -> &BucketConfig {
+        match scope {
+            Scope::Auth => &self.auth,
+            Scope::Account => &self.account,
+            Scope::Devices => &self.devices,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct LoginFailures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// In-memory token buckets per scope and key (client IP or account) plus an
+/// exponential lockout for failed logins.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<(Scope, String), TokenBucket>>,
+    failures: Mutex<HashMap<String, LoginFailures>>,
+}
+
+fn retry_after_secs(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil().max(1.0) as u64
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes one token from the bucket. Returns the seconds until the next
+    /// token is available if the bucket is empty.
+    pub fn check(&self, scope: Scope, key: &str) -> Result<(), u64> {
+        let config = self.config.bucket(scope);
+        let refill_per_sec = config.per_minute / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_TRACKED_KEYS {
+            buckets.retain(|(scope, _), bucket| {
+                let config = self.config.bucket(*scope);
+
+                bucket.tokens
+                    + now.duration_since(bucket.updated).as_secs_f64() * config.per_minute / 60.0
+                    < config.burst
+            });
+        }
+
+        let bucket = buckets
+            .entry((scope, key.to_owned()))
+            .or_insert(TokenBucket {
+                tokens: config.burst,
+                updated: now,
+            });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(config.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(retry_after_secs(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / refill_per_sec,
+        )))
+    }
+
+    /// Returns the remaining lockout in seconds if the account is locked.
+    pub fn login_locked(&self, account: &str) -> Option<u64> {
+        let failures = self.failures.lock().unwrap();
+        let locked_until = failures.get(account)?.locked_until?;
+        let now = Instant::now();
+
+        if locked_until > now {
+            Some(retry_after_secs(locked_until - now))
+        } else {
+            None
+        }
+    }
+
+    /// Locks the account once the threshold is reached, doubling the lockout
+    /// with every further failure up to `lockout_max`.
+    pub fn record_login_failure(&self, account: &str) {
+        let mut failures = self.failures.lock().unwrap();
+
+        if failures.len() > MAX_TRACKED_KEYS {
+            let now = Instant::now();
+            failures.retain(|_, entry| entry.locked_until.is_some_and(|until| until > now));
+        }
+
+        let entry = failures.entry(account.to_owned()).or_insert(LoginFailures {
+            count: 0,
+            locked_until: None,
+        });
+
+        entry.count += 1;
+
+        if entry.count >= self.config.lockout_threshold {
+            let exponent = (entry.count - self.config.lockout_threshold).min(16);
+            let lockout = self
+                .config
+                .lockout_base
+                .saturating_mul(2u64.pow(exponent))
+                .min(self.config.lockout_max);
+
+            entry.locked_until = Some(Instant::now() + Duration::from_secs(lockout));
+        }
+    }
+
+    pub fn record_login_success(&self, account: &str) {
+        self.failures.lock().unwrap().remove(account);
+    }
+
+    /// The peer address, or the `X-Real-IP` header if the peer is a trusted
+    /// proxy. Clients could otherwise send a new address with every request.
+    fn client(&self, request: &Request<'_>) -> String {
+        let remote = match request.remote() {
+            Some(remote) => remote.ip(),
+            None => return "unknown".to_string(),
+        };
+
+        let client = match self.config.trusted_proxies.contains(&remote) {
+            true => request.real_ip().unwrap_or(remote),
+            false => remote,
+        };
+
+        client.to_string()
+    }
+}
+
+#[derive(Default)]
+struct RetryAfter(AtomicU64);
+
+/// Request guard for rate limited routes. Routes under `/api/auth` use the
+/// auth bucket, everything else the device bucket, both keyed by client IP.
+pub struct RateLimit<'r> {
+    limiter: &'r RateLimiter,
+    retry_after: &'r RetryAfter,
+}
+
+impl<'r> RateLimit<'r> {
+    pub fn check_account(&self, account: &str) -> Result<(), ApiError> {
+        let account = account.to_lowercase();
+
+        if let Some(retry_after) = self.limiter.login_locked(&account) {
+            return Err(self.too_many_requests(retry_after));
+        }
+
+        match self.limiter.check(Scope::Account, &account) {
+            Ok(_) => Ok(()),
+            Err(retry_after) => Err(self.too_many_requests(retry_after)),
+        }
+    }
+
+    pub fn login_failed(&self, account: &str) {
+        self.limiter.record_login_failure(&account.to_lowercase());
+    }
+
+    pub fn login_succeeded(&self, account: &str) {
+        self.limiter.record_login_success(&account.to_lowercase());
+    }
+
+    pub fn too_many_requests(&self, retry_after: u64) -> ApiError {
+        self.retry_after.0.store(retry_after, Ordering::Relaxed);
+
+        ApiError::RateLimited
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<RateLimit<'r>, Self::Error> {
+        let limiter = match request.guard::<&State<RateLimiter>>().await {
+            Outcome::Success(limiter) => limiter.inner(),
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        let retry_after = request.local_cache(RetryAfter::default);
+
+        let scope = if request.uri().path().starts_with("/api/auth") {
+            Scope::Auth
+        } else {
+            Scope::Devices
+        };
+
+        match limiter.check(scope, &limiter.client(request)) {
+            Ok(_) => Outcome::Success(RateLimit {
+                limiter,
+                retry_after,
+            }),
+            Err(seconds) => {
+                retry_after.0.store(seconds, Ordering::Relaxed);
+                Outcome::Failure((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for RateLimit<'r> {
+    fn from_request_input(
+        _: &mut OpenApiGenerator,
+        _: String,
+        _: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// Adds the `Retry-After` header to `429` responses produced by [`RateLimit`].
+pub struct RateLimitHeaders;
+
+#[rocket::async_trait]
+impl Fairing for RateLimitHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limit",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status() != Status::TooManyRequests {
+            return;
+        }
+
+        let retry_after = request.local_cache(RetryAfter::default).0.load(Ordering::Relaxed);
+
+        if retry_after > 0 {
+            response.set_header(Header::new("Retry-After", retry_after.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        catchers,
+        http::Header,
+        local::blocking::{Client, LocalResponse},
+        routes,
+    };
+
+    use crate::config::AppConfig;
+
+    use super::*;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            auth: BucketConfig {
+                burst: 10.0,
+                per_minute: 60.0,
+            },
+            devices: BucketConfig {
+                burst: 2.0,
+                per_minute: 60.0,
+            },
+            lockout_threshold: 3,
+            lockout_base: 30,
+            lockout_max: 100,
+            ..AppConfig::default().rate_limit
+        }
+    }
+
+    /// Checks a password the way the auth routes do.
+    #[rocket::get("/api/auth/check?<password>")]
+    fn check(limit: RateLimit<'_>, password: &str) -> Result<(), ApiError> {
+        limit.check_account("User@example.com")?;
+
+        if password != "secret" {
+            limit.login_failed("User@example.com");
+            return Err(ApiError::Unauthorized("Invalid password".to_string()));
+        }
+
+        limit.login_succeeded("User@example.com");
+
+        Ok(())
+    }
+
+    #[rocket::get("/api/devices")]
+    fn devices(_limit: RateLimit<'_>) -> &'static str {
+        "[]"
+    }
+
+    fn client(config: RateLimitConfig) -> Client {
+        let rocket = rocket::build()
+            .mount("/", routes![check, devices])
+            .register("/", catchers![crate::too_many_requests])
+            .attach(RateLimitHeaders)
+            .manage(RateLimiter::new(config));
+
+        Client::untracked(rocket).unwrap()
+    }
+
+    fn from<'c>(client: &'c Client, real_ip: &str) -> LocalResponse<'c> {
+        client
+            .get("/api/devices")
+            .remote("10.0.0.1:4000".parse().unwrap())
+            .header(Header::new("X-Real-IP", real_ip.to_string()))
+            .dispatch()
+    }
+
+    #[test]
+    fn empties_buckets() {
+        let limiter = RateLimiter::new(config());
+
+        assert_eq!(limiter.check(Scope::Devices, "a"), Ok(()));
+        assert_eq!(limiter.check(Scope::Devices, "a"), Ok(()));
+        assert_eq!(limiter.check(Scope::Devices, "a"), Err(1));
+        assert_eq!(limiter.check(Scope::Devices, "b"), Ok(()));
+        assert_eq!(limiter.check(Scope::Auth, "a"), Ok(()));
+    }
+
+    #[test]
+    fn doubles_the_lockout_up_to_the_maximum() {
+        let limiter = RateLimiter::new(config());
+
+        limiter.record_login_failure("a");
+        limiter.record_login_failure("a");
+        assert_eq!(limiter.login_locked("a"), None);
+
+        limiter.record_login_failure("a");
+        assert_eq!(limiter.login_locked("a"), Some(30));
+
+        limiter.record_login_failure("a");
+        assert_eq!(limiter.login_locked("a"), Some(60));
+
+        limiter.record_login_failure("a");
+        assert_eq!(limiter.login_locked("a"), Some(100));
+        assert_eq!(limiter.login_locked("b"), None);
+
+        limiter.record_login_success("a");
+        assert_eq!(limiter.login_locked("a"), None);
+    }
+
+    #[test]
+    fn locks_accounts_with_retry_after() {
+        let client = client(config());
+
+        for _ in 0..3 {
+            let response = client.get("/api/auth/check?password=wrong").dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+
+        // Locked even for the right password
+        let response = client.get("/api/auth/check?password=secret").dispatch();
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("Retry-After"), Some("30"));
+    }
+
+    #[test]
+    fn limits_clients_with_retry_after() {
+        let client = client(config());
+
+        assert_eq!(from(&client, "192.0.2.1").status(), Status::Ok);
+        assert_eq!(from(&client, "192.0.2.2").status(), Status::Ok);
+
+        // The header of an untrusted peer is ignored
+        let response = from(&client, "192.0.2.3");
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("Retry-After"), Some("1"));
+    }
+
+    #[test]
+    fn trusts_the_header_of_proxies() {
+        let client = client(RateLimitConfig {
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            ..config()
+        });
+
+        for real_ip in ["192.0.2.1", "192.0.2.2", "192.0.2.3"] {
+            assert_eq!(from(&client, real_ip).status(), Status::Ok);
+            assert_eq!(from(&client, real_ip).status(), Status::Ok);
+        }
+
+        assert_eq!(from(&client, "192.0.2.1").status(), Status::TooManyRequests);
+    }
+}
@@ -1,83 +1,354 @@
-use okapi::openapi3::Responses;
-use rocket::{http::Status, response::Responder};
-use rocket_okapi::okapi::openapi3::{RefOr, Response as OpenApiReponse};
-use rocket_okapi::{gen::OpenApiGenerator, response::OpenApiResponderInner};
-use schemars::{JsonSchema, Map, _serde_json::json};
-
-#[derive(Debug)]
-pub struct CustomResponse {
-    pub status: Status,
-    pub message: String,
-}
-
-#[derive(JsonSchema)]
-struct Schema {
-    #[allow(dead_code)]
-    message: String,
-}
-
-fn gen_response(status: Status) -> OpenApiReponse {
-    let mut response = OpenApiReponse::default();
-    response.description = status.reason_lossy().to_owned();
-    response.content = vec![(
-        "application/json".to_owned(),
-        rocket_okapi::okapi::openapi3::MediaType {
-            schema: Some(schemars::schema_for!(Schema).schema),
-            ..Default::default()
-        },
-    )]
-    .into_iter()
-    .collect();
-
-    response
-}
-
-impl CustomResponse {
-    pub fn get_json_message(&self) -> String {
-        let object = json!({
-            "message": self.message.clone(),
-        });
-
-        schemars::_serde_json::to_string(&object).unwrap()
-    }
-}
-
-#[rocket::async_trait]
-impl<'r> Responder<'r, 'static> for CustomResponse {
-    fn respond_to(self, _: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
-        let message = self.get_json_message();
-        let response = rocket::Response::build()
-            .status(self.status)
-            .header(rocket::http::ContentType::JSON)
-            .streamed_body(std::io::Cursor::new(message))
-            .finalize();
-
-        Ok(response)
-    }
-}
-
-impl OpenApiResponderInner for CustomResponse {
-    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
-        let mut responses = Map::new();
-
-        let status_codes = vec![
-            Status::BadRequest,
-            Status::Unauthorized,
-            Status::Forbidden,
-            Status::NotFound,
-            Status::InternalServerError,
-        ];
-
-        for status in status_codes {
-            responses.insert(
-                status.code.to_string(),
-                RefOr::Object(gen_response(status)),
-            );
-        }
-
-        Ok(Responses {
-            responses,
-            ..Default::default()
-        })
-    }
-}
+use std::fmt;
+
+use okapi::openapi3::Responses;
+use rocket::{
+    http::{ContentType, Status},
+    response::Responder,
+    serde::json::Json,
+};
+use rocket_okapi::okapi::openapi3::{RefOr, Response as OpenApiReponse};
+use rocket_okapi::{gen::OpenApiGenerator, response::OpenApiResponderInner};
+use schemars::{
+    JsonSchema, Map,
+    _serde_json::{self, json},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Error returned by all API handlers. Every variant maps to a status code and
+/// a stable `code` that clients can match on instead of the message.
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Unauthorized(String),
+    Conflict(String),
+    RateLimited,
+    /// A bridge or device answered with an error or an unexpected body.
+    Upstream {
+        provider: String,
+        status: Option<u16>,
+        message: String,
+    },
+    /// A bridge or device did not answer in time.
+    Timeout {
+        provider: String,
+    },
+    /// A device presented a different certificate than the one pinned for it.
+    Untrusted {
+        provider: String,
+    },
+    Validation(String),
+    Database(String),
+    /// No database connection became available in time, e.g. because the
+    /// pool is exhausted.
+    Unavailable(String),
+    Internal(String),
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct Schema {
+    /// Stable machine-readable error code, e.g. `not_found` or `upstream_error`.
+    code: String,
+    message: String,
+    /// Only set for `upstream_error`, `upstream_timeout` and `upstream_untrusted`.
+    provider: Option<String>,
+}
+
+fn gen_response(status: Status) -> OpenApiReponse {
+    let mut response = OpenApiReponse::default();
+    response.description = status.reason_lossy().to_owned();
+    response.content = vec![(
+        "application/json".to_owned(),
+        rocket_okapi::okapi::openapi3::MediaType {
+            schema: Some(schemars::schema_for!(Schema).schema),
+            ..Default::default()
+        },
+    )]
+    .into_iter()
+    .collect();
+
+    response
+}
+
+impl ApiError {
+    pub fn upstream(provider: &str, message: &str) -> ApiError {
+        ApiError::Upstream {
+            provider: provider.to_owned(),
+            status: None,
+            message: message.to_owned(),
+        }
+    }
+
+    /// Attributes upstream errors created through the generic `From` impls to
+    /// a provider. Other variants are returned unchanged.
+    pub fn with_provider(self, provider: &str) -> ApiError {
+        match self {
+            ApiError::Upstream {
+                status, message, ..
+            } => ApiError::Upstream {
+                provider: provider.to_owned(),
+                status,
+                message,
+            },
+            ApiError::Timeout { .. } => ApiError::Timeout {
+                provider: provider.to_owned(),
+            },
+            ApiError::Untrusted { .. } => ApiError::Untrusted {
+                provider: provider.to_owned(),
+            },
+            error => error,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Unauthorized(_) => Status::Unauthorized,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::RateLimited => Status::TooManyRequests,
+            ApiError::Upstream { .. } => Status::BadGateway,
+            ApiError::Timeout { .. } => Status::GatewayTimeout,
+            ApiError::Untrusted { .. } => Status::BadGateway,
+            ApiError::Validation(_) => Status::UnprocessableEntity,
+            ApiError::Database(_) => Status::InternalServerError,
+            ApiError::Unavailable(_) => Status::ServiceUnavailable,
+            ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::RateLimited => "rate_limited",
+            ApiError::Upstream { .. } => "upstream_error",
+            ApiError::Timeout { .. } => "upstream_timeout",
+            ApiError::Untrusted { .. } => "upstream_untrusted",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Database(_) => "database_error",
+            ApiError::Unavailable(_) => "service_unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Message sent to the client. Database, pool and internal details are
+    /// only logged.
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::NotFound(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Conflict(message)
+            | ApiError::Validation(message) => message.clone(),
+            ApiError::RateLimited => "Too many requests".to_owned(),
+            ApiError::Upstream {
+                provider,
+                status: Some(status),
+                message,
+            } => format!("{} responded with {}: {}", provider, status, message),
+            ApiError::Upstream {
+                provider, message, ..
+            } => format!("{}: {}", provider, message),
+            ApiError::Timeout { provider } => format!("{} did not respond in time", provider),
+            ApiError::Untrusted { provider } => format!(
+                "{} presented a certificate that does not match the pinned one",
+                provider
+            ),
+            ApiError::Database(_) => "Database error".to_owned(),
+            ApiError::Unavailable(_) => "Service temporarily unavailable".to_owned(),
+            ApiError::Internal(_) => "Internal Server Error".to_owned(),
+        }
+    }
+
+    pub fn get_json_message(&self) -> String {
+        let mut object = json!({
+            "code": self.code(),
+            "message": self.message(),
+        });
+
+        if let ApiError::Upstream { provider, .. }
+        | ApiError::Timeout { provider }
+        | ApiError::Untrusted { provider } = self
+        {
+            object["provider"] = json!(provider);
+        }
+
+        _serde_json::to_string(&object).unwrap_or_default()
+    }
+
+    /// Body for responses produced by catchers, where only the status is known.
+    pub fn from_status(status: Status) -> ApiError {
+        let message = status.reason_lossy().to_owned();
+
+        match status.code {
+            401 => ApiError::Unauthorized(message),
+            404 => ApiError::NotFound(message),
+            409 => ApiError::Conflict(message),
+            422 => ApiError::Validation(message),
+            429 => ApiError::RateLimited,
+            500 => ApiError::Internal(message),
+            503 => ApiError::Unavailable(message),
+            _ => ApiError::BadRequest(message),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Database(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Internal(message) => write!(f, "{}: {}", self.code(), message),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(error: diesel::result::Error) -> ApiError {
+        match error {
+            diesel::result::Error::NotFound => ApiError::NotFound("Not found".to_owned()),
+            error => ApiError::Database(error.to_string()),
+        }
+    }
+}
+
+/// Only returned by the pool once `database_pool_timeout` passed without a
+/// free connection.
+impl From<r2d2::Error> for ApiError {
+    fn from(error: r2d2::Error) -> ApiError {
+        ApiError::Unavailable(error.to_string())
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(error: reqwest::Error) -> ApiError {
+        if error.is_timeout() {
+            return ApiError::Timeout {
+                provider: "upstream".to_owned(),
+            };
+        }
+
+        ApiError::Upstream {
+            provider: "upstream".to_owned(),
+            status: error.status().map(|status| status.as_u16()),
+            message: if error.is_connect() {
+                "Failed to connect".to_owned()
+            } else {
+                "Request failed".to_owned()
+            },
+        }
+    }
+}
+
+impl From<_serde_json::Error> for ApiError {
+    fn from(error: _serde_json::Error) -> ApiError {
+        ApiError::upstream("upstream", &format!("Invalid response: {}", error))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, _: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        if let ApiError::Database(_) | ApiError::Unavailable(_) | ApiError::Internal(_) = self {
+            println!("Error: {}", self);
+        }
+
+        let message = self.get_json_message();
+        let response = rocket::Response::build()
+            .status(self.status())
+            .header(rocket::http::ContentType::JSON)
+            .streamed_body(std::io::Cursor::new(message))
+            .finalize();
+
+        Ok(response)
+    }
+}
+
+/// JSON body with an `ETag` derived from its content. Requests whose
+/// `If-None-Match` matches get `304 Not Modified` without a body.
+pub struct ETagJson<T>(pub T);
+
+impl<T> ETagJson<T> {
+    fn matches(request: &rocket::Request<'_>, etag: &str) -> bool {
+        request
+            .headers()
+            .get("If-None-Match")
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: Serialize> Responder<'r, 'static> for ETagJson<T> {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let body = _serde_json::to_string(&self.0).map_err(|_| Status::InternalServerError)?;
+
+        let etag = format!(
+            "\"{}\"",
+            Sha256::digest(body.as_bytes())[..16]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        );
+
+        let mut response = rocket::Response::build();
+
+        response
+            .raw_header("Cache-Control", "private, no-cache")
+            .raw_header("ETag", etag.clone());
+
+        if ETagJson::<T>::matches(request, &etag) {
+            response.status(Status::NotModified);
+        } else {
+            response
+                .header(ContentType::JSON)
+                .sized_body(body.len(), std::io::Cursor::new(body));
+        }
+
+        response.ok()
+    }
+}
+
+impl<T: JsonSchema + Serialize + Send> OpenApiResponderInner for ETagJson<T> {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        Json::<T>::responses(gen)
+    }
+}
+
+impl OpenApiResponderInner for ApiError {
+    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Map::new();
+
+        let status_codes = vec![
+            Status::BadRequest,
+            Status::Unauthorized,
+            Status::NotFound,
+            Status::Conflict,
+            Status::UnprocessableEntity,
+            Status::TooManyRequests,
+            Status::InternalServerError,
+            Status::BadGateway,
+            Status::ServiceUnavailable,
+            Status::GatewayTimeout,
+        ];
+
+        for status in status_codes {
+            responses.insert(
+                status.code.to_string(),
+                RefOr::Object(gen_response(status)),
+            );
+        }
+
+        Ok(Responses {
+            responses,
+            ..Default::default()
+        })
+    }
+}
@@ -1,99 +1,161 @@
-pub fn hsb_to_hsv(hue: f32, saturation: f32, brightness: f32) -> (f32, f32, f32) {
-    (
-        hue / 65535.0 * 360.0,
-        saturation / 255.0 * 100.0,
-        brightness / 255.0 * 100.0,
-    )
-}
-
-pub fn hsv_to_hsb(hue: f32, saturation: f32, brightness: f32) -> (u16, u8, u8) {
-    (
-        (hue / 360.0 * 65535.0).round() as u16,
-        (saturation / 100.0 * 255.0).round() as u8,
-        (brightness / 100.0 * 255.0).round() as u8,
-    )
-}
-
-pub fn rgb_to_hsv(red: u8, green: u8, blue: u8) -> (f32, f32, f32) {
-    let red = red as f32 / 255.0;
-    let green = green as f32 / 255.0;
-    let blue = blue as f32 / 255.0;
-
-    let mut hsv = (0.0, 0.0, 0.0);
-
-    let max = red.max(green.max(blue));
-    let min = red.min(green.min(blue));
-
-    hsv.2 = max;
-
-    let delta = max - min;
-
-    if delta != 0.0 {
-        hsv.1 = delta / max;
-
-        if max == red {
-            hsv.0 = (green - blue) / delta;
-        } else if max == green {
-            hsv.0 = 2.0 + (blue - red) / delta;
-        } else {
-            hsv.0 = 4.0 + (red - green) / delta;
-        }
-
-        hsv.0 *= 60.0;
-
-        if hsv.0 < 0.0 {
-            hsv.0 += 360.0;
-        }
-    }
-
-    hsv
-}
-
-pub fn hsv_to_rgb(hue: f32, saturation: f32, brightness: f32) -> (u8, u8, u8) {
-    let mut rgb = (0, 0, 0);
-
-    let h = hue / 60.0;
-    let s = saturation / 100.0;
-    let v = brightness / 100.0;
-
-    let i = h.floor() as i32;
-    let f = h - i as f32;
-    let p = v * (1.0 - s);
-    let q = v * (1.0 - s * f);
-    let t = v * (1.0 - s * (1.0 - f));
-
-    match i {
-        0 => {
-            rgb.0 = (v * 255.0) as u8;
-            rgb.1 = (t * 255.0) as u8;
-            rgb.2 = (p * 255.0) as u8;
-        }
-        1 => {
-            rgb.0 = (q * 255.0) as u8;
-            rgb.1 = (v * 255.0) as u8;
-            rgb.2 = (p * 255.0) as u8;
-        }
-        2 => {
-            rgb.0 = (p * 255.0) as u8;
-            rgb.1 = (v * 255.0) as u8;
-            rgb.2 = (t * 255.0) as u8;
-        }
-        3 => {
-            rgb.0 = (p * 255.0) as u8;
-            rgb.1 = (q * 255.0) as u8;
-            rgb.2 = (v * 255.0) as u8;
-        }
-        4 => {
-            rgb.0 = (t * 255.0) as u8;
-            rgb.1 = (p * 255.0) as u8;
-            rgb.2 = (v * 255.0) as u8;
-        }
-        _ => {
-            rgb.0 = (v * 255.0) as u8;
-            rgb.1 = (p * 255.0) as u8;
-            rgb.2 = (q * 255.0) as u8;
-        }
-    }
-
-    rgb
-}
+pub fn hsb_to_hsv(hue: f32, saturation: f32, brightness: f32) -> (f32, f32, f32) {
+    (
+        hue / 65535.0 * 360.0,
+        saturation / 255.0 * 100.0,
+        brightness / 255.0 * 100.0,
+    )
+}
+
+pub fn hsv_to_hsb(hue: f32, saturation: f32, brightness: f32) -> (u16, u8, u8) {
+    (
+        (hue / 360.0 * 65535.0).round() as u16,
+        (saturation / 100.0 * 255.0).round() as u8,
+        (brightness / 100.0 * 255.0).round() as u8,
+    )
+}
+
+pub fn rgb_to_hsv(red: u8, green: u8, blue: u8) -> (f32, f32, f32) {
+    let red = red as f32 / 255.0;
+    let green = green as f32 / 255.0;
+    let blue = blue as f32 / 255.0;
+
+    let mut hsv = (0.0, 0.0, 0.0);
+
+    let max = red.max(green.max(blue));
+    let min = red.min(green.min(blue));
+
+    hsv.2 = max;
+
+    let delta = max - min;
+
+    if delta != 0.0 {
+        hsv.1 = delta / max;
+
+        if max == red {
+            hsv.0 = (green - blue) / delta;
+        } else if max == green {
+            hsv.0 = 2.0 + (blue - red) / delta;
+        } else {
+            hsv.0 = 4.0 + (red - green) / delta;
+        }
+
+        hsv.0 *= 60.0;
+
+        if hsv.0 < 0.0 {
+            hsv.0 += 360.0;
+        }
+    }
+
+    hsv
+}
+
+pub fn hsv_to_rgb(hue: f32, saturation: f32, brightness: f32) -> (u8, u8, u8) {
+    let mut rgb = (0, 0, 0);
+
+    let h = hue / 60.0;
+    let s = saturation / 100.0;
+    let v = brightness / 100.0;
+
+    let i = h.floor() as i32;
+    let f = h - i as f32;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+
+    match i {
+        0 => {
+            rgb.0 = (v * 255.0) as u8;
+            rgb.1 = (t * 255.0) as u8;
+            rgb.2 = (p * 255.0) as u8;
+        }
+        1 => {
+            rgb.0 = (q * 255.0) as u8;
+            rgb.1 = (v * 255.0) as u8;
+            rgb.2 = (p * 255.0) as u8;
+        }
+        2 => {
+            rgb.0 = (p * 255.0) as u8;
+            rgb.1 = (v * 255.0) as u8;
+            rgb.2 = (t * 255.0) as u8;
+        }
+        3 => {
+            rgb.0 = (p * 255.0) as u8;
+            rgb.1 = (q * 255.0) as u8;
+            rgb.2 = (v * 255.0) as u8;
+        }
+        4 => {
+            rgb.0 = (t * 255.0) as u8;
+            rgb.1 = (p * 255.0) as u8;
+            rgb.2 = (v * 255.0) as u8;
+        }
+        _ => {
+            rgb.0 = (v * 255.0) as u8;
+            rgb.1 = (p * 255.0) as u8;
+            rgb.2 = (q * 255.0) as u8;
+        }
+    }
+
+    rgb
+}
+
+/// CIE 1931 `x`/`y` as reported by Zigbee lights, using the Wide RGB D65
+/// conversion. `brightness` is in percent like for `hsv_to_rgb`.
+pub fn xy_to_rgb(x: f32, y: f32, brightness: f32) -> (u8, u8, u8) {
+    if y <= 0.0 {
+        return (0, 0, 0);
+    }
+
+    let z = 1.0 - x - y;
+    let big_x = x / y;
+    let big_z = z / y;
+
+    let linear = [
+        big_x * 1.656492 - 0.354851 - big_z * 0.255038,
+        -big_x * 0.707196 + 1.655397 + big_z * 0.036152,
+        big_x * 0.051713 - 0.121364 + big_z * 1.01153,
+    ];
+
+    let gamma = |value: f32| {
+        let value = value.max(0.0);
+
+        if value <= 0.0031308 {
+            12.92 * value
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        }
+    };
+
+    let rgb = linear.map(gamma);
+    let max = rgb[0].max(rgb[1].max(rgb[2]));
+
+    if max <= 0.0 {
+        return (0, 0, 0);
+    }
+
+    let scale = brightness / 100.0 / max * 255.0;
+
+    (
+        (rgb[0] * scale).round() as u8,
+        (rgb[1] * scale).round() as u8,
+        (rgb[2] * scale).round() as u8,
+    )
+}
+
+/// Hue and saturation of LIFX HSBK colors, both `0..=65535`. Hue wraps
+/// around, so `65535` is just below 360 degrees.
+pub fn hsbk_to_rgb(hue: u16, saturation: u16) -> (u8, u8, u8) {
+    hsv_to_rgb(
+        hue as f32 / 65536.0 * 360.0,
+        saturation as f32 / 65535.0 * 100.0,
+        100.0,
+    )
+}
+
+pub fn rgb_to_hsbk(red: u8, green: u8, blue: u8) -> (u16, u16) {
+    let (hue, saturation, _) = rgb_to_hsv(red, green, blue);
+
+    (
+        (hue / 360.0 * 65536.0).round() as u16,
+        (saturation * 65535.0).round() as u16,
+    )
+}
@@ -1,27 +1,32 @@
-use schemars::_serde_json::Value;
-
-pub trait ValueExt {
-    fn to_string(&self) -> String;
-    fn to_bool(&self) -> bool;
-    fn to_i64(&self) -> i64;
-    fn to_u64(&self) -> u64;
-    fn to_f64(&self) -> f64;
-}
-
-impl ValueExt for Option<&Value> {
-    fn to_string(&self) -> String {
-        self.unwrap().as_str().unwrap().to_string()
-    }
-    fn to_bool(&self) -> bool {
-        self.unwrap().as_bool().unwrap()
-    }
-    fn to_i64(&self) -> i64 {
-        self.unwrap().as_i64().unwrap()
-    }
-    fn to_u64(&self) -> u64 {
-        self.unwrap().as_u64().unwrap()
-    }
-    fn to_f64(&self) -> f64 {
-        self.unwrap().as_f64().unwrap()
-    }
-}
+use schemars::_serde_json::Value;
+
+use crate::repsonses::ApiError;
+
+/// Typed getters for fields of upstream JSON. A missing field or a field of
+/// the wrong type is an [`ApiError::Upstream`], so bridge replies can be read
+/// with `?` instead of panicking.
+pub trait ValueExt {
+    fn to_string(&self) -> Result<String, ApiError>;
+    fn to_bool(&self) -> Result<bool, ApiError>;
+    fn to_f64(&self) -> Result<f64, ApiError>;
+}
+
+fn unexpected_format() -> ApiError {
+    ApiError::upstream("upstream", "Unexpected response format")
+}
+
+impl ValueExt for Option<&Value> {
+    fn to_string(&self) -> Result<String, ApiError> {
+        self.and_then(|value| value.as_str())
+            .map(|value| value.to_string())
+            .ok_or_else(unexpected_format)
+    }
+    fn to_bool(&self) -> Result<bool, ApiError> {
+        self.and_then(|value| value.as_bool())
+            .ok_or_else(unexpected_format)
+    }
+    fn to_f64(&self) -> Result<f64, ApiError> {
+        self.and_then(|value| value.as_f64())
+            .ok_or_else(unexpected_format)
+    }
+}
@@ -0,0 +1,385 @@
+//! Helpers shared by the unit tests.
+
+use crate::config::{self, AppConfig};
+
+#[cfg(feature = "sqlite")]
+use crate::db::connection::{self, DbPool};
+
+/// All tests of the process share one configuration, the defaults.
+pub fn init_config() -> &'static AppConfig {
+    config::init(AppConfig::default())
+}
+
+/// An empty in-memory database. Every connection to `:memory:` opens a new
+/// one, so the pool holds a single connection.
+#[cfg(feature = "sqlite")]
+pub fn memory_pool() -> DbPool {
+    use diesel::r2d2::{ConnectionManager, Pool};
+
+    Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(connection::sqlite::ConnectionOptions))
+        .build(ConnectionManager::new(":memory:"))
+        .unwrap()
+}
+
+#[cfg(all(feature = "mqtt", feature = "sqlite"))]
+pub use self::mqtt::*;
+
+#[cfg(all(feature = "mqtt", feature = "sqlite"))]
+mod mqtt {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex, OnceLock},
+        time::Duration,
+    };
+
+    use bytes::BytesMut;
+    use rocket::tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        runtime::{Builder, Runtime},
+        sync::{
+            broadcast::channel,
+            mpsc::{unbounded_channel, UnboundedSender},
+        },
+        time::{sleep, timeout},
+    };
+    use rumqttc::{
+        mqttbytes::{self, matches, v4},
+        AsyncClient, ConnAck, ConnectReturnCode, Event, EventLoop, MqttOptions, Packet, PingResp,
+        PubAck, Publish, QoS, SubAck, SubscribeReasonCode,
+    };
+
+    use crate::{
+        db::connection::{self, DbPool},
+        plugins, InternalMessage,
+    };
+
+    use super::{init_config, memory_pool};
+
+    static MAX_PACKET_SIZE: usize = 1 << 20;
+
+    /// How long `next_publish` and `eventually` wait.
+    static RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    static POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Runs what has to outlive a single `#[rocket::async_test]`, whose
+    /// runtime is dropped at the end of the test.
+    fn runtime() -> &'static Runtime {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+        RUNTIME.get_or_init(|| {
+            Builder::new_multi_thread()
+                .worker_threads(2)
+                .enable_all()
+                .build()
+                .unwrap()
+        })
+    }
+
+    struct Subscriber {
+        filters: Vec<String>,
+        sender: UnboundedSender<Vec<u8>>,
+    }
+
+    /// A minimal MQTT 3.1.1 broker. Messages are delivered with QoS 0 to
+    /// matching subscriptions, retained ones also to later subscribers.
+    #[derive(Default)]
+    struct Broker {
+        subscribers: HashMap<usize, Subscriber>,
+        retained: HashMap<String, Publish>,
+    }
+
+    fn encode(publish: &Publish, retain: bool) -> Vec<u8> {
+        let mut publish = publish.clone();
+        publish.qos = QoS::AtMostOnce;
+        publish.pkid = 0;
+        publish.retain = retain;
+
+        let mut buffer = BytesMut::new();
+        publish.write(&mut buffer).unwrap();
+        buffer.to_vec()
+    }
+
+    impl Broker {
+        fn publish(&mut self, publish: Publish) {
+            for subscriber in self.subscribers.values() {
+                if subscriber
+                    .filters
+                    .iter()
+                    .any(|filter| matches(&publish.topic, filter))
+                {
+                    let _ = subscriber.sender.send(encode(&publish, false));
+                }
+            }
+
+            if publish.retain {
+                match publish.payload.is_empty() {
+                    true => self.retained.remove(&publish.topic),
+                    false => self.retained.insert(publish.topic.clone(), publish),
+                };
+            }
+        }
+
+        fn subscribe(&mut self, id: usize, filters: Vec<String>) {
+            let subscriber = match self.subscribers.get_mut(&id) {
+                Some(subscriber) => subscriber,
+                None => return,
+            };
+
+            for publish in self.retained.values() {
+                if filters.iter().any(|filter| matches(&publish.topic, filter)) {
+                    let _ = subscriber.sender.send(encode(publish, true));
+                }
+            }
+
+            subscriber.filters.extend(filters);
+        }
+    }
+
+    async fn serve_client(broker: Arc<Mutex<Broker>>, id: usize, stream: TcpStream) {
+        let (mut reader, mut writer) = stream.into_split();
+        let (sender, mut receiver) = unbounded_channel::<Vec<u8>>();
+
+        broker.lock().unwrap().subscribers.insert(
+            id,
+            Subscriber {
+                filters: Vec::new(),
+                sender: sender.clone(),
+            },
+        );
+
+        rocket::tokio::spawn(async move {
+            while let Some(bytes) = receiver.recv().await {
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut buffer = BytesMut::new();
+        let mut chunk = [0u8; 4096];
+
+        loop {
+            let packet = match v4::read(&mut buffer, MAX_PACKET_SIZE) {
+                Ok(packet) => packet,
+                Err(mqttbytes::Error::InsufficientBytes(_)) => {
+                    match reader.read(&mut chunk).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(length) => buffer.extend_from_slice(&chunk[..length]),
+                    }
+
+                    continue;
+                }
+                Err(_) => break,
+            };
+
+            let mut reply = BytesMut::new();
+
+            match packet {
+                Packet::Connect(_) => {
+                    ConnAck::new(ConnectReturnCode::Success, false)
+                        .write(&mut reply)
+                        .unwrap();
+                }
+                Packet::Subscribe(subscribe) => {
+                    let return_codes = subscribe
+                        .filters
+                        .iter()
+                        .map(|_| SubscribeReasonCode::Success(QoS::AtMostOnce))
+                        .collect();
+
+                    SubAck::new(subscribe.pkid, return_codes)
+                        .write(&mut reply)
+                        .unwrap();
+                    let _ = sender.send(reply.to_vec());
+
+                    let filters = subscribe
+                        .filters
+                        .into_iter()
+                        .map(|filter| filter.path)
+                        .collect();
+                    broker.lock().unwrap().subscribe(id, filters);
+
+                    continue;
+                }
+                Packet::Publish(publish) => {
+                    if publish.qos != QoS::AtMostOnce {
+                        PubAck::new(publish.pkid).write(&mut reply).unwrap();
+                    }
+
+                    broker.lock().unwrap().publish(publish);
+                }
+                Packet::PingReq => {
+                    PingResp.write(&mut reply).unwrap();
+                }
+                Packet::Disconnect => break,
+                _ => {}
+            }
+
+            let _ = sender.send(reply.to_vec());
+        }
+
+        broker.lock().unwrap().subscribers.remove(&id);
+    }
+
+    /// Starts the broker once per process.
+    fn broker() -> &'static (u16, Arc<Mutex<Broker>>) {
+        static BROKER: OnceLock<(u16, Arc<Mutex<Broker>>)> = OnceLock::new();
+
+        BROKER.get_or_init(|| {
+            let listener = runtime()
+                .block_on(TcpListener::bind("127.0.0.1:0"))
+                .unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let broker = Arc::new(Mutex::new(Broker::default()));
+            let shared = broker.clone();
+
+            runtime().spawn(async move {
+                for id in 0.. {
+                    let (stream, _) = match listener.accept().await {
+                        Ok(accepted) => accepted,
+                        Err(_) => break,
+                    };
+
+                    rocket::tokio::spawn(serve_client(shared.clone(), id, stream));
+                }
+            });
+
+            (port, broker)
+        })
+    }
+
+    pub fn broker_port() -> u16 {
+        broker().0
+    }
+
+    /// Waits until some client subscribed to exactly `filter`.
+    pub async fn wait_for_subscription(filter: &str) {
+        eventually(|| {
+            let broker = broker().1.lock().unwrap();
+
+            broker
+                .subscribers
+                .values()
+                .any(|subscriber| subscriber.filters.iter().any(|known| known == filter))
+                .then_some(())
+        })
+        .await
+    }
+
+    /// Polls `check` until it returns a value.
+    pub async fn eventually<T>(mut check: impl FnMut() -> Option<T>) -> T {
+        let poll = async {
+            loop {
+                if let Some(value) = check() {
+                    return value;
+                }
+
+                sleep(POLL_INTERVAL).await;
+            }
+        };
+
+        timeout(RECEIVE_TIMEOUT, poll)
+            .await
+            .expect("Condition not met in time")
+    }
+
+    /// The MQTT bridge as started by the fairing, connected to the test
+    /// broker with its own database.
+    pub struct Server {
+        pub pool: DbPool,
+    }
+
+    /// Starts the bridge once per process, in a thread so it does not block
+    /// the runtime of the calling test.
+    pub fn mqtt_server() -> &'static Server {
+        static SERVER: OnceLock<Server> = OnceLock::new();
+
+        SERVER.get_or_init(|| {
+            std::thread::spawn(|| {
+                let config = init_config();
+                let pool = memory_pool();
+                connection::run_migrations(&pool).unwrap();
+
+                let (queue, _) = channel::<InternalMessage>(1024);
+
+                let rocket = runtime()
+                    .block_on(rocket::custom(rocket::Config::debug_default()).ignite())
+                    .unwrap();
+
+                let options =
+                    MqttOptions::new(config.mqtt.client_id.as_str(), "127.0.0.1", broker_port());
+                let (client, eventloop) = AsyncClient::new(options, 64);
+
+                let _guard = runtime().enter();
+                plugins::mqtt::start(client, eventloop, pool.clone(), queue, rocket.shutdown());
+
+                // Keeps the shutdown handle from tripping
+                std::mem::forget(rocket);
+
+                Server { pool }
+            })
+            .join()
+            .unwrap()
+        })
+    }
+
+    /// A client of the test broker subscribed to `filters`.
+    pub async fn subscriber(filters: &[&str]) -> (AsyncClient, EventLoop) {
+        static CLIENT_ID: OnceLock<Mutex<usize>> = OnceLock::new();
+
+        let id = {
+            let mut next = CLIENT_ID.get_or_init(|| Mutex::new(0)).lock().unwrap();
+            *next += 1;
+            *next
+        };
+
+        let options = MqttOptions::new(format!("test-{}", id), "127.0.0.1", broker_port());
+        let (client, mut eventloop) = AsyncClient::new(options, 64);
+
+        for filter in filters {
+            client.subscribe(*filter, QoS::AtMostOnce).await.unwrap();
+        }
+
+        // Waits until the broker confirmed the subscriptions
+        let mut acknowledged = 0;
+
+        while acknowledged < filters.len() {
+            if let Event::Incoming(Packet::SubAck(_)) = eventloop.poll().await.unwrap() {
+                acknowledged += 1;
+            }
+        }
+
+        (client, eventloop)
+    }
+
+    /// A client of the test broker whose event loop runs in the background,
+    /// for publishing only.
+    pub async fn publisher() -> AsyncClient {
+        let (client, mut eventloop) = subscriber(&[]).await;
+
+        rocket::tokio::spawn(async move { while eventloop.poll().await.is_ok() {} });
+
+        client
+    }
+
+    /// The next message on `topic`, skipping the ones on other topics.
+    pub async fn next_publish(eventloop: &mut EventLoop, topic: &str) -> Publish {
+        let receive = async {
+            loop {
+                if let Event::Incoming(Packet::Publish(publish)) = eventloop.poll().await.unwrap() {
+                    if publish.topic == topic {
+                        return publish;
+                    }
+                }
+            }
+        };
+
+        timeout(RECEIVE_TIMEOUT, receive)
+            .await
+            .unwrap_or_else(|_| panic!("Nothing received on {}", topic))
+    }
+}
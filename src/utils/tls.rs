@@ -0,0 +1,277 @@
+use std::{future::poll_fn, net::IpAddr, task::Poll, time::Duration};
+
+use hyper::{
+    body::HttpBody,
+    client::conn::{self, SendRequest},
+    header::{CONTENT_TYPE, HOST},
+    Body, Method, Request,
+};
+use openssl::{nid::Nid, x509::X509};
+use rocket::tokio::{
+    self,
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
+};
+use sha2::{Digest, Sha256};
+
+use crate::repsonses::ApiError;
+
+/// How to talk to a device whose certificate is self-signed. Certificates
+/// are not checked against any CA or hostname, only against `pin`.
+pub enum Transport<'a> {
+    /// Without a pin any certificate is accepted, its fingerprint is returned
+    /// so it can be pinned (trust on first use).
+    Https { pin: Option<&'a str> },
+    Http,
+}
+
+/// A single HTTP/1.1 connection, nothing is sent until `send` is called.
+/// It is kept alive between requests.
+pub struct Connection {
+    sender: SendRequest<Body>,
+    host: String,
+    /// Hex encoded SHA-256 of the peer's certificate, `None` for plain HTTP.
+    pub fingerprint: Option<String>,
+    /// Subject common name of the peer's certificate.
+    pub common_name: Option<String>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Responses of bridges and devices are a few hundred KiB at most, larger
+/// ones are rejected rather than buffered.
+static MAX_BODY_SIZE: usize = 4 << 20;
+
+fn connect_error(message: &str) -> ApiError {
+    ApiError::upstream("upstream", message)
+}
+
+fn timeout_error() -> ApiError {
+    ApiError::Timeout {
+        provider: "upstream".to_owned(),
+    }
+}
+
+/// Splits `host[:port]` and `[v6][:port]` as accepted by `validate_host`.
+fn split_host(host: &str, default_port: u16) -> (String, u16) {
+    if let Some((address, port)) = host
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+    {
+        let port = port
+            .strip_prefix(':')
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(default_port);
+
+        return (address.to_owned(), port);
+    }
+
+    if host.parse::<IpAddr>().is_ok() {
+        return (host.to_owned(), default_port);
+    }
+
+    match host.rsplit_once(':') {
+        Some((name, port)) => match port.parse() {
+            Ok(port) => (name.to_owned(), port),
+            Err(_) => (host.to_owned(), default_port),
+        },
+        None => (host.to_owned(), default_port),
+    }
+}
+
+fn common_name(der: &[u8]) -> Option<String> {
+    let certificate = X509::from_der(der).ok()?;
+    let entry = certificate
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()?;
+
+    entry.data().to_string().ok()
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+async fn handshake<S>(stream: S) -> Result<SendRequest<Body>, ApiError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, connection) = conn::handshake(stream)
+        .await
+        .map_err(|_| connect_error("Failed to connect"))?;
+
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    Ok(sender)
+}
+
+/// Fails with `ApiError::Untrusted` before any request is sent when the
+/// certificate does not match the pin.
+pub async fn connect(
+    host: &str,
+    transport: Transport<'_>,
+    connect_timeout: Duration,
+) -> Result<Connection, ApiError> {
+    let default_port = match transport {
+        Transport::Https { .. } => 443,
+        Transport::Http => 80,
+    };
+
+    let (address, port) = split_host(host, default_port);
+
+    let stream = match timeout(connect_timeout, TcpStream::connect((address.as_str(), port))).await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(_)) => return Err(connect_error("Failed to connect")),
+        Err(_) => return Err(timeout_error()),
+    };
+
+    let pin = match transport {
+        Transport::Https { pin } => pin,
+        Transport::Http => {
+            return Ok(Connection {
+                sender: handshake(stream).await?,
+                host: host.to_owned(),
+                fingerprint: None,
+                common_name: None,
+            })
+        }
+    };
+
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+
+    let stream = match timeout(
+        connect_timeout,
+        tokio_native_tls::TlsConnector::from(connector).connect(&address, stream),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(_)) => return Err(connect_error("TLS handshake failed")),
+        Err(_) => return Err(timeout_error()),
+    };
+
+    let certificate = match stream.get_ref().peer_certificate() {
+        Ok(Some(certificate)) => certificate
+            .to_der()
+            .map_err(|_| connect_error("Invalid certificate"))?,
+        _ => return Err(connect_error("No certificate presented")),
+    };
+
+    let fingerprint = fingerprint(&certificate);
+
+    if pin.is_some_and(|pin| !pin.eq_ignore_ascii_case(&fingerprint)) {
+        return Err(ApiError::Untrusted {
+            provider: "upstream".to_owned(),
+        });
+    }
+
+    Ok(Connection {
+        sender: handshake(stream).await?,
+        host: host.to_owned(),
+        fingerprint: Some(fingerprint),
+        common_name: common_name(&certificate),
+    })
+}
+
+async fn read_body(mut body: Body) -> Result<Vec<u8>, ApiError> {
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| connect_error("Request failed"))?;
+
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(connect_error("Response too large"));
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+impl Connection {
+    /// Whether the peer kept the connection open for another request.
+    pub async fn is_reusable(&mut self) -> bool {
+        poll_fn(|cx| match self.sender.poll_ready(cx) {
+            Poll::Ready(result) => Poll::Ready(result.is_ok()),
+            Poll::Pending => Poll::Ready(false),
+        })
+        .await
+    }
+
+    /// `request_timeout` covers sending the request and reading the body.
+    pub async fn send(
+        &mut self,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+        request_timeout: Duration,
+    ) -> Result<Response, ApiError> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(HOST, self.host.as_str());
+
+        if body.is_some() {
+            request = request.header(CONTENT_TYPE, "application/json");
+        }
+
+        let request = request
+            .body(body.map(Body::from).unwrap_or_else(Body::empty))
+            .map_err(|e| ApiError::Internal(e.to_string()))?;
+
+        let exchange = async {
+            let response = self
+                .sender
+                .send_request(request)
+                .await
+                .map_err(|_| connect_error("Request failed"))?;
+
+            let status = response.status().as_u16();
+
+            let body = read_body(response.into_body()).await?;
+
+            Ok(Response {
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            })
+        };
+
+        match timeout(request_timeout, exchange).await {
+            Ok(result) => result,
+            Err(_) => Err(timeout_error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn limits_the_body_size() {
+        let body = read_body(Body::from(vec![b'a'; MAX_BODY_SIZE])).await;
+        assert_eq!(body.unwrap().len(), MAX_BODY_SIZE);
+
+        let chunks = (0..=MAX_BODY_SIZE / 1024)
+            .map(|_| Ok::<_, std::io::Error>(vec![b'a'; 1024]))
+            .collect::<Vec<_>>();
+        let body = read_body(Body::wrap_stream(futures::stream::iter(chunks))).await;
+        assert!(body.is_err());
+    }
+}
@@ -0,0 +1,517 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::Deref,
+};
+
+use jsonschema::{paths::PathChunk, JSONSchema};
+use okapi::openapi3::RequestBody;
+use rocket::{
+    data::{self, Data, FromData},
+    http::{ContentType, Status},
+    response::Responder,
+    serde::json::Json,
+    Request,
+};
+use rocket_okapi::{gen::OpenApiGenerator, request::OpenApiFromData};
+use schemars::{
+    _serde_json::{self, Value},
+    schema_for, JsonSchema,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+static USERNAME_MIN_LENGTH: usize = 3;
+static USERNAME_MAX_LENGTH: usize = 32;
+static PASSWORD_MIN_LENGTH: usize = 8;
+// bcrypt only looks at the first 72 bytes
+static PASSWORD_MAX_LENGTH: usize = 72;
+static LABEL_MAX_LENGTH: usize = 64;
+static BRIDGE_USER_MAX_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl ValidationErrors {
+    pub fn new() -> ValidationErrors {
+        ValidationErrors(Vec::new())
+    }
+
+    pub fn check(&mut self, field: &str, result: Result<(), String>) {
+        if let Err(message) = result {
+            self.0.push(FieldError {
+                field: field.to_owned(),
+                message,
+            });
+        }
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+pub fn validate_email(email: &str) -> Result<(), String> {
+    let parts: Vec<&str> = email.split('@').collect();
+
+    if email.len() > 254
+        || parts.len() != 2
+        || parts[0].is_empty()
+        || parts[0]
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+        || !parts[1].contains('.')
+        || validate_hostname(parts[1]).is_err()
+    {
+        return Err("Must be a valid email address".to_owned());
+    }
+
+    Ok(())
+}
+
+pub fn validate_username(username: &str) -> Result<(), String> {
+    if username.len() < USERNAME_MIN_LENGTH || username.len() > USERNAME_MAX_LENGTH {
+        return Err(format!(
+            "Must be between {} and {} characters long",
+            USERNAME_MIN_LENGTH, USERNAME_MAX_LENGTH
+        ));
+    }
+
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err("May only contain letters, digits, '_', '-' and '.'".to_owned());
+    }
+
+    Ok(())
+}
+
+pub fn validate_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < PASSWORD_MIN_LENGTH {
+        return Err(format!(
+            "Must be at least {} characters long",
+            PASSWORD_MIN_LENGTH
+        ));
+    }
+
+    if password.len() > PASSWORD_MAX_LENGTH {
+        return Err(format!(
+            "Must be at most {} bytes long",
+            PASSWORD_MAX_LENGTH
+        ));
+    }
+
+    if !password.chars().any(|c| c.is_alphabetic()) || !password.chars().any(|c| c.is_numeric()) {
+        return Err("Must contain at least one letter and one digit".to_owned());
+    }
+
+    Ok(())
+}
+
+/// Display names, icons and rooms set by the user.
+pub fn validate_label(label: &str) -> Result<(), String> {
+    if label.trim().is_empty() || label.chars().count() > LABEL_MAX_LENGTH {
+        return Err(format!(
+            "Must be between 1 and {} characters long",
+            LABEL_MAX_LENGTH
+        ));
+    }
+
+    if label.chars().any(|c| c.is_control()) {
+        return Err("Must not contain control characters".to_owned());
+    }
+
+    Ok(())
+}
+
+/// Username issued by a Hue bridge, empty until the bridge is paired. It ends
+/// up in the path of every request to the bridge.
+pub fn validate_bridge_user(user: &str) -> Result<(), String> {
+    if user.len() > BRIDGE_USER_MAX_LENGTH {
+        return Err(format!(
+            "Must be at most {} characters long",
+            BRIDGE_USER_MAX_LENGTH
+        ));
+    }
+
+    if !user
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("May only contain letters, digits, '-' and '_'".to_owned());
+    }
+
+    Ok(())
+}
+
+/// Hex encoded SHA-256 certificate fingerprint.
+pub fn validate_fingerprint(fingerprint: &str) -> Result<(), String> {
+    if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Must be a SHA-256 fingerprint of 64 hex digits".to_owned());
+    }
+
+    Ok(())
+}
+
+/// The last label must not be all digits, so dotted numbers that are not a
+/// valid IPv4 address (e.g. `999.999.999.999`) are not taken as a hostname.
+fn validate_hostname(hostname: &str) -> Result<(), String> {
+    let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+
+    if hostname.is_empty() || hostname.len() > 253 {
+        return Err("Must be a valid hostname".to_owned());
+    }
+
+    for label in hostname.split('.') {
+        if label.is_empty()
+            || label.len() > 63
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err("Must be a valid hostname".to_owned());
+        }
+    }
+
+    let top_level = hostname.rsplit('.').next().unwrap_or(hostname);
+
+    if top_level.chars().all(|c| c.is_ascii_digit()) {
+        return Err("Must be a valid hostname".to_owned());
+    }
+
+    Ok(())
+}
+
+fn validate_port(port: &str) -> Result<(), String> {
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(()),
+        _ => Err("Port must be between 1 and 65535".to_owned()),
+    }
+}
+
+/// Accepts an IPv4 address, a bracketed IPv6 address or a hostname, each with
+/// an optional port. IPv6 addresses must be bracketed, also without a port.
+/// Schemes, paths and credentials are rejected since the value ends up in
+/// `http://{host}/...` URLs.
+pub fn validate_host(host: &str) -> Result<(), String> {
+    if host.is_empty() {
+        return Err("Must not be empty".to_owned());
+    }
+
+    if host.contains(['/', '@', '?', '#']) {
+        return Err("Must not contain a scheme, path or credentials".to_owned());
+    }
+
+    if let Some(rest) = host.strip_prefix('[') {
+        let (address, port) = match rest.split_once(']') {
+            Some(parts) => parts,
+            None => return Err("Must be a valid IP address or hostname".to_owned()),
+        };
+
+        if address.parse::<Ipv6Addr>().is_err() {
+            return Err("Must be a valid IPv6 address".to_owned());
+        }
+
+        return match port {
+            "" => Ok(()),
+            port => match port.strip_prefix(':') {
+                Some(port) => validate_port(port),
+                None => Err("Must be a valid IP address or hostname".to_owned()),
+            },
+        };
+    }
+
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return Ok(());
+    }
+
+    if host.matches(':').count() > 1 {
+        return Err("IPv6 addresses must be enclosed in brackets".to_owned());
+    }
+
+    let (address, port) = match host.split_once(':') {
+        Some((address, port)) => (address, Some(port)),
+        None => (host, None),
+    };
+
+    if let Some(port) = port {
+        validate_port(port)?;
+    }
+
+    if address.parse::<Ipv4Addr>().is_ok() {
+        return Ok(());
+    }
+
+    validate_hostname(address).map_err(|_| "Must be a valid IP address or hostname".to_owned())
+}
+
+/// JSON body guard that runs [`Validate`] after deserializing. Invalid bodies
+/// fail with `422`, rendered with the field errors by the `422` catcher.
+#[derive(Debug)]
+pub struct Validated<T>(pub T);
+
+impl<T> Validated<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Validated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+fn fail<'r, T>(
+    request: &'r Request<'_>,
+    status: Status,
+    errors: ValidationErrors,
+) -> data::Outcome<'r, T, ()> {
+    request.local_cache(|| errors);
+    data::Outcome::Failure((status, ()))
+}
+
+#[rocket::async_trait]
+impl<'r, T: Deserialize<'r> + Validate> FromData<'r> for Validated<T> {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        match Json::<T>::from_data(request, data).await {
+            data::Outcome::Success(json) => {
+                let value = json.into_inner();
+
+                match value.validate() {
+                    Ok(_) => data::Outcome::Success(Validated(value)),
+                    Err(errors) => fail(request, Status::UnprocessableEntity, errors),
+                }
+            }
+            data::Outcome::Forward(data) => data::Outcome::Forward(data),
+            data::Outcome::Failure((status, e)) => fail(request, status, body_error(e)),
+        }
+    }
+}
+
+impl<'r, T: JsonSchema + Deserialize<'r> + Validate> OpenApiFromData<'r> for Validated<T> {
+    fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        Json::<T>::request_body(gen)
+    }
+}
+
+fn body_error(message: impl ToString) -> ValidationErrors {
+    ValidationErrors(vec![FieldError {
+        field: "body".to_owned(),
+        message: message.to_string(),
+    }])
+}
+
+/// Like [`Validated`], but the body is first checked against the JSON schema
+/// of `T`, the same one that is published for it.
+#[derive(Debug)]
+pub struct SchemaValidated<T>(pub T);
+
+impl<T> SchemaValidated<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// Field paths use the `hue_bridges[0].ip` form of the [`Validate`] errors.
+fn check_schema<T: JsonSchema>(value: &Value) -> Result<(), ValidationErrors> {
+    let schema = _serde_json::to_value(schema_for!(T)).map_err(body_error)?;
+    let schema = JSONSchema::compile(&schema).map_err(body_error)?;
+
+    let mut errors = ValidationErrors::new();
+
+    if let Err(schema_errors) = schema.validate(value) {
+        for error in schema_errors {
+            let mut field = String::new();
+
+            for chunk in error.instance_path.iter() {
+                match chunk {
+                    PathChunk::Property(name) if field.is_empty() => field.push_str(name),
+                    PathChunk::Property(name) => field.push_str(&format!(".{}", name)),
+                    PathChunk::Index(index) => field.push_str(&format!("[{}]", index)),
+                    PathChunk::Keyword(_) => {}
+                }
+            }
+
+            if field.is_empty() {
+                field.push_str("body");
+            }
+
+            errors.check(&field, Err(error.to_string()));
+        }
+    }
+
+    errors.into_result()
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + JsonSchema + Validate> FromData<'r> for SchemaValidated<T> {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let value = match Json::<Value>::from_data(request, data).await {
+            data::Outcome::Success(json) => json.into_inner(),
+            data::Outcome::Forward(data) => return data::Outcome::Forward(data),
+            data::Outcome::Failure((status, e)) => return fail(request, status, body_error(e)),
+        };
+
+        if let Err(errors) = check_schema::<T>(&value) {
+            return fail(request, Status::UnprocessableEntity, errors);
+        }
+
+        let value: T = match _serde_json::from_value(value) {
+            Ok(value) => value,
+            Err(e) => return fail(request, Status::UnprocessableEntity, body_error(e)),
+        };
+
+        match value.validate() {
+            Ok(_) => data::Outcome::Success(SchemaValidated(value)),
+            Err(errors) => fail(request, Status::UnprocessableEntity, errors),
+        }
+    }
+}
+
+impl<'r, T: JsonSchema + DeserializeOwned + Validate> OpenApiFromData<'r> for SchemaValidated<T> {
+    fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        Json::<T>::request_body(gen)
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ValidationResponse {
+    pub code: String,
+    pub message: String,
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationResponse {
+    pub fn from_request(request: &Request<'_>) -> ValidationResponse {
+        ValidationResponse {
+            code: "validation_failed".to_owned(),
+            message: "Validation failed".to_owned(),
+            errors: request.local_cache(ValidationErrors::new).0.clone(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> Responder<'r, 'static> for ValidationResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let body = _serde_json::to_string(&self).unwrap();
+
+        rocket::Response::build()
+            .status(Status::UnprocessableEntity)
+            .header(ContentType::JSON)
+            .streamed_body(std::io::Cursor::new(body))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_emails() {
+        assert!(validate_email("user@example.com").is_ok());
+        assert!(validate_email("user.name+tag@sub.example.com.").is_ok());
+
+        for email in [
+            "user",
+            "@example.com",
+            "user@localhost",
+            "user@@example.com",
+            "us er@example.com",
+            "user@example..com",
+            "user@-example.com",
+            "user@999.999.999.999",
+            "user@1.2.3.4",
+        ] {
+            assert!(validate_email(email).is_err(), "{}", email);
+        }
+    }
+
+    #[test]
+    fn validates_hosts() {
+        for host in [
+            "192.168.1.10",
+            "192.168.1.10:8080",
+            "hue-bridge",
+            "hue-bridge.local:80",
+            "[::1]",
+            "[fe80::1]:8443",
+        ] {
+            assert!(validate_host(host).is_ok(), "{}", host);
+        }
+
+        for host in [
+            "",
+            "http://192.168.1.10",
+            "user@192.168.1.10",
+            "192.168.1.10/api",
+            "192.168.1.10:0",
+            "192.168.1.10:65536",
+            "999.999.999.999",
+            "bridge.123",
+            "-bridge",
+        ] {
+            assert!(validate_host(host).is_err(), "{}", host);
+        }
+    }
+
+    #[test]
+    fn requires_brackets_for_ipv6_hosts() {
+        assert_eq!(
+            validate_host("fe80::1"),
+            Err("IPv6 addresses must be enclosed in brackets".to_owned())
+        );
+        assert_eq!(
+            validate_host("[192.168.1.10]"),
+            Err("Must be a valid IPv6 address".to_owned())
+        );
+
+        assert!(validate_host("[::1").is_err());
+        assert!(validate_host("[::1]8080").is_err());
+        assert!(validate_host("[::1]:0").is_err());
+    }
+
+    #[test]
+    fn validates_bridge_users() {
+        assert!(validate_bridge_user("").is_ok());
+        assert!(validate_bridge_user("1028d66426293e821ecfd9ef1a0731df").is_ok());
+        assert!(validate_bridge_user("user/../config").is_err());
+        assert!(validate_bridge_user("user?x=1").is_err());
+        assert!(validate_bridge_user(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn validates_passwords() {
+        assert!(validate_password("password1").is_ok());
+
+        // Counted in chars, limited in bytes
+        assert!(validate_password("pässwör1").is_ok());
+        assert!(validate_password("äöü1").is_err());
+        assert!(validate_password(&format!("{}1", "a".repeat(71))).is_ok());
+        assert!(validate_password(&format!("{}1", "ä".repeat(36))).is_err());
+
+        assert!(validate_password("password").is_err());
+        assert!(validate_password("12345678").is_err());
+    }
+}